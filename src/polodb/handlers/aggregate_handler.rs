@@ -55,15 +55,25 @@ impl Handler for AggregateHandle {
             index += 1;
         }
 
+        let hint = FindHandler::parse_hint(doc)?;
+
         let session_opt = ctx.session.clone();
         let cursor = if let Some(session) = session_opt {
             let txn = session.get_transaction().ok_or(anyhow!("transaction not started"))?;
             let collection = txn.collection::<Document>(&col_name);
-            collection.aggregate(pipeline_arr).run()?
+            let mut aggregate = collection.aggregate(pipeline_arr);
+            if let Some(hint) = hint {
+                aggregate = aggregate.hint(hint)
+            };
+            aggregate.run()?
         } else {
             let db = ctx.app_context.db();
             let collection = db.collection::<Document>(&col_name);
-            collection.aggregate(pipeline_arr).run()?
+            let mut aggregate = collection.aggregate(pipeline_arr);
+            if let Some(hint) = hint {
+                aggregate = aggregate.hint(hint)
+            };
+            aggregate.run()?
         };
 
         let cursor = Arc::new(Mutex::new(cursor));
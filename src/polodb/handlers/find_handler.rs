@@ -20,6 +20,7 @@ use crate::reply::Reply;
 use async_trait::async_trait;
 use log::debug;
 use polodb_core::{ClientCursor, CollectionT};
+use polodb_core::options::Hint;
 
 pub(crate) struct FindHandler {}
 
@@ -55,6 +56,17 @@ impl FindHandler {
         Ok(doc)
     }
 
+    /// The hint is the name of the index, or the key pattern of the index.
+    pub(crate) fn parse_hint(doc: &RawDocumentBuf) -> Result<Option<Hint>> {
+        let hint = match doc.get("hint")? {
+            Some(RawBsonRef::String(name)) => Some(Hint::Name(name.to_string())),
+            Some(RawBsonRef::Document(keys)) => Some(Hint::Keys(bson::from_slice::<Document>(keys.as_bytes())?)),
+            Some(_) => return Err(anyhow!("hint is not a string or a document")),
+            None => None,
+        };
+        Ok(hint)
+    }
+
     fn consume_first_batch(cursor: &mut ClientCursor<Document>, batch_size: isize) -> Result<(RawArrayBuf, bool)> {
         let mut raw_arr = RawArrayBuf::new();
         let mut has_more = false;
@@ -130,6 +142,24 @@ impl Handler for FindHandler {
             }
         });
 
+        let sort = match doc.get("sort")? {
+            Some(val) => {
+                let doc = val.as_document().ok_or(anyhow!("sort is not a document"))?;
+                Some(bson::from_slice::<bson::Document>(doc.as_bytes())?)
+            },
+            None => None,
+        };
+
+        let projection = match doc.get("projection")? {
+            Some(val) => {
+                let doc = val.as_document().ok_or(anyhow!("projection is not a document"))?;
+                Some(bson::from_slice::<bson::Document>(doc.as_bytes())?)
+            },
+            None => None,
+        };
+
+        let hint = FindHandler::parse_hint(doc)?;
+
         let batch_size = match doc.get("batchSize")? {
             Some(val) => {
                 val.as_i32().unwrap_or(DEFAULT_BATCH_SIZE)
@@ -149,6 +179,15 @@ impl Handler for FindHandler {
             if let Some(skip) = skip {
                 find = find.skip(skip as u64)
             };
+            if let Some(sort) = sort {
+                find = find.sort(sort)
+            };
+            if let Some(projection) = projection {
+                find = find.projection(projection)
+            };
+            if let Some(hint) = hint {
+                find = find.hint(hint)
+            };
             find.run()?
         } else {
             let collection = db.collection::<Document>(collection_name);
@@ -159,6 +198,15 @@ impl Handler for FindHandler {
             if let Some(skip) = skip {
                 find = find.skip(skip as u64)
             };
+            if let Some(sort) = sort {
+                find = find.sort(sort)
+            };
+            if let Some(projection) = projection {
+                find = find.projection(projection)
+            };
+            if let Some(hint) = hint {
+                find = find.hint(hint)
+            };
             find.run()?
         };
         if single_batch {
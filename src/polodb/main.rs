@@ -366,6 +366,12 @@ mod tests {
                 let all = cursor.try_collect::<Vec<Document>>().await.unwrap();
                 assert_eq!(990, all.len());
 
+                // test sort
+                let cursor = my_coll.find(doc! {}).sort(doc! { "_id": -1 }).limit(3).await.unwrap();
+                let all = cursor.try_collect::<Vec<Document>>().await.unwrap();
+                let ids = all.iter().map(|doc| doc.get_i32("_id").unwrap()).collect::<Vec<i32>>();
+                assert_eq!(vec![999, 998, 997], ids);
+
                 Ok(())
             }
         }
@@ -16,6 +16,7 @@ use std::sync::Weak;
 use bson::Document;
 use serde::de::DeserializeOwned;
 use crate::{ClientCursor, Error, Result};
+use crate::options::Hint;
 use crate::db::db_inner::DatabaseInner;
 use crate::transaction::TransactionInner;
 
@@ -24,6 +25,7 @@ pub struct Aggregate<'a, 'b, T: DeserializeOwned + Send + Sync = Document> {
     name: &'a str,
     pipeline: Vec<Document>,
     txn: Option<&'b TransactionInner>,
+    hint: Option<Hint>,
     _phantom: std::marker::PhantomData<T>,
 }
 
@@ -34,10 +36,18 @@ impl <'a, 'b , T: DeserializeOwned + Send + Sync> Aggregate<'a, 'b, T> {
             name,
             pipeline,
             txn,
+            hint: None,
             _phantom: Default::default(),
         }
     }
 
+    /// Use the index of the hint to find the documents of the first stage,
+    /// or scan the collection without any index if the hint is `{ "$natural": 1 }`.
+    pub fn hint(mut self, hint: Hint) -> Self {
+        self.hint = Some(hint);
+        self
+    }
+
     pub fn run(self) -> Result<ClientCursor<T>> {
         let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
         let txn = match self.txn {
@@ -47,7 +57,28 @@ impl <'a, 'b , T: DeserializeOwned + Send + Sync> Aggregate<'a, 'b, T> {
                 txn
             }
         };
-        db.aggregate_with_owned_session(&self.name, self.pipeline, txn.clone())
+        db.aggregate_with_owned_session(&self.name, self.pipeline, self.hint, txn.clone())
+    }
+
+    /// Explain how the pipeline is executed without running it,
+    /// the result contains the chosen plan, the rejected indexes and the compiled program.
+    pub fn explain(self) -> Result<Document> {
+        self.explain_internal(false)
+    }
+
+    /// Explain the pipeline like [`Aggregate::explain`], and run it to report the stats of the execution,
+    /// such as the documents examined and returned.
+    pub fn explain_with_stats(self) -> Result<Document> {
+        self.explain_internal(true)
+    }
+
+    fn explain_internal(self, with_stats: bool) -> Result<Document> {
+        let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
+        let txn = match self.txn {
+            Some(txn) => txn.clone(),
+            None => db.start_transaction()?,
+        };
+        db.explain_aggregate(self.name, self.pipeline, self.hint, txn, with_stats)
     }
 
     pub fn with_type<U>(self) -> Aggregate<'a, 'b, U>
@@ -57,6 +88,7 @@ impl <'a, 'b , T: DeserializeOwned + Send + Sync> Aggregate<'a, 'b, T> {
             name: self.name,
             pipeline: self.pipeline,
             txn: self.txn,
+            hint: self.hint,
             _phantom: Default::default(),
         }
     }
@@ -17,8 +17,16 @@ use bson::{Document, doc};
 use serde::de::DeserializeOwned;
 use crate::db::db_inner::DatabaseInner;
 use crate::{ClientCursor, Error, Result};
+use crate::options::Hint;
 use crate::transaction::TransactionInner;
 
+// A simple find is executed with the filter,
+// it's an aggregation if any of skip, limit, sort and projection is set.
+enum FindQuery {
+    Filter(Document),
+    Pipeline(Vec<Document>),
+}
+
 pub struct Find<'a, 'b, T: DeserializeOwned + Send + Sync> {
     db: Weak<DatabaseInner>,
     name: &'a str,
@@ -27,6 +35,8 @@ pub struct Find<'a, 'b, T: DeserializeOwned + Send + Sync> {
     skip: Option<u64>,
     limit: Option<u64>,
     sort: Option<Document>,
+    projection: Option<Document>,
+    hint: Option<Hint>,
     _phantom: std::marker::PhantomData<T>,
 }
 
@@ -40,6 +50,8 @@ impl <'a, 'b , T: DeserializeOwned + Send + Sync> Find<'a, 'b, T> {
             skip: None,
             limit: None,
             sort: None,
+            projection: None,
+            hint: None,
             _phantom: Default::default(),
         }
     }
@@ -59,7 +71,22 @@ impl <'a, 'b , T: DeserializeOwned + Send + Sync> Find<'a, 'b, T> {
         self
     }
 
-    pub fn run(self) -> Result<ClientCursor<T>> {
+    /// Return only the fields selected by the projection, such as `{ "name": 1, "_id": 0 }`,
+    /// or all the fields but the excluded ones, such as `{ "history": 0 }`.
+    /// The arrays can be narrowed by `$slice` and `$elemMatch`.
+    pub fn projection(mut self, projection: Document) -> Self {
+        self.projection = Some(projection);
+        self
+    }
+
+    /// Use the index of the hint instead of the one chosen by the planner,
+    /// or scan the collection without any index if the hint is `{ "$natural": 1 }`.
+    pub fn hint(mut self, hint: Hint) -> Self {
+        self.hint = Some(hint);
+        self
+    }
+
+    pub fn run(mut self) -> Result<ClientCursor<T>> {
         let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
         let txn = match self.txn {
             Some(txn) => txn.clone(),
@@ -68,10 +95,47 @@ impl <'a, 'b , T: DeserializeOwned + Send + Sync> Find<'a, 'b, T> {
                 txn
             }
         };
-        match (self.skip.as_ref(), self.limit.as_ref(), self.sort.as_ref()) {
-            (None, None, None) => {
-                db.find_with_owned_session(&self.name, self.filter, txn)
+        let name = self.name;
+        let hint = self.hint.take();
+        match self.into_query() {
+            FindQuery::Filter(filter) => {
+                db.find_with_owned_session(name, filter, hint, txn)
+            }
+            FindQuery::Pipeline(pipeline) => {
+                db.aggregate_with_owned_session(name, pipeline, hint, txn)
             }
+        }
+    }
+
+    /// Explain how the query is executed without running it,
+    /// the result contains the chosen plan, the rejected indexes and the compiled program.
+    pub fn explain(self) -> Result<Document> {
+        self.explain_internal(false)
+    }
+
+    /// Explain the query like [`Find::explain`], and run it to report the stats of the execution,
+    /// such as the documents examined and returned.
+    pub fn explain_with_stats(self) -> Result<Document> {
+        self.explain_internal(true)
+    }
+
+    fn explain_internal(mut self, with_stats: bool) -> Result<Document> {
+        let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
+        let txn = match self.txn {
+            Some(txn) => txn.clone(),
+            None => db.start_transaction()?,
+        };
+        let name = self.name;
+        let hint = self.hint.take();
+        match self.into_query() {
+            FindQuery::Filter(filter) => db.explain_find(name, filter, hint, txn, with_stats),
+            FindQuery::Pipeline(pipeline) => db.explain_aggregate(name, pipeline, hint, txn, with_stats),
+        }
+    }
+
+    fn into_query(self) -> FindQuery {
+        match (self.skip.as_ref(), self.limit.as_ref(), self.sort.as_ref(), self.projection.as_ref()) {
+            (None, None, None, None) => FindQuery::Filter(self.filter),
             _ => {
                 let mut pipeline = vec![
                     doc! {
@@ -97,7 +161,13 @@ impl <'a, 'b , T: DeserializeOwned + Send + Sync> Find<'a, 'b, T> {
                     });
                 }
 
-                db.aggregate_with_owned_session(&self.name, pipeline, txn)
+                if let Some(projection) = self.projection {
+                    pipeline.push(doc! {
+                        "$project": projection,
+                    });
+                }
+
+                FindQuery::Pipeline(pipeline)
             }
         }
     }
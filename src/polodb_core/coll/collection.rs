@@ -18,7 +18,7 @@ use std::borrow::Borrow;
 use std::sync::Weak;
 use serde::de::DeserializeOwned;
 use crate::options::UpdateOptions;
-use crate::{Error, IndexModel, Result};
+use crate::{Error, IndexBuildProgress, IndexModel, Result};
 use crate::db::db_inner::DatabaseInner;
 use crate::action::{Aggregate, Find};
 use crate::results::{DeleteResult, InsertManyResult, InsertOneResult, UpdateResult};
@@ -74,10 +74,32 @@ pub trait CollectionT<T> {
     ///
     /// The size of data deleted returns.
     fn delete_many(&self, query: Document) -> Result<DeleteResult>;
+
+    /// Creates the index on this collection.
+    ///
+    /// With the `background` option, the documents are indexed in chunks
+    /// committed separately, so the collection is not locked during the build.
+    /// The index is used by the queries after it's built.
     fn create_index(&self, index: IndexModel) -> Result<()>;
 
+    /// Creates the indexes on this collection by one scan of the documents,
+    /// the indexes existing in the collection are skipped.
+    /// The `background` option is ignored, the indexes are built in one transaction.
+    fn create_indexes(&self, indexes: Vec<IndexModel>) -> Result<()>;
+
+    /// Lists the indexes of this collection in the order of creation,
+    /// the name of every index is in the options.
+    fn list_indexes(&self) -> Result<Vec<IndexModel>>;
+
+    /// Lists the names of the indexes of this collection.
+    fn list_index_names(&self) -> Result<Vec<String>>;
+
     /// Drops the index specified by `name` from this collection.
     fn drop_index(&self, name: impl AsRef<str>) -> Result<()>;
+
+    /// The progress of the index specified by `name` if it's being built in the background,
+    /// `None` if the index is built or not found.
+    fn index_build_progress(&self, name: impl AsRef<str>) -> Result<Option<IndexBuildProgress>>;
     fn drop(&self) -> Result<()>;
 
     /// Inserts `doc` into the collection.
@@ -97,6 +119,11 @@ pub trait CollectionT<T> {
     fn find_one(&self, filter: Document) -> Result<Option<T>>
     where T: DeserializeOwned + Send + Sync;
 
+    /// Finds a single document in the collection matching `filter`,
+    /// only the fields selected by `projection` are returned.
+    fn find_one_with_projection(&self, filter: Document, projection: Document) -> Result<Option<T>>
+    where T: DeserializeOwned + Send + Sync;
+
     /// Runs an aggregation operation.
     fn aggregate(&self, pipeline: impl IntoIterator<Item = Document>) -> Aggregate<'_, '_>;
 }
@@ -205,11 +232,33 @@ impl<T> CollectionT<T> for Collection<T> {
 
     fn create_index(&self, index: IndexModel) -> Result<()> {
         let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
+        let background = index.options.as_ref().and_then(|options| options.background).unwrap_or(false);
+        if background {
+            return db.create_index_in_background(&self.name, index);
+        }
         let txn = db.start_transaction()?;
         try_db_op!(txn, db.create_index(&self.name, index, &txn));
         Ok(())
     }
 
+    fn create_indexes(&self, indexes: Vec<IndexModel>) -> Result<()> {
+        let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
+        let txn = db.start_transaction()?;
+        try_db_op!(txn, db.create_indexes(&self.name, indexes, &txn));
+        Ok(())
+    }
+
+    fn list_indexes(&self) -> Result<Vec<IndexModel>> {
+        let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
+        let txn = db.start_transaction()?;
+        db.list_indexes(&self.name, &txn)
+    }
+
+    fn list_index_names(&self) -> Result<Vec<String>> {
+        let indexes = self.list_indexes()?;
+        Ok(indexes.into_iter().filter_map(|index| index.options.and_then(|options| options.name)).collect())
+    }
+
     fn drop_index(&self, name: impl AsRef<str>) -> Result<()> {
         let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
         let txn = db.start_transaction()?;
@@ -217,6 +266,12 @@ impl<T> CollectionT<T> for Collection<T> {
         Ok(())
     }
 
+    fn index_build_progress(&self, name: impl AsRef<str>) -> Result<Option<IndexBuildProgress>> {
+        let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
+        let txn = db.start_transaction()?;
+        db.index_build_progress(&self.name, name.as_ref(), &txn)
+    }
+
     fn drop(&self) -> Result<()> {
         let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
         let txn = db.start_transaction()?;
@@ -259,6 +314,16 @@ impl<T> CollectionT<T> for Collection<T> {
         Ok(Some(cursor.deserialize_current()?))
     }
 
+    fn find_one_with_projection(&self, filter: Document, projection: Document) -> Result<Option<T>>
+    where T: DeserializeOwned + Send + Sync {
+        let mut cursor = self.find(filter).projection(projection).run()?;
+        let test = cursor.advance()?;
+        if !test {
+            return Ok(None);
+        }
+        Ok(Some(cursor.deserialize_current()?))
+    }
+
     fn aggregate(&self, pipeline: impl IntoIterator<Item = Document>) -> Aggregate<'_, '_> {
         Aggregate::new(
             self.db.clone(),
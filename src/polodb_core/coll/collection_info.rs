@@ -12,14 +12,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bson::{Binary, DateTime};
+use bson::{Binary, Bson, DateTime, Document};
 use bson::spec::BinarySubtype;
 use serde::{Deserialize, Serialize};
 use indexmap::IndexMap;
 use uuid::Uuid;
-use crate::IndexOptions;
+use crate::{IndexBuildProgress, IndexModel, IndexOptions};
+use crate::index::VectorSimilarity;
 use crate::utils::bson::bson_datetime_now;
 
+/// The way the values of the keys are indexed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum IndexType {
+    /// The values are indexed in the order of the keys.
+    #[default]
+    Regular,
+    /// The words of the string values are indexed for the `$text` queries.
+    Text,
+    /// The GeoJSON points are indexed for the geospatial queries.
+    #[serde(rename = "2dsphere")]
+    Geo2dSphere,
+    /// The arrays of numbers are indexed for the `$vectorSearch` stage.
+    Vector,
+    /// The hashes of the values are indexed for the equality queries.
+    Hashed,
+    /// All the paths under a subdocument are indexed,
+    /// the key is `"$**"` or ends with `".$**"`.
+    Wildcard,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct IndexInfo {
@@ -28,19 +50,153 @@ pub struct IndexInfo {
     pub keys: IndexMap<String, i8>,
 
     pub options: Option<IndexOptions>,
+
+    /// The layout version of the keys of the index.
+    /// The indexes written by the older versions don't have this field,
+    /// they are rebuilt when the database is opened.
+    #[serde(default)]
+    pub key_version: u32,
+
+    /// Set once a document with an array value of a key is indexed,
+    /// every element of the array is an entry of the index.
+    #[serde(default)]
+    pub multikey: bool,
+
+    #[serde(default)]
+    pub index_type: IndexType,
+
+    /// Set while the index is built in the background,
+    /// the index is maintained by the writes, but not used by the queries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub building: Option<IndexBuildProgress>,
 }
 
 impl IndexInfo {
 
-    pub fn single_index(name: String, order: i8, options: Option<IndexOptions>) -> IndexInfo {
-        let mut keys = IndexMap::new();
-        keys.insert(name, order);
+    /// The values of the keys are written in the order-preserving encoding.
+    pub const CURRENT_KEY_VERSION: u32 = 1;
+
+    pub fn new(keys: IndexMap<String, i8>, options: Option<IndexOptions>) -> IndexInfo {
         IndexInfo {
             keys,
             options,
+            key_version: IndexInfo::CURRENT_KEY_VERSION,
+            multikey: false,
+            index_type: IndexType::Regular,
+            building: None,
+        }
+    }
+
+    /// A text index of the string values of the keys.
+    pub fn new_text(keys: Vec<String>, options: Option<IndexOptions>) -> IndexInfo {
+        IndexInfo {
+            index_type: IndexType::Text,
+            ..IndexInfo::new(keys.into_iter().map(|key| (key, 1)).collect(), options)
+        }
+    }
+
+    /// A geospatial index of the points of the key.
+    pub fn new_2dsphere(key: String, options: Option<IndexOptions>) -> IndexInfo {
+        IndexInfo {
+            index_type: IndexType::Geo2dSphere,
+            ..IndexInfo::new(std::iter::once((key, 1)).collect(), options)
         }
     }
 
+    /// A vector index of the arrays of numbers of the key.
+    pub fn new_vector(key: String, options: Option<IndexOptions>) -> IndexInfo {
+        IndexInfo {
+            index_type: IndexType::Vector,
+            ..IndexInfo::new(std::iter::once((key, 1)).collect(), options)
+        }
+    }
+
+    /// A hashed index of the values of the key.
+    pub fn new_hashed(key: String, options: Option<IndexOptions>) -> IndexInfo {
+        IndexInfo {
+            index_type: IndexType::Hashed,
+            ..IndexInfo::new(std::iter::once((key, 1)).collect(), options)
+        }
+    }
+
+    /// A wildcard index of all the paths matched by the key, such as `"attrs.$**"`.
+    pub fn new_wildcard(key: String, options: Option<IndexOptions>) -> IndexInfo {
+        IndexInfo {
+            index_type: IndexType::Wildcard,
+            ..IndexInfo::new(std::iter::once((key, 1)).collect(), options)
+        }
+    }
+
+    /// The model which creates the index again, the name is in the options.
+    pub fn to_index_model(&self, name: &str) -> IndexModel {
+        let mut keys = Document::new();
+        for (key, order) in &self.keys {
+            let value = match self.index_type {
+                IndexType::Regular | IndexType::Wildcard => Bson::Int32(*order as i32),
+                IndexType::Text => Bson::String("text".into()),
+                IndexType::Geo2dSphere => Bson::String("2dsphere".into()),
+                IndexType::Vector => Bson::String("vector".into()),
+                IndexType::Hashed => Bson::String("hashed".into()),
+            };
+            keys.insert(key.clone(), value);
+        }
+
+        // the build mode is not a part of the index
+        let options = IndexOptions {
+            name: Some(name.to_string()),
+            background: None,
+            ..self.options.clone().unwrap_or_default()
+        };
+
+        IndexModel {
+            keys,
+            options: Some(options),
+        }
+    }
+
+    #[inline]
+    pub fn is_text(&self) -> bool {
+        self.index_type == IndexType::Text
+    }
+
+    #[inline]
+    pub fn is_2dsphere(&self) -> bool {
+        self.index_type == IndexType::Geo2dSphere
+    }
+
+    #[inline]
+    pub fn is_vector(&self) -> bool {
+        self.index_type == IndexType::Vector
+    }
+
+    #[inline]
+    pub fn is_hashed(&self) -> bool {
+        self.index_type == IndexType::Hashed
+    }
+
+    #[inline]
+    pub fn is_wildcard(&self) -> bool {
+        self.index_type == IndexType::Wildcard
+    }
+
+    /// The subdocument indexed by a wildcard index,
+    /// an empty string is the whole document.
+    pub fn wildcard_root(&self) -> Option<&str> {
+        if !self.is_wildcard() {
+            return None;
+        }
+        let (key, _) = self.keys.first()?;
+        if key == "$**" {
+            return Some("");
+        }
+        key.strip_suffix(".$**")
+    }
+
+    #[inline]
+    pub fn is_building(&self) -> bool {
+        self.building.is_some()
+    }
+
     #[inline]
     pub fn is_unique(&self) -> bool {
         self.options
@@ -50,6 +206,59 @@ impl IndexInfo {
             .unwrap_or(false)
     }
 
+    #[inline]
+    pub fn is_sparse(&self) -> bool {
+        self.options
+            .as_ref()
+            .and_then(|options| options.sparse)
+            .unwrap_or(false)
+    }
+
+    #[inline]
+    pub fn partial_filter_expression(&self) -> Option<&Document> {
+        self.options
+            .as_ref()
+            .and_then(|options| options.partial_filter_expression.as_ref())
+    }
+
+    #[inline]
+    pub fn expire_after_seconds(&self) -> Option<u64> {
+        self.options
+            .as_ref()
+            .and_then(|options| options.expire_after_seconds)
+    }
+
+    #[inline]
+    pub fn vector_dimensions(&self) -> Option<u32> {
+        self.options
+            .as_ref()
+            .and_then(|options| options.dimensions)
+    }
+
+    /// The similarity of a vector index, the default similarity is cosine.
+    pub fn vector_similarity(&self) -> VectorSimilarity {
+        let name = self.options
+            .as_ref()
+            .and_then(|options| options.similarity.as_deref());
+        // the similarity is validated when the index is created
+        VectorSimilarity::parse(name).unwrap_or(VectorSimilarity::Cosine)
+    }
+
+    /// The weight of a key of a text index, the default weight is 1.
+    pub fn text_weight(&self, key: &str) -> f64 {
+        self.options
+            .as_ref()
+            .and_then(|options| options.weights.as_ref())
+            .and_then(|weights| weights.get(key))
+            .and_then(|weight| match weight {
+                Bson::Int32(v) => Some(*v as f64),
+                Bson::Int64(v) => Some(*v as f64),
+                Bson::Double(v) => Some(*v),
+                _ => None,
+            })
+            .unwrap_or(1.0)
+    }
+
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -19,7 +19,7 @@ use serde::Serialize;
 use crate::db::db_inner::DatabaseInner;
 use crate::options::UpdateOptions;
 use serde::de::DeserializeOwned;
-use crate::{CollectionT, Error, IndexModel, Result};
+use crate::{CollectionT, Error, IndexBuildProgress, IndexModel, Result};
 use crate::action::{Aggregate, Find};
 use crate::results::{DeleteResult, InsertManyResult, InsertOneResult, UpdateResult};
 use crate::transaction::TransactionInner;
@@ -121,12 +121,33 @@ impl<T> CollectionT<T> for TransactionalCollection<T> {
         Ok(())
     }
 
+    fn create_indexes(&self, indexes: Vec<IndexModel>) -> Result<()> {
+        let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
+        db.create_indexes(&self.name, indexes, &self.txn)?;
+        Ok(())
+    }
+
+    fn list_indexes(&self) -> Result<Vec<IndexModel>> {
+        let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
+        db.list_indexes(&self.name, &self.txn)
+    }
+
+    fn list_index_names(&self) -> Result<Vec<String>> {
+        let indexes = self.list_indexes()?;
+        Ok(indexes.into_iter().filter_map(|index| index.options.and_then(|options| options.name)).collect())
+    }
+
     fn drop_index(&self, name: impl AsRef<str>) -> Result<()> {
         let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
         db.drop_index(&self.name, name.as_ref(), &self.txn)?;
         Ok(())
     }
 
+    fn index_build_progress(&self, name: impl AsRef<str>) -> Result<Option<IndexBuildProgress>> {
+        let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
+        db.index_build_progress(&self.name, name.as_ref(), &self.txn)
+    }
+
     fn drop(&self) -> crate::Result<()> {
         let db = self.db.upgrade().ok_or(Error::DbIsClosed)?;
         db.drop_collection(&self.name, &self.txn)?;
@@ -166,6 +187,16 @@ impl<T> CollectionT<T> for TransactionalCollection<T> {
         Ok(Some(cursor.deserialize_current()?))
     }
 
+    fn find_one_with_projection(&self, filter: Document, projection: Document) -> Result<Option<T>>
+    where T: DeserializeOwned + Send + Sync {
+        let mut cursor = self.find(filter).projection(projection).run()?;
+        let test = cursor.advance()?;
+        if !test {
+            return Ok(None);
+        }
+        Ok(Some(cursor.deserialize_current()?))
+    }
+
     fn aggregate(&self, pipeline: impl IntoIterator<Item = Document>) -> Aggregate<'_, '_> {
         Aggregate::new(
             self.db.clone(),
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::path::{Path, PathBuf};
+
 ///
 /// Config builder for the database
 ///
@@ -80,6 +82,50 @@ impl ConfigBuilder {
         self
     }
 
+    pub fn get_sort_memory_limit(&self) -> usize {
+        self.inner.sort_memory_limit
+    }
+
+    /// The memory in bytes a `$sort` stage can use to buffer the documents,
+    /// the sorted documents are written to temporary files beyond the limit.
+    pub fn set_sort_memory_limit(&mut self, v: usize) -> &mut Self {
+        self.inner.sort_memory_limit = v;
+        self
+    }
+
+    pub fn get_sort_spill_dir(&self) -> &Path {
+        &self.inner.sort_spill_dir
+    }
+
+    /// The directory of the temporary files written by a `$sort` stage
+    /// beyond the memory limit, it's the temporary directory of the system by default.
+    pub fn set_sort_spill_dir<P: Into<PathBuf>>(&mut self, v: P) -> &mut Self {
+        self.inner.sort_spill_dir = v.into();
+        self
+    }
+
+    pub fn get_ttl_monitor_sleep_secs(&self) -> u64 {
+        self.inner.ttl_monitor_sleep_secs
+    }
+
+    /// The interval in seconds the background task removes
+    /// the expired documents of the TTL indexes, 0 disables the task.
+    pub fn set_ttl_monitor_sleep_secs(&mut self, v: u64) -> &mut Self {
+        self.inner.ttl_monitor_sleep_secs = v;
+        self
+    }
+
+    pub fn get_index_build_chunk_size(&self) -> usize {
+        self.inner.index_build_chunk_size
+    }
+
+    /// The documents indexed in a transaction by a background index build,
+    /// every chunk of the documents is committed separately.
+    pub fn set_index_build_chunk_size(&mut self, v: usize) -> &mut Self {
+        self.inner.index_build_chunk_size = v.max(1);
+        self
+    }
+
     pub fn take(self) -> Config {
         self.inner
     }
@@ -93,9 +139,16 @@ pub struct Config {
     pub lsm_page_size:     u32,
     pub lsm_block_size:    u32,
     pub sync_log_count:    u64,
+    pub sort_memory_limit: usize,
+    pub sort_spill_dir: PathBuf,
+    pub ttl_monitor_sleep_secs: u64,
+    pub index_build_chunk_size: usize,
 }
 
 const SYNC_LOG_COUNT: u64 = 1000;
+pub(crate) const SORT_MEMORY_LIMIT: usize = 100 * 1024 * 1024;
+const TTL_MONITOR_SLEEP_SECS: u64 = 60;
+const INDEX_BUILD_CHUNK_SIZE: usize = 1000;
 
 impl Default for Config {
 
@@ -106,6 +159,10 @@ impl Default for Config {
             lsm_page_size: 4096,
             lsm_block_size: 4 * 1024 * 1024,
             sync_log_count: SYNC_LOG_COUNT,
+            sort_memory_limit: SORT_MEMORY_LIMIT,
+            sort_spill_dir: std::env::temp_dir(),
+            ttl_monitor_sleep_secs: TTL_MONITOR_SLEEP_SECS,
+            index_build_chunk_size: INDEX_BUILD_CHUNK_SIZE,
         }
     }
 
@@ -16,6 +16,7 @@ use std::cmp::Ordering;
 use std::sync::Arc;
 use bson::Bson;
 use crate::db::RocksDBIterator;
+use crate::index::IndexKeyRange;
 use crate::Result;
 use crate::transaction::TransactionInner;
 
@@ -25,6 +26,10 @@ pub(crate) struct Cursor {
     pub(crate)  prefix_bytes: Vec<u8>,
     kv_cursor:    RocksDBIterator,
     current_key:  Option<Arc<[u8]>>,
+    /// The ranges of the current index scan, in the order of scanning.
+    index_ranges: Vec<IndexKeyRange>,
+    index_range_idx: usize,
+    reverse: bool,
 }
 
 impl Cursor {
@@ -41,6 +46,9 @@ impl Cursor {
             prefix_bytes,
             kv_cursor,
             current_key: None,
+            index_ranges: Vec::new(),
+            index_range_idx: 0,
+            reverse: false,
         }
     }
 
@@ -91,6 +99,14 @@ impl Cursor {
         self.reset_by_custom_key(key_buffer.as_slice())
     }
 
+    /// Move to the first key after the key.
+    pub fn reset_after_key(&mut self, key_buffer: &[u8]) -> Result<()> {
+        if self.reset_by_custom_key(key_buffer)? {
+            self.next()?;
+        }
+        Ok(())
+    }
+
     fn reset_by_custom_key(&mut self, key_buffer: &[u8]) -> Result<bool> {
         self.kv_cursor.seek(key_buffer);
 
@@ -104,31 +120,81 @@ impl Cursor {
         Ok(false)
     }
 
-    pub fn reset_by_index_value(&mut self, index_value: &Bson) -> Result<bool> {
-        let key_buffer = {
-            let mut key_buffer = self.prefix_bytes.clone();
-            let primary_key_buffer = crate::utils::bson::stacked_key([
-                index_value,
-            ])?;
+    /// Start to scan the keys of an index in the ranges.
+    ///
+    /// The ranges must be sorted and must not overlap.
+    /// If `reverse` is true, the keys are scanned from the last one.
+    /// Return false if there is no key in the ranges.
+    pub fn reset_by_index_ranges(&mut self, ranges: &[IndexKeyRange], reverse: bool) -> Result<bool> {
+        self.index_ranges = ranges.to_vec();
+        if reverse {
+            self.index_ranges.reverse();
+        }
+        self.index_range_idx = 0;
+        self.reverse = reverse;
 
-            key_buffer.extend_from_slice(&primary_key_buffer);
+        self.seek_index_range()
+    }
 
-            key_buffer
-        };
+    /// Move to the next key of the index scan.
+    /// Return false if the scan is finished.
+    pub fn next_index_key(&mut self) -> Result<bool> {
+        if self.index_range_idx >= self.index_ranges.len() {
+            return Ok(false);
+        }
 
-        self.kv_cursor.seek(key_buffer.as_slice());
+        if self.reverse {
+            self.kv_cursor.prev();
+        } else {
+            self.kv_cursor.next();
+        }
 
-        if self.kv_cursor.valid() {
-            self.current_key = Some(self.kv_cursor.copy_key_arc()?);
-            if let Some(found) = &self.current_key {
-                let starts_with = found.as_ref().starts_with(key_buffer.as_slice());
-                return Ok(starts_with);
+        if self.update_index_key()? {
+            return Ok(true);
+        }
+
+        self.index_range_idx += 1;
+        self.seek_index_range()
+    }
+
+    // Seek to the first key of the current range,
+    // the empty ranges are skipped.
+    fn seek_index_range(&mut self) -> Result<bool> {
+        while self.index_range_idx < self.index_ranges.len() {
+            let range = &self.index_ranges[self.index_range_idx];
+            if self.reverse {
+                self.kv_cursor.seek_for_prev(range.upper.as_slice());
+                // the upper bound is exclusive
+                if self.kv_cursor.valid() && self.kv_cursor.copy_key()? >= range.upper {
+                    self.kv_cursor.prev();
+                }
+            } else {
+                self.kv_cursor.seek(range.lower.as_slice());
+            }
+
+            if self.update_index_key()? {
+                return Ok(true);
             }
+
+            self.index_range_idx += 1;
         }
 
         Ok(false)
     }
 
+    fn update_index_key(&mut self) -> Result<bool> {
+        if !self.kv_cursor.valid() {
+            self.current_key = None;
+            return Ok(false);
+        }
+
+        let key = self.kv_cursor.copy_key_arc()?;
+        let in_range = self.index_ranges[self.index_range_idx].contains(key.as_ref());
+        self.current_key = Some(key);
+
+        Ok(in_range)
+    }
+
     pub fn peek_key(&self) -> Option<Arc<[u8]>> {
         self.current_key.clone()
     }
@@ -72,10 +72,11 @@ impl Database {
     }
 
     pub fn open_path_with_config<P: AsRef<Path>>(path: P, config: Config) -> Result<Database>  {
-        let inner = DatabaseInner::open_file(path.as_ref(), config)?;
+        let inner = Arc::new(DatabaseInner::open_file(path.as_ref(), config)?);
+        DatabaseInner::start_ttl_monitor(&inner)?;
 
         Ok(Database {
-            inner: Arc::new(inner),
+            inner,
         })
     }
 
@@ -14,30 +14,38 @@
 
 use std::borrow::Borrow;
 use std::collections::HashMap;
-use bson::{Bson, Document};
+use std::convert::TryFrom;
+use bson::{doc, Bson, Document};
 use serde::Serialize;
 use super::db::Result;
 use crate::errors::Error;
-use crate::options::UpdateOptions;
+use crate::options::{Hint, UpdateOptions};
 use crate::Config;
-use crate::vm::SubProgram;
+use crate::vm::{PlanCache, PlanContext, SubProgram};
 use crate::meta_doc_helper::meta_doc_key;
-use crate::index::{IndexBuilder, IndexModel, IndexOptions};
+use crate::index::{build_indexes, IndexBuildProgress, IndexBuilder, IndexModel, IndexOptions, PartialFilter, VectorSimilarity};
 use crate::db::client_cursor::ClientCursor;
 use crate::results::{DeleteResult, InsertManyResult, InsertOneResult, UpdateResult};
+use std::ops::Bound;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use bson::oid::ObjectId;
+use indexmap::IndexMap;
 use serde::de::DeserializeOwned;
 use crate::coll::collection_info::{
     CollectionSpecification,
     IndexInfo,
+    IndexType,
 };
 use crate::cursor::Cursor;
-use crate::index::{IndexHelper, IndexHelperOperation};
+use crate::index::{IndexHelper, IndexHelperOperation, IndexKeyRange};
 use crate::metrics::Metrics;
 use crate::db::rocksdb_wrapper::RocksDBWrapper;
+use crate::db::ttl_monitor::TtlMonitor;
 use crate::transaction::TransactionInner;
 use crate::vm::VM;
+use crate::utils::bson::bson_datetime_now;
 
 const TABLE_META_PREFIX: &'static str = "$TABLE_META";
 
@@ -48,8 +56,9 @@ pub(crate) struct DatabaseInner {
     rocksdb:      RocksDBWrapper,
     node_id:      [u8; 6],
     metrics:      Metrics,
-    #[allow(dead_code)]
     config:       Config,
+    ttl_monitor:  Mutex<Option<TtlMonitor>>,
+    plan_cache:   Arc<PlanCache>,
 }
 
 impl DatabaseInner {
@@ -80,11 +89,160 @@ impl DatabaseInner {
             node_id,
             metrics,
             config,
+            ttl_monitor: Mutex::new(None),
+            plan_cache: Arc::new(PlanCache::new()),
         };
 
+        ctx.upgrade_index_keys()?;
+
         Ok(ctx)
     }
 
+    /// Rebuild the indexes whose keys are written in a legacy layout,
+    /// and resume the background builds interrupted by closing the database.
+    ///
+    /// The legacy indexes are rebuilt in chunks like the background builds,
+    /// so the transactions are bounded by the size of the chunks.
+    fn upgrade_index_keys(&self) -> Result<()> {
+        let mut txn = self.start_transaction()?;
+        txn.set_auto_commit(false);
+
+        let mut builds = Vec::<(String, String)>::new();
+        for meta in self.query_all_meta(&txn)? {
+            let mut collection_spec = bson::from_document::<CollectionSpecification>(meta)?;
+            let col_name = collection_spec._id.clone();
+            let mut upgraded = false;
+
+            for (index_name, index_info) in collection_spec.indexes.iter_mut() {
+                if index_info.key_version < IndexInfo::CURRENT_KEY_VERSION && !index_info.is_building() {
+                    index_info.building = Some(IndexBuildProgress {
+                        docs_total: self.count_documents(&col_name, &txn)?,
+                        ..Default::default()
+                    });
+                    upgraded = true;
+                }
+                if index_info.is_building() {
+                    builds.push((col_name.clone(), index_name.clone()));
+                }
+            }
+
+            if upgraded {
+                DatabaseInner::update_collection_spec(&col_name, &collection_spec, &txn)?;
+            }
+        }
+
+        txn.commit()?;
+
+        for (col_name, index_name) in builds {
+            self.build_index_in_chunks(&col_name, &index_name)?;
+        }
+
+        Ok(())
+    }
+
+    /// Start the background task removing the expired documents of the TTL indexes.
+    pub(crate) fn start_ttl_monitor(db: &Arc<DatabaseInner>) -> Result<()> {
+        if db.config.ttl_monitor_sleep_secs == 0 {
+            return Ok(());
+        }
+        let interval = Duration::from_secs(db.config.ttl_monitor_sleep_secs);
+        let monitor = TtlMonitor::start(Arc::downgrade(db), interval)?;
+        *db.ttl_monitor.lock()? = Some(monitor);
+        Ok(())
+    }
+
+    /// Remove the documents whose date of a TTL index is expired,
+    /// return the number of the removed documents.
+    pub(crate) fn remove_expired_documents(&self) -> Result<usize> {
+        let now = bson_datetime_now();
+        let mut txn = self.start_transaction()?;
+        txn.set_auto_commit(false);
+
+        let mut removed_count = 0;
+        for meta in self.query_all_meta(&txn)? {
+            let collection_spec = bson::from_document::<CollectionSpecification>(meta)?;
+            for (index_name, index_info) in &collection_spec.indexes {
+                // the documents not indexed yet are removed after the build
+                let expire_after_seconds = match index_info.expire_after_seconds() {
+                    Some(seconds) if !index_info.is_building() => seconds,
+                    _ => continue,
+                };
+                let expire_millis = i64::try_from(expire_after_seconds)
+                    .unwrap_or(i64::MAX)
+                    .saturating_mul(1000);
+                let threshold = bson::DateTime::from_millis(now.timestamp_millis().saturating_sub(expire_millis));
+                removed_count += self.remove_expired_by_index(
+                    &txn,
+                    collection_spec.name(),
+                    index_name,
+                    index_info,
+                    threshold,
+                )?;
+            }
+        }
+
+        txn.commit()?;
+
+        Ok(removed_count)
+    }
+
+    // The expired documents are found by the range of the index,
+    // only the dates are compared with the threshold.
+    fn remove_expired_by_index(
+        &self,
+        txn: &TransactionInner,
+        col_name: &str,
+        index_name: &str,
+        index_info: &IndexInfo,
+        threshold: bson::DateTime,
+    ) -> Result<usize> {
+        let order = match index_info.keys.get_index(0) {
+            Some((_, order)) => *order,
+            None => return Ok(0),
+        };
+        let threshold = Bson::DateTime(threshold);
+
+        let prefix = IndexHelper::make_index_prefix(col_name, index_name)?;
+        let ranges = IndexKeyRange::between(
+            &prefix,
+            Bound::Unbounded,
+            Bound::Excluded(&threshold),
+            order,
+        )?.unwrap_or_default();
+
+        let mut pkeys = Vec::<Bson>::new();
+        for range in &ranges {
+            let kv_cursor = txn.rocksdb_txn.new_iterator();
+            kv_cursor.seek(&range.lower);
+
+            while kv_cursor.valid() {
+                let index_key = kv_cursor.copy_key_arc()?;
+                if !range.contains(&index_key) {
+                    break;
+                }
+                let mut slices = crate::utils::bson::split_stacked_keys(&index_key)?;
+                pkeys.push(slices.pop().expect("pkey must exist"));
+                kv_cursor.next();
+            }
+        }
+
+        // the keys of the index are consistent with the documents in the transaction,
+        // so the documents are deleted by the primary keys
+        let mut removed_count = 0;
+        for pkey in pkeys {
+            removed_count += self.internal_delete_by_query(
+                txn,
+                col_name,
+                doc! {
+                    "_id": pkey,
+                },
+                false,
+            )?;
+        }
+
+        Ok(removed_count)
+    }
+
     pub fn metrics(&self) -> Metrics {
         self.metrics.clone()
     }
@@ -94,6 +252,10 @@ impl DatabaseInner {
     }
 
     fn internal_get_collection_id_by_name(&self, txn: &TransactionInner, name: &str) -> Result<CollectionSpecification> {
+        DatabaseInner::get_collection_spec(txn, name)
+    }
+
+    fn get_collection_spec(txn: &TransactionInner, name: &str) -> Result<CollectionSpecification> {
         let mut cursor =  {
             let kv_cursor = txn.rocksdb_txn.new_iterator();
             Cursor::new_with_str_prefix(TABLE_META_PREFIX.to_string(), kv_cursor)?
@@ -113,6 +275,54 @@ impl DatabaseInner {
         Ok(entry)
     }
 
+    // Read the specification and lock it until the transaction ends,
+    // it's used before the specification is updated.
+    fn get_collection_spec_for_update(txn: &TransactionInner, name: &str) -> Result<CollectionSpecification> {
+        let stacked_key = crate::utils::bson::stacked_key(&[
+            Bson::String(TABLE_META_PREFIX.to_string()),
+            Bson::String(name.to_string()),
+        ])?;
+
+        let data = txn.rocksdb_txn
+            .get_for_update(stacked_key.as_slice())?
+            .ok_or_else(|| Error::CollectionNotFound(name.to_string()))?;
+
+        let entry = bson::from_slice::<CollectionSpecification>(data.as_slice())?;
+        Ok(entry)
+    }
+
+    // Read the specification for the writes of the documents.
+    //
+    // The specification is locked shared until the transaction ends,
+    // so the indexes can't be added during the writes, and a background build
+    // scans the documents after the writes which don't know the index are committed.
+    fn get_collection_spec_for_write(txn: &TransactionInner, name: &str) -> Result<CollectionSpecification> {
+        let stacked_key = crate::utils::bson::stacked_key(&[
+            Bson::String(TABLE_META_PREFIX.to_string()),
+            Bson::String(name.to_string()),
+        ])?;
+
+        let data = txn.rocksdb_txn
+            .get_for_share(stacked_key.as_slice())?
+            .ok_or_else(|| Error::CollectionNotFound(name.to_string()))?;
+
+        let entry = bson::from_slice::<CollectionSpecification>(data.as_slice())?;
+        Ok(entry)
+    }
+
+    // The specification is created if the collection doesn't exist,
+    // it's saved by the caller.
+    fn get_or_new_collection_spec_for_update(&self, txn: &TransactionInner, col_name: &str) -> Result<CollectionSpecification> {
+        match DatabaseInner::get_collection_spec_for_update(txn, col_name) {
+            Ok(spec) => Ok(spec),
+            Err(Error::CollectionNotFound(_)) => {
+                let uuid = uuid::Uuid::now_v1(&self.node_id);
+                Ok(CollectionSpecification::new(col_name.to_string(), uuid))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
     pub fn get_collection_meta_by_name_advanced_auto(
         &self,
         name: &str,
@@ -125,7 +335,26 @@ impl DatabaseInner {
     }
 
     pub fn get_collection_meta_by_name_advanced(&self, txn: &TransactionInner, name: &str, create_if_not_exist: bool, node_id: &[u8; 6]) -> Result<Option<CollectionSpecification>> {
-        match self.internal_get_collection_id_by_name(txn, name) {
+        let result = self.internal_get_collection_id_by_name(txn, name);
+        self.spec_or_create(txn, name, result, create_if_not_exist, node_id)
+    }
+
+    /// Get the specification of the collection to write the documents,
+    /// see [`DatabaseInner::get_collection_spec_for_write`].
+    pub(crate) fn get_collection_meta_for_write(&self, txn: &TransactionInner, name: &str, create_if_not_exist: bool, node_id: &[u8; 6]) -> Result<Option<CollectionSpecification>> {
+        let result = DatabaseInner::get_collection_spec_for_write(txn, name);
+        self.spec_or_create(txn, name, result, create_if_not_exist, node_id)
+    }
+
+    fn spec_or_create(
+        &self,
+        txn: &TransactionInner,
+        name: &str,
+        result: Result<CollectionSpecification>,
+        create_if_not_exist: bool,
+        node_id: &[u8; 6],
+    ) -> Result<Option<CollectionSpecification>> {
+        match result {
             Ok(meta) => Ok(Some(meta)),
             Err(Error::CollectionNotFound(_)) => {
                 if create_if_not_exist {
@@ -200,62 +429,248 @@ impl DatabaseInner {
     pub fn create_index(&self, col_name: &str, index: IndexModel, txn: &TransactionInner) -> Result<()> {
         DatabaseInner::validate_col_name(col_name)?;
 
-        self.internal_create_index(txn, col_name, index)?;
+        let index_info = DatabaseInner::make_index_info(index)?;
+        self.create_index_with_info(txn, col_name, index_info, false)?;
 
         Ok(())
     }
 
-    fn internal_create_index(&self, txn: &TransactionInner, col_name: &str, index: IndexModel) -> Result<()> {
+    /// Build the index in the background.
+    ///
+    /// The index is added to the collection as building in a transaction first,
+    /// so the writes after it maintain the index, and the queries don't use it.
+    /// The index is added after the writes in progress are committed,
+    /// because they lock the specification of the collection.
+    /// Then the documents are indexed in chunks, every chunk is committed separately.
+    pub fn create_index_in_background(&self, col_name: &str, index: IndexModel) -> Result<()> {
+        DatabaseInner::validate_col_name(col_name)?;
+
+        let index_info = DatabaseInner::make_index_info(index)?;
+        let txn = self.start_transaction()?;
+        let index_name = match self.create_index_with_info(&txn, col_name, index_info, true) {
+            Ok(index_name) => index_name,
+            Err(err) => {
+                txn.rollback()?;
+                return Err(err);
+            }
+        };
+        txn.commit()?;
+
+        match index_name {
+            Some(index_name) => self.build_index_in_chunks(col_name, &index_name),
+            None => Ok(()),
+        }
+    }
+
+    fn make_index_info(index: IndexModel) -> Result<IndexInfo> {
+        if index.keys.is_empty() {
+            return Err(Error::ValidationError("the keys of an index can not be empty".into()));
+        }
+
+        if index.keys.values().any(|value| value.as_str() == Some("text")) {
+            return DatabaseInner::text_index_info(index);
+        }
+
+        if index.keys.values().any(|value| value.as_str() == Some("2dsphere")) {
+            return DatabaseInner::geo_2dsphere_index_info(index);
+        }
+
+        if index.keys.values().any(|value| value.as_str() == Some("vector")) {
+            return DatabaseInner::vector_index_info(index);
+        }
+
+        if index.keys.values().any(|value| value.as_str() == Some("hashed")) {
+            return DatabaseInner::hashed_index_info(index);
+        }
+
+        if index.keys.keys().any(|key| key == "$**" || key.ends_with(".$**")) {
+            return DatabaseInner::wildcard_index_info(index);
+        }
+
+        let mut keys = IndexMap::<String, i8>::with_capacity(index.keys.len());
+        for (key, order) in index.keys.iter() {
+            let order = match DatabaseInner::index_order(order) {
+                Some(order) => order,
+                None => return Err(Error::InvalidOrderOfIndex(key.to_string())),
+            };
+            keys.insert(key.clone(), order);
+        }
+
+        if let Some(filter) = index.options.as_ref().and_then(|options| options.partial_filter_expression.as_ref()) {
+            PartialFilter::parse(filter)?;
+        }
+
+        let is_ttl = index.options.as_ref().and_then(|options| options.expire_after_seconds).is_some();
+        if is_ttl && keys.len() != 1 {
+            return Err(Error::ValidationError("a TTL index must have only one key".into()));
+        }
+
+        Ok(IndexInfo::new(keys, index.options))
+    }
+
+    // A collection has at most one text index,
+    // all the keys of the index are "text".
+    fn text_index_info(index: IndexModel) -> Result<IndexInfo> {
+        let mut keys = Vec::<String>::with_capacity(index.keys.len());
+        for (key, value) in index.keys.iter() {
+            if value.as_str() != Some("text") {
+                return Err(Error::ValidationError(format!("all the keys of a text index must be \"text\": {}", key)));
+            }
+            keys.push(key.clone());
+        }
+
+        if let Some(options) = &index.options {
+            if options.unique.unwrap_or(false) || options.expire_after_seconds.is_some() {
+                return Err(Error::ValidationError("a text index can not be unique or TTL".into()));
+            }
+            if let Some(filter) = &options.partial_filter_expression {
+                PartialFilter::parse(filter)?;
+            }
+            if let Some(weights) = &options.weights {
+                for (key, weight) in weights {
+                    let is_valid = keys.contains(key) && match weight {
+                        Bson::Int32(v) => *v > 0,
+                        Bson::Int64(v) => *v > 0,
+                        Bson::Double(v) => *v > 0.0,
+                        _ => false,
+                    };
+                    if !is_valid {
+                        return Err(Error::ValidationError(format!("invalid weight of the text index: {}", key)));
+                    }
+                }
+            }
+        }
+
+        Ok(IndexInfo::new_text(keys, index.options))
+    }
+
+    // A 2dsphere index has only one key of the GeoJSON points.
+    fn geo_2dsphere_index_info(index: IndexModel) -> Result<IndexInfo> {
+        let (key, _) = index.keys.iter().next().unwrap();
+        if index.keys.len() != 1 {
+            return Err(Error::ValidationError("a 2dsphere index must have only one key".into()));
+        }
+
+        if let Some(options) = &index.options {
+            if options.unique.unwrap_or(false) || options.expire_after_seconds.is_some() {
+                return Err(Error::ValidationError("a 2dsphere index can not be unique or TTL".into()));
+            }
+            if let Some(filter) = &options.partial_filter_expression {
+                PartialFilter::parse(filter)?;
+            }
+        }
+
+        Ok(IndexInfo::new_2dsphere(key.clone(), index.options))
+    }
+
+    // A vector index has only one key of the arrays of numbers.
+    fn vector_index_info(index: IndexModel) -> Result<IndexInfo> {
+        let (key, _) = index.keys.iter().next().unwrap();
         if index.keys.len() != 1 {
-            return Err(Error::OnlySupportSingleFieldIndexes(Box::new(index.keys)));
+            return Err(Error::ValidationError("a vector index must have only one key".into()));
+        }
+
+        if let Some(options) = &index.options {
+            if options.unique.unwrap_or(false) || options.expire_after_seconds.is_some() {
+                return Err(Error::ValidationError("a vector index can not be unique or TTL".into()));
+            }
+            if options.dimensions == Some(0) {
+                return Err(Error::ValidationError("the dimensions of a vector index can not be 0".into()));
+            }
+            VectorSimilarity::parse(options.similarity.as_deref())?;
+            if let Some(filter) = &options.partial_filter_expression {
+                PartialFilter::parse(filter)?;
+            }
         }
 
-        let options = index.options.as_ref();
+        Ok(IndexInfo::new_vector(key.clone(), index.options))
+    }
 
-        let tuples = index.keys.iter().collect::<Vec<(&String, &Bson)>>();
-        let first_tuple = tuples.first().unwrap();
+    // A hashed index has only one key, the arrays can not be indexed.
+    fn hashed_index_info(index: IndexModel) -> Result<IndexInfo> {
+        let (key, _) = index.keys.iter().next().unwrap();
+        if index.keys.len() != 1 {
+            return Err(Error::ValidationError("a hashed index must have only one key".into()));
+        }
 
-        let (key, value) = first_tuple;
+        if let Some(options) = &index.options {
+            if options.unique.unwrap_or(false) || options.expire_after_seconds.is_some() {
+                return Err(Error::ValidationError("a hashed index can not be unique or TTL".into()));
+            }
+            if let Some(filter) = &options.partial_filter_expression {
+                PartialFilter::parse(filter)?;
+            }
+        }
+
+        Ok(IndexInfo::new_hashed(key.clone(), index.options))
+    }
+
+    // A wildcard index has only one key, such as `{ "attrs.$**": 1 }`,
+    // all the paths under the subdocument are indexed in ascending order.
+    fn wildcard_index_info(index: IndexModel) -> Result<IndexInfo> {
+        let (key, order) = index.keys.iter().next().unwrap();
+        if index.keys.len() != 1 {
+            return Err(Error::ValidationError("a wildcard index must have only one key".into()));
+        }
+        if DatabaseInner::index_order(order) != Some(1) {
+            return Err(Error::InvalidOrderOfIndex(key.to_string()));
+        }
+
+        if let Some(options) = &index.options {
+            let is_sparse = options.sparse.unwrap_or(false);
+            if options.unique.unwrap_or(false) || is_sparse || options.expire_after_seconds.is_some() {
+                return Err(Error::ValidationError("a wildcard index can not be unique, sparse or TTL".into()));
+            }
+            if let Some(filter) = &options.partial_filter_expression {
+                PartialFilter::parse(filter)?;
+            }
+        }
 
-        self.create_single_index(txn, col_name, key.as_str(), value, options)
+        Ok(IndexInfo::new_wildcard(key.clone(), index.options))
     }
 
-    fn create_single_index(
+    /// Return the name of the index if it's added to be built in the background.
+    fn create_index_with_info(
         &self,
         txn: &TransactionInner,
         col_name: &str,
-        key: &str,
-        order: &Bson,
-        options: Option<&IndexOptions>,
-    ) -> Result<()> {
-        if !DatabaseInner::is_num_1(order) {
-            return Err(Error::OnlySupportsAscendingOrder(key.to_string()));
+        mut index_info: IndexInfo,
+        background: bool,
+    ) -> Result<Option<String>> {
+        let index_name = DatabaseInner::make_index_name(&index_info.keys, index_info.index_type, index_info.options.as_ref())?;
+
+        let mut collection_spec = self.get_or_new_collection_spec_for_update(txn, col_name)?;
+
+        if collection_spec.indexes.get(&index_name).is_some() {
+            return Ok(None)
         }
 
-        let index_name = DatabaseInner::make_index_name(key, 1, options)?;
+        if index_info.is_text() && collection_spec.indexes.values().any(|info| info.is_text()) {
+            return Err(Error::ValidationError(format!("the collection already has a text index: {}", col_name)));
+        }
 
-        let test_collection_spec = self.internal_get_collection_id_by_name(txn, col_name);
-        let mut collection_spec = match test_collection_spec {
-            Ok(spec) => spec,
-            Err(Error::CollectionNotFound(_)) => {
-                let uuid = uuid::Uuid::now_v1(&self.node_id);
-                CollectionSpecification::new(col_name.to_string(), uuid)
+        if background {
+            // the lists of a vector index are needed before any vector is indexed
+            if index_info.is_vector() {
+                IndexBuilder::new(txn, col_name, &index_name, &index_info).train_vector_lists()?;
             }
-            Err(err) => {
-                return Err(err);
-            }
-        };
-
-        if collection_spec.indexes.get(&index_name).is_some() {
-            return Ok(())
+            // the cursor of counting mustn't commit the transaction
+            let mut count_txn = txn.clone();
+            count_txn.set_auto_commit(false);
+            index_info.building = Some(IndexBuildProgress {
+                docs_total: self.count_documents(col_name, &count_txn)?,
+                ..Default::default()
+            });
+        } else {
+            index_info.multikey = self.build_index(
+                txn,
+                col_name,
+                index_name.as_str(),
+                &index_info,
+            )?;
         }
 
-        let index_info = IndexInfo::single_index(
-            key.to_string(),
-            1,
-            options.map(|x| x.clone()),
-        );
-        collection_spec.indexes.insert(index_name.clone(), index_info.clone());
+        collection_spec.indexes.insert(index_name.clone(), index_info);
 
         DatabaseInner::update_collection_spec(
             col_name,
@@ -263,21 +678,185 @@ impl DatabaseInner {
             txn,
         )?;
 
-        self.build_index(
-            txn,
+        Ok(if background { Some(index_name) } else { None })
+    }
+
+    /// Create the indexes by one scan of the collection,
+    /// the indexes existing in the collection are skipped.
+    pub fn create_indexes(&self, col_name: &str, indexes: Vec<IndexModel>, txn: &TransactionInner) -> Result<()> {
+        DatabaseInner::validate_col_name(col_name)?;
+
+        let mut collection_spec = self.get_or_new_collection_spec_for_update(txn, col_name)?;
+
+        let mut new_indexes = Vec::<(String, IndexInfo)>::with_capacity(indexes.len());
+        for index in indexes {
+            let index_info = DatabaseInner::make_index_info(index)?;
+            let index_name = DatabaseInner::make_index_name(&index_info.keys, index_info.index_type, index_info.options.as_ref())?;
+            let exists = collection_spec.indexes.contains_key(&index_name)
+                || new_indexes.iter().any(|(name, _)| name == &index_name);
+            if !exists {
+                new_indexes.push((index_name, index_info));
+            }
+        }
+
+        if new_indexes.is_empty() {
+            return Ok(());
+        }
+
+        let text_count = collection_spec.indexes.values()
+            .chain(new_indexes.iter().map(|(_, index_info)| index_info))
+            .filter(|index_info| index_info.is_text())
+            .count();
+        if text_count > 1 {
+            return Err(Error::ValidationError(format!("the collection already has a text index: {}", col_name)));
+        }
+
+        for (index_name, index_info) in &new_indexes {
+            if index_info.is_vector() {
+                IndexBuilder::new(txn, col_name, index_name, index_info).train_vector_lists()?;
+            }
+        }
+
+        let index_refs: Vec<(&str, &IndexInfo)> = new_indexes
+            .iter()
+            .map(|(index_name, index_info)| (index_name.as_str(), index_info))
+            .collect();
+        let multikeys = build_indexes(txn, col_name, &index_refs)?;
+
+        for ((index_name, mut index_info), multikey) in new_indexes.into_iter().zip(multikeys) {
+            index_info.multikey = multikey;
+            collection_spec.indexes.insert(index_name, index_info);
+        }
+
+        DatabaseInner::update_collection_spec(
             col_name,
-            index_name.as_str(),
-            &index_info,
+            &collection_spec,
+            txn,
         )
     }
 
+    /// The models of the indexes of the collection in the order of creation.
+    pub fn list_indexes(&self, col_name: &str, txn: &TransactionInner) -> Result<Vec<IndexModel>> {
+        DatabaseInner::validate_col_name(col_name)?;
+
+        let collection_spec = match DatabaseInner::get_collection_spec(txn, col_name) {
+            Ok(spec) => spec,
+            Err(Error::CollectionNotFound(_)) => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        Ok(collection_spec.indexes
+            .iter()
+            .map(|(index_name, index_info)| index_info.to_index_model(index_name))
+            .collect())
+    }
+
+    // Every chunk starts after the key recorded in the progress of the build,
+    // so a build interrupted by closing the database is resumed from the last chunk committed.
+    fn build_index_in_chunks(&self, col_name: &str, index_name: &str) -> Result<()> {
+        loop {
+            let txn = self.start_transaction()?;
+            match self.build_index_chunk(&txn, col_name, index_name) {
+                Ok(true) => txn.commit()?,
+                Ok(false) => return txn.commit(),
+                Err(err) => {
+                    txn.rollback()?;
+                    self.abort_index_build(col_name, index_name)?;
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    // Index a chunk of the documents after the recorded key, and update the progress of the build.
+    // The keys written in a legacy layout are removed in chunks before the documents are indexed.
+    // Return true if there are more keys to remove or documents to index.
+    fn build_index_chunk(
+        &self,
+        txn: &TransactionInner,
+        col_name: &str,
+        index_name: &str,
+    ) -> Result<bool> {
+        // the collection is locked to update the progress,
+        // so the index can't be dropped during the chunk
+        let mut collection_spec = DatabaseInner::get_collection_spec_for_update(txn, col_name)?;
+        let chunk = match collection_spec.indexes.get(index_name) {
+            Some(index_info)
+                if index_info.is_building()
+                    && index_info.key_version < IndexInfo::CURRENT_KEY_VERSION =>
+            {
+                let has_more = IndexBuilder::new(txn, col_name, index_name, index_info)
+                    .clear_chunk(self.config.index_build_chunk_size)?;
+                if !has_more {
+                    let index_info = collection_spec.indexes.get_mut(index_name).unwrap();
+                    index_info.key_version = IndexInfo::CURRENT_KEY_VERSION;
+                    DatabaseInner::update_collection_spec(col_name, &collection_spec, txn)?;
+                }
+                return Ok(true);
+            }
+            Some(index_info) if index_info.is_building() => {
+                let after = index_info.building
+                    .as_ref()
+                    .and_then(|progress| progress.resume_after.as_ref())
+                    .map(|key| key.bytes.as_slice());
+                IndexBuilder::new(txn, col_name, index_name, index_info)
+                    .execute_chunk(after, self.config.index_build_chunk_size)?
+            }
+            _ => return Ok(false),
+        };
+
+        let index_info = collection_spec.indexes.get_mut(index_name).unwrap();
+        index_info.multikey |= chunk.multikey;
+        if chunk.finished {
+            index_info.building = None;
+        } else if let Some(progress) = index_info.building.as_mut() {
+            progress.docs_scanned += chunk.count;
+            progress.resume_after = chunk.last_key.map(|bytes| bson::Binary {
+                subtype: bson::spec::BinarySubtype::Generic,
+                bytes,
+            });
+        }
+
+        DatabaseInner::update_collection_spec(col_name, &collection_spec, txn)?;
+
+        Ok(!chunk.finished)
+    }
+
+    // Remove the keys and the index which failed to be built.
+    fn abort_index_build(&self, col_name: &str, index_name: &str) -> Result<()> {
+        let txn = self.start_transaction()?;
+        let mut collection_spec = DatabaseInner::get_collection_spec_for_update(&txn, col_name)?;
+        match collection_spec.indexes.get(index_name) {
+            Some(index_info) if index_info.is_building() => {
+                IndexBuilder::new(&txn, col_name, index_name, index_info).clear()?;
+            }
+            _ => return txn.commit(),
+        }
+        collection_spec.indexes.shift_remove(index_name);
+        DatabaseInner::update_collection_spec(col_name, &collection_spec, &txn)?;
+        txn.commit()
+    }
+
+    /// The progress of the index if it's being built in the background.
+    pub fn index_build_progress(&self, col_name: &str, index_name: &str, txn: &TransactionInner) -> Result<Option<IndexBuildProgress>> {
+        DatabaseInner::validate_col_name(col_name)?;
+
+        let collection_spec = match DatabaseInner::get_collection_spec(txn, col_name) {
+            Ok(spec) => spec,
+            Err(Error::CollectionNotFound(_)) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        Ok(collection_spec.indexes.get(index_name).and_then(|index_info| index_info.building.clone()))
+    }
+
     fn build_index(
         &self,
         txn: &TransactionInner,
         col_name: &str,
         index_name: &str,
         index_info: &IndexInfo,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         let mut builder = IndexBuilder::new(
             txn,
             col_name,
@@ -285,6 +864,10 @@ impl DatabaseInner {
             index_info,
         );
 
+        if index_info.is_vector() {
+            builder.train_vector_lists()?;
+        }
+
         builder.execute(IndexHelperOperation::Insert)
     }
 
@@ -297,7 +880,7 @@ impl DatabaseInner {
     }
 
     fn internal_drop_index(&self, col_name: &str, index_name: &str, txn: &TransactionInner) -> Result<()> {
-        let test_collection_spec = self.internal_get_collection_id_by_name(txn, col_name);
+        let test_collection_spec = DatabaseInner::get_collection_spec_for_update(txn, col_name);
         let mut collection_spec = match test_collection_spec {
             Ok(spec) => spec,
             Err(Error::CollectionNotFound(_)) => {
@@ -322,7 +905,13 @@ impl DatabaseInner {
             index_info,
         );
 
-        builder.execute(IndexHelperOperation::Delete)?;
+        // the centroids of a vector index are not referenced by the documents,
+        // and the documents not scanned by a building index have no keys
+        if index_info.is_vector() || index_info.is_building() {
+            builder.clear()?;
+        } else {
+            builder.execute(IndexHelperOperation::Delete)?;
+        }
 
         collection_spec.indexes.shift_remove(index_name);
 
@@ -335,6 +924,24 @@ impl DatabaseInner {
         Ok(())
     }
 
+    /// Set the indexes multikey after a document with an array value of a key is indexed.
+    pub(crate) fn mark_multikey_indexes(txn: &TransactionInner, col_name: &str, index_names: &[String]) -> Result<()> {
+        let mut collection_spec = DatabaseInner::get_collection_spec_for_update(txn, col_name)?;
+        let mut changed = false;
+        for index_name in index_names {
+            if let Some(index_info) = collection_spec.indexes.get_mut(index_name) {
+                changed |= !index_info.multikey;
+                index_info.multikey = true;
+            }
+        }
+
+        if changed {
+            DatabaseInner::update_collection_spec(col_name, &collection_spec, txn)?;
+        }
+
+        Ok(())
+    }
+
     fn update_collection_spec(col_name: &str, collection_spec: &CollectionSpecification, txn: &TransactionInner) -> Result<()> {
         let stacked_key = crate::utils::bson::stacked_key(&[
             Bson::String(TABLE_META_PREFIX.to_string()),
@@ -348,7 +955,10 @@ impl DatabaseInner {
         Ok(())
     }
 
-    fn make_index_name(key: &str, order: i32, index_options: Option<&IndexOptions>) -> Result<String> {
+    /// The default name of an index joins every key with its order,
+    /// for example `{ "tenant": 1, "created_at": 1 }` is named `tenant_1_created_at_1`,
+    /// and `{ "body": "text" }` is named `body_text`.
+    fn make_index_name(keys: &IndexMap<String, i8>, index_type: IndexType, index_options: Option<&IndexOptions>) -> Result<String> {
         if let Some(options) = index_options {
             if let Some(name) = &options.name {
                 DatabaseInner::validate_index_name(name)?;
@@ -356,21 +966,33 @@ impl DatabaseInner {
             }
         }
 
-        let mut index_name = key.to_string().replace(".", "_");
+        let mut index_name = String::new();
 
-        index_name += "_";
-        let num_str = order.to_string();
-        index_name += &num_str;
+        for (key, order) in keys {
+            if !index_name.is_empty() {
+                index_name += "_";
+            }
+            index_name += &key.replace(".", "_");
+            index_name += "_";
+            match index_type {
+                IndexType::Regular | IndexType::Wildcard => index_name += &order.to_string(),
+                IndexType::Text => index_name += "text",
+                IndexType::Geo2dSphere => index_name += "2dsphere",
+                IndexType::Vector => index_name += "vector",
+                IndexType::Hashed => index_name += "hashed",
+            }
+        }
 
         Ok(index_name)
     }
 
+    /// 1 is ascending order, -1 is descending order.
     #[inline]
-    fn is_num_1(val: &Bson) -> bool {
+    fn index_order(val: &Bson) -> Option<i8> {
         match val {
-            Bson::Int32(1) => true,
-            Bson::Int64(1) => true,
-            _ => false,
+            Bson::Int32(1) | Bson::Int64(1) => Some(1),
+            Bson::Int32(-1) | Bson::Int64(-1) => Some(-1),
+            _ => None,
         }
     }
 
@@ -417,15 +1039,16 @@ impl DatabaseInner {
     }
 
     fn insert_one_internal(&self, txn: &TransactionInner, col_name: &str, doc: Document, node_id: &[u8; 6]) -> Result<InsertOneResult> {
-        let col_meta = self.get_collection_meta_by_name_advanced(txn, col_name, true, node_id)?
+        let col_meta = self.get_collection_meta_for_write(txn, col_name, true, node_id)?
             .expect("internal: meta must exist");
         let (result, _) = self.insert_one_with_meta(txn, col_meta, doc)?;
+        self.plan_cache.record_writes(col_name, 1);
         Ok(result)
     }
 
     /// Insert one item with the collection spec
     /// return the new spec for the outside to do the following operation
-    fn insert_one_with_meta(&self, txn: &TransactionInner, col_spec: CollectionSpecification, doc: Document) -> Result<(InsertOneResult, CollectionSpecification)> {
+    fn insert_one_with_meta(&self, txn: &TransactionInner, mut col_spec: CollectionSpecification, doc: Document) -> Result<(InsertOneResult, CollectionSpecification)> {
         let doc  = DatabaseInner::fix_doc(doc);
 
         let pkey = doc.get("_id").unwrap();
@@ -442,7 +1065,14 @@ impl DatabaseInner {
             &doc_buf,
         )?;
 
-        self.try_insert_index(txn, &col_spec, &doc, pkey)?;
+        // the spec is returned to insert the following documents
+        let multikey_indexes = self.try_insert_index(txn, &col_spec, &doc, pkey)?;
+        if !multikey_indexes.is_empty() {
+            DatabaseInner::mark_multikey_indexes(txn, &col_spec._id, &multikey_indexes)?;
+            for index_name in &multikey_indexes {
+                col_spec.indexes[index_name].multikey = true;
+            }
+        }
 
         Ok((
             InsertOneResult { inserted_id: pkey.clone() },
@@ -450,7 +1080,7 @@ impl DatabaseInner {
         ))
     }
 
-    fn try_insert_index(&self, txn: &TransactionInner, col_spec: &CollectionSpecification, doc: &Document, pkey: &Bson) -> Result<()> {
+    fn try_insert_index(&self, txn: &TransactionInner, col_spec: &CollectionSpecification, doc: &Document, pkey: &Bson) -> Result<Vec<String>> {
         let mut index_helper = IndexHelper::new(
             txn,
             col_spec,
@@ -480,7 +1110,7 @@ impl DatabaseInner {
         docs: impl IntoIterator<Item = impl Borrow<T>>,
         node_id: &[u8; 6],
     ) -> Result<InsertManyResult> {
-        let mut col_spec = self.get_collection_meta_by_name_advanced(txn, col_name, true, node_id)?
+        let mut col_spec = self.get_collection_meta_for_write(txn, col_name, true, node_id)?
             .expect("internal: meta must exist");
         let mut inserted_ids: HashMap<usize, Bson> = HashMap::new();
         let mut counter: usize = 0;
@@ -493,6 +1123,7 @@ impl DatabaseInner {
             counter += 1;
             col_spec = new_col_spec;
         }
+        self.plan_cache.record_writes(col_name, counter as u64);
 
         Ok(InsertManyResult {
             inserted_ids,
@@ -568,7 +1199,7 @@ impl DatabaseInner {
         options: UpdateOptions,
         txn: &TransactionInner,
     ) -> Result<UpdateResult> {
-        let meta_opt = self.get_collection_meta_by_name_advanced_auto(col_name, false, txn)?;
+        let meta_opt = self.get_collection_meta_for_write(txn, col_name, false, &self.node_id)?;
 
         let result = match &meta_opt {
             Some(col_spec) => {
@@ -587,6 +1218,8 @@ impl DatabaseInner {
                 );
                 vm.execute()?;
 
+                self.plan_cache.record_writes(col_name, vm.r4 as u64);
+
                 // vm.r2 as u64
                 UpdateResult {
                     matched_count: vm.r2 as u64,
@@ -698,7 +1331,7 @@ impl DatabaseInner {
     }
 
     fn internal_delete_by_query(&self, txn: &TransactionInner, col_name: &str, query: Document, is_many: bool) -> Result<usize> {
-        let col_spec = self.get_collection_meta_by_name_advanced(txn, col_name, true, &self.node_id)?;
+        let col_spec = self.get_collection_meta_for_write(txn, col_name, true, &self.node_id)?;
         if col_spec.is_none() {
             return Ok(0);
         }
@@ -718,12 +1351,13 @@ impl DatabaseInner {
             self.metrics.clone(),
         );
         vm.execute()?;
+        self.plan_cache.record_writes(col_name, vm.r2 as u64);
 
         Ok(vm.r2 as usize)
     }
 
     fn internal_delete_all(&self, txn: &TransactionInner, col_name: &str) -> Result<usize> {
-        let test_collection_spec = self.internal_get_collection_id_by_name(txn, col_name);
+        let test_collection_spec = DatabaseInner::get_collection_spec_for_write(txn, col_name);
         let collection_spec = match test_collection_spec {
             Ok(collection_spec) => collection_spec,
             Err(Error::CollectionNotFound(_)) => return Ok(0),
@@ -747,6 +1381,7 @@ impl DatabaseInner {
 
             vm.r2 as usize
         }; // Delete content end
+        self.plan_cache.record_writes(col_name, delete_count as u64);
 
         Ok(delete_count)
     }
@@ -837,40 +1472,119 @@ impl DatabaseInner {
         &self,
         col_name: &str,
         filter: impl Into<Option<Document>>,
+        hint: Option<Hint>,
         txn: TransactionInner,
     ) -> Result<ClientCursor<T>> {
+        let subprogram = self.compile_find(col_name, filter.into(), hint, &txn, true)?;
+
+        let vm = VM::new(
+            txn,
+            subprogram,
+            self.metrics.clone(),
+        );
+
+        let handle = ClientCursor::new(vm);
+
+        Ok(handle)
+    }
+
+    fn compile_find(
+        &self,
+        col_name: &str,
+        filter: Option<Document>,
+        hint: Option<Hint>,
+        txn: &TransactionInner,
+        skip_annotation: bool,
+    ) -> Result<SubProgram> {
         DatabaseInner::validate_col_name(col_name)?;
-        let filter_query = filter.into();
         let meta_opt = self.get_collection_meta_by_name_advanced_auto(
             col_name,
             false,
-            &txn,
+            txn,
         )?;
         let subprogram = match meta_opt {
             Some(col_spec) => {
-                let subprogram = match filter_query {
-                    Some(query) => SubProgram::compile_query(
-                        &col_spec,
-                        &query,
-                        true
-                    ),
-                    None => SubProgram::compile_query_all(&col_spec, true),
-                }?;
-
-                subprogram
+                SubProgram::compile_query_with_context(
+                    &col_spec,
+                    &filter.unwrap_or_default(),
+                    Some(&self.plan_context(txn, hint)),
+                    skip_annotation,
+                )?
             }
             None => SubProgram::compile_empty_query(),
         };
 
-        let vm = VM::new(
-            txn,
-            subprogram,
-            self.metrics.clone(),
-        );
+        Ok(subprogram)
+    }
 
-        let handle = ClientCursor::new(vm);
+    pub(crate) fn explain_find(
+        &self,
+        col_name: &str,
+        filter: Document,
+        hint: Option<Hint>,
+        txn: TransactionInner,
+        with_stats: bool,
+    ) -> Result<Document> {
+        let subprogram = self.compile_find(col_name, Some(filter), hint, &txn, false)?;
+        DatabaseInner::explain_program(col_name, subprogram, txn, with_stats)
+    }
 
-        Ok(handle)
+    pub(crate) fn explain_aggregate(
+        &self,
+        col_name: &str,
+        pipeline: impl IntoIterator<Item = Document>,
+        hint: Option<Hint>,
+        txn: TransactionInner,
+        with_stats: bool,
+    ) -> Result<Document> {
+        let subprogram = self.compile_aggregate(col_name, pipeline, hint, &txn, false)?;
+        DatabaseInner::explain_program(col_name, subprogram, txn, with_stats)
+    }
+
+    // The plan of the query and the listing of the program,
+    // the program is run to collect the stats of the execution if `with_stats` is set.
+    fn explain_program(
+        col_name: &str,
+        subprogram: SubProgram,
+        txn: TransactionInner,
+        with_stats: bool,
+    ) -> Result<Document> {
+        // the collection doesn't exist
+        let query_plan = subprogram.query_plan.clone().unwrap_or_else(|| doc! {
+            "winningPlan": { "stage": "EOF" },
+            "rejectedPlans": [],
+        });
+
+        let mut query_planner = doc! {
+            "namespace": col_name,
+        };
+        query_planner.extend(query_plan);
+
+        let mut result = doc! {
+            "queryPlanner": query_planner,
+            "program": subprogram.to_string(),
+        };
+
+        if with_stats {
+            // the stats of the query are not added to the metrics of the database
+            let metrics = Metrics::new();
+            metrics.enable();
+
+            let started = std::time::Instant::now();
+            let mut cursor = ClientCursor::<Document>::new(VM::new(txn, subprogram, metrics.clone()));
+            let mut returned: i64 = 0;
+            while cursor.advance()? {
+                returned += 1;
+            }
+
+            result.insert("executionStats", doc! {
+                "nReturned": returned,
+                "docsExamined": metrics.scanned_doc_count() as i64,
+                "executionTimeMillis": started.elapsed().as_millis() as i64,
+            });
+        }
+
+        Ok(result)
     }
 
     pub(crate) fn count_documents(&self, col_name: &str, txn: &TransactionInner) -> Result<u64> {
@@ -932,22 +1646,10 @@ impl DatabaseInner {
         &self,
         col_name: &str,
         pipeline: impl IntoIterator<Item = Document>,
+        hint: Option<Hint>,
         txn: TransactionInner,
     ) -> Result<ClientCursor<T>> {
-        DatabaseInner::validate_col_name(col_name)?;
-        let meta_opt = self.get_collection_meta_by_name_advanced_auto(col_name, false, &txn)?;
-        let subprogram = match meta_opt {
-            Some(col_spec) => {
-                let subprogram = SubProgram::compile_aggregate(
-                    &col_spec,
-                    pipeline,
-                    true
-                )?;
-
-                subprogram
-            }
-            None => SubProgram::compile_empty_query(),
-        };
+        let subprogram = self.compile_aggregate(col_name, pipeline, hint, &txn, true)?;
 
         let vm = VM::new(
             txn,
@@ -960,6 +1662,42 @@ impl DatabaseInner {
         Ok(handle)
     }
 
+    // The reads are planned by the keys in the transaction,
+    // and the plans are shared by the queries of the same shape unless the plan is hinted.
+    fn plan_context(&self, txn: &TransactionInner, hint: Option<Hint>) -> PlanContext {
+        PlanContext {
+            txn: txn.clone(),
+            cache: self.plan_cache.clone(),
+            hint,
+        }
+    }
+
+    fn compile_aggregate(
+        &self,
+        col_name: &str,
+        pipeline: impl IntoIterator<Item = Document>,
+        hint: Option<Hint>,
+        txn: &TransactionInner,
+        skip_annotation: bool,
+    ) -> Result<SubProgram> {
+        DatabaseInner::validate_col_name(col_name)?;
+        let meta_opt = self.get_collection_meta_by_name_advanced_auto(col_name, false, txn)?;
+        let subprogram = match meta_opt {
+            Some(col_spec) => {
+                SubProgram::compile_aggregate(
+                    &col_spec,
+                    pipeline,
+                    &self.config,
+                    Some(&self.plan_context(txn, hint)),
+                    skip_annotation,
+                )?
+            }
+            None => SubProgram::compile_empty_query(),
+        };
+
+        Ok(subprogram)
+    }
+
 }
 
 fn collection_metas_to_names(doc_meta: Vec<Document>) -> Vec<String> {
@@ -975,7 +1713,12 @@ fn collection_metas_to_names(doc_meta: Vec<Document>) -> Vec<String> {
 #[cfg(test)]
 mod tests {
     use crate::db::db_inner::DatabaseInner;
-    use bson::Bson;
+    use bson::{doc, Bson, Document};
+    use indexmap::indexmap;
+    use crate::coll::collection_info::IndexType;
+    use crate::{Config, IndexModel, IndexOptions};
+    use crate::index::{IndexBuilder, IndexHelper};
+    use crate::test_utils::mk_db_path;
 
     #[test]
     fn test_validate_col_name() {
@@ -995,15 +1738,181 @@ mod tests {
 
     #[test]
     fn test_make_index_name() {
-        assert_eq!(DatabaseInner::make_index_name("test", 1, None).unwrap(), "test_1");
-        assert_eq!(DatabaseInner::make_index_name("test.ok", 1, None).unwrap(), "test_ok_1");
+        assert_eq!(DatabaseInner::make_index_name(&indexmap! { "test".into() => 1 }, IndexType::Regular, None).unwrap(), "test_1");
+        assert_eq!(DatabaseInner::make_index_name(&indexmap! { "test.ok".into() => 1 }, IndexType::Regular, None).unwrap(), "test_ok_1");
+        assert_eq!(DatabaseInner::make_index_name(&indexmap! {
+            "tenant".into() => 1,
+            "created_at".into() => 1,
+        }, IndexType::Regular, None).unwrap(), "tenant_1_created_at_1");
+        assert_eq!(DatabaseInner::make_index_name(&indexmap! {
+            "tenant".into() => 1,
+            "created_at".into() => -1,
+        }, IndexType::Regular, None).unwrap(), "tenant_1_created_at_-1");
+        assert_eq!(DatabaseInner::make_index_name(&indexmap! {
+            "title".into() => 1,
+            "body".into() => 1,
+        }, IndexType::Text, None).unwrap(), "title_text_body_text");
+    }
+
+    #[test]
+    fn test_index_order() {
+        assert_eq!(DatabaseInner::index_order(&Bson::Int32(1)), Some(1));
+        assert_eq!(DatabaseInner::index_order(&Bson::Int64(-1)), Some(-1));
+        assert_eq!(DatabaseInner::index_order(&Bson::Int32(2)), None);
+        assert_eq!(DatabaseInner::index_order(&Bson::String("a".to_string())), None);
+    }
+
+    #[test]
+    fn test_upgrade_legacy_index_keys() {
+        let db_path = mk_db_path("test-upgrade-legacy-index-keys");
+        let docs = vec![
+            doc! { "_id": 1, "age": -3 },
+            doc! { "_id": 2, "age": 2.5 },
+            doc! { "_id": 3, "age": 10_i64 },
+            doc! { "_id": 4, "age": -0.5 },
+            doc! { "_id": 5, "age": "ten" },
+        ];
+
+        {
+            let db = DatabaseInner::open_file(&db_path, Config::default()).unwrap();
+            let mut txn = db.start_transaction().unwrap();
+            txn.set_auto_commit(false);
+            db.insert_many::<Document>("users", &docs, &txn).unwrap();
+            db.create_index("users", IndexModel {
+                keys: doc! { "age": 1 },
+                options: None,
+            }, &txn).unwrap();
+
+            // write the keys of the index in the legacy layout
+            let mut spec = db.internal_get_collection_id_by_name(&txn, "users").unwrap();
+            let index_info = spec.indexes.get_mut("age_1").unwrap();
+            IndexBuilder::new(&txn, "users", "age_1", index_info).clear().unwrap();
+            index_info.key_version = 0;
+            for doc in &docs {
+                let mut key = IndexHelper::make_index_prefix("users", "age_1").unwrap();
+                crate::utils::bson::stacked_key_bytes(&mut key, doc.get("age").unwrap()).unwrap();
+                crate::utils::bson::stacked_key_bytes(&mut key, doc.get("_id").unwrap()).unwrap();
+                txn.put(&key, &[bson::spec::ElementType::Null as u8]).unwrap();
+            }
+            DatabaseInner::update_collection_spec("users", &spec, &txn).unwrap();
+            txn.commit().unwrap();
+        }
+
+        // the keys are removed and written again in several chunks
+        let db = DatabaseInner::open_file(&db_path, Config {
+            index_build_chunk_size: 2,
+            ..Config::default()
+        }).unwrap();
+        let txn = db.start_transaction().unwrap();
+        let spec = db.internal_get_collection_id_by_name(&txn, "users").unwrap();
+        assert_eq!(spec.indexes["age_1"].key_version, crate::coll::collection_info::IndexInfo::CURRENT_KEY_VERSION);
+        assert_eq!(db.index_build_progress("users", "age_1", &txn).unwrap(), None);
+
+        let mut cursor = db.find_with_owned_session::<Document>("users", doc! {
+            "age": { "$gt": -1 },
+        }, None, txn).unwrap();
+        let mut ids = Vec::new();
+        while cursor.advance().unwrap() {
+            let doc = cursor.deserialize_current().unwrap();
+            ids.push(doc.get_i32("_id").unwrap());
+        }
+        assert_eq!(ids, vec![4, 2, 3]);
     }
 
     #[test]
-    fn test_is_is_num_1() {
-        assert!(DatabaseInner::is_num_1(&Bson::Int32(1)));
-        assert!(!DatabaseInner::is_num_1(&Bson::Int32(2)));
-        assert!(!DatabaseInner::is_num_1(&Bson::String("a".to_string())))
+    fn test_resume_interrupted_index_build() {
+        let db_path = mk_db_path("test-resume-interrupted-index-build");
+        let config = || Config {
+            index_build_chunk_size: 2,
+            ..Config::default()
+        };
+        let docs = (1..=5).map(|id| doc! { "_id": id, "age": 10 - id }).collect::<Vec<Document>>();
+
+        {
+            let db = DatabaseInner::open_file(&db_path, config()).unwrap();
+            let txn = db.start_transaction().unwrap();
+            db.insert_many::<Document>("users", &docs, &txn).unwrap();
+            txn.commit().unwrap();
+
+            // the database is closed after the first chunk of the build
+            let txn = db.start_transaction().unwrap();
+            let index_info = DatabaseInner::make_index_info(IndexModel {
+                keys: doc! { "age": 1 },
+                options: None,
+            }).unwrap();
+            let index_name = db.create_index_with_info(&txn, "users", index_info, true).unwrap().unwrap();
+            txn.commit().unwrap();
+
+            let txn = db.start_transaction().unwrap();
+            assert!(db.build_index_chunk(&txn, "users", &index_name).unwrap());
+            txn.commit().unwrap();
+
+            let txn = db.start_transaction().unwrap();
+            let progress = db.index_build_progress("users", &index_name, &txn).unwrap().unwrap();
+            assert_eq!(progress.docs_scanned, 2);
+            assert_eq!(progress.docs_total, 5);
+            assert!(progress.resume_after.is_some());
+            txn.commit().unwrap();
+        }
+
+        let db = DatabaseInner::open_file(&db_path, config()).unwrap();
+        let txn = db.start_transaction().unwrap();
+        assert_eq!(db.index_build_progress("users", "age_1", &txn).unwrap(), None);
+
+        let mut cursor = db.find_with_owned_session::<Document>("users", doc! {
+            "age": { "$gte": 6 },
+        }, None, txn).unwrap();
+        let mut ids = Vec::new();
+        while cursor.advance().unwrap() {
+            let doc = cursor.deserialize_current().unwrap();
+            ids.push(doc.get_i32("_id").unwrap());
+        }
+        assert_eq!(ids, vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_remove_expired_documents() {
+        let db_path = mk_db_path("test-remove-expired-documents");
+        let db = DatabaseInner::open_file(&db_path, Config::default()).unwrap();
+        let now = crate::utils::bson::bson_datetime_now().timestamp_millis();
+        let txn = db.start_transaction().unwrap();
+
+        db.create_index("sessions", IndexModel {
+            keys: doc! { "created_at": -1 },
+            options: Some(IndexOptions {
+                expire_after_seconds: Some(60),
+                ..Default::default()
+            }),
+        }, &txn).unwrap();
+        db.insert_many::<Document>("sessions", &vec![
+            doc! { "_id": 1, "created_at": bson::DateTime::from_millis(now - 120_000) },
+            doc! { "_id": 2, "created_at": bson::DateTime::from_millis(now) },
+            doc! { "_id": 3, "created_at": bson::DateTime::from_millis(now - 61_000) },
+            doc! { "_id": 4, "created_at": "yesterday" },
+            doc! { "_id": 5 },
+        ], &txn).unwrap();
+        txn.commit().unwrap();
+
+        assert_eq!(db.remove_expired_documents().unwrap(), 2);
+
+        let txn = db.start_transaction().unwrap();
+        let mut cursor = db.find_with_owned_session::<Document>("sessions", doc! {}, None, txn).unwrap();
+        let mut ids = Vec::new();
+        while cursor.advance().unwrap() {
+            let doc = cursor.deserialize_current().unwrap();
+            ids.push(doc.get_i32("_id").unwrap());
+        }
+        assert_eq!(ids, vec![2, 4, 5]);
+
+        let txn = db.start_transaction().unwrap();
+        let result = db.create_index("sessions", IndexModel {
+            keys: doc! { "user": 1, "created_at": 1 },
+            options: Some(IndexOptions {
+                expire_after_seconds: Some(60),
+                ..Default::default()
+            }),
+        }, &txn);
+        assert!(matches!(result, Err(crate::Error::ValidationError(_))));
     }
 
 }
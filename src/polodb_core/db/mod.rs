@@ -19,7 +19,9 @@ mod rocksdb_wrapper;
 mod rocksdb_transaction;
 mod rocksdb_iterator;
 mod rocksdb_options;
+mod ttl_monitor;
 
 pub use db::{Database, Result};
+pub(crate) use db::SHOULD_LOG;
 pub(crate) use rocksdb_transaction::RocksDBTransaction;
 pub(crate) use rocksdb_iterator::RocksDBIterator;
@@ -41,6 +41,11 @@ impl RocksDBIterator {
         self.inner.seek(key)
     }
 
+    /// Seek to the last key which is less than or equal to `key`.
+    pub fn seek_for_prev(&self, key: &[u8]) {
+        self.inner.seek_for_prev(key)
+    }
+
     pub fn valid(&self) -> bool {
         self.inner.valid()
     }
@@ -49,7 +54,6 @@ impl RocksDBIterator {
         self.inner.next()
     }
 
-    #[allow(dead_code)]
     pub fn prev(&self) {
         self.inner.prev()
     }
@@ -107,6 +111,12 @@ impl RocksDBIteratorInner {
         }
     }
 
+    pub fn seek_for_prev(&self, key: &[u8]) {
+        unsafe {
+            ffi::rocksdb_iter_seek_for_prev(self.inner, key.as_ptr() as *const i8, key.len());
+        }
+    }
+
     pub fn valid(&self) -> bool {
         unsafe {
             ffi::rocksdb_iter_valid(self.inner) != 0
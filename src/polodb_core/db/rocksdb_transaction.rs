@@ -68,6 +68,20 @@ impl RocksDBTransaction {
         inner.get(key)
     }
 
+    /// Read the value and lock the key until the transaction is committed or rolled back,
+    /// the value is the latest committed one after the lock is acquired.
+    pub fn get_for_update(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let inner = self.inner.lock().unwrap();
+        inner.get_locked(key, true)
+    }
+
+    /// Read the value and lock the key shared with the other readers locking it,
+    /// the writers of the key wait until the transaction is committed or rolled back.
+    pub fn get_for_share(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let inner = self.inner.lock().unwrap();
+        inner.get_locked(key, false)
+    }
+
     pub fn delete(&self, key: &[u8]) -> Result<()> {
         let inner = self.inner.lock().unwrap();
         inner.delete(key)
@@ -166,6 +180,31 @@ impl RocksDBTransactionInner {
         }
     }
 
+    pub fn get_locked(&self, key: &[u8], exclusive: bool) -> Result<Option<Vec<u8>>> {
+        unsafe {
+            let mut err: *mut c_char = ptr::null_mut();
+            let mut value_len: usize = 0;
+            let value = ffi::rocksdb_transaction_get_for_update(
+                self.inner,
+                self.read_options.get(),
+                key.as_ptr() as *const i8,
+                key.len(),
+                &mut value_len,
+                exclusive as u8,
+                &mut err,
+            );
+
+            check_err!(err);
+
+            if value.is_null() {
+                return Ok(None);
+            }
+
+            let value = std::slice::from_raw_parts(value as *const u8, value_len).to_vec();
+            Ok(Some(value))
+        }
+    }
+
     pub fn delete(&self, key: &[u8]) -> Result<()> {
         unsafe {
             let mut err: *mut c_char = ptr::null_mut();
@@ -0,0 +1,80 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Weak;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::Result;
+use super::db_inner::DatabaseInner;
+
+/// The background thread removing the expired documents of the TTL indexes.
+///
+/// The thread holds a weak reference of the database,
+/// it stops when the monitor is dropped with the database.
+pub(crate) struct TtlMonitor {
+    stop_sender: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TtlMonitor {
+
+    pub fn start(db: Weak<DatabaseInner>, interval: Duration) -> Result<TtlMonitor> {
+        let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+        let handle = thread::Builder::new()
+            .name("polodb-ttl-monitor".to_string())
+            .spawn(move || TtlMonitor::run(db, interval, stop_receiver))?;
+
+        Ok(TtlMonitor {
+            stop_sender: Some(stop_sender),
+            handle: Some(handle),
+        })
+    }
+
+    fn run(db: Weak<DatabaseInner>, interval: Duration, stop_receiver: Receiver<()>) {
+        loop {
+            match stop_receiver.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => (),
+                _ => return,
+            }
+
+            let db = match db.upgrade() {
+                Some(db) => db,
+                None => return,
+            };
+
+            if let Err(err) = db.remove_expired_documents() {
+                crate::polo_log!("failed to remove the expired documents: {}", err);
+            }
+        }
+    }
+
+}
+
+impl Drop for TtlMonitor {
+
+    fn drop(&mut self) {
+        // the thread stops when the channel is closed
+        self.stop_sender.take();
+
+        if let Some(handle) = self.handle.take() {
+            // the database is dropped by the thread itself
+            // if the thread holds the last reference
+            if handle.thread().id() != thread::current().id() {
+                let _ = handle.join();
+            }
+        }
+    }
+
+}
@@ -258,10 +258,6 @@ pub enum Error {
     FromUtf8Error(Box<FromUtf8Error>),
     #[error("the database is not ready")]
     DbNotReady,
-    #[error("only support single field indexes currently: {0:?}")]
-    OnlySupportSingleFieldIndexes(Box<Document>),
-    #[error("only support ascending order index currently: {0}")]
-    OnlySupportsAscendingOrder(String),
     #[error("duplicate key error collection: {}, index: {}, key: {}", .0.ns, .0.name, .0.key)]
     DuplicateKey(Box<DuplicateKeyError>),
     #[error("the element type {0} is unknown")]
@@ -278,6 +274,10 @@ pub enum Error {
     SetIsNotADocument,
     #[error("the field '{0}' is not a valid field name")]
     UpsertError(String),
+    #[error("index '{0}' not found")]
+    IndexNotFound(String),
+    #[error("bad hint: {0}")]
+    BadHint(String),
 }
 
 impl Error {
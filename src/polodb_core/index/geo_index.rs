@@ -0,0 +1,516 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use bson::{Bson, Document};
+use crate::{Error, Result};
+
+/// The radius of the earth in meters, the distances are measured on the sphere.
+const EARTH_RADIUS: f64 = 6_378_100.0;
+
+/// The bits of the longitude and the latitude in a cell of the index.
+const CELL_BITS: u32 = 26;
+
+/// The most cells of each axis to cover a bounding box,
+/// the cells are bigger if the box is bigger.
+const MAX_COVER_CELLS: u64 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct GeoPoint {
+    pub lng: f64,
+    pub lat: f64,
+}
+
+impl GeoPoint {
+
+    /// Parse a GeoJSON point, such as `{ "type": "Point", "coordinates": [lng, lat] }`,
+    /// or a legacy coordinate pair, such as `[lng, lat]`.
+    pub fn from_bson(value: &Bson) -> Option<GeoPoint> {
+        let coordinates = match value {
+            Bson::Document(doc) => {
+                if doc.get_str("type").ok()? != "Point" {
+                    return None;
+                }
+                doc.get_array("coordinates").ok()?
+            }
+            Bson::Array(arr) => arr,
+            _ => return None,
+        };
+        GeoPoint::from_coordinates(coordinates)
+    }
+
+    fn from_coordinates(coordinates: &[Bson]) -> Option<GeoPoint> {
+        if coordinates.len() != 2 {
+            return None;
+        }
+        let lng = number(&coordinates[0])?;
+        let lat = number(&coordinates[1])?;
+        if !(-180.0..=180.0).contains(&lng) || !(-90.0..=90.0).contains(&lat) {
+            return None;
+        }
+        Some(GeoPoint { lng, lat })
+    }
+
+    /// The great-circle distance in meters by the haversine formula.
+    pub fn distance(&self, other: &GeoPoint) -> f64 {
+        let lat1 = self.lat.to_radians();
+        let lat2 = other.lat.to_radians();
+        let d_lat = lat2 - lat1;
+        let d_lng = (other.lng - self.lng).to_radians();
+
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lng / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().min(1.0).asin()
+    }
+
+    /// The cell of the point in the index.
+    ///
+    /// The longitude and the latitude are quantized to 26 bits,
+    /// and their bits are interleaved like a geohash,
+    /// so the points in a cell of any level are a range of the cells.
+    pub fn cell(&self) -> i64 {
+        interleave(quantize(self.lng, 180.0), quantize(self.lat, 90.0), CELL_BITS) as i64
+    }
+
+}
+
+impl fmt::Display for GeoPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {}]", self.lng, self.lat)
+    }
+}
+
+#[inline]
+fn number(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Int32(v) => Some(*v as f64),
+        Bson::Int64(v) => Some(*v as f64),
+        Bson::Double(v) if v.is_finite() => Some(*v),
+        _ => None,
+    }
+}
+
+fn quantize(value: f64, max: f64) -> u64 {
+    let cells = (1u64 << CELL_BITS) as f64;
+    let cell = ((value + max) / (2.0 * max) * cells).floor();
+    (cell.max(0.0) as u64).min((1u64 << CELL_BITS) - 1)
+}
+
+fn interleave(x: u64, y: u64, bits: u32) -> u64 {
+    let mut result = 0u64;
+    for i in (0..bits).rev() {
+        result = (result << 1) | ((x >> i) & 1);
+        result = (result << 1) | ((y >> i) & 1);
+    }
+    result
+}
+
+/// A polygon is the outer ring and the holes.
+type Polygon = Vec<Vec<GeoPoint>>;
+
+#[derive(Debug, Clone)]
+pub(crate) enum GeoShape {
+    Point(GeoPoint),
+    Polygons(Vec<Polygon>),
+    /// The center and the radius in meters.
+    Circle(GeoPoint, f64),
+}
+
+impl GeoShape {
+
+    fn parse_geometry(value: &Bson) -> Result<GeoShape> {
+        let doc = match value {
+            Bson::Document(doc) => doc,
+            _ => return Err(invalid_geometry()),
+        };
+        let coordinates = doc.get_array("coordinates").map_err(|_| invalid_geometry())?;
+        match doc.get_str("type").map_err(|_| invalid_geometry())? {
+            "Point" => {
+                let point = GeoPoint::from_coordinates(coordinates).ok_or_else(invalid_geometry)?;
+                Ok(GeoShape::Point(point))
+            }
+            "Polygon" => Ok(GeoShape::Polygons(vec![parse_polygon(coordinates)?])),
+            "MultiPolygon" => {
+                let mut polygons = Vec::with_capacity(coordinates.len());
+                for polygon in coordinates {
+                    let polygon = polygon.as_array().ok_or_else(invalid_geometry)?;
+                    polygons.push(parse_polygon(polygon)?);
+                }
+                Ok(GeoShape::Polygons(polygons))
+            }
+            ty => Err(Error::ValidationError(format!("unsupported GeoJSON type: {}", ty))),
+        }
+    }
+
+    // `[[lng, lat], radius]`, the radius is in radians.
+    fn parse_center_sphere(value: &Bson) -> Result<GeoShape> {
+        let arr = value.as_array().filter(|arr| arr.len() == 2).ok_or_else(invalid_geometry)?;
+        let center = arr[0].as_array()
+            .and_then(|coordinates| GeoPoint::from_coordinates(coordinates))
+            .ok_or_else(invalid_geometry)?;
+        let radius = number(&arr[1]).filter(|radius| *radius >= 0.0).ok_or_else(invalid_geometry)?;
+        Ok(GeoShape::Circle(center, radius * EARTH_RADIUS))
+    }
+
+    fn contains(&self, point: &GeoPoint) -> bool {
+        match self {
+            GeoShape::Point(p) => p == point,
+            GeoShape::Polygons(polygons) => polygons.iter().any(|polygon| polygon_contains(polygon, point)),
+            GeoShape::Circle(center, radius) => center.distance(point) <= *radius,
+        }
+    }
+
+    fn bounding_boxes(&self) -> Vec<GeoBox> {
+        match self {
+            GeoShape::Point(p) => vec![GeoBox::new(p.lng, p.lat, p.lng, p.lat)],
+            GeoShape::Polygons(polygons) => {
+                polygons.iter()
+                    .map(|polygon| {
+                        let mut bbox = GeoBox::new(180.0, 90.0, -180.0, -90.0);
+                        for p in &polygon[0] {
+                            bbox.min_lng = bbox.min_lng.min(p.lng);
+                            bbox.min_lat = bbox.min_lat.min(p.lat);
+                            bbox.max_lng = bbox.max_lng.max(p.lng);
+                            bbox.max_lat = bbox.max_lat.max(p.lat);
+                        }
+                        bbox
+                    })
+                    .collect()
+            }
+            GeoShape::Circle(center, radius) => GeoBox::around(center, *radius),
+        }
+    }
+
+}
+
+fn parse_polygon(rings: &[Bson]) -> Result<Polygon> {
+    let mut polygon = Vec::with_capacity(rings.len());
+    for ring in rings {
+        let ring = ring.as_array().ok_or_else(invalid_geometry)?;
+        let mut points = Vec::with_capacity(ring.len());
+        for point in ring {
+            let point = point.as_array()
+                .and_then(|coordinates| GeoPoint::from_coordinates(coordinates))
+                .ok_or_else(invalid_geometry)?;
+            points.push(point);
+        }
+        // a ring is closed by the first point
+        if points.len() < 4 || points.first() != points.last() {
+            return Err(Error::ValidationError("a ring of a polygon must have at least 4 points and be closed".into()));
+        }
+        polygon.push(points);
+    }
+    if polygon.is_empty() {
+        return Err(invalid_geometry());
+    }
+    Ok(polygon)
+}
+
+// The edges of the polygons are the straight lines of the longitude and the latitude,
+// the point is tested by the ray casting.
+fn polygon_contains(polygon: &Polygon, point: &GeoPoint) -> bool {
+    let ring_contains = |ring: &[GeoPoint]| -> bool {
+        let mut inside = false;
+        for edge in ring.windows(2) {
+            let (a, b) = (&edge[0], &edge[1]);
+            if (a.lat > point.lat) != (b.lat > point.lat) {
+                let lng = a.lng + (point.lat - a.lat) / (b.lat - a.lat) * (b.lng - a.lng);
+                if point.lng < lng {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    };
+
+    ring_contains(&polygon[0]) && !polygon[1..].iter().any(|hole| ring_contains(hole))
+}
+
+#[inline]
+fn invalid_geometry() -> Error {
+    Error::ValidationError("invalid GeoJSON geometry".into())
+}
+
+/// A box of the longitude and the latitude.
+#[derive(Debug, Clone, Copy)]
+struct GeoBox {
+    min_lng: f64,
+    min_lat: f64,
+    max_lng: f64,
+    max_lat: f64,
+}
+
+impl GeoBox {
+
+    fn new(min_lng: f64, min_lat: f64, max_lng: f64, max_lat: f64) -> GeoBox {
+        GeoBox { min_lng, min_lat, max_lng, max_lat }
+    }
+
+    // The boxes containing the points within the distance of the center,
+    // the box crossing the antimeridian is split into two.
+    fn around(center: &GeoPoint, distance: f64) -> Vec<GeoBox> {
+        let angle = distance / EARTH_RADIUS;
+        let d_lat = angle.to_degrees();
+        let min_lat = center.lat - d_lat;
+        let max_lat = center.lat + d_lat;
+        if min_lat <= -90.0 || max_lat >= 90.0 || angle >= std::f64::consts::FRAC_PI_2 {
+            return vec![GeoBox::new(-180.0, min_lat.max(-90.0), 180.0, max_lat.min(90.0))];
+        }
+
+        let d_lng = (angle.sin() / center.lat.to_radians().cos()).min(1.0).asin().to_degrees();
+        let min_lng = center.lng - d_lng;
+        let max_lng = center.lng + d_lng;
+        if min_lng < -180.0 {
+            vec![
+                GeoBox::new(min_lng + 360.0, min_lat, 180.0, max_lat),
+                GeoBox::new(-180.0, min_lat, max_lng, max_lat),
+            ]
+        } else if max_lng > 180.0 {
+            vec![
+                GeoBox::new(min_lng, min_lat, 180.0, max_lat),
+                GeoBox::new(-180.0, min_lat, max_lng - 360.0, max_lat),
+            ]
+        } else {
+            vec![GeoBox::new(min_lng, min_lat, max_lng, max_lat)]
+        }
+    }
+
+    // The ranges of the cells covering the box,
+    // the level of the cells is chosen to cover the box by a few cells.
+    fn cover(&self, ranges: &mut Vec<(i64, i64)>) {
+        let (min_x, max_x) = (quantize(self.min_lng, 180.0), quantize(self.max_lng, 180.0));
+        let (min_y, max_y) = (quantize(self.min_lat, 90.0), quantize(self.max_lat, 90.0));
+
+        let mut shift = 0;
+        while shift < CELL_BITS
+            && (((max_x >> shift) - (min_x >> shift) + 1) > MAX_COVER_CELLS
+                || ((max_y >> shift) - (min_y >> shift) + 1) > MAX_COVER_CELLS) {
+            shift += 1;
+        }
+
+        let level = CELL_BITS - shift;
+        for x in (min_x >> shift)..=(max_x >> shift) {
+            for y in (min_y >> shift)..=(max_y >> shift) {
+                let cell = interleave(x, y, level);
+                let lower = cell << (2 * shift);
+                let upper = (cell + 1) << (2 * shift);
+                ranges.push((lower as i64, upper as i64));
+            }
+        }
+    }
+
+}
+
+/// The condition of a geospatial query on a key of a `2dsphere` index.
+#[derive(Debug, Clone)]
+pub(crate) enum GeoQuery {
+    /// `$near` and `$nearSphere`, the documents are sorted by the distance.
+    Near {
+        point: GeoPoint,
+        min_distance: f64,
+        max_distance: Option<f64>,
+    },
+    /// `$geoWithin`
+    Within(GeoShape),
+    /// `$geoIntersects`
+    Intersects(GeoShape),
+}
+
+impl GeoQuery {
+
+    pub fn is_geo_operator(op: &str) -> bool {
+        matches!(op, "$near" | "$nearSphere" | "$geoWithin" | "$geoIntersects")
+    }
+
+    pub fn parse(op: &str, value: &Bson) -> Result<GeoQuery> {
+        let doc = match value {
+            Bson::Document(doc) => doc,
+            _ => return Err(Error::ValidationError(format!("{} needs a document", op))),
+        };
+
+        match op {
+            "$near" | "$nearSphere" => GeoQuery::parse_near(op, doc),
+            "$geoWithin" => {
+                let shape = match doc.iter().next() {
+                    Some((key, value)) if doc.len() == 1 && key == "$geometry" => GeoShape::parse_geometry(value)?,
+                    Some((key, value)) if doc.len() == 1 && key == "$centerSphere" => GeoShape::parse_center_sphere(value)?,
+                    _ => return Err(Error::ValidationError("$geoWithin needs $geometry or $centerSphere".into())),
+                };
+                if matches!(shape, GeoShape::Point(_)) {
+                    return Err(Error::ValidationError("$geoWithin needs a polygon".into()));
+                }
+                Ok(GeoQuery::Within(shape))
+            }
+            "$geoIntersects" => {
+                let geometry = doc.get("$geometry")
+                    .filter(|_| doc.len() == 1)
+                    .ok_or_else(|| Error::ValidationError("$geoIntersects needs $geometry".into()))?;
+                Ok(GeoQuery::Intersects(GeoShape::parse_geometry(geometry)?))
+            }
+            _ => Err(Error::ValidationError(format!("unknown geospatial operator: {}", op))),
+        }
+    }
+
+    // `{ "$geometry": Point, "$maxDistance": meters, "$minDistance": meters }`
+    fn parse_near(op: &str, doc: &Document) -> Result<GeoQuery> {
+        let mut point = None;
+        let mut min_distance = 0.0;
+        let mut max_distance = None;
+        for (key, value) in doc {
+            match key.as_str() {
+                "$geometry" => match GeoShape::parse_geometry(value)? {
+                    GeoShape::Point(p) => point = Some(p),
+                    _ => return Err(Error::ValidationError(format!("{} needs a point", op))),
+                },
+                "$minDistance" | "$maxDistance" => {
+                    let distance = number(value)
+                        .filter(|distance| *distance >= 0.0)
+                        .ok_or_else(|| Error::ValidationError(format!("invalid {} of {}", key, op)))?;
+                    if key == "$minDistance" {
+                        min_distance = distance;
+                    } else {
+                        max_distance = Some(distance);
+                    }
+                }
+                _ => return Err(Error::ValidationError(format!("unknown option of {}: {}", op, key))),
+            }
+        }
+        let point = point.ok_or_else(|| Error::ValidationError(format!("{} needs $geometry", op)))?;
+
+        Ok(GeoQuery::Near {
+            point,
+            min_distance,
+            max_distance,
+        })
+    }
+
+    #[inline]
+    pub fn is_near(&self) -> bool {
+        matches!(self, GeoQuery::Near { .. })
+    }
+
+    /// Test the point of a document,
+    /// return the distance for `$near`, or 0 if the point matches the others.
+    pub fn test(&self, point: &GeoPoint) -> Option<f64> {
+        match self {
+            GeoQuery::Near { point: center, min_distance, max_distance } => {
+                let distance = center.distance(point);
+                let in_range = distance >= *min_distance
+                    && max_distance.map(|max| distance <= max).unwrap_or(true);
+                if in_range { Some(distance) } else { None }
+            }
+            GeoQuery::Within(shape) | GeoQuery::Intersects(shape) => {
+                if shape.contains(point) { Some(0.0) } else { None }
+            }
+        }
+    }
+
+    /// The ranges of the cells containing the matching points,
+    /// `None` if all the cells have to be scanned.
+    pub fn cell_ranges(&self) -> Option<Vec<(i64, i64)>> {
+        let boxes = match self {
+            GeoQuery::Near { max_distance: None, .. } => return None,
+            GeoQuery::Near { point, max_distance: Some(distance), .. } => GeoBox::around(point, *distance),
+            GeoQuery::Within(shape) | GeoQuery::Intersects(shape) => shape.bounding_boxes(),
+        };
+
+        let mut ranges = Vec::new();
+        for bbox in boxes {
+            bbox.cover(&mut ranges);
+        }
+        Some(ranges)
+    }
+
+}
+
+impl fmt::Display for GeoQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeoQuery::Near { point, max_distance, .. } => {
+                write!(f, "$near {}", point)?;
+                if let Some(max_distance) = max_distance {
+                    write!(f, " within {}m", max_distance)?;
+                }
+                Ok(())
+            }
+            GeoQuery::Within(_) => write!(f, "$geoWithin"),
+            GeoQuery::Intersects(_) => write!(f, "$geoIntersects"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Bson};
+    use super::{GeoPoint, GeoQuery};
+
+    #[test]
+    fn test_parse_point() {
+        let point = GeoPoint::from_bson(&Bson::Document(doc! {
+            "type": "Point",
+            "coordinates": [-73.97, 40.77],
+        })).unwrap();
+        assert_eq!(point, GeoPoint { lng: -73.97, lat: 40.77 });
+        assert!(GeoPoint::from_bson(&Bson::Array(vec![Bson::Int32(10), Bson::Int32(20)])).is_some());
+        assert!(GeoPoint::from_bson(&Bson::Array(vec![Bson::Int32(200), Bson::Int32(20)])).is_none());
+        assert!(GeoPoint::from_bson(&Bson::String("10, 20".into())).is_none());
+    }
+
+    #[test]
+    fn test_distance() {
+        let london = GeoPoint { lng: -0.1278, lat: 51.5074 };
+        let paris = GeoPoint { lng: 2.3522, lat: 48.8566 };
+        let distance = london.distance(&paris);
+        assert!((distance - 343_900.0).abs() < 1_000.0, "distance: {}", distance);
+    }
+
+    #[test]
+    fn test_cells_cover_the_matches() {
+        let query = GeoQuery::parse("$near", &Bson::Document(doc! {
+            "$geometry": { "type": "Point", "coordinates": [179.99, 0.0] },
+            "$maxDistance": 5000,
+        })).unwrap();
+        let ranges = query.cell_ranges().unwrap();
+
+        // the point across the antimeridian
+        let point = GeoPoint { lng: -179.99, lat: 0.01 };
+        assert!(query.test(&point).is_some());
+        let cell = point.cell();
+        assert!(ranges.iter().any(|(lower, upper)| *lower <= cell && cell < *upper));
+
+        let far = GeoPoint { lng: 179.0, lat: 0.0 };
+        assert!(query.test(&far).is_none());
+    }
+
+    #[test]
+    fn test_polygon() {
+        let query = GeoQuery::parse("$geoWithin", &Bson::Document(doc! {
+            "$geometry": {
+                "type": "Polygon",
+                "coordinates": [
+                    [[0, 0], [10, 0], [10, 10], [0, 10], [0, 0]],
+                    [[4, 4], [6, 4], [6, 6], [4, 6], [4, 4]],
+                ],
+            },
+        })).unwrap();
+        assert!(query.test(&GeoPoint { lng: 1.0, lat: 1.0 }).is_some());
+        assert!(query.test(&GeoPoint { lng: 5.0, lat: 5.0 }).is_none());
+        assert!(query.test(&GeoPoint { lng: 11.0, lat: 1.0 }).is_none());
+
+        let result = GeoQuery::parse("$geoWithin", &Bson::Document(doc! {
+            "$geometry": { "type": "Polygon", "coordinates": [[[0, 0], [10, 0], [10, 10]]] },
+        }));
+        assert!(result.is_err());
+    }
+
+}
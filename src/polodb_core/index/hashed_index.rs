@@ -0,0 +1,63 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bson::Bson;
+use crate::utils::bson::index_value_bytes_with_order;
+use crate::{Error, Result};
+
+// The parameters of the 64-bit FNV-1a hash.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// The hash of a value in a hashed index.
+///
+/// The value is hashed in the encoding of the index keys,
+/// so the equal numbers of different types, such as 1 and 1.0, have the same hash.
+/// The hashes are stored in the database, so the function must not be changed.
+pub(crate) fn hash_value(value: &Bson) -> Result<i64> {
+    if let Bson::Array(_) = value {
+        return Err(Error::ValidationError("a hashed index can not index an array".into()));
+    }
+
+    let mut buf = Vec::new();
+    index_value_bytes_with_order(&mut buf, value, 1)?;
+
+    let mut hash = FNV_OFFSET_BASIS;
+    for byte in buf {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    Ok(hash as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Bson};
+    use super::hash_value;
+
+    #[test]
+    fn test_hash_value() {
+        assert_eq!(hash_value(&Bson::Int32(1)).unwrap(), hash_value(&Bson::Double(1.0)).unwrap());
+        assert_eq!(hash_value(&Bson::Int64(1)).unwrap(), hash_value(&Bson::Int32(1)).unwrap());
+        assert_ne!(hash_value(&Bson::Int32(1)).unwrap(), hash_value(&Bson::Int32(2)).unwrap());
+        assert_ne!(hash_value(&Bson::Int32(1)).unwrap(), hash_value(&Bson::String("1".into())).unwrap());
+        assert_ne!(hash_value(&Bson::String("ab".into())).unwrap(), hash_value(&Bson::String("ba".into())).unwrap());
+
+        // the hashes are persisted, they must be stable
+        assert_eq!(hash_value(&Bson::Null).unwrap(), hash_value(&Bson::Null).unwrap());
+        assert!(hash_value(&Bson::Array(vec![Bson::Int32(1)])).is_err());
+        assert!(hash_value(&Bson::Document(doc! { "a": 1 })).is_err());
+    }
+
+}
@@ -16,9 +16,60 @@ use bson::Document;
 use crate::Result;
 use crate::coll::collection_info::IndexInfo;
 use crate::cursor::Cursor;
-use crate::index::{IndexHelper, IndexHelperOperation};
+use crate::index::{vector_index, IndexHelper, IndexHelperOperation};
 use crate::transaction::TransactionInner;
 
+/// The documents indexed by a chunk of a background index build.
+#[derive(Default)]
+pub(crate) struct IndexChunk {
+    pub count: u64,
+    /// The key of the last document of the chunk, the next chunk starts after it.
+    pub last_key: Option<Vec<u8>>,
+    /// Any document has an array value of a key.
+    pub multikey: bool,
+    /// No document is after the chunk.
+    pub finished: bool,
+}
+
+/// Index the documents by several indexes in one scan of the collection,
+/// return whether each index has any document with an array value of a key.
+pub(crate) fn build_indexes(
+    txn: &TransactionInner,
+    col_name: &str,
+    indexes: &[(&str, &IndexInfo)],
+) -> Result<Vec<bool>> {
+    let multi_cursor = txn.rocksdb_txn.new_iterator();
+    let mut cursor = Cursor::new_with_str_prefix(
+        col_name.to_string(),
+        multi_cursor,
+    )?;
+
+    cursor.reset()?;
+
+    let mut multikeys = vec![false; indexes.len()];
+    while cursor.has_next() {
+        let current_data = cursor.copy_data()?;
+        let data_doc = bson::from_slice::<Document>(current_data.as_ref())?;
+        let pkey = data_doc.get("_id").unwrap();
+
+        for ((index_name, index_info), multikey) in indexes.iter().zip(multikeys.iter_mut()) {
+            *multikey |= IndexHelper::try_execute_with_index_info(
+                IndexHelperOperation::Insert,
+                &data_doc,
+                col_name,
+                pkey,
+                index_name,
+                index_info,
+                txn,
+            )?;
+        }
+
+        cursor.next()?;
+    }
+
+    Ok(multikeys)
+}
+
 pub(crate) struct IndexBuilder<'b, 'c, 'd, 'e> {
     txn: &'b TransactionInner,
     col_name: &'c str,
@@ -43,7 +94,8 @@ impl<'b, 'c, 'd, 'e> IndexBuilder<'b, 'c, 'd, 'e> {
         }
     }
 
-    pub fn execute(&mut self, op: IndexHelperOperation) -> Result<()> {
+    /// Return true if any document has an array value of a key.
+    pub fn execute(&mut self, op: IndexHelperOperation) -> Result<bool> {
         let multi_cursor = self.txn.rocksdb_txn.new_iterator();
         let mut cursor = Cursor::new_with_str_prefix(
             self.col_name.to_string(),
@@ -52,19 +104,120 @@ impl<'b, 'c, 'd, 'e> IndexBuilder<'b, 'c, 'd, 'e> {
 
         cursor.reset()?;
 
+        let mut is_multikey = false;
         while cursor.has_next() {
             // get the value and insert index
             let current_data = cursor.copy_data()?;
 
-            self.execute_index_item(op, current_data.as_ref())?;
+            is_multikey |= self.execute_index_item(op, current_data.as_ref())?;
 
             cursor.next()?;
         }
 
+        Ok(is_multikey)
+    }
+
+    /// Index at most `limit` documents after the key of the collection,
+    /// or from the first document if the key is `None`.
+    ///
+    /// Every document is locked until the transaction is committed,
+    /// so it can't be changed by the writers before its keys are written.
+    pub fn execute_chunk(&mut self, after: Option<&[u8]>, limit: usize) -> Result<IndexChunk> {
+        let multi_cursor = self.txn.rocksdb_txn.new_iterator();
+        let mut cursor = Cursor::new_with_str_prefix(
+            self.col_name.to_string(),
+            multi_cursor,
+        )?;
+
+        match after {
+            Some(key) => cursor.reset_after_key(key)?,
+            None => cursor.reset()?,
+        }
+
+        let mut chunk = IndexChunk::default();
+        while cursor.has_next() && (chunk.count as usize) < limit {
+            let key = cursor.peek_key().expect("key must exist");
+
+            // the document may be deleted after it's found by the cursor
+            if let Some(current_data) = self.txn.rocksdb_txn.get_for_update(key.as_ref())? {
+                // the keys written by the writers since the index is added are replaced,
+                // so a unique index doesn't conflict with the document itself
+                self.execute_index_item(IndexHelperOperation::Delete, current_data.as_ref())?;
+                chunk.multikey |= self.execute_index_item(IndexHelperOperation::Insert, current_data.as_ref())?;
+            }
+            chunk.count += 1;
+            chunk.last_key = Some(key.to_vec());
+
+            cursor.next()?;
+        }
+        chunk.finished = !cursor.has_next();
+
+        Ok(chunk)
+    }
+
+    /// Train the centroids of the lists of a vector index by the vectors of the documents,
+    /// it must be done before the vectors are inserted into the lists.
+    pub fn train_vector_lists(&mut self) -> Result<()> {
+        let multi_cursor = self.txn.rocksdb_txn.new_iterator();
+        let mut cursor = Cursor::new_with_str_prefix(
+            self.col_name.to_string(),
+            multi_cursor,
+        )?;
+
+        cursor.reset()?;
+
+        let mut vectors = Vec::new();
+        while cursor.has_next() {
+            let data_doc = bson::from_slice::<Document>(cursor.copy_data()?.as_ref())?;
+            if let Some(vector) = vector_index::document_vector(&data_doc, self.index_info)? {
+                vectors.push(vector);
+            }
+
+            cursor.next()?;
+        }
+
+        let centroids = vector_index::train_centroids(&vectors, self.index_info.vector_similarity());
+        let prefix = IndexHelper::make_index_prefix(self.col_name, self.index_name)?;
+        vector_index::save_centroids(self.txn, &prefix, &centroids)
+    }
+
+    /// Delete all the keys of the index,
+    /// including the keys written in a legacy layout.
+    pub fn clear(&mut self) -> Result<()> {
+        self.clear_chunk(usize::MAX)?;
         Ok(())
     }
 
-    fn execute_index_item(&mut self, op: IndexHelperOperation, current_data: &[u8]) -> Result<()> {
+    /// Delete at most `limit` keys of the index,
+    /// return true if there are more keys.
+    pub fn clear_chunk(&mut self, limit: usize) -> Result<bool> {
+        let prefix = IndexHelper::make_index_prefix(self.col_name, self.index_name)?;
+        let kv_cursor = self.txn.rocksdb_txn.new_iterator();
+        kv_cursor.seek(&prefix);
+
+        let mut keys = Vec::new();
+        let mut has_more = false;
+        while kv_cursor.valid() {
+            let key = kv_cursor.copy_key_arc()?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            if keys.len() >= limit {
+                has_more = true;
+                break;
+            }
+            keys.push(key);
+            kv_cursor.next();
+        }
+
+        for key in keys {
+            self.txn.delete(key.as_ref())?;
+        }
+
+        Ok(has_more)
+    }
+
+    fn execute_index_item(&mut self, op: IndexHelperOperation, current_data: &[u8]) -> Result<bool> {
         let data_doc = bson::from_slice::<Document>(current_data)?;
         let pkey = data_doc.get("_id").unwrap();
 
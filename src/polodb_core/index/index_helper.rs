@@ -12,14 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashSet;
 use bson::{Bson, Document};
 use bson::spec::ElementType;
-use crate::Result;
+use crate::{Error, Result};
 use crate::coll::collection_info::{
     CollectionSpecification,
     IndexInfo,
 };
 use crate::errors::DuplicateKeyError;
+use crate::index::{collect_wildcard_entries, hash_value, vector_index, GeoPoint, PartialFilter};
 use crate::transaction::TransactionInner;
 
 pub(crate) const INDEX_PREFIX: &'static str = "$I";
@@ -55,11 +57,13 @@ impl<'b, 'c, 'd, 'e> IndexHelper<'b, 'c, 'd, 'e> {
         }
     }
 
-    pub fn execute(&mut self, op: IndexHelperOperation) -> Result<()> {
+    /// Return the names of the indexes becoming multikey by the document.
+    pub fn execute(&mut self, op: IndexHelperOperation) -> Result<Vec<String>> {
         let index_meta = &self.col_spec.indexes;
+        let mut multikey_indexes = Vec::new();
 
         for (index_name, index_info) in index_meta.iter() {
-            IndexHelper::try_execute_with_index_info(
+            let is_multikey = IndexHelper::try_execute_with_index_info(
                 op,
                 &self.doc,
                 self.col_spec._id.as_str(),
@@ -68,13 +72,21 @@ impl<'b, 'c, 'd, 'e> IndexHelper<'b, 'c, 'd, 'e> {
                 index_info,
                 self.txn,
             )?;
+            if is_multikey && !index_info.multikey {
+                multikey_indexes.push(index_name.clone());
+            }
         }
 
-        Ok(())
+        Ok(multikey_indexes)
     }
 
     // The key of the collection value: collection_id + '\t' + primary_key
-    // The key of the index in the table: '$I' + '\t' + collection_id + '\t' + index_name + '\t' + primary_key
+    // The key of the index in the table: '$I' + '\t' + collection_id + '\t' + index_name + '\t' + values + '\t' + primary_key
+    //
+    // For a compound index, the values of all the keys are stacked in the order of the index,
+    // so a prefix of the keys is a prefix of the index key.
+    //
+    // Return true if a key of the document is an array.
     pub(crate) fn try_execute_with_index_info(
         op: IndexHelperOperation,
         data_doc: &Document,
@@ -83,29 +95,125 @@ impl<'b, 'c, 'd, 'e> IndexHelper<'b, 'c, 'd, 'e> {
         index_name: &str,
         index_info: &IndexInfo,
         txn: &TransactionInner,
-    ) -> Result<()> {
-        let tuples = index_info.keys.iter().collect::<Vec<(&String, &i8)>>();
-        let first_tuple = tuples.first().unwrap();
-        let (keys, _order) = first_tuple;
+    ) -> Result<bool> {
+        if !IndexHelper::is_indexed(data_doc, index_info)? {
+            return Ok(false);
+        }
+
+        if index_info.is_text() {
+            IndexHelper::execute_text_index(op, data_doc, col_name, pkey, index_name, index_info, txn)?;
+            return Ok(false);
+        }
+
+        if index_info.is_2dsphere() {
+            IndexHelper::execute_geo_index(op, data_doc, col_name, pkey, index_name, index_info, txn)?;
+            return Ok(false);
+        }
+
+        if index_info.is_vector() {
+            IndexHelper::execute_vector_index(op, data_doc, col_name, pkey, index_name, index_info, txn)?;
+            return Ok(false);
+        }
+
+        if index_info.is_hashed() {
+            IndexHelper::execute_hashed_index(op, data_doc, col_name, pkey, index_name, index_info, txn)?;
+            return Ok(false);
+        }
 
-        let value = crate::utils::bson::try_get_document_value(data_doc, keys);
-        if value.is_none() {
-            return Ok(())
+        if index_info.is_wildcard() {
+            IndexHelper::execute_wildcard_index(op, data_doc, col_name, pkey, index_name, index_info, txn)?;
+            return Ok(false);
         }
 
-        if index_info.is_unique() {
-            IndexHelper::check_unique_key(
+        let (entries, is_multikey) = IndexHelper::collect_index_entries(data_doc, index_info)?;
+
+        for values in &entries {
+            if op == IndexHelperOperation::Insert && index_info.is_unique() {
+                IndexHelper::check_unique_key(
+                    col_name,
+                    index_name,
+                    index_info,
+                    values,
+                    txn,
+                )?;
+            }
+
+            let index_key = IndexHelper::make_index_key(
                 col_name,
                 index_name,
-                value.as_ref().unwrap(),
-                txn,
+                index_info,
+                values,
+                Some(pkey),
             )?;
+
+            if op == IndexHelperOperation::Insert {
+                let value_buf = [ElementType::Null as u8];
+                txn.put(index_key.as_slice(), &value_buf)?;
+            } else {
+                txn.delete(index_key.as_slice())?;
+            }
         }
 
+        Ok(is_multikey)
+    }
+
+    // The key of a text index: '$I' + '\t' + collection_id + '\t' + index_name + '\t' + term + '\t' + primary_key
+    // The value is the score of the term in the document, in big-endian f64.
+    fn execute_text_index(
+        op: IndexHelperOperation,
+        data_doc: &Document,
+        col_name: &str,
+        pkey: &Bson,
+        index_name: &str,
+        index_info: &IndexInfo,
+        txn: &TransactionInner,
+    ) -> Result<()> {
+        let scores = crate::index::text_index::term_scores(data_doc, index_info);
+
+        for (term, score) in scores {
+            let index_key = IndexHelper::make_index_key(
+                col_name,
+                index_name,
+                index_info,
+                &[Bson::String(term)],
+                Some(pkey),
+            )?;
+
+            if op == IndexHelperOperation::Insert {
+                txn.put(index_key.as_slice(), &score.to_be_bytes())?;
+            } else {
+                txn.delete(index_key.as_slice())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // The key of a 2dsphere index: '$I' + '\t' + collection_id + '\t' + index_name + '\t' + cell + '\t' + primary_key
+    // The documents without the key are not indexed.
+    fn execute_geo_index(
+        op: IndexHelperOperation,
+        data_doc: &Document,
+        col_name: &str,
+        pkey: &Bson,
+        index_name: &str,
+        index_info: &IndexInfo,
+        txn: &TransactionInner,
+    ) -> Result<()> {
+        let (key, _) = index_info.keys.first().expect("a 2dsphere index has a key");
+        let value = match crate::utils::bson::try_get_document_value(data_doc, key) {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+        let point = GeoPoint::from_bson(&value).ok_or_else(|| {
+            Error::ValidationError(format!("can't extract a GeoJSON point from the key: {}", key))
+        })?;
+
         let index_key = IndexHelper::make_index_key(
             col_name,
             index_name,
-            value.as_ref().unwrap(),
+            index_info,
+            &[Bson::Int64(point.cell())],
             Some(pkey),
         )?;
 
@@ -119,16 +227,211 @@ impl<'b, 'c, 'd, 'e> IndexHelper<'b, 'c, 'd, 'e> {
         Ok(())
     }
 
+    // The key of a vector index: '$I' + '\t' + collection_id + '\t' + index_name + '\t' + list + '\t' + primary_key
+    // The value is the vector, the list is the nearest centroid of the vector.
+    fn execute_vector_index(
+        op: IndexHelperOperation,
+        data_doc: &Document,
+        col_name: &str,
+        pkey: &Bson,
+        index_name: &str,
+        index_info: &IndexInfo,
+        txn: &TransactionInner,
+    ) -> Result<()> {
+        let vector = match vector_index::document_vector(data_doc, index_info)? {
+            Some(vector) => vector,
+            None => return Ok(()),
+        };
+
+        let index_prefix = IndexHelper::make_index_prefix(col_name, index_name)?;
+        let centroids = vector_index::load_centroids(txn, &index_prefix)?;
+        let list = vector_index::nearest_list(&centroids, &vector, index_info.vector_similarity());
+
+        let mut index_key = vector_index::list_prefix(&index_prefix, list)?;
+        crate::utils::bson::stacked_key_bytes(&mut index_key, pkey)?;
+
+        if op == IndexHelperOperation::Insert {
+            txn.put(index_key.as_slice(), &vector_index::encode_vector(&vector))?;
+        } else {
+            txn.delete(index_key.as_slice())?;
+        }
+
+        Ok(())
+    }
+
+    // The key of a hashed index: '$I' + '\t' + collection_id + '\t' + index_name + '\t' + hash + '\t' + primary_key
+    // A missing field is indexed as the hash of null.
+    fn execute_hashed_index(
+        op: IndexHelperOperation,
+        data_doc: &Document,
+        col_name: &str,
+        pkey: &Bson,
+        index_name: &str,
+        index_info: &IndexInfo,
+        txn: &TransactionInner,
+    ) -> Result<()> {
+        let values = IndexHelper::collect_index_values(data_doc, index_info);
+        let hash = hash_value(&values[0])?;
+
+        let index_key = IndexHelper::make_index_key(
+            col_name,
+            index_name,
+            index_info,
+            &[Bson::Int64(hash)],
+            Some(pkey),
+        )?;
+
+        if op == IndexHelperOperation::Insert {
+            let value_buf = [ElementType::Null as u8];
+            txn.put(index_key.as_slice(), &value_buf)?;
+        } else {
+            txn.delete(index_key.as_slice())?;
+        }
+
+        Ok(())
+    }
+
+    // The key of a wildcard index: '$I' + '\t' + collection_id + '\t' + index_name + '\t' + path + '\t' + value + '\t' + primary_key
+    // A document has an entry for every distinct path and value.
+    fn execute_wildcard_index(
+        op: IndexHelperOperation,
+        data_doc: &Document,
+        col_name: &str,
+        pkey: &Bson,
+        index_name: &str,
+        index_info: &IndexInfo,
+        txn: &TransactionInner,
+    ) -> Result<()> {
+        let root = index_info.wildcard_root().expect("a wildcard index has a root");
+        let index_prefix = IndexHelper::make_index_prefix(col_name, index_name)?;
+
+        let mut index_keys = HashSet::<Vec<u8>>::new();
+        for (path, value) in collect_wildcard_entries(data_doc, root) {
+            let mut index_key = index_prefix.clone();
+            crate::utils::bson::index_value_bytes_with_order(&mut index_key, &Bson::String(path), 1)?;
+            crate::utils::bson::index_value_bytes_with_order(&mut index_key, &value, 1)?;
+            crate::utils::bson::stacked_key_bytes(&mut index_key, pkey)?;
+            if !index_keys.insert(index_key.clone()) {
+                continue;
+            }
+
+            if op == IndexHelperOperation::Insert {
+                let value_buf = [ElementType::Null as u8];
+                txn.put(index_key.as_slice(), &value_buf)?;
+            } else {
+                txn.delete(index_key.as_slice())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Collect the values of the entries of the index from the document.
+    ///
+    /// The values of a key are collected like the values tested by the query operators,
+    /// every value of the path is an entry, and an array value is expanded to its elements,
+    /// e.g. the key "items.tags" of `{ "items": [{ "tags": ["a", "b"] }, { "tags": "c" }] }`.
+    /// The nested arrays are indexed as the arrays, an empty array is indexed as null.
+    /// Only one key of a compound index can have multiple values.
+    fn collect_index_entries(data_doc: &Document, index_info: &IndexInfo) -> Result<(Vec<Vec<Bson>>, bool)> {
+        let key_values = index_info.keys
+            .keys()
+            .map(|key| crate::utils::bson::get_path_values(data_doc, key))
+            .collect::<Vec<Vec<Bson>>>();
+        let is_multikey = |values: &Vec<Bson>| {
+            values.len() > 1 || values.iter().any(|value| matches!(value, Bson::Array(_)))
+        };
+
+        // a missing field is indexed as null
+        let values = key_values
+            .iter()
+            .map(|values| values.first().cloned().unwrap_or(Bson::Null))
+            .collect::<Vec<Bson>>();
+
+        let array_pos = match key_values.iter().position(is_multikey) {
+            Some(pos) => pos,
+            None => return Ok((vec![values], false)),
+        };
+        if key_values.iter().skip(array_pos + 1).any(is_multikey) {
+            return Err(Error::ValidationError(format!(
+                "cannot index parallel arrays of the keys: {}",
+                index_info.keys.keys().cloned().collect::<Vec<String>>().join(", "),
+            )));
+        }
+
+        let mut elements = key_values[array_pos]
+            .iter()
+            .flat_map(|value| match value {
+                Bson::Array(arr) => arr.clone(),
+                _ => vec![value.clone()],
+            })
+            .collect::<Vec<Bson>>();
+        if elements.is_empty() {
+            elements.push(Bson::Null);
+        }
+
+        // the equal elements, such as 1 and 1.0, have the same entry
+        let mut encoded_entries = HashSet::<Vec<u8>>::with_capacity(elements.len());
+        let mut entries = Vec::with_capacity(elements.len());
+        for element in elements {
+            let mut entry = values.clone();
+            entry[array_pos] = element;
+
+            let mut encoded = Vec::new();
+            IndexHelper::append_index_values(&mut encoded, index_info, &entry)?;
+            if encoded_entries.insert(encoded) {
+                entries.push(entry);
+            }
+        }
+
+        Ok((entries, true))
+    }
+
+    /// A sparse index skips the documents without any key of the index,
+    /// a partial index skips the documents not matching its filter.
+    fn is_indexed(data_doc: &Document, index_info: &IndexInfo) -> Result<bool> {
+        if index_info.is_sparse() {
+            let has_key = index_info.keys
+                .keys()
+                .any(|key| crate::utils::bson::try_get_document_value(data_doc, key).is_some());
+            if !has_key {
+                return Ok(false);
+            }
+        }
+
+        if let Some(filter) = index_info.partial_filter_expression() {
+            let filter = PartialFilter::parse(filter)?;
+            return Ok(filter.matches(data_doc));
+        }
+
+        Ok(true)
+    }
+
+    /// Collect the values of the keys of the index from the document.
+    ///
+    /// A missing field is indexed as null, so every document of the collection
+    /// can be found in the index unless the index is sparse or partial.
+    fn collect_index_values(data_doc: &Document, index_info: &IndexInfo) -> Vec<Bson> {
+        index_info.keys
+            .keys()
+            .map(|key| {
+                crate::utils::bson::try_get_document_value(data_doc, key).unwrap_or(Bson::Null)
+            })
+            .collect()
+    }
+
     fn check_unique_key(
         col_name: &str,
         index_name: &str,
-        value: &Bson,
+        index_info: &IndexInfo,
+        values: &[Bson],
         txn: &TransactionInner,
     ) -> Result<()> {
         let index_key_tester = IndexHelper::make_index_key(
             col_name,
             index_name,
-            value,
+            index_info,
+            values,
             None,
         )?;
 
@@ -143,7 +446,7 @@ impl<'b, 'c, 'd, 'e> IndexHelper<'b, 'c, 'd, 'e> {
         if current_key.starts_with(&index_key_tester) {
             return Err(DuplicateKeyError {
                 name: index_name.to_string(),
-                key: value.to_string(),
+                key: IndexHelper::format_key_values(values),
                 ns: col_name.to_string(),
             }.into());
         }
@@ -151,23 +454,53 @@ impl<'b, 'c, 'd, 'e> IndexHelper<'b, 'c, 'd, 'e> {
         Ok(())
     }
 
-    pub fn make_index_key(col_name: &str, index_name: &str, value: &Bson, pkey: Option<&Bson>) -> Result<Vec<u8>> {
+    fn format_key_values(values: &[Bson]) -> String {
+        if values.len() == 1 {
+            return values[0].to_string();
+        }
+        Bson::Array(values.to_vec()).to_string()
+    }
+
+    /// The prefix of all the keys of an index.
+    pub fn make_index_prefix(col_name: &str, index_name: &str) -> Result<Vec<u8>> {
         let b_prefix = Bson::String(INDEX_PREFIX.to_string());
         let b_col_name = Bson::String(col_name.to_string());
-        let b_index_name = &Bson::String(index_name.to_string());
+        let b_index_name = Bson::String(index_name.to_string());
 
-        let mut buf: Vec<&Bson> = vec![
+        crate::utils::bson::stacked_key([
             &b_prefix,
             &b_col_name,
             &b_index_name,
-            value,
-        ];
+        ])
+    }
+
+    /// Append the values of the leading keys of the index to the buffer.
+    ///
+    /// The value of a descending key is encoded in reverse byte order,
+    /// so the index keys are sorted in the order of the index.
+    pub fn append_index_values(buf: &mut Vec<u8>, index_info: &IndexInfo, values: &[Bson]) -> Result<()> {
+        for (value, order) in values.iter().zip(index_info.keys.values()) {
+            crate::utils::bson::index_value_bytes_with_order(buf, value, *order)?;
+        }
+        Ok(())
+    }
+
+    pub fn make_index_key(
+        col_name: &str,
+        index_name: &str,
+        index_info: &IndexInfo,
+        values: &[Bson],
+        pkey: Option<&Bson>,
+    ) -> Result<Vec<u8>> {
+        let mut buf = IndexHelper::make_index_prefix(col_name, index_name)?;
+
+        IndexHelper::append_index_values(&mut buf, index_info, values)?;
 
         if let Some(pkey) = pkey {
-            buf.push(pkey);
+            crate::utils::bson::stacked_key_bytes(&mut buf, pkey)?;
         }
 
-        crate::utils::bson::stacked_key(buf)
+        Ok(buf)
     }
 
 }
@@ -175,6 +508,8 @@ impl<'b, 'c, 'd, 'e> IndexHelper<'b, 'c, 'd, 'e> {
 #[cfg(test)]
 mod tests {
     use bson::Bson;
+    use indexmap::indexmap;
+    use crate::coll::collection_info::IndexInfo;
     use crate::utils::str::escape_binary_to_string;
     use super::IndexHelper;
 
@@ -183,13 +518,55 @@ mod tests {
         let index_key = IndexHelper::make_index_key(
             "users",
             "name",
-            &Bson::String("value".to_string()),
+            &IndexInfo::new(indexmap! { "name".into() => 1 }, None),
+            &[Bson::String("value".to_string())],
             Some(&Bson::String("Vincent".to_string())),
         ).unwrap() ;
 
         let escaped_string = escape_binary_to_string(index_key) .unwrap();
 
-        assert_eq!(escaped_string, "\\x02$I\\x00\\x02users\\x00\\x02name\\x00\\x02value\\x00\\x02Vincent\\x00");
+        assert_eq!(escaped_string, "\\x02$I\\x00\\x02users\\x00\\x02name\\x00#value\\x00\\x00\\x02Vincent\\x00");
+    }
+
+    #[test]
+    fn test_make_compound_index_key() {
+        let index_key = IndexHelper::make_index_key(
+            "users",
+            "tenant_1_name_1",
+            &IndexInfo::new(indexmap! { "tenant".into() => 1, "name".into() => 1 }, None),
+            &[Bson::String("acme".to_string()), Bson::String("value".to_string())],
+            Some(&Bson::String("Vincent".to_string())),
+        ).unwrap() ;
+
+        let escaped_string = escape_binary_to_string(index_key) .unwrap();
+
+        assert_eq!(escaped_string, "\\x02$I\\x00\\x02users\\x00\\x02tenant_1_name_1\\x00#acme\\x00\\x00#value\\x00\\x00\\x02Vincent\\x00");
+    }
+
+    #[test]
+    fn test_make_descending_index_key() {
+        let index_info = IndexInfo::new(indexmap! { "age".into() => -1 }, None);
+        let make_key = |age: i32| {
+            IndexHelper::make_index_key(
+                "users",
+                "age_-1",
+                &index_info,
+                &[Bson::Int32(age)],
+                Some(&Bson::Int32(1)),
+            ).unwrap()
+        };
+
+        let index_key = make_key(3);
+        let escaped_string = escape_binary_to_string(&index_key).unwrap();
+        assert_eq!(escaped_string, "\\x02$I\\x00\\x02users\\x00\\x02age_-1\\x00\\xfe\\xdd?\\xf7\\xff\\xff\\xff\\xff\\xff\\xff\\xfe\\x10\\x00\\x00\\x00\\x01");
+
+        // the bigger value is placed before
+        assert!(make_key(10) < make_key(3));
+
+        let values = crate::utils::bson::split_stacked_keys(&index_key).unwrap();
+        // the numbers are read as Int64
+        assert_eq!(values[3], Bson::Int64(3));
+        assert_eq!(values[4], Bson::Int32(1));
     }
 
 }
@@ -0,0 +1,230 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::ops::Bound;
+use bson::Bson;
+use crate::Result;
+use crate::utils::bson::{class_key_prefix, index_value_bytes_with_order, prefix_upper_bound};
+use crate::utils::memcomparable;
+
+/// A range of the keys of an index.
+///
+/// The lower bound is inclusive and the upper bound is exclusive.
+#[derive(Debug, Clone)]
+pub(crate) struct IndexKeyRange {
+    pub lower: Vec<u8>,
+    pub upper: Vec<u8>,
+}
+
+impl IndexKeyRange {
+
+    /// The range of all the keys starting with the prefix.
+    pub fn with_prefix(prefix: Vec<u8>) -> IndexKeyRange {
+        let upper = prefix_upper_bound(&prefix).expect("the prefix of an index must have an upper bound");
+        IndexKeyRange {
+            lower: prefix,
+            upper,
+        }
+    }
+
+    /// The ranges of the keys whose value following the prefix is between the bounds.
+    ///
+    /// The values of the same type class as the bounds are included,
+    /// for example, `Int32`, `Int64` and `Double` values are compared with a number.
+    ///
+    /// Return `None` if the bounds can not be searched by an index.
+    pub fn between(
+        prefix: &[u8],
+        lower: Bound<&Bson>,
+        upper: Bound<&Bson>,
+        order: i8,
+    ) -> Result<Option<Vec<IndexKeyRange>>> {
+        if bound_value(lower).is_none() && bound_value(upper).is_none() {
+            return Ok(Some(vec![IndexKeyRange::with_prefix(prefix.to_vec())]));
+        }
+        let class = match bounds_class(lower, upper) {
+            Some(class) => class,
+            None => return Ok(None),
+        };
+
+        let range = IndexKeyRange::of_class(prefix, class, lower, upper, order)?;
+        Ok(Some(range.into_iter().collect()))
+    }
+
+    /// Check whether the bounds can be searched by an index.
+    pub fn is_searchable(lower: Bound<&Bson>, upper: Bound<&Bson>) -> bool {
+        bounds_class(lower, upper).is_some()
+    }
+
+    // The range of the keys of a type class,
+    // the values of the bounds must be in the class.
+    fn of_class(
+        prefix: &[u8],
+        class: u8,
+        lower: Bound<&Bson>,
+        upper: Bound<&Bson>,
+        order: i8,
+    ) -> Result<Option<IndexKeyRange>> {
+        let encode = |value: &Bson| -> Result<Vec<u8>> {
+            let mut key = prefix.to_vec();
+            index_value_bytes_with_order(&mut key, value, order)?;
+            Ok(key)
+        };
+        let class_prefix = {
+            let mut key = prefix.to_vec();
+            key.extend_from_slice(&class_key_prefix(class, order));
+            key
+        };
+
+        // the keys of a descending key are in the reverse order of the values
+        let (first, last) = if order >= 0 {
+            (lower, upper)
+        } else {
+            (upper, lower)
+        };
+
+        let range_lower = match first {
+            Bound::Unbounded => class_prefix.clone(),
+            Bound::Included(value) => encode(value)?,
+            Bound::Excluded(value) => prefix_upper_bound(&encode(value)?).unwrap(),
+        };
+        let range_upper = match last {
+            Bound::Unbounded => prefix_upper_bound(&class_prefix).unwrap(),
+            Bound::Included(value) => prefix_upper_bound(&encode(value)?).unwrap(),
+            Bound::Excluded(value) => encode(value)?,
+        };
+
+        if range_lower >= range_upper {
+            return Ok(None);
+        }
+
+        Ok(Some(IndexKeyRange {
+            lower: range_lower,
+            upper: range_upper,
+        }))
+    }
+
+    /// Sort the ranges in the order of the keys,
+    /// and merge the overlapping ranges.
+    pub fn normalize(mut ranges: Vec<IndexKeyRange>) -> Vec<IndexKeyRange> {
+        ranges.sort_by(|a, b| a.lower.cmp(&b.lower));
+
+        let mut result: Vec<IndexKeyRange> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            if let Some(last) = result.last_mut() {
+                if range.lower <= last.upper {
+                    if range.upper > last.upper {
+                        last.upper = range.upper;
+                    }
+                    continue;
+                }
+            }
+            result.push(range);
+        }
+
+        result
+    }
+
+    #[inline]
+    pub fn contains(&self, key: &[u8]) -> bool {
+        key >= self.lower.as_slice() && key < self.upper.as_slice()
+    }
+
+}
+
+fn bound_value(bound: Bound<&Bson>) -> Option<&Bson> {
+    match bound {
+        Bound::Included(value) | Bound::Excluded(value) => Some(value),
+        Bound::Unbounded => None,
+    }
+}
+
+// The type class of the bounds, the bounds must be in the same class.
+fn bounds_class(lower: Bound<&Bson>, upper: Bound<&Bson>) -> Option<u8> {
+    match (bound_value(lower), bound_value(upper)) {
+        (Some(lower_value), Some(upper_value)) => {
+            let class = searchable_class(lower_value)?;
+            if searchable_class(upper_value) != Some(class) {
+                return None;
+            }
+            Some(class)
+        }
+        (Some(value), None) | (None, Some(value)) => searchable_class(value),
+        (None, None) => None,
+    }
+}
+
+/// The type class compared with the value in a range query.
+fn searchable_class(value: &Bson) -> Option<u8> {
+    match value {
+        Bson::Double(_) | Bson::Int32(_) | Bson::Int64(_) | Bson::Decimal128(_)
+        | Bson::String(_) | Bson::Boolean(_) | Bson::ObjectId(_)
+        | Bson::Timestamp(_) | Bson::DateTime(_) => Some(memcomparable::type_class(value)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+    use bson::Bson;
+    use super::IndexKeyRange;
+
+    #[test]
+    fn test_normalize_ranges() {
+        let ranges = IndexKeyRange::normalize(vec![
+            IndexKeyRange { lower: vec![5], upper: vec![7] },
+            IndexKeyRange { lower: vec![1], upper: vec![3] },
+            IndexKeyRange { lower: vec![2], upper: vec![4] },
+        ]);
+        assert_eq!(ranges.len(), 2);
+        assert_eq!((ranges[0].lower.clone(), ranges[0].upper.clone()), (vec![1], vec![4]));
+        assert_eq!((ranges[1].lower.clone(), ranges[1].upper.clone()), (vec![5], vec![7]));
+    }
+
+    #[test]
+    fn test_range_between_numbers() {
+        let lower = Bson::Int32(-1);
+        let upper = Bson::Int32(1);
+        let ranges = IndexKeyRange::between(
+            &[],
+            Bound::Included(&lower),
+            Bound::Excluded(&upper),
+            1,
+        ).unwrap().unwrap();
+
+        let contains = |value: Bson| {
+            let mut key = Vec::new();
+            crate::utils::bson::index_value_bytes_with_order(&mut key, &value, 1).unwrap();
+            ranges.iter().any(|range| range.contains(&key))
+        };
+
+        assert!(contains(Bson::Int32(-1)));
+        assert!(contains(Bson::Int32(0)));
+        assert!(contains(Bson::Int64(0)));
+        assert!(!contains(Bson::Int32(1)));
+        assert!(!contains(Bson::Int32(-2)));
+        assert!(contains(Bson::Double(-0.5)));
+        assert!(contains(Bson::Double(0.99)));
+        assert!(!contains(Bson::Double(-1.01)));
+        assert!(!contains(Bson::Int64(i64::MAX)));
+        assert!(!contains(Bson::String("0".to_string())));
+
+        // a string can not be compared with a number
+        let text = Bson::String("a".to_string());
+        assert!(!IndexKeyRange::is_searchable(Bound::Included(&lower), Bound::Included(&text)));
+    }
+
+}
@@ -15,7 +15,7 @@
 use bson::Document;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct IndexModel {
     #[serde(rename = "key")]
@@ -26,7 +26,7 @@ pub struct IndexModel {
     pub options: Option<IndexOptions>,
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct IndexOptions {
 
@@ -37,4 +37,48 @@ pub struct IndexOptions {
     /// key value matches an existing value in the index. The default value is false.
     pub unique: Option<bool>,
 
+    /// If true, the index only references documents with at least one of the keys of the index.
+    /// The default value is false.
+    pub sparse: Option<bool>,
+
+    /// The index only references documents matching the filter.
+    /// The filter supports equality, `$eq`, `$gt`, `$gte`, `$lt`, `$lte`, `$exists`
+    /// and `$and` at the top level.
+    pub partial_filter_expression: Option<Document>,
+
+    /// The documents are removed by a background task after the date of the key is
+    /// older than the specified number of seconds. The index must have only one key.
+    pub expire_after_seconds: Option<u64>,
+
+    /// The weights of the keys of a text index, the matches of a key with
+    /// a bigger weight have a higher score. The default weight is 1.
+    pub weights: Option<Document>,
+
+    /// The length of the vectors of a vector index,
+    /// the vectors of other lengths are rejected if it's specified.
+    pub dimensions: Option<u32>,
+
+    /// The similarity of the vectors of a vector index,
+    /// `cosine` (the default), `euclidean` or `dotProduct`.
+    pub similarity: Option<String>,
+
+    /// Build the index in the background, the documents are indexed in chunks
+    /// committed separately, so the writers are not blocked during the build.
+    /// It's ignored in a transaction, the index is built in the transaction.
+    pub background: Option<bool>,
+
+}
+
+/// The progress of an index built in the background.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexBuildProgress {
+    /// The documents indexed by the build.
+    pub docs_scanned: u64,
+    /// The documents of the collection when the build started.
+    pub docs_total: u64,
+    /// The key of the last document indexed,
+    /// an interrupted build is resumed after it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) resume_after: Option<bson::Binary>,
 }
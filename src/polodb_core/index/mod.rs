@@ -15,7 +15,22 @@
 mod index_helper;
 mod index_model;
 mod index_builder;
+mod index_key_range;
+mod partial_filter;
+mod geo_index;
+mod hashed_index;
+mod wildcard_index;
+pub(crate) mod vector_index;
+mod stemmer;
+mod text_index;
 
-pub(crate) use index_helper::{IndexHelper, IndexHelperOperation, INDEX_PREFIX};
-pub(crate) use index_builder::IndexBuilder;
-pub use index_model::{IndexModel, IndexOptions};
+pub(crate) use index_helper::{IndexHelper, IndexHelperOperation};
+pub(crate) use index_builder::{build_indexes, IndexBuilder};
+pub(crate) use index_key_range::IndexKeyRange;
+pub(crate) use partial_filter::PartialFilter;
+pub(crate) use text_index::{TextSearch, TEXT_SCORE_FIELD};
+pub(crate) use geo_index::{GeoPoint, GeoQuery};
+pub(crate) use hashed_index::hash_value;
+pub(crate) use wildcard_index::{collect_wildcard_entries, covers_path};
+pub(crate) use vector_index::{VectorSearch, VectorSimilarity, VECTOR_SEARCH_SCORE_FIELD};
+pub use index_model::{IndexBuildProgress, IndexModel, IndexOptions};
@@ -0,0 +1,367 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Ordering;
+use bson::{Bson, Document};
+use crate::{Error, Result};
+use crate::utils::bson::{try_get_document_value, value_cmp};
+use crate::utils::memcomparable::type_class;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum FilterOp {
+    Eq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Exists,
+    In,
+}
+
+impl FilterOp {
+
+    fn from_name(name: &str) -> Option<FilterOp> {
+        let op = match name {
+            "$eq" => FilterOp::Eq,
+            "$gt" => FilterOp::Gt,
+            "$gte" => FilterOp::Gte,
+            "$lt" => FilterOp::Lt,
+            "$lte" => FilterOp::Lte,
+            "$exists" => FilterOp::Exists,
+            "$in" => FilterOp::In,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+}
+
+struct FilterCondition {
+    key: String,
+    op: FilterOp,
+    value: Bson,
+}
+
+impl FilterCondition {
+
+    /// Test the value of the key of a document, `None` if the key is missing.
+    fn matches_value(&self, value: Option<&Bson>) -> bool {
+        match self.op {
+            FilterOp::Eq => match value {
+                Some(value) => values_equal(value, &self.value),
+                // a missing field equals null
+                None => self.value == Bson::Null,
+            },
+            FilterOp::Exists => value.is_some() == is_truthy(&self.value),
+            FilterOp::In => match &self.value {
+                Bson::Array(arr) => arr.iter().any(|item| {
+                    FilterCondition {
+                        key: String::new(),
+                        op: FilterOp::Eq,
+                        value: item.clone(),
+                    }.matches_value(value)
+                }),
+                _ => false,
+            },
+            op => {
+                let value = match value {
+                    Some(value) => value,
+                    None => return false,
+                };
+                let ord = match compare(value, &self.value) {
+                    Some(ord) => ord,
+                    None => return false,
+                };
+                match op {
+                    FilterOp::Gt => ord == Ordering::Greater,
+                    FilterOp::Gte => ord != Ordering::Less,
+                    FilterOp::Lt => ord == Ordering::Less,
+                    FilterOp::Lte => ord != Ordering::Greater,
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+
+    /// Check whether every document matching the condition of the query
+    /// matches this condition too.
+    fn is_implied_by(&self, query_cond: &FilterCondition) -> bool {
+        if query_cond.key != self.key {
+            return false;
+        }
+        match query_cond.op {
+            FilterOp::Eq => self.is_implied_by_value(&query_cond.value),
+            FilterOp::In => match &query_cond.value {
+                Bson::Array(arr) => {
+                    !arr.is_empty() && arr.iter().all(|item| self.is_implied_by_value(item))
+                }
+                _ => false,
+            },
+            FilterOp::Exists => {
+                if is_truthy(&query_cond.value) {
+                    self.op == FilterOp::Exists && is_truthy(&self.value)
+                } else {
+                    self.matches_value(None)
+                }
+            }
+            query_op => {
+                // the range operators only match the existing values
+                if self.op == FilterOp::Exists {
+                    return is_truthy(&self.value);
+                }
+                let ord = match compare(&query_cond.value, &self.value) {
+                    Some(ord) => ord,
+                    None => return false,
+                };
+                match (self.op, query_op) {
+                    (FilterOp::Gt, FilterOp::Gt) => ord != Ordering::Less,
+                    (FilterOp::Gt, FilterOp::Gte) => ord == Ordering::Greater,
+                    (FilterOp::Gte, FilterOp::Gt | FilterOp::Gte) => ord != Ordering::Less,
+                    (FilterOp::Lt, FilterOp::Lt) => ord != Ordering::Greater,
+                    (FilterOp::Lt, FilterOp::Lte) => ord == Ordering::Less,
+                    (FilterOp::Lte, FilterOp::Lt | FilterOp::Lte) => ord != Ordering::Greater,
+                    _ => false,
+                }
+            }
+        }
+    }
+
+    // The equality to null matches the missing fields too.
+    fn is_implied_by_value(&self, value: &Bson) -> bool {
+        self.matches_value(Some(value)) && (*value != Bson::Null || self.matches_value(None))
+    }
+
+}
+
+/// The filter of a partial index.
+///
+/// The documents not matching the filter are not referenced by the index,
+/// so the index can only serve the queries implying the filter.
+pub(crate) struct PartialFilter {
+    conditions: Vec<FilterCondition>,
+}
+
+impl PartialFilter {
+
+    pub fn parse(filter: &Document) -> Result<PartialFilter> {
+        let mut conditions = Vec::with_capacity(filter.len());
+        PartialFilter::parse_conditions(filter, &mut conditions)?;
+        Ok(PartialFilter {
+            conditions,
+        })
+    }
+
+    /// The filter of a sparse index with only one key.
+    pub fn exists(key: &str) -> PartialFilter {
+        PartialFilter {
+            conditions: vec![FilterCondition {
+                key: key.to_string(),
+                op: FilterOp::Exists,
+                value: Bson::Boolean(true),
+            }],
+        }
+    }
+
+    fn parse_conditions(filter: &Document, conditions: &mut Vec<FilterCondition>) -> Result<()> {
+        for (key, value) in filter {
+            if key == "$and" {
+                let arr = match value {
+                    Bson::Array(arr) => arr,
+                    _ => return Err(PartialFilter::invalid_filter(key)),
+                };
+                for item in arr {
+                    match item {
+                        Bson::Document(doc) => PartialFilter::parse_conditions(doc, conditions)?,
+                        _ => return Err(PartialFilter::invalid_filter(key)),
+                    }
+                }
+                continue;
+            }
+            if key.starts_with('$') {
+                return Err(PartialFilter::invalid_filter(key));
+            }
+
+            match value {
+                Bson::Document(doc) if is_operator_doc(doc) => {
+                    for (op_name, op_value) in doc {
+                        let op = match FilterOp::from_name(op_name) {
+                            Some(FilterOp::In) | None => {
+                                return Err(PartialFilter::invalid_filter(op_name));
+                            }
+                            Some(op) => op,
+                        };
+                        conditions.push(FilterCondition {
+                            key: key.clone(),
+                            op,
+                            value: op_value.clone(),
+                        });
+                    }
+                }
+                _ => {
+                    conditions.push(FilterCondition {
+                        key: key.clone(),
+                        op: FilterOp::Eq,
+                        value: value.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn invalid_filter(name: &str) -> Error {
+        Error::ValidationError(format!("unsupported expression in partial index: {}", name))
+    }
+
+    pub fn matches(&self, doc: &Document) -> bool {
+        self.conditions.iter().all(|cond| {
+            let value = try_get_document_value(doc, &cond.key);
+            cond.matches_value(value.as_ref())
+        })
+    }
+
+    /// Check whether every document matching the query matches the filter.
+    ///
+    /// The conditions of the query which are not understood are ignored,
+    /// they can only narrow the results of the query.
+    pub fn is_implied_by(&self, query: &Document) -> bool {
+        let mut query_conditions = Vec::with_capacity(query.len());
+        collect_query_conditions(query, &mut query_conditions);
+
+        self.conditions.iter().all(|cond| {
+            query_conditions.iter().any(|query_cond| cond.is_implied_by(query_cond))
+        })
+    }
+
+}
+
+fn collect_query_conditions(query: &Document, conditions: &mut Vec<FilterCondition>) {
+    for (key, value) in query {
+        if key == "$and" {
+            if let Bson::Array(arr) = value {
+                for item in arr {
+                    if let Bson::Document(doc) = item {
+                        collect_query_conditions(doc, conditions);
+                    }
+                }
+            }
+            continue;
+        }
+        if key.starts_with('$') {
+            continue;
+        }
+
+        match value {
+            Bson::Document(doc) if is_operator_doc(doc) => {
+                for (op_name, op_value) in doc {
+                    if let Some(op) = FilterOp::from_name(op_name) {
+                        conditions.push(FilterCondition {
+                            key: key.clone(),
+                            op,
+                            value: op_value.clone(),
+                        });
+                    }
+                }
+            }
+            _ => {
+                conditions.push(FilterCondition {
+                    key: key.clone(),
+                    op: FilterOp::Eq,
+                    value: value.clone(),
+                });
+            }
+        }
+    }
+}
+
+#[inline]
+fn is_operator_doc(doc: &Document) -> bool {
+    doc.keys().next().map(|key| key.starts_with('$')).unwrap_or(false)
+}
+
+fn is_truthy(value: &Bson) -> bool {
+    match value {
+        Bson::Boolean(b) => *b,
+        Bson::Int32(i) => *i != 0,
+        Bson::Int64(i) => *i != 0,
+        Bson::Double(d) => *d != 0.0,
+        Bson::Null | Bson::Undefined => false,
+        _ => true,
+    }
+}
+
+// The values of different types are not compared by the range operators.
+fn compare(a: &Bson, b: &Bson) -> Option<Ordering> {
+    if type_class(a) != type_class(b) {
+        return None;
+    }
+    value_cmp(a, b).ok()
+}
+
+#[inline]
+fn values_equal(a: &Bson, b: &Bson) -> bool {
+    matches!(value_cmp(a, b), Ok(Ordering::Equal))
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+    use super::PartialFilter;
+
+    #[test]
+    fn test_matches() {
+        let filter = PartialFilter::parse(&doc! {
+            "deleted": false,
+            "age": { "$gte": 18 },
+        }).unwrap();
+
+        assert!(filter.matches(&doc! { "deleted": false, "age": 18 }));
+        assert!(filter.matches(&doc! { "deleted": false, "age": 30.5 }));
+        assert!(!filter.matches(&doc! { "deleted": true, "age": 30 }));
+        assert!(!filter.matches(&doc! { "deleted": false, "age": 17 }));
+        assert!(!filter.matches(&doc! { "deleted": false, "age": "30" }));
+        assert!(!filter.matches(&doc! { "deleted": false }));
+    }
+
+    #[test]
+    fn test_invalid_filter() {
+        assert!(PartialFilter::parse(&doc! { "$or": [] }).is_err());
+        assert!(PartialFilter::parse(&doc! { "age": { "$ne": 1 } }).is_err());
+    }
+
+    #[test]
+    fn test_is_implied_by() {
+        let filter = PartialFilter::parse(&doc! {
+            "deleted": false,
+            "age": { "$gt": 18 },
+        }).unwrap();
+
+        assert!(filter.is_implied_by(&doc! { "deleted": false, "age": 20, "name": "David" }));
+        assert!(filter.is_implied_by(&doc! { "deleted": false, "age": { "$gte": 19 } }));
+        assert!(filter.is_implied_by(&doc! { "$and": [{ "deleted": false }, { "age": { "$gt": 18 } }] }));
+        assert!(filter.is_implied_by(&doc! { "deleted": false, "age": { "$in": [19, 20] } }));
+        assert!(!filter.is_implied_by(&doc! { "deleted": false, "age": { "$gte": 18 } }));
+        assert!(!filter.is_implied_by(&doc! { "deleted": false, "age": { "$in": [18, 20] } }));
+        assert!(!filter.is_implied_by(&doc! { "age": 20 }));
+        assert!(!filter.is_implied_by(&doc! { "deleted": false }));
+
+        let filter = PartialFilter::exists("email");
+        assert!(filter.is_implied_by(&doc! { "email": "a@b.com" }));
+        assert!(filter.is_implied_by(&doc! { "email": { "$exists": true } }));
+        assert!(filter.is_implied_by(&doc! { "email": { "$gt": "a" } }));
+        assert!(!filter.is_implied_by(&doc! { "email": null }));
+        assert!(!filter.is_implied_by(&doc! {}));
+    }
+
+}
@@ -0,0 +1,323 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Reference: https://tartarus.org/martin/PorterStemmer/def.txt
+
+/// Reduce an English word in lowercase to its stem by the Porter algorithm,
+/// for example "connection", "connected" and "connecting" are "connect".
+///
+/// The words with the characters other than ASCII letters are not changed.
+pub(crate) fn stem(word: &str) -> String {
+    if word.len() <= 2 || !word.bytes().all(|ch| ch.is_ascii_lowercase()) {
+        return word.to_string();
+    }
+
+    let mut stemmer = Stemmer {
+        b: word.as_bytes().to_vec(),
+        k: word.len() as isize - 1,
+        j: 0,
+    };
+    stemmer.step1ab();
+    if stemmer.k > 0 {
+        stemmer.step1c();
+        stemmer.step2();
+        stemmer.step3();
+        stemmer.step4();
+        stemmer.step5();
+    }
+
+    stemmer.b.truncate((stemmer.k + 1) as usize);
+    String::from_utf8(stemmer.b).expect("the stem is ASCII")
+}
+
+// `b[0..=k]` is the word being stemmed,
+// `b[0..=j]` is the stem before the suffix matched by `ends`.
+struct Stemmer {
+    b: Vec<u8>,
+    k: isize,
+    j: isize,
+}
+
+impl Stemmer {
+
+    #[inline]
+    fn at(&self, i: isize) -> u8 {
+        self.b[i as usize]
+    }
+
+    /// A consonant is a letter other than a, e, i, o and u,
+    /// and other than y preceded by a consonant.
+    fn cons(&self, i: isize) -> bool {
+        match self.at(i) {
+            b'a' | b'e' | b'i' | b'o' | b'u' => false,
+            b'y' => i == 0 || !self.cons(i - 1),
+            _ => true,
+        }
+    }
+
+    /// The number of the vowel-consonant sequences in `b[0..=j]`,
+    /// the word is in the form of `[C](VC){m}[V]`.
+    fn m(&self) -> usize {
+        let mut n = 0;
+        let mut i = 0;
+        loop {
+            if i > self.j {
+                return n;
+            }
+            if !self.cons(i) {
+                break;
+            }
+            i += 1;
+        }
+        i += 1;
+        loop {
+            loop {
+                if i > self.j {
+                    return n;
+                }
+                if self.cons(i) {
+                    break;
+                }
+                i += 1;
+            }
+            i += 1;
+            n += 1;
+            loop {
+                if i > self.j {
+                    return n;
+                }
+                if !self.cons(i) {
+                    break;
+                }
+                i += 1;
+            }
+            i += 1;
+        }
+    }
+
+    /// `b[0..=j]` contains a vowel.
+    fn vowel_in_stem(&self) -> bool {
+        (0..=self.j).any(|i| !self.cons(i))
+    }
+
+    /// `b[i - 1..=i]` is a double consonant.
+    fn double_cons(&self, i: isize) -> bool {
+        i >= 1 && self.at(i) == self.at(i - 1) && self.cons(i)
+    }
+
+    /// `b[i - 2..=i]` is consonant-vowel-consonant,
+    /// and the last consonant is not w, x or y.
+    fn cvc(&self, i: isize) -> bool {
+        if i < 2 || !self.cons(i) || self.cons(i - 1) || !self.cons(i - 2) {
+            return false;
+        }
+        !matches!(self.at(i), b'w' | b'x' | b'y')
+    }
+
+    fn ends(&mut self, suffix: &str) -> bool {
+        let len = suffix.len() as isize;
+        if len > self.k + 1 {
+            return false;
+        }
+        let start = (self.k + 1 - len) as usize;
+        if &self.b[start..=(self.k as usize)] != suffix.as_bytes() {
+            return false;
+        }
+        self.j = self.k - len;
+        true
+    }
+
+    /// Replace the suffix after `j` with the string.
+    fn set_to(&mut self, s: &str) {
+        let start = (self.j + 1) as usize;
+        self.b.truncate(start);
+        self.b.extend_from_slice(s.as_bytes());
+        self.k = self.j + s.len() as isize;
+    }
+
+    fn replace(&mut self, s: &str) {
+        if self.m() > 0 {
+            self.set_to(s);
+        }
+    }
+
+    fn replace_any(&mut self, rules: &[(&str, &str)]) {
+        for (suffix, replacement) in rules {
+            if self.ends(suffix) {
+                self.replace(replacement);
+                return;
+            }
+        }
+    }
+
+    // Remove the plurals and -ed or -ing.
+    fn step1ab(&mut self) {
+        if self.at(self.k) == b's' {
+            if self.ends("sses") {
+                self.k -= 2;
+            } else if self.ends("ies") {
+                self.set_to("i");
+            } else if self.at(self.k - 1) != b's' {
+                self.k -= 1;
+            }
+        }
+        if self.ends("eed") {
+            if self.m() > 0 {
+                self.k -= 1;
+            }
+        } else if (self.ends("ed") || self.ends("ing")) && self.vowel_in_stem() {
+            self.k = self.j;
+            if self.ends("at") {
+                self.set_to("ate");
+            } else if self.ends("bl") {
+                self.set_to("ble");
+            } else if self.ends("iz") {
+                self.set_to("ize");
+            } else if self.double_cons(self.k) {
+                if !matches!(self.at(self.k), b'l' | b's' | b'z') {
+                    self.k -= 1;
+                }
+            } else {
+                self.j = self.k;
+                if self.m() == 1 && self.cvc(self.k) {
+                    self.set_to("e");
+                }
+            }
+        }
+    }
+
+    // Turn the terminal y to i when there is another vowel in the stem.
+    fn step1c(&mut self) {
+        if self.ends("y") && self.vowel_in_stem() {
+            let k = self.k as usize;
+            self.b[k] = b'i';
+        }
+    }
+
+    // Map the double suffixes to the single ones.
+    fn step2(&mut self) {
+        let rules: &[(&str, &str)] = match self.at(self.k - 1) {
+            b'a' => &[("ational", "ate"), ("tional", "tion")],
+            b'c' => &[("enci", "ence"), ("anci", "ance")],
+            b'e' => &[("izer", "ize")],
+            b'l' => &[("bli", "ble"), ("alli", "al"), ("entli", "ent"), ("eli", "e"), ("ousli", "ous")],
+            b'o' => &[("ization", "ize"), ("ation", "ate"), ("ator", "ate")],
+            b's' => &[("alism", "al"), ("iveness", "ive"), ("fulness", "ful"), ("ousness", "ous")],
+            b't' => &[("aliti", "al"), ("iviti", "ive"), ("biliti", "ble")],
+            b'g' => &[("logi", "log")],
+            _ => return,
+        };
+        self.replace_any(rules);
+    }
+
+    // Handle -ic-, -full, -ness etc.
+    fn step3(&mut self) {
+        let rules: &[(&str, &str)] = match self.at(self.k) {
+            b'e' => &[("icate", "ic"), ("ative", ""), ("alize", "al")],
+            b'i' => &[("iciti", "ic")],
+            b'l' => &[("ical", "ic"), ("ful", "")],
+            b's' => &[("ness", "")],
+            _ => return,
+        };
+        self.replace_any(rules);
+    }
+
+    // Remove -ant, -ence etc. in the context of `m() > 1`.
+    fn step4(&mut self) {
+        let suffixes: &[&str] = match self.at(self.k - 1) {
+            b'a' => &["al"],
+            b'c' => &["ance", "ence"],
+            b'e' => &["er"],
+            b'i' => &["ic"],
+            b'l' => &["able", "ible"],
+            b'n' => &["ant", "ement", "ment", "ent"],
+            b'o' => {
+                let is_ion = self.ends("ion") && self.j >= 0 && matches!(self.at(self.j), b's' | b't');
+                if !is_ion && !self.ends("ou") {
+                    return;
+                }
+                &[]
+            }
+            b's' => &["ism"],
+            b't' => &["ate", "iti"],
+            b'u' => &["ous"],
+            b'v' => &["ive"],
+            b'z' => &["ize"],
+            _ => return,
+        };
+        if !suffixes.is_empty() && !suffixes.iter().any(|suffix| self.ends(suffix)) {
+            return;
+        }
+        if self.m() > 1 {
+            self.k = self.j;
+        }
+    }
+
+    // Remove the final -e if `m() > 1`, and change -ll to -l if `m() > 1`.
+    fn step5(&mut self) {
+        self.j = self.k;
+        if self.at(self.k) == b'e' {
+            let m = self.m();
+            if m > 1 || (m == 1 && !self.cvc(self.k - 1)) {
+                self.k -= 1;
+            }
+        }
+        if self.at(self.k) == b'l' && self.double_cons(self.k) && self.m() > 1 {
+            self.k -= 1;
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::stem;
+
+    #[test]
+    fn test_stem() {
+        let cases = [
+            ("caresses", "caress"),
+            ("ponies", "poni"),
+            ("cats", "cat"),
+            ("feed", "feed"),
+            ("agreed", "agre"),
+            ("plastered", "plaster"),
+            ("motoring", "motor"),
+            ("sing", "sing"),
+            ("conflated", "conflat"),
+            ("troubled", "troubl"),
+            ("sized", "size"),
+            ("hopping", "hop"),
+            ("falling", "fall"),
+            ("filing", "file"),
+            ("happy", "happi"),
+            ("relational", "relat"),
+            ("conditional", "condit"),
+            ("generalization", "gener"),
+            ("running", "run"),
+            ("connection", "connect"),
+            ("connected", "connect"),
+            ("connecting", "connect"),
+            ("adoption", "adopt"),
+            ("controll", "control"),
+            ("rate", "rate"),
+            ("is", "is"),
+            ("café", "café"),
+        ];
+        for (word, expected) in cases {
+            assert_eq!(stem(word), expected, "stem of {}", word);
+        }
+    }
+
+}
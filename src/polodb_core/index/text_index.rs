@@ -0,0 +1,262 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use bson::{Bson, Document};
+use crate::coll::collection_info::IndexInfo;
+use crate::{Error, Result};
+use super::stemmer::stem;
+
+/// The reserved field holding the score of a document found by `$text`,
+/// it's read by `{ "$meta": "textScore" }` and removed from the results.
+pub(crate) const TEXT_SCORE_FIELD: &str = "$textScore";
+
+const STOP_WORDS: &[&str] = &[
+    "a", "about", "above", "after", "again", "against", "all", "am", "an", "and",
+    "any", "are", "as", "at", "be", "because", "been", "before", "being", "below",
+    "between", "both", "but", "by", "can", "did", "do", "does", "doing", "down",
+    "during", "each", "few", "for", "from", "further", "had", "has", "have", "having",
+    "he", "her", "here", "hers", "herself", "him", "himself", "his", "how", "i",
+    "if", "in", "into", "is", "it", "its", "itself", "just", "me", "more",
+    "most", "my", "myself", "no", "nor", "not", "now", "of", "off", "on",
+    "once", "only", "or", "other", "our", "ours", "ourselves", "out", "over", "own",
+    "same", "she", "should", "so", "some", "such", "than", "that", "the", "their",
+    "theirs", "them", "themselves", "then", "there", "these", "they", "this", "those", "through",
+    "to", "too", "under", "until", "up", "very", "was", "we", "were", "what",
+    "when", "where", "which", "while", "who", "whom", "why", "will", "with", "you",
+    "your", "yours", "yourself", "yourselves",
+];
+
+/// Split the text into the words in lowercase,
+/// the stop words are dropped and the others are stemmed.
+pub(crate) fn tokenize(text: &str) -> Vec<String> {
+    text.split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .filter(|word| !STOP_WORDS.contains(&word.as_str()))
+        .map(|word| stem(&word))
+        .collect()
+}
+
+/// The terms of the document and their scores in the text index.
+///
+/// For a key with `n` terms, where a term appears `c` times,
+/// the score of the term is `weight * freq * (0.5 + 0.5 * c / n)`,
+/// and `freq = 1 + 1/2 + ... + 1/2^(c - 1)`,
+/// so the repeated terms count less and less.
+/// The scores of a term in different keys are added up.
+pub(crate) fn term_scores(doc: &Document, index_info: &IndexInfo) -> BTreeMap<String, f64> {
+    let mut scores = BTreeMap::<String, f64>::new();
+
+    for key in index_info.keys.keys() {
+        let value = match crate::utils::bson::try_get_document_value(doc, key) {
+            Some(value) => value,
+            None => continue,
+        };
+        let mut texts = Vec::new();
+        collect_texts(&value, &mut texts);
+
+        let terms: Vec<String> = texts.iter().flat_map(|text| tokenize(text)).collect();
+        if terms.is_empty() {
+            continue;
+        }
+
+        let mut counts = BTreeMap::<&str, usize>::new();
+        for term in &terms {
+            *counts.entry(term.as_str()).or_insert(0) += 1;
+        }
+
+        let weight = index_info.text_weight(key);
+        let total = terms.len() as f64;
+        for (term, count) in counts {
+            let freq = (0..count).map(|i| 0.5f64.powi(i as i32)).sum::<f64>();
+            let coeff = 0.5 + 0.5 * (count as f64) / total;
+            *scores.entry(term.to_string()).or_insert(0.0) += weight * freq * coeff;
+        }
+    }
+
+    scores
+}
+
+// Only the strings and the strings in the arrays are indexed.
+fn collect_texts(value: &Bson, texts: &mut Vec<String>) {
+    match value {
+        Bson::String(text) => texts.push(text.clone()),
+        Bson::Array(arr) => {
+            for item in arr {
+                collect_texts(item, texts);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// The search of a `$text` query.
+///
+/// The words of the search string are combined by "or",
+/// the words with a leading `-` exclude the documents containing them,
+/// and the quoted phrases must be contained by the documents.
+#[derive(Debug, Clone)]
+pub(crate) struct TextSearch {
+    pub terms: Vec<String>,
+    pub negated_terms: Vec<String>,
+    /// The phrases in lowercase.
+    pub phrases: Vec<String>,
+    /// The keys of the text index, the phrases are searched in them.
+    pub keys: Vec<String>,
+}
+
+impl TextSearch {
+
+    pub fn parse(value: &Bson, index_info: &IndexInfo) -> Result<TextSearch> {
+        let doc = match value {
+            Bson::Document(doc) => doc,
+            _ => return Err(Error::ValidationError("$text needs a document".into())),
+        };
+
+        let mut search: Option<&str> = None;
+        for (key, value) in doc {
+            match (key.as_str(), value) {
+                ("$search", Bson::String(text)) => search = Some(text),
+                ("$language", Bson::String(lang)) if lang == "english" || lang == "en" => (),
+                ("$caseSensitive", Bson::Boolean(false)) => (),
+                ("$diacriticSensitive", Bson::Boolean(false)) => (),
+                _ => {
+                    return Err(Error::ValidationError(format!("unsupported option of $text: {}", key)));
+                }
+            }
+        }
+        let search = search.ok_or_else(|| Error::ValidationError("$text needs a $search string".into()))?;
+
+        let mut result = TextSearch {
+            terms: Vec::new(),
+            negated_terms: Vec::new(),
+            phrases: Vec::new(),
+            keys: index_info.keys.keys().cloned().collect(),
+        };
+
+        for (index, part) in search.split('"').enumerate() {
+            // the parts at the odd positions are in the quotes
+            if index % 2 == 1 {
+                let phrase = part.trim();
+                if !phrase.is_empty() {
+                    result.phrases.push(phrase.to_lowercase());
+                    result.add_terms(tokenize(phrase), false);
+                }
+                continue;
+            }
+            for word in part.split_whitespace() {
+                match word.strip_prefix('-') {
+                    Some(negated) => result.add_terms(tokenize(negated), true),
+                    None => result.add_terms(tokenize(word), false),
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn add_terms(&mut self, terms: Vec<String>, negated: bool) {
+        let target = if negated { &mut self.negated_terms } else { &mut self.terms };
+        for term in terms {
+            if !target.contains(&term) {
+                target.push(term);
+            }
+        }
+    }
+
+    /// Check whether the keys of the document contain all the phrases.
+    pub fn matches_phrases(&self, doc: &Document) -> bool {
+        if self.phrases.is_empty() {
+            return true;
+        }
+
+        let mut texts = Vec::new();
+        for key in &self.keys {
+            if let Some(value) = crate::utils::bson::try_get_document_value(doc, key) {
+                collect_texts(&value, &mut texts);
+            }
+        }
+        let texts: Vec<String> = texts.iter().map(|text| text.to_lowercase()).collect();
+
+        self.phrases
+            .iter()
+            .all(|phrase| texts.iter().any(|text| text.contains(phrase.as_str())))
+    }
+
+    /// The searched terms in the format of the bounds of an index scan.
+    pub fn bounds(&self) -> Document {
+        let mut doc = Document::new();
+        doc.insert("terms", self.terms.clone());
+        if !self.negated_terms.is_empty() {
+            doc.insert("negatedTerms", self.negated_terms.clone());
+        }
+        if !self.phrases.is_empty() {
+            doc.insert("phrases", self.phrases.clone());
+        }
+        doc
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Bson};
+    use crate::coll::collection_info::IndexInfo;
+    use super::{term_scores, tokenize, TextSearch};
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("The quick brown foxes, jumping over the lazy dogs!"),
+            vec!["quick", "brown", "fox", "jump", "lazi", "dog"],
+        );
+        assert_eq!(tokenize("Rust-lang 2024"), vec!["rust", "lang", "2024"]);
+        assert!(tokenize("  ... ").is_empty());
+    }
+
+    #[test]
+    fn test_term_scores() {
+        let index_info = IndexInfo::new_text(vec!["title".into(), "body".into()], None);
+        let scores = term_scores(&doc! {
+            "title": "Coffee",
+            "body": "coffee beans and coffee shops",
+            "tags": "ignored",
+        }, &index_info);
+
+        assert_eq!(scores.len(), 3);
+        // 1 * 1 * (0.5 + 0.5 * 1 / 1) + 1 * 1.5 * (0.5 + 0.5 * 2 / 4)
+        assert_eq!(scores["coffe"], 1.0 + 1.125);
+        assert_eq!(scores["bean"], 0.625);
+        assert!(!scores.contains_key("ignor"));
+    }
+
+    #[test]
+    fn test_parse_search() {
+        let index_info = IndexInfo::new_text(vec!["body".into()], None);
+        let search = TextSearch::parse(&Bson::Document(doc! {
+            "$search": "coffee -tea \"Fresh Beans\"",
+        }), &index_info).unwrap();
+
+        assert_eq!(search.terms, vec!["coffe", "fresh", "bean"]);
+        assert_eq!(search.negated_terms, vec!["tea"]);
+        assert_eq!(search.phrases, vec!["fresh beans"]);
+        assert!(search.matches_phrases(&doc! { "body": "FRESH BEANS of coffee" }));
+        assert!(!search.matches_phrases(&doc! { "body": "fresh coffee beans" }));
+
+        assert!(TextSearch::parse(&Bson::Document(doc! { "$search": "a", "$language": "french" }), &index_info).is_err());
+        assert!(TextSearch::parse(&Bson::String("coffee".into()), &index_info).is_err());
+    }
+
+}
@@ -0,0 +1,344 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use bson::{Bson, Document};
+use crate::coll::collection_info::IndexInfo;
+use crate::transaction::TransactionInner;
+use crate::{Error, Result};
+
+/// The reserved field holding the score of a document found by `$vectorSearch`,
+/// it's read by `{ "$meta": "vectorSearchScore" }` and removed from the results.
+pub(crate) const VECTOR_SEARCH_SCORE_FIELD: &str = "$vectorSearchScore";
+
+/// The most lists of a vector index.
+const MAX_LISTS: usize = 256;
+
+/// The iterations of the k-means to train the centroids of the lists.
+const TRAIN_ITERATIONS: usize = 10;
+
+/// The function measuring how similar two vectors are,
+/// a bigger score is more similar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum VectorSimilarity {
+    /// `(1 + cos) / 2`
+    Cosine,
+    /// `1 / (1 + d^2)`, where `d` is the L2 distance.
+    Euclidean,
+    /// `(1 + dot) / 2`, the vectors are supposed to be normalized.
+    DotProduct,
+}
+
+impl VectorSimilarity {
+
+    pub fn parse(name: Option<&str>) -> Result<VectorSimilarity> {
+        match name {
+            None | Some("cosine") => Ok(VectorSimilarity::Cosine),
+            Some("euclidean") => Ok(VectorSimilarity::Euclidean),
+            Some("dotProduct") => Ok(VectorSimilarity::DotProduct),
+            Some(name) => Err(Error::ValidationError(format!("unknown similarity of the vector index: {}", name))),
+        }
+    }
+
+    pub fn score(&self, a: &[f64], b: &[f64]) -> f64 {
+        match self {
+            VectorSimilarity::Cosine => {
+                let norm = (dot(a, a) * dot(b, b)).sqrt();
+                if norm == 0.0 {
+                    return 0.5;
+                }
+                (1.0 + dot(a, b) / norm) / 2.0
+            }
+            VectorSimilarity::Euclidean => {
+                let distance: f64 = a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum();
+                1.0 / (1.0 + distance)
+            }
+            VectorSimilarity::DotProduct => (1.0 + dot(a, b)) / 2.0,
+        }
+    }
+
+}
+
+#[inline]
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// The `$vectorSearch` stage, such as:
+///
+/// ```json
+/// {
+///     "path": "embedding",
+///     "queryVector": [0.1, 0.2],
+///     "numCandidates": 100,
+///     "limit": 10,
+///     "filter": { "category": "note" }
+/// }
+/// ```
+pub(crate) struct VectorSearch {
+    pub index: Option<String>,
+    pub path: String,
+    pub query_vector: Vec<f64>,
+    /// The nearest vectors to consider, the default is 10 times of the limit.
+    pub num_candidates: usize,
+    pub limit: usize,
+    pub filter: Document,
+}
+
+impl VectorSearch {
+
+    pub fn parse(stage: &Document) -> Result<VectorSearch> {
+        let invalid = |name: &str| Error::ValidationError(format!("invalid field of $vectorSearch: {}", name));
+        let count = |value: &Bson| -> Option<usize> {
+            match value {
+                Bson::Int32(v) if *v > 0 => Some(*v as usize),
+                Bson::Int64(v) if *v > 0 => Some(*v as usize),
+                _ => None,
+            }
+        };
+
+        let mut index = None;
+        let mut path = None;
+        let mut query_vector = None;
+        let mut num_candidates = None;
+        let mut limit = None;
+        let mut filter = Document::new();
+        for (key, value) in stage {
+            match key.as_str() {
+                "index" => index = Some(value.as_str().ok_or_else(|| invalid(key))?.to_string()),
+                "path" => path = Some(value.as_str().ok_or_else(|| invalid(key))?.to_string()),
+                "queryVector" => query_vector = Some(parse_vector(value, None).ok_or_else(|| invalid(key))?),
+                "numCandidates" => num_candidates = Some(count(value).ok_or_else(|| invalid(key))?),
+                "limit" => limit = Some(count(value).ok_or_else(|| invalid(key))?),
+                "filter" => filter = value.as_document().ok_or_else(|| invalid(key))?.clone(),
+                _ => return Err(invalid(key)),
+            }
+        }
+
+        let limit = limit.ok_or_else(|| invalid("limit"))?;
+        let num_candidates = num_candidates.unwrap_or_else(|| limit.saturating_mul(10));
+        if num_candidates < limit {
+            return Err(Error::ValidationError("numCandidates of $vectorSearch must not be less than limit".into()));
+        }
+
+        Ok(VectorSearch {
+            index,
+            path: path.ok_or_else(|| invalid("path"))?,
+            query_vector: query_vector.ok_or_else(|| invalid("queryVector"))?,
+            num_candidates,
+            limit,
+            filter,
+        })
+    }
+
+}
+
+/// Read the vector of the key of a document,
+/// `None` if the document doesn't have the key.
+pub(crate) fn document_vector(doc: &Document, index_info: &IndexInfo) -> Result<Option<Vec<f64>>> {
+    let (key, _) = index_info.keys.first().expect("a vector index has a key");
+    let value = match crate::utils::bson::try_get_document_value(doc, key) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let vector = parse_vector(&value, index_info.vector_dimensions()).ok_or_else(|| {
+        Error::ValidationError(format!("the key of the vector index is not a vector: {}", key))
+    })?;
+    Ok(Some(vector))
+}
+
+/// Parse an array of numbers, the length must be the dimensions if it's specified.
+pub(crate) fn parse_vector(value: &Bson, dimensions: Option<u32>) -> Option<Vec<f64>> {
+    let arr = value.as_array()?;
+    if arr.is_empty() || dimensions.map(|dims| dims as usize != arr.len()).unwrap_or(false) {
+        return None;
+    }
+    arr.iter()
+        .map(|item| match item {
+            Bson::Int32(v) => Some(*v as f64),
+            Bson::Int64(v) => Some(*v as f64),
+            Bson::Double(v) if v.is_finite() => Some(*v),
+            _ => None,
+        })
+        .collect()
+}
+
+pub(crate) fn encode_vector(vector: &[f64]) -> Vec<u8> {
+    vector.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+pub(crate) fn decode_vector(bytes: &[u8]) -> Result<Vec<f64>> {
+    let chunks = bytes.chunks_exact(8);
+    if !chunks.remainder().is_empty() {
+        return Err(Error::DecodeEOF);
+    }
+    Ok(chunks
+        .map(|chunk| f64::from_le_bytes(<[u8; 8]>::try_from(chunk).unwrap()))
+        .collect())
+}
+
+// The keys of a vector index:
+//   the centroids: index_prefix + null + list
+//   the vectors:   index_prefix + list + primary_key
+//
+// The null is placed before the numbers, so the centroids are not in the lists.
+
+pub(crate) fn centroids_prefix(index_prefix: &[u8]) -> Result<Vec<u8>> {
+    let mut key = index_prefix.to_vec();
+    crate::utils::bson::index_value_bytes_with_order(&mut key, &Bson::Null, 1)?;
+    Ok(key)
+}
+
+pub(crate) fn list_prefix(index_prefix: &[u8], list: usize) -> Result<Vec<u8>> {
+    let mut key = index_prefix.to_vec();
+    crate::utils::bson::index_value_bytes_with_order(&mut key, &Bson::Int64(list as i64), 1)?;
+    Ok(key)
+}
+
+/// Read the centroids of the lists in the order of the lists.
+pub(crate) fn load_centroids(txn: &TransactionInner, index_prefix: &[u8]) -> Result<Vec<Vec<f64>>> {
+    let prefix = centroids_prefix(index_prefix)?;
+    let kv_cursor = txn.rocksdb_txn.new_iterator();
+    kv_cursor.seek(&prefix);
+
+    let mut centroids = Vec::new();
+    while kv_cursor.valid() {
+        let key = kv_cursor.copy_key_arc()?;
+        if !key.starts_with(&prefix) {
+            break;
+        }
+        centroids.push(decode_vector(&kv_cursor.copy_data()?)?);
+        kv_cursor.next();
+    }
+
+    Ok(centroids)
+}
+
+pub(crate) fn save_centroids(txn: &TransactionInner, index_prefix: &[u8], centroids: &[Vec<f64>]) -> Result<()> {
+    for (list, centroid) in centroids.iter().enumerate() {
+        let mut key = centroids_prefix(index_prefix)?;
+        crate::utils::bson::index_value_bytes_with_order(&mut key, &Bson::Int64(list as i64), 1)?;
+        txn.put(&key, &encode_vector(centroid))?;
+    }
+    Ok(())
+}
+
+/// The lists in the descending order of the similarity of their centroids,
+/// all the vectors are in the list 0 if there is no centroid.
+pub(crate) fn rank_lists(centroids: &[Vec<f64>], vector: &[f64], similarity: VectorSimilarity) -> Vec<usize> {
+    if centroids.is_empty() {
+        return vec![0];
+    }
+    let mut scores: Vec<(usize, f64)> = centroids
+        .iter()
+        .enumerate()
+        .map(|(list, centroid)| (list, similarity.score(centroid, vector)))
+        .collect();
+    scores.sort_by(|(a_list, a_score), (b_list, b_score)| {
+        b_score.partial_cmp(a_score).unwrap_or(Ordering::Equal).then(a_list.cmp(b_list))
+    });
+    scores.into_iter().map(|(list, _)| list).collect()
+}
+
+pub(crate) fn nearest_list(centroids: &[Vec<f64>], vector: &[f64], similarity: VectorSimilarity) -> usize {
+    rank_lists(centroids, vector, similarity)[0]
+}
+
+/// Train the centroids of the lists by the k-means,
+/// there are about `sqrt(n)` lists for `n` vectors.
+///
+/// The centroids are initialized by the vectors picked evenly,
+/// so the same vectors are always trained to the same centroids.
+pub(crate) fn train_centroids(vectors: &[Vec<f64>], similarity: VectorSimilarity) -> Vec<Vec<f64>> {
+    let list_count = ((vectors.len() as f64).sqrt().round() as usize).min(MAX_LISTS);
+    if list_count <= 1 {
+        return Vec::new();
+    }
+    let dimensions = vectors[0].len();
+    let vectors: Vec<&Vec<f64>> = vectors.iter().filter(|vector| vector.len() == dimensions).collect();
+
+    let step = vectors.len() / list_count;
+    let mut centroids: Vec<Vec<f64>> = (0..list_count).map(|i| vectors[i * step].clone()).collect();
+
+    for _ in 0..TRAIN_ITERATIONS {
+        let mut sums = vec![vec![0.0; dimensions]; list_count];
+        let mut counts = vec![0usize; list_count];
+        for vector in &vectors {
+            let list = nearest_list(&centroids, vector, similarity);
+            counts[list] += 1;
+            for (sum, value) in sums[list].iter_mut().zip(vector.iter()) {
+                *sum += value;
+            }
+        }
+
+        // the empty list keeps its centroid
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            if count > 0 {
+                *centroid = sum.into_iter().map(|value| value / count as f64).collect();
+            }
+        }
+    }
+
+    centroids
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::Bson;
+    use super::{decode_vector, encode_vector, nearest_list, parse_vector, train_centroids, VectorSimilarity};
+
+    #[test]
+    fn test_similarity() {
+        let a = [1.0, 0.0];
+        let b = [0.0, 1.0];
+        assert_eq!(VectorSimilarity::Cosine.score(&a, &a), 1.0);
+        assert_eq!(VectorSimilarity::Cosine.score(&a, &b), 0.5);
+        assert_eq!(VectorSimilarity::Euclidean.score(&a, &b), 1.0 / 3.0);
+        assert_eq!(VectorSimilarity::DotProduct.score(&a, &a), 1.0);
+        assert!(VectorSimilarity::parse(Some("manhattan")).is_err());
+    }
+
+    #[test]
+    fn test_parse_vector() {
+        let value = Bson::Array(vec![Bson::Int32(1), Bson::Double(0.5)]);
+        assert_eq!(parse_vector(&value, None), Some(vec![1.0, 0.5]));
+        assert_eq!(parse_vector(&value, Some(3)), None);
+        assert_eq!(parse_vector(&Bson::Array(vec![Bson::String("a".into())]), None), None);
+
+        let vector = vec![1.5, -2.0, 0.0];
+        assert_eq!(decode_vector(&encode_vector(&vector)).unwrap(), vector);
+    }
+
+    #[test]
+    fn test_train_centroids() {
+        // two clusters around (0, 0) and (10, 10)
+        let mut vectors = Vec::new();
+        for i in 0..8 {
+            let offset = (i as f64) * 0.1;
+            vectors.push(vec![offset, offset]);
+            vectors.push(vec![10.0 + offset, 10.0 - offset]);
+        }
+        let centroids = train_centroids(&vectors, VectorSimilarity::Euclidean);
+        assert_eq!(centroids.len(), 4);
+
+        let near_origin = nearest_list(&centroids, &[0.2, 0.2], VectorSimilarity::Euclidean);
+        let near_ten = nearest_list(&centroids, &[10.2, 9.8], VectorSimilarity::Euclidean);
+        assert_ne!(near_origin, near_ten);
+        assert!(centroids[near_origin][0] < 5.0);
+
+        assert!(train_centroids(&vectors[..2], VectorSimilarity::Cosine).is_empty());
+    }
+
+}
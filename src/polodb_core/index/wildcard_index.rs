@@ -0,0 +1,134 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use bson::{Bson, Document};
+use crate::index::IndexKeyRange;
+
+/// Collect the paths and the values under the root of a wildcard index.
+///
+/// The fields of the subdocuments are expanded into their paths,
+/// and the elements of an array are indexed with the path of the array.
+/// The empty subdocuments and arrays, and the values which can't be searched by the index,
+/// such as the regular expressions, are not indexed.
+/// The `_id` is not indexed by a wildcard index of the whole document.
+pub(crate) fn collect_wildcard_entries(doc: &Document, root: &str) -> Vec<(String, Bson)> {
+    let mut entries = Vec::new();
+    if root.is_empty() {
+        for (key, value) in doc {
+            if key != "_id" {
+                collect_entries(key.clone(), value, true, &mut entries);
+            }
+        }
+    } else if let Some(value) = crate::utils::bson::try_get_document_value(doc, root) {
+        collect_entries(root.to_string(), &value, true, &mut entries);
+    }
+    entries
+}
+
+// The arrays in the arrays are not expanded.
+fn collect_entries(path: String, value: &Bson, expand_array: bool, entries: &mut Vec<(String, Bson)>) {
+    match value {
+        Bson::Document(doc) => {
+            for (key, value) in doc {
+                collect_entries(format!("{}.{}", path, key), value, true, entries);
+            }
+        }
+        Bson::Array(arr) if expand_array => {
+            for item in arr {
+                collect_entries(path.clone(), item, false, entries);
+            }
+        }
+        _ => {
+            if IndexKeyRange::is_searchable(Bound::Included(value), Bound::Included(value)) {
+                entries.push((path, value.clone()));
+            }
+        }
+    }
+}
+
+/// Check whether the path of a query is covered by the wildcard index of the root.
+///
+/// The positions of the arrays, such as `"attrs.tags.0"`, are not indexed.
+pub(crate) fn covers_path(root: &str, path: &str) -> bool {
+    let sub_path = if root.is_empty() {
+        if path == "_id" || path.starts_with("_id.") {
+            return false;
+        }
+        path
+    } else if path == root {
+        ""
+    } else {
+        match path.strip_prefix(root).and_then(|remains| remains.strip_prefix('.')) {
+            Some(sub_path) => sub_path,
+            None => return false,
+        }
+    };
+
+    sub_path
+        .split('.')
+        .filter(|part| !part.is_empty())
+        .all(|part| !part.starts_with('$') && !part.bytes().all(|ch| ch.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Bson, Regex};
+    use super::{collect_wildcard_entries, covers_path};
+
+    #[test]
+    fn test_collect_wildcard_entries() {
+        let doc = doc! {
+            "_id": 1,
+            "name": "lamp",
+            "attrs": {
+                "color": "red",
+                "size": { "w": 10, "h": 20 },
+                "tags": ["a", "b", ["c"]],
+                "parts": [{ "k": 1 }],
+                "empty": {},
+                "pattern": Regex { pattern: "^a".into(), options: "".into() },
+            },
+        };
+
+        let entries = collect_wildcard_entries(&doc, "attrs");
+        assert_eq!(entries, vec![
+            ("attrs.color".to_string(), Bson::String("red".into())),
+            ("attrs.size.w".to_string(), Bson::Int32(10)),
+            ("attrs.size.h".to_string(), Bson::Int32(20)),
+            ("attrs.tags".to_string(), Bson::String("a".into())),
+            ("attrs.tags".to_string(), Bson::String("b".into())),
+            ("attrs.parts.k".to_string(), Bson::Int32(1)),
+        ]);
+
+        let entries = collect_wildcard_entries(&doc, "");
+        assert_eq!(entries[0], ("name".to_string(), Bson::String("lamp".into())));
+        assert_eq!(entries.len(), 7);
+
+        assert!(collect_wildcard_entries(&doc, "missing").is_empty());
+    }
+
+    #[test]
+    fn test_covers_path() {
+        assert!(covers_path("attrs", "attrs.color"));
+        assert!(covers_path("attrs", "attrs.size.w"));
+        assert!(covers_path("attrs", "attrs"));
+        assert!(!covers_path("attrs", "attrs_2.color"));
+        assert!(!covers_path("attrs", "name"));
+        assert!(!covers_path("attrs", "attrs.tags.0"));
+        assert!(covers_path("", "name"));
+        assert!(!covers_path("", "_id"));
+    }
+
+}
@@ -164,6 +164,6 @@ pub use transaction::Transaction;
 pub use db::client_cursor::ClientCursor;
 pub use errors::Error;
 pub use metrics::Metrics;
-pub use index::{IndexModel, IndexOptions};
+pub use index::{IndexBuildProgress, IndexModel, IndexOptions};
 
 pub extern crate bson;
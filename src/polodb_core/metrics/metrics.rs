@@ -43,11 +43,22 @@ impl Metrics {
         self.inner.find_by_index_count.load(Ordering::SeqCst)
     }
 
+    #[inline]
+    pub(crate) fn add_scanned_doc_count(&self) {
+        self.inner.add_scanned_doc_count();
+    }
+
+    /// The count of the documents read by the collection scans and the index scans.
+    pub fn scanned_doc_count(&self) -> usize {
+        self.inner.scanned_doc_count.load(Ordering::SeqCst)
+    }
+
 }
 
 struct MetricsInner {
     enable: AtomicBool,
     find_by_index_count: AtomicUsize,
+    scanned_doc_count: AtomicUsize,
 }
 
 macro_rules! test_enable {
@@ -65,6 +76,7 @@ impl MetricsInner {
         MetricsInner {
             enable: AtomicBool::new(false),
             find_by_index_count: AtomicUsize::new(0),
+            scanned_doc_count: AtomicUsize::new(0),
         }
     }
 
@@ -79,5 +91,11 @@ impl MetricsInner {
         self.find_by_index_count.fetch_add(1, Ordering::SeqCst);
     }
 
+    pub(crate) fn add_scanned_doc_count(&self) {
+        test_enable!(self);
+
+        self.scanned_doc_count.fetch_add(1, Ordering::SeqCst);
+    }
+
 }
 
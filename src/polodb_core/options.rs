@@ -55,3 +55,13 @@ impl Default for UpdateOptions {
         UpdateOptions { upsert: None }
     }
 }
+
+/// The index used by a query regardless of the plan chosen by the planner.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hint {
+    /// The key pattern of the index, such as `{ "age": 1 }`,
+    /// or `{ "$natural": 1 }` to scan the collection without any index.
+    Keys(bson::Document),
+    /// The name of the index.
+    Name(String),
+}
@@ -14,8 +14,8 @@
 
 use bson::{doc, Document};
 use serde::{Deserialize, Serialize};
-use polodb_core::{Result, CollectionT, Database};
-use polodb_core::test_utils::prepare_db as project_prepare_db;
+use polodb_core::{Result, CollectionT, ConfigBuilder, Database};
+use polodb_core::test_utils::{prepare_db as project_prepare_db, prepare_db_with_config};
 
 #[cfg(test)]
 fn prepare_db(db_name: &str) -> Result<Database> {
@@ -372,3 +372,225 @@ fn test_aggregate_abs() {
         assert_eq!(abs_weight, weight.abs());
     }
 }
+
+#[test]
+fn test_aggregate_multiple_stages() {
+    let db = prepare_db("test-aggregate-multiple-stages").unwrap();
+    let fruits = db.collection::<Document>("fruits");
+
+    let names = fruits
+        .aggregate(vec![
+            doc! {
+                "$match": {
+                    "shape": "round",
+                },
+            },
+            doc! {
+                "$sort": {
+                    "weight": -1,
+                },
+            },
+            doc! {
+                "$skip": 1,
+            },
+            doc! {
+                "$limit": 2,
+            },
+        ])
+        .run()
+        .unwrap()
+        .map(|doc| doc.unwrap().get_str("name").unwrap().to_string())
+        .collect::<Vec<String>>();
+    assert_eq!(names, vec!["peach", "pear"]);
+
+    let result = fruits
+        .aggregate(vec![
+            doc! {
+                "$limit": 3,
+            },
+            doc! {
+                "$count": "count",
+            },
+        ])
+        .run()
+        .unwrap()
+        .collect::<Result<Vec<Document>>>()
+        .unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].get("count").unwrap().as_i64().unwrap(), 3);
+}
+
+#[test]
+fn test_aggregate_chained_group() {
+    let db = prepare_db("test-aggregate-chained-group").unwrap();
+    let fruits = db.collection::<Document>("fruits");
+
+    // the group only receives the documents matched by the previous stage
+    let result = fruits
+        .aggregate(vec![
+            doc! {
+                "$match": {
+                    "shape": "round",
+                },
+            },
+            doc! {
+                "$group": {
+                    "_id": "round",
+                    "count": {
+                        "$sum": 1,
+                    },
+                },
+            },
+        ])
+        .run()
+        .unwrap()
+        .collect::<Result<Vec<Document>>>()
+        .unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].get_str("_id").unwrap(), "round");
+    assert_eq!(result[0].get_i64("count").unwrap(), 4);
+
+    let result = fruits
+        .aggregate(vec![
+            doc! {
+                "$group": {
+                    "_id": "all",
+                    "count": {
+                        "$sum": 1,
+                    },
+                },
+            },
+            doc! {
+                "$unset": "_id",
+            },
+        ])
+        .run()
+        .unwrap()
+        .collect::<Result<Vec<Document>>>()
+        .unwrap();
+    assert_eq!(result, vec![doc! { "count": 5_i64 }]);
+}
+
+#[test]
+fn test_aggregate_chained_add_fields_and_unset() {
+    let db = prepare_db("test-aggregate-chained-add-fields-and-unset").unwrap();
+    let fruits = db.collection::<Document>("fruits");
+
+    let result = fruits
+        .aggregate(vec![
+            doc! {
+                "$match": {
+                    "color": "yellow",
+                },
+            },
+            doc! {
+                "$addFields": {
+                    "abs_weight": {
+                        "$abs": "$weight",
+                    },
+                },
+            },
+            doc! {
+                "$unset": ["color", "shape"],
+            },
+        ])
+        .run()
+        .unwrap()
+        .collect::<Result<Vec<Document>>>()
+        .unwrap();
+    assert_eq!(result.len(), 2);
+    for doc in &result {
+        assert_eq!(doc.get("color"), None);
+        assert_eq!(doc.get("shape"), None);
+        assert_eq!(doc.get_i32("abs_weight").unwrap(), doc.get_i32("weight").unwrap());
+    }
+    assert_eq!(result[0].get_str("name").unwrap(), "banana");
+    assert_eq!(result[1].get_str("name").unwrap(), "pear");
+
+    // every document is counted once after passing the stages
+    let result = fruits
+        .aggregate(vec![
+            doc! {
+                "$unset": "color",
+            },
+            doc! {
+                "$addFields": {
+                    "abs_weight": {
+                        "$abs": "$weight",
+                    },
+                },
+            },
+            doc! {
+                "$count": "count",
+            },
+        ])
+        .run()
+        .unwrap()
+        .collect::<Result<Vec<Document>>>()
+        .unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].get("count").unwrap().as_i64().unwrap(), 5);
+}
+
+#[test]
+fn test_aggregate_sort_spill_to_disk() {
+    let mut config_builder = ConfigBuilder::new();
+    config_builder.set_sort_memory_limit(1024);
+    let db = prepare_db_with_config("test-aggregate-sort-spill-to-disk", config_builder.take()).unwrap();
+    let col = db.collection::<Document>("numbers");
+    col.insert_many((0..1000).map(|i| doc! {
+        "num": (i * 7919) % 1000,
+        "content": "the documents are larger than the memory limit",
+    }).collect::<Vec<Document>>()).unwrap();
+
+    let nums = col
+        .aggregate(vec![
+            doc! {
+                "$sort": {
+                    "num": -1,
+                },
+            },
+        ])
+        .run()
+        .unwrap()
+        .map(|doc| doc.unwrap().get_i32("num").unwrap())
+        .collect::<Vec<i32>>();
+    assert_eq!(nums, (0..1000).rev().collect::<Vec<i32>>());
+}
+
+#[test]
+fn test_aggregate_sort_spill_dir() {
+    let spill_dir = polodb_core::test_utils::mk_db_path("test-aggregate-sort-spill-dir");
+    let mut config_builder = ConfigBuilder::new();
+    config_builder
+        .set_sort_memory_limit(1024)
+        .set_sort_spill_dir(&spill_dir);
+    let db = prepare_db_with_config("test-aggregate-sort-spill-dir-db", config_builder.take()).unwrap();
+    let col = db.collection::<Document>("numbers");
+    col.insert_many((0..100).map(|i| doc! {
+        "num": (i * 37) % 100,
+        "content": "the documents are larger than the memory limit",
+    }).collect::<Vec<Document>>()).unwrap();
+
+    let sort_nums = || -> Result<Vec<i32>> {
+        col
+            .aggregate(vec![
+                doc! {
+                    "$sort": {
+                        "num": 1,
+                    },
+                },
+            ])
+            .run()?
+            .map(|doc| doc.map(|doc| doc.get_i32("num").unwrap()))
+            .collect()
+    };
+
+    // the spill directory doesn't exist
+    assert!(sort_nums().is_err());
+
+    std::fs::create_dir_all(&spill_dir).unwrap();
+    assert_eq!(sort_nums().unwrap(), (0..100).collect::<Vec<i32>>());
+    // the files are removed after the sort
+    assert_eq!(std::fs::read_dir(&spill_dir).unwrap().count(), 0);
+}
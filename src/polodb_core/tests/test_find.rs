@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use polodb_core::{Result, CollectionT};
+use polodb_core::{Result, CollectionT, IndexModel};
 use polodb_core::bson::{doc, Document};
 
 mod common;
@@ -354,3 +354,441 @@ fn test_find_sort() {
     assert_eq!(result[1].get("name").unwrap().as_str().unwrap(), "banana");
     assert_eq!(result[2].get("name").unwrap().as_str().unwrap(), "orange");
 }
+
+#[test]
+fn test_find_sort_with_skip_and_limit() {
+    let db = prepare_db("test-find-sort-with-skip-and-limit").unwrap();
+
+    let col = db.collection::<Document>("numbers");
+    col.insert_many((0..100).map(|i| doc! {
+        "num": (i * 37) % 100,
+    }).collect::<Vec<Document>>()).unwrap();
+
+    let nums = col
+        .find(doc! {})
+        .sort(doc! {
+            "num": -1,
+        })
+        .skip(5)
+        .limit(3)
+        .run()
+        .unwrap()
+        .map(|doc| doc.unwrap().get_i32("num").unwrap())
+        .collect::<Vec<i32>>();
+    assert_eq!(nums, vec![94, 93, 92]);
+
+    let nums = col
+        .find(doc! {})
+        .sort(doc! {
+            "num": 1,
+        })
+        .limit(4)
+        .run()
+        .unwrap()
+        .map(|doc| doc.unwrap().get_i32("num").unwrap())
+        .collect::<Vec<i32>>();
+    assert_eq!(nums, vec![0, 1, 2, 3]);
+}
+
+#[test]
+fn test_find_projection() {
+    let db = prepare_db("test-find-projection").unwrap();
+    let col = db.collection::<Document>("orders");
+    col.insert_many(vec![
+        doc! {
+            "_id": 1,
+            "name": "first",
+            "customer": { "name": "alice", "city": "paris" },
+            "items": [
+                { "sku": "a", "qty": 1 },
+                { "sku": "b", "qty": 5 },
+                { "sku": "c", "qty": 2 },
+            ],
+            "history": [1, 2, 3, 4, 5],
+        },
+        doc! {
+            "_id": 2,
+            "name": "second",
+            "customer": { "name": "bob", "city": "tokyo" },
+            "items": [],
+            "history": [6],
+        },
+    ]).unwrap();
+
+    let doc = col.find_one_with_projection(doc! { "_id": 1 }, doc! {
+        "name": 1,
+        "customer.city": 1,
+    }).unwrap().unwrap();
+    assert_eq!(doc, doc! {
+        "_id": 1,
+        "name": "first",
+        "customer": { "city": "paris" },
+    });
+
+    let doc = col.find_one_with_projection(doc! { "_id": 1 }, doc! {
+        "name": 1,
+        "items.sku": 1,
+        "_id": 0,
+    }).unwrap().unwrap();
+    assert_eq!(doc, doc! {
+        "name": "first",
+        "items": [{ "sku": "a" }, { "sku": "b" }, { "sku": "c" }],
+    });
+
+    // the excluded fields are removed, the others are kept
+    let docs = col.find(doc! {})
+        .projection(doc! { "history": 0, "items": 0, "customer.name": 0 })
+        .run()
+        .unwrap()
+        .collect::<Result<Vec<Document>>>()
+        .unwrap();
+    assert_eq!(docs, vec![
+        doc! { "_id": 1, "name": "first", "customer": { "city": "paris" } },
+        doc! { "_id": 2, "name": "second", "customer": { "city": "tokyo" } },
+    ]);
+
+    // `$slice` keeps the other fields
+    let doc = col.find_one_with_projection(doc! { "_id": 1 }, doc! {
+        "history": { "$slice": -2 },
+        "items": 0,
+        "customer": 0,
+    }).unwrap().unwrap();
+    assert_eq!(doc, doc! { "_id": 1, "name": "first", "history": [4, 5] });
+
+    let doc = col.find_one_with_projection(doc! { "_id": 1 }, doc! {
+        "history": { "$slice": [1, 2] },
+        "name": 1,
+    }).unwrap().unwrap();
+    assert_eq!(doc, doc! { "_id": 1, "name": "first", "history": [2, 3] });
+
+    // `$elemMatch` keeps the first matching element
+    let docs = col.find(doc! {})
+        .sort(doc! { "_id": 1 })
+        .projection(doc! { "items": { "$elemMatch": { "qty": { "$gte": 2 } } } })
+        .run()
+        .unwrap()
+        .collect::<Result<Vec<Document>>>()
+        .unwrap();
+    assert_eq!(docs, vec![
+        doc! { "_id": 1, "items": [{ "sku": "b", "qty": 5 }] },
+        doc! { "_id": 2 },
+    ]);
+
+    let doc = col.find_one_with_projection(doc! { "_id": 1 }, doc! {
+        "history": { "$elemMatch": { "$gt": 3 } },
+        "_id": false,
+    }).unwrap().unwrap();
+    assert_eq!(doc, doc! { "history": [4] });
+
+    let err = col.find(doc! {})
+        .projection(doc! { "name": 1, "history": 0 })
+        .run()
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("inclusion projection"));
+}
+
+#[test]
+fn test_find_exists_type_nor() {
+    let db = prepare_db("test-find-exists-type-nor").unwrap();
+    let col = db.collection::<Document>("items");
+    col.insert_many(vec![
+        doc! { "_id": 1, "value": "a", "meta": { "tag": "x" } },
+        doc! { "_id": 2, "value": 2 },
+        doc! { "_id": 3, "value": 3.5 },
+        doc! { "_id": 4, "value": null },
+        doc! { "_id": 5 },
+    ]).unwrap();
+
+    let find_ids = |filter: Document| -> Vec<i32> {
+        col.find(filter)
+            .sort(doc! { "_id": 1 })
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+            .collect()
+    };
+
+    assert_eq!(find_ids(doc! { "value": { "$exists": true } }), vec![1, 2, 3, 4]);
+    assert_eq!(find_ids(doc! { "value": { "$exists": false } }), vec![5]);
+    assert_eq!(find_ids(doc! { "meta.tag": { "$exists": 1 } }), vec![1]);
+    assert_eq!(find_ids(doc! { "value": { "$not": { "$exists": true } } }), vec![5]);
+
+    assert_eq!(find_ids(doc! { "value": { "$type": "string" } }), vec![1]);
+    assert_eq!(find_ids(doc! { "value": { "$type": 10 } }), vec![4]);
+    assert_eq!(find_ids(doc! { "value": { "$type": "number" } }), vec![2, 3]);
+    assert_eq!(find_ids(doc! { "value": { "$type": ["double", "null"] } }), vec![3, 4]);
+    assert_eq!(find_ids(doc! { "meta": { "$type": "object" } }), vec![1]);
+
+    // `$not` also matches the documents without the field
+    assert_eq!(find_ids(doc! { "value": { "$not": { "$type": "number" } } }), vec![1, 4, 5]);
+    assert_eq!(find_ids(doc! { "value": { "$ne": 2 } }), vec![1, 3, 4, 5]);
+    assert_eq!(find_ids(doc! { "value": { "$nin": [2, "a"] } }), vec![3, 4, 5]);
+
+    assert_eq!(find_ids(doc! {
+        "$nor": [{ "value": { "$type": "string" } }, { "value": { "$exists": false } }],
+    }), vec![2, 3, 4]);
+    assert_eq!(find_ids(doc! {
+        "$nor": [{ "value": 2 }],
+        "value": { "$type": "number" },
+    }), vec![3]);
+    assert_eq!(find_ids(doc! {
+        "$or": [{ "value": 2 }, { "value": "a" }],
+        "meta": { "$exists": false },
+    }), vec![2]);
+
+    let err = col.find(doc! { "value": { "$type": "float" } }).run().err().unwrap();
+    assert!(matches!(err, polodb_core::Error::InvalidField(_)));
+}
+
+#[test]
+fn test_find_elem_match_all() {
+    let db = prepare_db("test-find-elem-match-all").unwrap();
+    let col = db.collection::<Document>("orders");
+    col.insert_many(vec![
+        doc! {
+            "_id": 1,
+            "items": [{ "sku": "A1", "qty": 2 }, { "sku": "B2", "qty": 10 }],
+            "tags": ["a", "b", "c"],
+            "scores": [82, 90],
+        },
+        doc! {
+            "_id": 2,
+            "items": [{ "sku": "A1", "qty": 8 }, "broken"],
+            "tags": ["a"],
+            "scores": [70, 88],
+        },
+        doc! {
+            "_id": 3,
+            "items": [],
+            "tags": "b",
+            "scores": 83,
+        },
+        doc! { "_id": 4 },
+    ]).unwrap();
+
+    let find_ids = |filter: Document| -> Vec<i32> {
+        col.find(filter)
+            .sort(doc! { "_id": 1 })
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+            .collect()
+    };
+
+    assert_eq!(find_ids(doc! {
+        "items": { "$elemMatch": { "sku": "A1", "qty": { "$gt": 5 } } },
+    }), vec![2]);
+    assert_eq!(find_ids(doc! {
+        "items": { "$elemMatch": { "$or": [{ "qty": { "$gt": 9 } }, { "sku": "C3" }] } },
+    }), vec![1]);
+    assert_eq!(find_ids(doc! { "scores": { "$elemMatch": { "$gte": 80, "$lt": 85 } } }), vec![1]);
+    // `$elemMatch` only matches the arrays
+    assert_eq!(find_ids(doc! { "scores": { "$elemMatch": { "$gt": 80 } } }), vec![1, 2]);
+    assert_eq!(find_ids(doc! {
+        "items": { "$not": { "$elemMatch": { "sku": "A1" } } },
+    }), vec![3, 4]);
+
+    assert_eq!(find_ids(doc! { "tags": { "$all": ["a", "b"] } }), vec![1]);
+    assert_eq!(find_ids(doc! { "tags": { "$all": ["a"] } }), vec![1, 2]);
+    assert_eq!(find_ids(doc! { "tags": { "$all": ["b"] } }), vec![1, 3]);
+    assert_eq!(find_ids(doc! { "tags": { "$all": [] } }), Vec::<i32>::new());
+    assert_eq!(find_ids(doc! {
+        "items": { "$all": [
+            { "$elemMatch": { "sku": "A1" } },
+            { "$elemMatch": { "qty": { "$gte": 10 } } },
+        ] },
+    }), vec![1]);
+    assert_eq!(find_ids(doc! {
+        "items": { "$all": [{ "sku": "A1", "qty": 8 }] },
+    }), vec![2]);
+
+    let err = col.find(doc! { "tags": { "$all": "a" } }).run().err().unwrap();
+    assert!(matches!(err, polodb_core::Error::InvalidField(_)));
+}
+
+#[test]
+fn test_find_array_paths() {
+    let db = prepare_db("test-find-array-paths").unwrap();
+    let col = db.collection::<Document>("orders");
+    col.insert_many(vec![
+        doc! {
+            "_id": 1,
+            "items": [{ "sku": "A1", "qty": 2 }, { "sku": "B2", "qty": 10 }],
+            "tags": ["x", "y"],
+            "matrix": [[1, 2], [3]],
+        },
+        doc! {
+            "_id": 2,
+            "items": [{ "sku": "B2", "qty": 1 }],
+            "tags": "x",
+            "matrix": [1, 2],
+        },
+        doc! {
+            "_id": 3,
+            "items": { "sku": "A1", "qty": 7 },
+            "tags": ["z"],
+        },
+    ]).unwrap();
+
+    let find_ids = |filter: Document| -> Vec<i32> {
+        col.find(filter)
+            .sort(doc! { "_id": 1 })
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+            .collect()
+    };
+
+    assert_eq!(find_ids(doc! { "items.sku": "A1" }), vec![1, 3]);
+    assert_eq!(find_ids(doc! { "items.qty": { "$gt": 5 } }), vec![1, 3]);
+    assert_eq!(find_ids(doc! { "items.0.sku": "B2" }), vec![2]);
+    assert_eq!(find_ids(doc! { "tags": "x" }), vec![1, 2]);
+    assert_eq!(find_ids(doc! { "tags": ["x", "y"] }), vec![1]);
+    assert_eq!(find_ids(doc! { "tags": { "$in": ["y", "z"] } }), vec![1, 3]);
+    assert_eq!(find_ids(doc! { "tags": { "$ne": "x" } }), vec![3]);
+    assert_eq!(find_ids(doc! { "tags.1": "y" }), vec![1]);
+
+    // the nested arrays are not traversed
+    assert_eq!(find_ids(doc! { "matrix": 1 }), vec![2]);
+    assert_eq!(find_ids(doc! { "matrix": [3] }), vec![1]);
+
+    // an array is sorted by its smallest element in the ascending order
+    let ids = col.find(doc! {})
+        .sort(doc! { "items.qty": 1 })
+        .run()
+        .unwrap()
+        .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+        .collect::<Vec<i32>>();
+    assert_eq!(ids, vec![2, 1, 3]);
+    let ids = col.find(doc! {})
+        .sort(doc! { "items.qty": -1 })
+        .run()
+        .unwrap()
+        .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+        .collect::<Vec<i32>>();
+    assert_eq!(ids, vec![1, 3, 2]);
+
+    // the keys of the index are found in the documents of the arrays
+    col.create_index(IndexModel {
+        keys: doc! { "items.sku": 1 },
+        options: None,
+    }).unwrap();
+    assert_eq!(find_ids(doc! { "items.sku": "A1" }), vec![1, 3]);
+    assert_eq!(find_ids(doc! { "items.sku": "B2" }), vec![1, 2]);
+}
+
+#[test]
+fn test_find_expr() {
+    let db = prepare_db("test-find-expr").unwrap();
+    let col = db.collection::<Document>("budgets");
+    col.insert_many(vec![
+        doc! { "_id": 1, "category": "food", "budget": 400, "spent": 450 },
+        doc! { "_id": 2, "category": "drinks", "budget": 100, "spent": 150, "bonus": 60 },
+        doc! { "_id": 3, "category": "clothes", "budget": 100, "spent": 50 },
+        doc! { "_id": 4, "category": "misc", "budget": 500.5, "spent": 300 },
+    ]).unwrap();
+
+    let find_ids = |filter: Document| -> Vec<i32> {
+        col.find(filter)
+            .sort(doc! { "_id": 1 })
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+            .collect()
+    };
+
+    assert_eq!(find_ids(doc! { "$expr": { "$gt": ["$spent", "$budget"] } }), vec![1, 2]);
+    // the sum is null without a bonus, and numbers are greater than null
+    assert_eq!(find_ids(doc! {
+        "$expr": { "$gt": ["$spent", { "$add": ["$budget", "$bonus"] }] },
+    }), vec![1, 3, 4]);
+    assert_eq!(find_ids(doc! {
+        "$expr": { "$lte": [{ "$subtract": ["$budget", "$spent"] }, 50] },
+    }), vec![1, 2, 3]);
+    assert_eq!(find_ids(doc! {
+        "$expr": {
+            "$and": [
+                { "$gte": [{ "$multiply": ["$spent", 2] }, "$budget"] },
+                { "$ne": ["$category", "food"] },
+            ],
+        },
+    }), vec![2, 3, 4]);
+    assert_eq!(find_ids(doc! {
+        "category": { "$ne": "misc" },
+        "$expr": { "$eq": [{ "$divide": ["$spent", "$budget"] }, 0.5] },
+    }), vec![3]);
+    assert_eq!(find_ids(doc! {
+        "_id": 2,
+        "$expr": { "$not": [{ "$lt": ["$spent", "$budget"] }] },
+    }), vec![2]);
+
+    let result = col
+        .aggregate(vec![
+            doc! { "$match": { "$expr": { "$lt": ["$spent", "$budget"] } } },
+            doc! { "$sort": { "_id": 1 } },
+        ])
+        .run()
+        .unwrap()
+        .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+        .collect::<Vec<i32>>();
+    assert_eq!(result, vec![3, 4]);
+
+    let updated = col.update_many(
+        doc! { "$expr": { "$gt": ["$spent", "$budget"] } },
+        doc! { "$set": { "over": true } },
+    ).unwrap();
+    assert_eq!(updated.modified_count, 2);
+    assert_eq!(find_ids(doc! { "over": true }), vec![1, 2]);
+
+    assert!(col.find(doc! { "$expr": { "$subtract": [1, 2, 3] } }).run().is_err());
+}
+
+#[test]
+fn test_find_mod_bits() {
+    let db = prepare_db("test-find-mod-bits").unwrap();
+    let col = db.collection::<Document>("users");
+    col.insert_many(vec![
+        doc! { "_id": 1, "qty": 12, "permissions": 0b0101 },
+        doc! { "_id": 2, "qty": -7, "permissions": 0b0110_i64 },
+        doc! { "_id": 3, "qty": 9.5, "permissions": 6.0 },
+        doc! { "_id": 4, "qty": [3, 20], "permissions": -1 },
+        doc! { "_id": 5, "qty": "12", "permissions": "5" },
+    ]).unwrap();
+
+    let find_ids = |filter: Document| -> Vec<i32> {
+        col.find(filter)
+            .sort(doc! { "_id": 1 })
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+            .collect()
+    };
+
+    assert_eq!(find_ids(doc! { "qty": { "$mod": [4, 0] } }), vec![1, 4]);
+    // the remainder has the sign of the dividend, and the fraction is truncated
+    assert_eq!(find_ids(doc! { "qty": { "$mod": [3, -1] } }), vec![2]);
+    assert_eq!(find_ids(doc! { "qty": { "$mod": [3.9, 0.5] } }), vec![1, 3, 4]);
+    assert_eq!(find_ids(doc! { "qty": { "$not": { "$mod": [2, 0] } } }), vec![2, 3, 5]);
+
+    assert_eq!(find_ids(doc! { "permissions": { "$bitsAllSet": [0, 2] } }), vec![1, 4]);
+    assert_eq!(find_ids(doc! { "permissions": { "$bitsAllSet": 6 } }), vec![2, 3, 4]);
+    assert_eq!(find_ids(doc! { "permissions": { "$bitsAnySet": [0, 3] } }), vec![1, 4]);
+    assert_eq!(find_ids(doc! { "permissions": { "$bitsAllClear": [1] } }), vec![1]);
+    assert_eq!(find_ids(doc! { "permissions": { "$bitsAnyClear": 5 } }), vec![2, 3]);
+    // the negative numbers are sign-extended
+    assert_eq!(find_ids(doc! { "permissions": { "$bitsAllSet": [63, 100] } }), vec![4]);
+
+    let err = col.find(doc! { "qty": { "$mod": 4 } }).run().err().unwrap();
+    assert!(matches!(err, polodb_core::Error::FieldTypeUnexpected(_)));
+    let err = col.find(doc! { "qty": { "$mod": ["4", 0] } }).run().err().unwrap();
+    assert!(matches!(err, polodb_core::Error::FieldTypeUnexpected(_)));
+    let err = col.find(doc! { "qty": { "$mod": [0, 0] } }).run().err().unwrap();
+    assert!(matches!(err, polodb_core::Error::ValidationError(_)));
+    let err = col.find(doc! { "permissions": { "$bitsAllSet": "1" } }).run().err().unwrap();
+    assert!(matches!(err, polodb_core::Error::FieldTypeUnexpected(_)));
+    let err = col.find(doc! { "permissions": { "$bitsAnySet": [1.5] } }).run().err().unwrap();
+    assert!(matches!(err, polodb_core::Error::FieldTypeUnexpected(_)));
+    let err = col.find(doc! { "permissions": { "$bitsAnyClear": -1 } }).run().err().unwrap();
+    assert!(matches!(err, polodb_core::Error::ValidationError(_)));
+}
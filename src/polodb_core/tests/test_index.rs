@@ -12,210 +12,289 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use polodb_core::{CollectionT, IndexModel, IndexOptions, Result};
-use bson::{doc, Document};
-use crate::common::prepare_db;
+use std::time::{Duration, Instant};
+use polodb_core::{CollectionT, ConfigBuilder, Error, IndexModel, IndexOptions, Result};
+use polodb_core::options::Hint;
+use bson::{doc, Bson, DateTime, Document};
+use crate::common::{prepare_db, prepare_db_with_config};
 
 mod common;
 
 #[test]
 fn test_create_multi_keys_index() {
-    let db = prepare_db("test-create-multi-keys-index").unwrap();
-    let col = db.collection::<Document>("teacher");
-    let result = col.create_index(IndexModel {
-        keys: doc! {
-            "age": 1,
-            "name": 1,
-        },
-        options: None,
-    });
-    assert!(result.is_err());
-    assert!(result.unwrap_err().to_string().contains("only support single field indexes currently"));
-}
-
-#[test]
-fn test_create_reverse_order_index() {
-    let db = prepare_db("test-create-reverse-order-index").unwrap();
-    let col = db.collection::<Document>("teacher");
-    let result = col.create_index(IndexModel {
-        keys: doc! {
-            "age": -1,
-        },
-        options: None,
-    });
-    assert!(result.is_err());
-    assert!(result.unwrap_err().to_string().contains("only support ascending order index currently"));
-}
-
-#[test]
-fn test_create_index() {
     vec![
-        prepare_db("test-create-index").unwrap(),
+        prepare_db("test-create-multi-keys-index").unwrap(),
     ].iter().for_each(|db| {
-        let col = db.collection("teacher");
+        let metrics = db.metrics();
+        metrics.enable();
 
+        let col = db.collection::<Document>("events");
         col.create_index(IndexModel {
             keys: doc! {
-                "age": 1,
+                "tenant": 1,
+                "created_at": 1,
             },
             options: None,
         }).unwrap();
 
-        col.insert_one(doc! {
-            "name": "David",
-            "age": 33,
-        }).unwrap();
+        col.insert_many(vec![
+            doc! { "tenant": "a", "created_at": 1, "name": "first" },
+            doc! { "tenant": "a", "created_at": 2, "name": "second" },
+            doc! { "tenant": "ab", "created_at": 1, "name": "third" },
+            doc! { "tenant": "b", "created_at": 2, "name": "fourth" },
+        ]).unwrap();
+
+        let doc = col.find_one(doc! {
+            "tenant": "a",
+            "created_at": 2,
+        }).unwrap().unwrap();
+        assert_eq!(doc.get_str("name").unwrap(), "second");
+        assert_eq!(metrics.find_by_index_count(), 1);
+
+        // a prefix of the keys can use the index too
+        let tenant_a = col
+            .find(doc! {
+                "tenant": "a",
+            })
+            .run()
+            .unwrap()
+            .collect::<Result<Vec<Document>>>()
+            .unwrap();
+        assert_eq!(tenant_a.len(), 2);
+        assert_eq!(metrics.find_by_index_count(), 2);
+
+        // the leading key is missing, so the index can not be used
+        let created_at_2 = col
+            .find(doc! {
+                "created_at": 2,
+            })
+            .run()
+            .unwrap()
+            .collect::<Result<Vec<Document>>>()
+            .unwrap();
+        assert_eq!(created_at_2.len(), 2);
+        assert_eq!(metrics.find_by_index_count(), 2);
     });
 }
 
 #[test]
-fn test_create_index_with_data() {
+fn test_update_multi_keys_index() {
     vec![
-        prepare_db("test-create-index-with-data").unwrap(),
+        prepare_db("test-update-multi-keys-index").unwrap(),
     ].iter().for_each(|db| {
-        let metrics = db.metrics();
-        metrics.enable();
-
-        let col = db.collection::<Document>("teacher");
+        let col = db.collection::<Document>("events");
 
-        col.insert_one(doc! {
-            "name": "David",
-            "age": 33,
-        }).unwrap();
+        col.insert_many(vec![
+            doc! { "tenant": "a", "created_at": 1, "name": "first" },
+            doc! { "tenant": "a", "created_at": 2, "name": "second" },
+        ]).unwrap();
 
         col.create_index(IndexModel {
             keys: doc! {
-                "age": 1,
+                "tenant": 1,
+                "created_at": 1,
             },
             options: None,
         }).unwrap();
 
+        col.update_one(doc! {
+            "name": "second",
+        }, doc! {
+            "$set": {
+                "created_at": 3,
+            },
+        }).unwrap();
+
+        assert!(col.find_one(doc! {
+            "tenant": "a",
+            "created_at": 2,
+        }).unwrap().is_none());
+
         let doc = col.find_one(doc! {
-            "age": 33
+            "tenant": "a",
+            "created_at": 3,
         }).unwrap().unwrap();
+        assert_eq!(doc.get_str("name").unwrap(), "second");
 
-        assert_eq!(doc.get_str("name").unwrap(), "David");
+        col.delete_one(doc! {
+            "name": "second",
+        }).unwrap();
 
-        assert_eq!(metrics.find_by_index_count(), 1);
+        assert!(col.find_one(doc! {
+            "tenant": "a",
+            "created_at": 3,
+        }).unwrap().is_none());
     });
 }
 
 #[test]
-fn test_find_by_index() {
+fn test_create_unique_multi_keys_index() {
     vec![
-        prepare_db("test-find-by-index").unwrap(),
+        prepare_db("test-create-unique-multi-keys-index").unwrap(),
     ].iter().for_each(|db| {
-        let metrics = db.metrics();
-        metrics.enable();
-
-        let col = db.collection::<Document>("teacher");
+        let col = db.collection::<Document>("users");
 
         col.create_index(IndexModel {
             keys: doc! {
-                "age": 1,
+                "tenant": 1,
+                "name": 1,
             },
-            options: None,
+            options: Some(IndexOptions {
+                unique: Some(true),
+                ..Default::default()
+            }),
         }).unwrap();
 
         col.insert_one(doc! {
+            "tenant": "a",
             "name": "David",
-            "age": 33,
         }).unwrap();
 
-        let doc = col.find_one(doc! {
-            "age": 33
-        }).unwrap().unwrap();
+        col.insert_one(doc! {
+            "tenant": "b",
+            "name": "David",
+        }).unwrap();
 
-        assert_eq!(doc.get_str("name").unwrap(), "David");
+        let result = col.insert_one(doc! {
+            "tenant": "a",
+            "name": "David",
+        });
 
-        assert_eq!(metrics.find_by_index_count(), 1);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("duplicate key error"));
+
+        // the documents without the keys are indexed as null too
+        col.insert_one(doc! {
+            "age": 1,
+        }).unwrap();
+
+        let result = col.insert_one(doc! {
+            "age": 2,
+        });
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("duplicate key error"));
     });
 }
 
 #[test]
-fn test_index_order() {
+fn test_create_reverse_order_index() {
     vec![
-        prepare_db("test-index-order").unwrap(),
+        prepare_db("test-create-reverse-order-index").unwrap(),
     ].iter().for_each(|db| {
         let metrics = db.metrics();
         metrics.enable();
 
         let col = db.collection::<Document>("teacher");
-
         col.create_index(IndexModel {
             keys: doc! {
-                "age": 1,
+                "name": -1,
             },
             options: None,
         }).unwrap();
 
         col.insert_many(vec![
-            doc! {
-                "name": "David",
-                "age": 23,
-            },
-            doc! {
-                "name": "John",
-            },
-            doc! {
-                "name": "Dick",
-                "age": 23,
-            }
+            doc! { "name": "a", "age": 1 },
+            doc! { "name": "ab", "age": 2 },
+            doc! { "name": "b", "age": 3 },
+            doc! { "name": "a", "age": 4 },
         ]).unwrap();
 
-        let people23 = col
+        let name_a = col
             .find(doc! {
-                "age": 23
+                "name": "a",
             })
             .run()
             .unwrap()
             .collect::<Result<Vec<Document>>>()
             .unwrap();
+        assert_eq!(name_a.len(), 2);
+        assert_eq!(metrics.find_by_index_count(), 1);
 
-        assert_eq!(people23.len(), 2);
+        let names = |sort: Document| {
+            col.aggregate(vec![
+                doc! {
+                    "$sort": sort,
+                },
+            ])
+                .run()
+                .unwrap()
+                .map(|doc| doc.unwrap().get_str("name").unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(names(doc! { "name": -1 }), vec!["b", "ab", "a", "a"]);
+        assert_eq!(metrics.find_by_index_count(), 2);
+
+        // scan the index in reverse order
+        assert_eq!(names(doc! { "name": 1 }), vec!["a", "a", "ab", "b"]);
+        assert_eq!(metrics.find_by_index_count(), 3);
     });
 }
 
 #[test]
-fn test_create_unique_index() {
+fn test_find_latest_by_compound_index() {
     vec![
-        prepare_db("test-create-unique-index").unwrap(),
+        prepare_db("test-find-latest-by-compound-index").unwrap(),
     ].iter().for_each(|db| {
-        let col = db.collection("teacher");
+        let metrics = db.metrics();
+        metrics.enable();
 
+        let col = db.collection::<Document>("events");
         col.create_index(IndexModel {
             keys: doc! {
-                "name": 1,
+                "tenant": 1,
+                "created_at": -1,
             },
-            options: Some(IndexOptions{
-                unique: Some(true),
-                ..Default::default()
-            }),
+            options: None,
         }).unwrap();
 
-        col.insert_one(doc! {
-            "name": "David",
-            "age": 33,
-        }).unwrap();
+        col.insert_many(vec![
+            doc! { "tenant": "a", "created_at": 1 },
+            doc! { "tenant": "b", "created_at": 5 },
+            doc! { "tenant": "a", "created_at": 3 },
+            doc! { "tenant": "a", "created_at": 2 },
+        ]).unwrap();
 
-        let result = col.insert_one(doc! {
-            "name": "David",
-            "age": 33,
-        });
+        let latest = col
+            .aggregate(vec![
+                doc! {
+                    "$match": {
+                        "tenant": "a",
+                    },
+                },
+                doc! {
+                    "$sort": {
+                        "created_at": -1,
+                    },
+                },
+                doc! {
+                    "$limit": 2,
+                },
+            ])
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap().get_i32("created_at").unwrap())
+            .collect::<Vec<i32>>();
+        assert_eq!(latest, vec![3, 2]);
+        assert_eq!(metrics.find_by_index_count(), 1);
 
+        let result = col.create_index(IndexModel {
+            keys: doc! {
+                "created_at": 2,
+            },
+            options: None,
+        });
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("duplicate key error"));
+        assert!(result.unwrap_err().to_string().contains("invalid order of index"));
     });
 }
 
 #[test]
-fn test_update_with_index() {
+fn test_create_index() {
     vec![
-        prepare_db("test-update-with-index").unwrap(),
+        prepare_db("test-create-index").unwrap(),
     ].iter().for_each(|db| {
-        let metrics = db.metrics();
-        metrics.enable();
-
         let col = db.collection("teacher");
 
         col.create_index(IndexModel {
@@ -229,17 +308,33 @@ fn test_update_with_index() {
             "name": "David",
             "age": 33,
         }).unwrap();
+    });
+}
 
-        col.update_many(doc! {
+#[test]
+fn test_create_index_with_data() {
+    vec![
+        prepare_db("test-create-index-with-data").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("teacher");
+
+        col.insert_one(doc! {
+            "name": "David",
             "age": 33,
-        }, doc! {
-            "$set": {
-                "age": 34,
+        }).unwrap();
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "age": 1,
             },
+            options: None,
         }).unwrap();
 
         let doc = col.find_one(doc! {
-            "age": 34
+            "age": 33
         }).unwrap().unwrap();
 
         assert_eq!(doc.get_str("name").unwrap(), "David");
@@ -249,9 +344,9 @@ fn test_update_with_index() {
 }
 
 #[test]
-fn test_delete_with_index() {
+fn test_find_by_index() {
     vec![
-        prepare_db("test-delete-with-index").unwrap(),
+        prepare_db("test-find-by-index").unwrap(),
     ].iter().for_each(|db| {
         let metrics = db.metrics();
         metrics.enable();
@@ -270,30 +365,20 @@ fn test_delete_with_index() {
             "age": 33,
         }).unwrap();
 
-        let result = col.delete_many(doc! {
-            "age": 33,
-        }).unwrap();
-
-        assert_eq!(result.deleted_count, 1);
-
-        let count = col.count_documents().unwrap();
-        assert_eq!(count, 0);
-
-        let result = col.find_one(doc! {
+        let doc = col.find_one(doc! {
             "age": 33
-        }).unwrap();
-        assert_eq!(result, None);
+        }).unwrap().unwrap();
 
-        assert!(col.find_one(doc! {
-            "age": 33
-        }).unwrap().is_none());
+        assert_eq!(doc.get_str("name").unwrap(), "David");
+
+        assert_eq!(metrics.find_by_index_count(), 1);
     });
 }
 
 #[test]
-fn test_drop_index() {
+fn test_find_by_index_range() {
     vec![
-        prepare_db("test-drop-index").unwrap(),
+        prepare_db("test-find-by-index-range").unwrap(),
     ].iter().for_each(|db| {
         let metrics = db.metrics();
         metrics.enable();
@@ -307,21 +392,1730 @@ fn test_drop_index() {
             options: None,
         }).unwrap();
 
-        col.insert_one(doc! {
-            "name": "David",
-            "age": 33,
-        }).unwrap();
+        col.insert_many(vec![
+            doc! { "name": "Alice", "age": -3 },
+            doc! { "name": "Bob", "age": 12 },
+            doc! { "name": "Carol", "age": 30 },
+            doc! { "name": "David", "age": 33_i64 },
+            doc! { "name": "Eve", "age": 40.5 },
+            doc! { "name": "Frank", "age": "unknown" },
+            doc! { "name": "Grace" },
+        ]).unwrap();
 
-        col.drop_index("age_1").unwrap();
+        let find_names = |filter: Document| {
+            let mut names = col
+                .find(filter)
+                .run()
+                .unwrap()
+                .map(|doc| doc.unwrap().get_str("name").unwrap().to_string())
+                .collect::<Vec<String>>();
+            names.sort();
+            names
+        };
 
-        {
-            let doc = col.find_one(doc! {
-                "age": 33,
-            }).unwrap().unwrap();
+        assert_eq!(find_names(doc! { "age": { "$gt": 30 } }), vec!["David", "Eve"]);
+        assert_eq!(metrics.find_by_index_count(), 1);
 
-            assert_eq!(doc.get_str("name").unwrap(), "David");
-        }
+        assert_eq!(find_names(doc! { "age": { "$gte": 30 } }), vec!["Carol", "David", "Eve"]);
+        assert_eq!(find_names(doc! { "age": { "$lt": 30 } }), vec!["Alice", "Bob"]);
+        assert_eq!(find_names(doc! { "age": { "$lte": 30.0 } }), vec!["Alice", "Bob", "Carol"]);
+        assert_eq!(find_names(doc! { "age": { "$gt": -10, "$lt": 20 } }), vec!["Alice", "Bob"]);
+        assert_eq!(find_names(doc! { "age": { "$in": [12, 33, 100] } }), vec!["Bob", "David"]);
+        assert_eq!(find_names(doc! { "age": { "$gte": "a" } }), vec!["Frank"]);
+        assert_eq!(metrics.find_by_index_count(), 7);
 
-        assert_eq!(metrics.find_by_index_count(), 0);
+        // no value in the range
+        assert!(find_names(doc! { "age": { "$gt": 100 } }).is_empty());
+        assert!(find_names(doc! { "age": { "$in": [] } }).is_empty());
+    });
+}
+
+#[test]
+fn test_find_by_compound_index_range() {
+    vec![
+        prepare_db("test-find-by-compound-index-range").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("events");
+        col.create_index(IndexModel {
+            keys: doc! {
+                "tenant": 1,
+                "created_at": -1,
+            },
+            options: None,
+        }).unwrap();
+
+        col.insert_many(vec![
+            doc! { "tenant": "a", "created_at": 1 },
+            doc! { "tenant": "a", "created_at": 2 },
+            doc! { "tenant": "a", "created_at": 3 },
+            doc! { "tenant": "b", "created_at": 2 },
+        ]).unwrap();
+
+        let created_at = col
+            .find(doc! {
+                "tenant": "a",
+                "created_at": {
+                    "$gte": 2,
+                },
+            })
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap().get_i32("created_at").unwrap())
+            .collect::<Vec<i32>>();
+        // in the order of the index
+        assert_eq!(created_at, vec![3, 2]);
+        assert_eq!(metrics.find_by_index_count(), 1);
+    });
+}
+
+#[test]
+fn test_find_sorted_by_index_with_limit() {
+    vec![
+        prepare_db("test-find-sorted-by-index-with-limit").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("events");
+        col.create_index(IndexModel {
+            keys: doc! {
+                "created_at": 1,
+            },
+            options: None,
+        }).unwrap();
+
+        let docs = (0..100).map(|i| doc! {
+            "created_at": (i * 7) % 100,
+            "tag": "event",
+        }).collect::<Vec<Document>>();
+        col.insert_many(docs).unwrap();
+
+        let latest = col
+            .find(doc! {})
+            .sort(doc! { "created_at": -1 })
+            .limit(3)
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap().get_i32("created_at").unwrap())
+            .collect::<Vec<i32>>();
+        assert_eq!(latest, vec![99, 98, 97]);
+        assert_eq!(metrics.find_by_index_count(), 1);
+        // only the first entries of the index are read
+        assert_eq!(metrics.scanned_doc_count(), 3);
+
+        let skipped = col
+            .find(doc! { "created_at": { "$gte": 10 } })
+            .sort(doc! { "created_at": 1 })
+            .skip(5)
+            .limit(2)
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap().get_i32("created_at").unwrap())
+            .collect::<Vec<i32>>();
+        assert_eq!(skipped, vec![15, 16]);
+        assert_eq!(metrics.scanned_doc_count(), 3 + 7);
+
+        // the collection scan is stopped by the limit too
+        let tagged = col
+            .find(doc! { "tag": "event" })
+            .limit(4)
+            .run()
+            .unwrap()
+            .count();
+        assert_eq!(tagged, 4);
+        assert_eq!(metrics.scanned_doc_count(), 3 + 7 + 4);
+    });
+}
+
+#[test]
+fn test_sort_missing_values_with_index() {
+    let db = prepare_db("test-sort-missing-values-with-index").unwrap();
+    let metrics = db.metrics();
+    metrics.enable();
+
+    let docs = vec![
+        doc! { "_id": 1, "created_at": 3 },
+        doc! { "_id": 2 },
+        doc! { "_id": 3, "created_at": 1 },
+        doc! { "_id": 4, "created_at": null },
+        doc! { "_id": 5, "created_at": 2 },
+        doc! { "_id": 6 },
+    ];
+    let scanned = db.collection::<Document>("scanned");
+    scanned.insert_many(docs.clone()).unwrap();
+    let indexed = db.collection::<Document>("indexed");
+    indexed.insert_many(docs).unwrap();
+    indexed.create_index(IndexModel {
+        keys: doc! { "created_at": 1 },
+        options: None,
+    }).unwrap();
+
+    let find_sorted = |col: &polodb_core::Collection<Document>, order: i32| -> Vec<Document> {
+        col.find(doc! {})
+            .sort(doc! { "created_at": order })
+            .run()
+            .unwrap()
+            .collect::<Result<Vec<Document>>>()
+            .unwrap()
+    };
+
+    // the missing values are sorted as null, before the numbers
+    let count = metrics.find_by_index_count();
+    let ascending = find_sorted(&indexed, 1);
+    assert_eq!(metrics.find_by_index_count(), count + 1);
+    assert_eq!(find_sorted(&scanned, 1), ascending);
+    let ids = ascending.iter().map(|doc| doc.get_i32("_id").unwrap()).collect::<Vec<i32>>();
+    assert_eq!(ids, vec![2, 4, 6, 3, 5, 1]);
+
+    let sort_values = |docs: Vec<Document>| -> Vec<Bson> {
+        docs.iter()
+            .map(|doc| doc.get("created_at").cloned().unwrap_or(Bson::Null))
+            .collect()
+    };
+    let descending = sort_values(find_sorted(&indexed, -1));
+    assert_eq!(metrics.find_by_index_count(), count + 2);
+    assert_eq!(sort_values(find_sorted(&scanned, -1)), descending);
+    assert_eq!(descending, vec![
+        Bson::Int32(3), Bson::Int32(2), Bson::Int32(1), Bson::Null, Bson::Null, Bson::Null,
+    ]);
+}
+
+#[test]
+fn test_index_with_mixed_numbers() {
+    vec![
+        prepare_db("test-index-with-mixed-numbers").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("scores");
+        col.create_index(IndexModel {
+            keys: doc! {
+                "score": 1,
+            },
+            options: None,
+        }).unwrap();
+
+        col.insert_many(vec![
+            doc! { "name": "a", "score": 2 },
+            doc! { "name": "b", "score": -2.5 },
+            doc! { "name": "c", "score": 9007199254740993_i64 },
+            doc! { "name": "d", "score": -1_i64 },
+            doc! { "name": "e", "score": 1.5 },
+            doc! { "name": "f", "score": 9007199254740992.0 },
+            doc! { "name": "g", "score": -3 },
+        ]).unwrap();
+
+        let names = |pipeline: Vec<Document>| {
+            col.aggregate(pipeline)
+                .run()
+                .unwrap()
+                .map(|doc| doc.unwrap().get_str("name").unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(
+            names(vec![doc! { "$sort": { "score": 1 } }]),
+            vec!["g", "b", "d", "e", "a", "f", "c"],
+        );
+        assert_eq!(
+            names(vec![
+                doc! { "$match": { "score": { "$gte": -2.5, "$lt": 2 } } },
+                doc! { "$sort": { "score": -1 } },
+            ]),
+            vec!["e", "d", "b"],
+        );
+        assert_eq!(metrics.find_by_index_count(), 2);
+
+        // the numbers of different types are equal in the index
+        assert_eq!(names(vec![doc! { "$match": { "score": 2.0 } }]), vec!["a"]);
+        assert_eq!(names(vec![doc! { "$match": { "score": -1 } }]), vec!["d"]);
+        assert_eq!(metrics.find_by_index_count(), 4);
+    });
+}
+
+#[test]
+fn test_index_order() {
+    vec![
+        prepare_db("test-index-order").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("teacher");
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "age": 1,
+            },
+            options: None,
+        }).unwrap();
+
+        col.insert_many(vec![
+            doc! {
+                "name": "David",
+                "age": 23,
+            },
+            doc! {
+                "name": "John",
+            },
+            doc! {
+                "name": "Dick",
+                "age": 23,
+            }
+        ]).unwrap();
+
+        let people23 = col
+            .find(doc! {
+                "age": 23
+            })
+            .run()
+            .unwrap()
+            .collect::<Result<Vec<Document>>>()
+            .unwrap();
+
+        assert_eq!(people23.len(), 2);
+    });
+}
+
+#[test]
+fn test_create_unique_index() {
+    vec![
+        prepare_db("test-create-unique-index").unwrap(),
+    ].iter().for_each(|db| {
+        let col = db.collection("teacher");
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "name": 1,
+            },
+            options: Some(IndexOptions{
+                unique: Some(true),
+                ..Default::default()
+            }),
+        }).unwrap();
+
+        col.insert_one(doc! {
+            "name": "David",
+            "age": 33,
+        }).unwrap();
+
+        let result = col.insert_one(doc! {
+            "name": "David",
+            "age": 33,
+        });
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("duplicate key error"));
+    });
+}
+
+#[test]
+fn test_update_with_index() {
+    vec![
+        prepare_db("test-update-with-index").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection("teacher");
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "age": 1,
+            },
+            options: None,
+        }).unwrap();
+
+        col.insert_one(doc! {
+            "name": "David",
+            "age": 33,
+        }).unwrap();
+
+        col.update_many(doc! {
+            "age": 33,
+        }, doc! {
+            "$set": {
+                "age": 34,
+            },
+        }).unwrap();
+
+        let doc = col.find_one(doc! {
+            "age": 34
+        }).unwrap().unwrap();
+
+        assert_eq!(doc.get_str("name").unwrap(), "David");
+
+        assert_eq!(metrics.find_by_index_count(), 1);
+    });
+}
+
+#[test]
+fn test_delete_with_index() {
+    vec![
+        prepare_db("test-delete-with-index").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("teacher");
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "age": 1,
+            },
+            options: None,
+        }).unwrap();
+
+        col.insert_one(doc! {
+            "name": "David",
+            "age": 33,
+        }).unwrap();
+
+        let result = col.delete_many(doc! {
+            "age": 33,
+        }).unwrap();
+
+        assert_eq!(result.deleted_count, 1);
+
+        let count = col.count_documents().unwrap();
+        assert_eq!(count, 0);
+
+        let result = col.find_one(doc! {
+            "age": 33
+        }).unwrap();
+        assert_eq!(result, None);
+
+        assert!(col.find_one(doc! {
+            "age": 33
+        }).unwrap().is_none());
+    });
+}
+
+#[test]
+fn test_drop_index() {
+    vec![
+        prepare_db("test-drop-index").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("teacher");
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "age": 1,
+            },
+            options: None,
+        }).unwrap();
+
+        col.insert_one(doc! {
+            "name": "David",
+            "age": 33,
+        }).unwrap();
+
+        col.drop_index("age_1").unwrap();
+
+        {
+            let doc = col.find_one(doc! {
+                "age": 33,
+            }).unwrap().unwrap();
+
+            assert_eq!(doc.get_str("name").unwrap(), "David");
+        }
+
+        assert_eq!(metrics.find_by_index_count(), 0);
+    });
+}
+
+#[test]
+fn test_partial_unique_index() {
+    vec![
+        prepare_db("test-partial-unique-index").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("users");
+
+        col.insert_one(doc! {
+            "name": "David",
+            "deleted": true,
+        }).unwrap();
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "name": 1,
+            },
+            options: Some(IndexOptions {
+                unique: Some(true),
+                partial_filter_expression: Some(doc! {
+                    "deleted": false,
+                }),
+                ..Default::default()
+            }),
+        }).unwrap();
+
+        // the deleted rows are not constrained
+        col.insert_one(doc! {
+            "name": "David",
+            "deleted": true,
+        }).unwrap();
+        col.insert_one(doc! {
+            "name": "David",
+            "deleted": false,
+        }).unwrap();
+
+        let result = col.insert_one(doc! {
+            "name": "David",
+            "deleted": false,
+        });
+        assert!(matches!(result, Err(polodb_core::Error::DuplicateKey(_))));
+
+        // the query doesn't imply the filter
+        let docs = col.find(doc! {
+            "name": "David",
+        }).run().unwrap().collect::<Result<Vec<Document>>>().unwrap();
+        assert_eq!(docs.len(), 3);
+        assert_eq!(metrics.find_by_index_count(), 0);
+
+        let docs = col.find(doc! {
+            "name": "David",
+            "deleted": false,
+        }).run().unwrap().collect::<Result<Vec<Document>>>().unwrap();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(metrics.find_by_index_count(), 1);
+
+        let result = col.create_index(IndexModel {
+            keys: doc! {
+                "age": 1,
+            },
+            options: Some(IndexOptions {
+                partial_filter_expression: Some(doc! {
+                    "$or": [{ "deleted": false }],
+                }),
+                ..Default::default()
+            }),
+        });
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+    });
+}
+
+#[test]
+fn test_sparse_index() {
+    vec![
+        prepare_db("test-sparse-index").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("users");
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "email": 1,
+            },
+            options: Some(IndexOptions {
+                unique: Some(true),
+                sparse: Some(true),
+                ..Default::default()
+            }),
+        }).unwrap();
+
+        // the documents without the key are not constrained
+        col.insert_many(vec![
+            doc! { "name": "David" },
+            doc! { "name": "John" },
+            doc! { "name": "Alice", "email": "alice@example.com" },
+            doc! { "name": "Bob", "email": "bob@example.com" },
+        ]).unwrap();
+
+        let names = col.find(doc! {})
+            .sort(doc! {
+                "email": 1,
+            })
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap().get_str("name").unwrap().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(names.len(), 4);
+        assert_eq!(metrics.find_by_index_count(), 0);
+
+        let names = col.find(doc! {
+            "email": {
+                "$gt": "b",
+            },
+        })
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap().get_str("name").unwrap().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(names, vec!["Bob"]);
+        assert_eq!(metrics.find_by_index_count(), 1);
+    });
+}
+
+#[test]
+fn test_ttl_index() {
+    let mut config_builder = ConfigBuilder::new();
+    config_builder.set_ttl_monitor_sleep_secs(1);
+    let db = prepare_db_with_config("test-ttl-index", config_builder.take()).unwrap();
+
+    let col = db.collection::<Document>("sessions");
+    col.create_index(IndexModel {
+        keys: doc! {
+            "last_access": 1,
+        },
+        options: Some(IndexOptions {
+            expire_after_seconds: Some(0),
+            ..Default::default()
+        }),
+    }).unwrap();
+
+    col.insert_many(vec![
+        doc! {
+            "user": "David",
+            "last_access": DateTime::from_millis(DateTime::now().timestamp_millis() - 1000),
+        },
+        doc! {
+            "user": "John",
+        },
+    ]).unwrap();
+
+    let started = Instant::now();
+    while col.count_documents().unwrap() > 1 {
+        assert!(started.elapsed() < Duration::from_secs(10), "the expired document is not removed");
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    let doc = col.find_one(doc! {}).unwrap().unwrap();
+    assert_eq!(doc.get_str("user").unwrap(), "John");
+}
+
+#[test]
+fn test_multikey_index() {
+    vec![
+        prepare_db("test-multikey-index").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("articles");
+        col.insert_many(vec![
+            doc! { "_id": 1, "tenant": "a", "tags": ["rust", "database"] },
+            doc! { "_id": 2, "tenant": "a", "tags": ["go", "rust", "rust"] },
+            doc! { "_id": 3, "tenant": "b", "tags": "rust" },
+            doc! { "_id": 4, "tenant": "a", "tags": [] },
+        ]).unwrap();
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "tenant": 1,
+                "tags": 1,
+            },
+            options: None,
+        }).unwrap();
+
+        let find_ids = |query: Document| -> Vec<i32> {
+            col.find(query)
+                .run()
+                .unwrap()
+                .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+                .collect()
+        };
+
+        let mut ids = find_ids(doc! { "tenant": "a", "tags": "rust" });
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(metrics.find_by_index_count(), 1);
+
+        // every document is returned once
+        let mut ids = find_ids(doc! { "tenant": "a" });
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 4]);
+        assert_eq!(metrics.find_by_index_count(), 2);
+
+        col.update_one(doc! { "_id": 1 }, doc! {
+            "$set": { "tags": ["database"] },
+        }).unwrap();
+        col.delete_one(doc! { "_id": 2 }).unwrap();
+        assert!(find_ids(doc! { "tenant": "a", "tags": "rust" }).is_empty());
+        assert_eq!(find_ids(doc! { "tenant": "a", "tags": "database" }), vec![1]);
+
+        let result = col.insert_one(doc! {
+            "tenant": ["a", "b"],
+            "tags": ["rust"],
+        });
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+    });
+}
+
+#[test]
+fn test_nested_array_index() {
+    let db = prepare_db("test-nested-array-index").unwrap();
+    let metrics = db.metrics();
+    metrics.enable();
+
+    let docs = vec![
+        doc! { "_id": 1, "tags": [[1, 2], 3], "items": [{ "tags": [4, [5]] }] },
+        doc! { "_id": 2, "tags": [1, [3]], "items": [{ "tags": 4 }, { "tags": [5, 6] }] },
+        doc! { "_id": 3, "tags": [[1]], "items": [] },
+    ];
+    let scanned = db.collection::<Document>("scanned");
+    scanned.insert_many(docs.clone()).unwrap();
+    let indexed = db.collection::<Document>("indexed");
+    indexed.insert_many(docs).unwrap();
+    for key in ["tags", "items.tags"] {
+        indexed.create_index(IndexModel {
+            keys: doc! { key: 1 },
+            options: None,
+        }).unwrap();
+    }
+
+    let find_ids = |col: &polodb_core::Collection<Document>, query: Document| -> Vec<i32> {
+        let mut ids: Vec<i32> = col.find(query)
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+            .collect();
+        ids.sort();
+        ids
+    };
+
+    // the nested arrays are not traversed by the queries, with or without the index
+    let queries = vec![
+        (doc! { "tags": 1 }, vec![2]),
+        (doc! { "tags": 3 }, vec![1]),
+        (doc! { "items.tags": 5 }, vec![2]),
+        (doc! { "items.tags": 4 }, vec![1, 2]),
+    ];
+    for (query, expected) in queries {
+        assert_eq!(find_ids(&scanned, query.clone()), expected, "{}", query);
+        let count = metrics.find_by_index_count();
+        assert_eq!(find_ids(&indexed, query.clone()), expected, "{}", query);
+        assert_eq!(metrics.find_by_index_count(), count + 1, "{}", query);
+    }
+
+    // the nested arrays are matched as the values
+    let query = doc! { "tags": { "$in": [[1], [3]] } };
+    assert_eq!(find_ids(&scanned, query.clone()), vec![2, 3]);
+    assert_eq!(find_ids(&indexed, query), vec![2, 3]);
+}
+
+#[test]
+fn test_unique_multikey_index() {
+    vec![
+        prepare_db("test-unique-multikey-index").unwrap(),
+    ].iter().for_each(|db| {
+        let col = db.collection::<Document>("users");
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "emails": 1,
+            },
+            options: Some(IndexOptions {
+                unique: Some(true),
+                ..Default::default()
+            }),
+        }).unwrap();
+
+        // the same element in an array is not a duplicate
+        col.insert_one(doc! {
+            "name": "David",
+            "emails": ["david@example.com", "david@example.com", "dave@example.com"],
+        }).unwrap();
+
+        let result = col.insert_one(doc! {
+            "name": "Dave",
+            "emails": ["dave@example.com"],
+        });
+        assert!(matches!(result, Err(polodb_core::Error::DuplicateKey(_))));
+
+        col.insert_one(doc! {
+            "name": "John",
+            "emails": "john@example.com",
+        }).unwrap();
+
+        let doc = col.find_one(doc! {
+            "emails": "dave@example.com",
+        }).unwrap().unwrap();
+        assert_eq!(doc.get_str("name").unwrap(), "David");
+    });
+}
+
+#[test]
+fn test_text_index() {
+    vec![
+        prepare_db("test-text-index").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("notes");
+        col.insert_many(vec![
+            doc! { "_id": 1, "title": "Coffee", "body": "Brewing coffee with fresh beans", "folder": "home" },
+            doc! { "_id": 2, "title": "Tea", "body": "Green tea and coffee", "folder": "work" },
+            doc! { "_id": 3, "title": "Groceries", "body": ["milk", "bread", "tea leaves"], "folder": "home" },
+            doc! { "_id": 4, "title": "Meeting", "body": "Quarterly planning", "folder": "work" },
+        ]).unwrap();
+
+        let result = col.find(doc! { "$text": { "$search": "coffee" } }).run();
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "title": "text",
+                "body": "text",
+            },
+            options: Some(IndexOptions {
+                weights: Some(doc! { "title": 2 }),
+                ..Default::default()
+            }),
+        }).unwrap();
+
+        let result = col.create_index(IndexModel {
+            keys: doc! {
+                "folder": "text",
+            },
+            options: None,
+        });
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+
+        let find_ids = |query: Document| -> Vec<i32> {
+            col.find(query)
+                .run()
+                .unwrap()
+                .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+                .collect()
+        };
+
+        // the documents are returned in the descending order of the scores,
+        // and the score is not a field of the results
+        let docs: Vec<Document> = col.find(doc! { "$text": { "$search": "brewed coffees" } })
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap())
+            .collect();
+        assert_eq!(docs.iter().map(|doc| doc.get_i32("_id").unwrap()).collect::<Vec<i32>>(), vec![1, 2]);
+        assert!(!docs[0].contains_key("$textScore"));
+        assert_eq!(metrics.find_by_index_count(), 1);
+
+        assert_eq!(find_ids(doc! { "$text": { "$search": "TEA" } }), vec![2, 3]);
+        assert_eq!(find_ids(doc! { "$text": { "$search": "tea -coffee" } }), vec![3]);
+        assert_eq!(find_ids(doc! { "$text": { "$search": "\"tea leaves\"" } }), vec![3]);
+        assert_eq!(find_ids(doc! { "$text": { "$search": "tea" }, "folder": "work" }), vec![2]);
+        assert!(find_ids(doc! { "$text": { "$search": "the" } }).is_empty());
+
+        let docs: Vec<Document> = col.aggregate(vec![
+            doc! { "$match": { "$text": { "$search": "coffee tea" } } },
+            doc! { "$addFields": { "score": { "$meta": "textScore" } } },
+            doc! { "$sort": { "score": { "$meta": "textScore" } } },
+        ])
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap())
+            .collect();
+        assert_eq!(docs.len(), 3);
+        assert_eq!(docs[0].get_i32("_id").unwrap(), 2);
+        let scores: Vec<f64> = docs.iter().map(|doc| doc.get_f64("score").unwrap()).collect();
+        assert!(scores[0] >= scores[1] && scores[1] >= scores[2]);
+
+        col.update_one(doc! { "_id": 4 }, doc! {
+            "$set": { "body": "Coffee break" },
+        }).unwrap();
+        col.delete_one(doc! { "_id": 1 }).unwrap();
+        let mut ids = find_ids(doc! { "$text": { "$search": "coffee" } });
+        ids.sort();
+        assert_eq!(ids, vec![2, 4]);
+
+        let result = col.delete_many(doc! { "$text": { "$search": "coffee" } });
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+    });
+}
+
+#[test]
+fn test_2dsphere_index() {
+    vec![
+        prepare_db("test-2dsphere-index").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("places");
+        col.insert_many(vec![
+            doc! { "_id": 1, "name": "Central Park", "location": { "type": "Point", "coordinates": [-73.9654, 40.7829] }, "kind": "park" },
+            doc! { "_id": 2, "name": "Times Square", "location": { "type": "Point", "coordinates": [-73.9855, 40.7580] }, "kind": "square" },
+            doc! { "_id": 3, "name": "Brooklyn Bridge", "location": [-73.9969, 40.7061], "kind": "bridge" },
+            doc! { "_id": 4, "name": "Statue of Liberty", "location": { "type": "Point", "coordinates": [-74.0445, 40.6892] }, "kind": "monument" },
+            doc! { "_id": 5, "name": "No location" },
+        ]).unwrap();
+
+        let near = |max_distance: Option<i32>| -> Document {
+            let mut near = doc! {
+                "$geometry": { "type": "Point", "coordinates": [-73.9851, 40.7589] },
+            };
+            if let Some(max_distance) = max_distance {
+                near.insert("$maxDistance", max_distance);
+            }
+            doc! { "location": { "$near": near } }
+        };
+
+        let result = col.find(near(None)).run();
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "location": "2dsphere",
+            },
+            options: None,
+        }).unwrap();
+
+        let result = col.insert_one(doc! { "location": "here" });
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+
+        let find_ids = |query: Document| -> Vec<i32> {
+            col.find(query)
+                .run()
+                .unwrap()
+                .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+                .collect()
+        };
+
+        // sorted by the distance
+        assert_eq!(find_ids(near(None)), vec![2, 1, 3, 4]);
+        assert_eq!(metrics.find_by_index_count(), 1);
+        assert_eq!(find_ids(near(Some(7000))), vec![2, 1, 3]);
+
+        let mut query = near(Some(7000));
+        query.insert("kind", "park");
+        assert_eq!(find_ids(query), vec![1]);
+
+        let mut ids = find_ids(doc! {
+            "location": {
+                "$geoWithin": {
+                    "$geometry": {
+                        "type": "Polygon",
+                        "coordinates": [[[-74.0, 40.70], [-73.95, 40.70], [-73.95, 40.80], [-74.0, 40.80], [-74.0, 40.70]]],
+                    },
+                },
+            },
+        });
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        // 0.0008 radians is about 5.1 km
+        let mut ids = find_ids(doc! {
+            "location": {
+                "$geoWithin": { "$centerSphere": [[-73.9851, 40.7589], 0.0008] },
+            },
+        });
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+
+        assert_eq!(find_ids(doc! {
+            "location": {
+                "$geoIntersects": { "$geometry": { "type": "Point", "coordinates": [-73.9969, 40.7061] } },
+            },
+        }), vec![3]);
+
+        col.update_one(doc! { "_id": 4 }, doc! {
+            "$set": { "location": { "type": "Point", "coordinates": [-73.9850, 40.7590] } },
+        }).unwrap();
+        col.delete_one(doc! { "_id": 2 }).unwrap();
+        assert_eq!(find_ids(near(Some(1000))), vec![4]);
+    });
+}
+
+#[test]
+fn test_vector_index() {
+    vec![
+        prepare_db("test-vector-index").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        // the points of a 4x4 grid, the ids are odd or even in turn
+        let col = db.collection::<Document>("points");
+        let docs: Vec<Document> = (0..16).map(|i| {
+            let id = i + 1;
+            doc! {
+                "_id": id,
+                "embedding": [(i / 4 + 1) as f64, (i % 4 + 1) as f64],
+                "category": if id % 2 == 1 { "odd" } else { "even" },
+            }
+        }).collect();
+        col.insert_many(docs).unwrap();
+
+        let search = |stage: Document| -> Result<Vec<Document>> {
+            col.aggregate(vec![
+                doc! { "$vectorSearch": stage },
+                doc! { "$addFields": { "score": { "$meta": "vectorSearchScore" } } },
+            ])
+                .run()?
+                .collect()
+        };
+        let search_ids = |stage: Document| -> Vec<i32> {
+            search(stage)
+                .unwrap()
+                .iter()
+                .map(|doc| doc.get_i32("_id").unwrap())
+                .collect()
+        };
+
+        let result = search(doc! { "path": "embedding", "queryVector": [1.1, 1.0], "limit": 3 });
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "embedding": "vector",
+            },
+            options: Some(IndexOptions {
+                dimensions: Some(2),
+                similarity: Some("euclidean".into()),
+                ..Default::default()
+            }),
+        }).unwrap();
+
+        let result = col.insert_one(doc! { "embedding": [1.0, 2.0, 3.0] });
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+
+        let docs = search(doc! {
+            "path": "embedding",
+            "queryVector": [1.1, 1.0],
+            "numCandidates": 16,
+            "limit": 3,
+        }).unwrap();
+        assert_eq!(docs.iter().map(|doc| doc.get_i32("_id").unwrap()).collect::<Vec<i32>>(), vec![1, 5, 2]);
+        assert!(!docs[0].contains_key("$vectorSearchScore"));
+        assert!((docs[0].get_f64("score").unwrap() - 1.0 / 1.01).abs() < 1e-9);
+        assert_eq!(metrics.find_by_index_count(), 1);
+
+        assert_eq!(search_ids(doc! {
+            "path": "embedding",
+            "queryVector": [1.1, 1.0],
+            "numCandidates": 16,
+            "limit": 3,
+            "filter": { "category": "odd" },
+        }), vec![1, 5, 9]);
+
+        // the default candidates are 10 times of the limit
+        assert_eq!(search_ids(doc! { "path": "embedding", "queryVector": [4.0, 4.0], "limit": 2 })[0], 16);
+
+        let result = search(doc! { "path": "embedding", "queryVector": [1.0], "limit": 3 });
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+        let result = search(doc! { "path": "embedding", "queryVector": [1.0, 1.0], "numCandidates": 2, "limit": 3 });
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+        let result = search(doc! { "path": "embedding", "queryVector": [1.0, 1.0], "limit": 3, "index": "other" });
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+
+        let result = col.aggregate(vec![
+            doc! { "$match": { "category": "odd" } },
+            doc! { "$vectorSearch": { "path": "embedding", "queryVector": [1.0, 1.0], "limit": 3 } },
+        ]).run();
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+
+        // the index is updated with the documents
+        col.delete_one(doc! { "_id": 1 }).unwrap();
+        col.update_one(doc! { "_id": 16 }, doc! {
+            "$set": { "embedding": [1.0, 1.0] },
+        }).unwrap();
+        col.insert_one(doc! { "_id": 17, "embedding": [9.0, 9.0] }).unwrap();
+        let query = doc! {
+            "path": "embedding",
+            "queryVector": [1.1, 1.0],
+            "numCandidates": 17,
+            "limit": 2,
+        };
+        assert_eq!(search_ids(query), vec![16, 5]);
+        assert_eq!(search_ids(doc! { "path": "embedding", "queryVector": [9.0, 9.0], "numCandidates": 17, "limit": 1 }), vec![17]);
+    });
+}
+
+#[test]
+fn test_hashed_index() {
+    vec![
+        prepare_db("test-hashed-index").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("pages");
+        col.insert_many(vec![
+            doc! { "_id": 1, "url": "https://example.com/a", "size": 1 },
+            doc! { "_id": 2, "url": "https://example.com/b", "size": 2 },
+            doc! { "_id": 3, "url": "https://example.com/a", "size": 3 },
+            doc! { "_id": 4, "size": 4 },
+        ]).unwrap();
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "url": "hashed",
+            },
+            options: None,
+        }).unwrap();
+
+        let result = col.create_index(IndexModel {
+            keys: doc! {
+                "size": "hashed",
+            },
+            options: Some(IndexOptions {
+                unique: Some(true),
+                ..Default::default()
+            }),
+        });
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+
+        let result = col.insert_one(doc! { "url": ["https://example.com/c"] });
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+
+        let find_ids = |query: Document| -> Vec<i32> {
+            let mut ids: Vec<i32> = col.find(query)
+                .run()
+                .unwrap()
+                .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(find_ids(doc! { "url": "https://example.com/a" }), vec![1, 3]);
+        assert_eq!(metrics.find_by_index_count(), 1);
+        assert_eq!(find_ids(doc! { "url": { "$eq": "https://example.com/b" } }), vec![2]);
+        assert_eq!(find_ids(doc! { "url": { "$in": ["https://example.com/b", "https://example.com/z"] } }), vec![2]);
+        assert_eq!(metrics.find_by_index_count(), 3);
+        assert_eq!(find_ids(doc! { "url": "https://example.com/a", "size": { "$gt": 1 } }), vec![3]);
+
+        // the ranges can't be searched by the hashes
+        assert_eq!(find_ids(doc! { "url": { "$gt": "https://example.com/a" } }), vec![2]);
+        assert_eq!(metrics.find_by_index_count(), 4);
+
+        col.update_one(doc! { "_id": 1 }, doc! {
+            "$set": { "url": "https://example.com/b" },
+        }).unwrap();
+        col.delete_one(doc! { "_id": 3 }).unwrap();
+        assert!(find_ids(doc! { "url": "https://example.com/a" }).is_empty());
+        assert_eq!(find_ids(doc! { "url": "https://example.com/b" }), vec![1, 2]);
+    });
+}
+
+#[test]
+fn test_wildcard_index() {
+    vec![
+        prepare_db("test-wildcard-index").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("products");
+        col.insert_many(vec![
+            doc! { "_id": 1, "name": "lamp", "attrs": { "color": "red", "size": { "w": 10 } } },
+            doc! { "_id": 2, "name": "desk", "attrs": { "color": "blue", "legs": 4 } },
+            doc! { "_id": 3, "name": "chair", "attrs": { "color": ["red", "black"], "legs": 3 } },
+            doc! { "_id": 4, "name": "shelf", "attrs": { "scores": [1, 100] } },
+            doc! { "_id": 5, "name": "rug" },
+        ]).unwrap();
+
+        col.create_index(IndexModel {
+            keys: doc! {
+                "attrs.$**": 1,
+            },
+            options: None,
+        }).unwrap();
+
+        let result = col.create_index(IndexModel {
+            keys: doc! {
+                "$**": -1,
+            },
+            options: None,
+        });
+        assert!(result.is_err());
+
+        let find_ids = |query: Document| -> Vec<i32> {
+            let mut ids: Vec<i32> = col.find(query)
+                .run()
+                .unwrap()
+                .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(find_ids(doc! { "attrs.color": "red" }), vec![1, 3]);
+        assert_eq!(metrics.find_by_index_count(), 1);
+        assert_eq!(find_ids(doc! { "attrs.size.w": 10 }), vec![1]);
+        assert_eq!(find_ids(doc! { "attrs.legs": { "$gte": 4 } }), vec![2]);
+        assert_eq!(find_ids(doc! { "attrs.color": { "$in": ["blue", "green"] } }), vec![2]);
+        assert_eq!(find_ids(doc! { "attrs.color": "red", "name": "chair" }), vec![3]);
+        assert_eq!(metrics.find_by_index_count(), 5);
+
+        // the elements of an array may match the bounds separately
+        assert_eq!(find_ids(doc! { "attrs.legs": { "$gt": 2, "$lt": 4 } }), vec![3]);
+        // the missing values are not indexed
+        assert!(find_ids(doc! { "attrs.legs": null }).is_empty());
+        assert_eq!(find_ids(doc! { "name": "rug" }), vec![5]);
+        assert_eq!(metrics.find_by_index_count(), 5);
+
+        col.update_one(doc! { "_id": 2 }, doc! {
+            "$set": { "attrs": { "color": "red", "legs": 4 } },
+        }).unwrap();
+        col.delete_one(doc! { "_id": 1 }).unwrap();
+        col.insert_one(doc! { "_id": 6, "attrs": { "material": "oak" } }).unwrap();
+        assert_eq!(find_ids(doc! { "attrs.color": "red" }), vec![2, 3]);
+        assert_eq!(find_ids(doc! { "attrs.material": "oak" }), vec![6]);
+    });
+}
+
+#[test]
+fn test_background_index() {
+    let mut config_builder = ConfigBuilder::new();
+    config_builder.set_index_build_chunk_size(3);
+    let db = prepare_db_with_config("test-background-index", config_builder.take()).unwrap();
+    let metrics = db.metrics();
+    metrics.enable();
+
+    let col = db.collection::<Document>("orders");
+    col.insert_many((0..10).map(|i| doc! {
+        "_id": i,
+        "amount": i % 4,
+        "tags": [format!("t{}", i % 3)],
+    })).unwrap();
+
+    col.create_index(IndexModel {
+        keys: doc! {
+            "amount": 1,
+        },
+        options: Some(IndexOptions {
+            background: Some(true),
+            ..Default::default()
+        }),
+    }).unwrap();
+    assert_eq!(col.index_build_progress("amount_1").unwrap(), None);
+    assert_eq!(col.index_build_progress("not_exist").unwrap(), None);
+
+    let find_ids = |query: Document| -> Vec<i32> {
+        let mut ids: Vec<i32> = col.find(query)
+            .run()
+            .unwrap()
+            .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+            .collect();
+        ids.sort();
+        ids
+    };
+
+    assert_eq!(find_ids(doc! { "amount": 1 }), vec![1, 5, 9]);
+    assert_eq!(metrics.find_by_index_count(), 1);
+
+    col.insert_one(doc! { "_id": 10, "amount": 1 }).unwrap();
+    col.delete_one(doc! { "_id": 5 }).unwrap();
+    assert_eq!(find_ids(doc! { "amount": 1 }), vec![1, 9, 10]);
+
+    // the multikey flag is collected from the chunks
+    col.create_index(IndexModel {
+        keys: doc! {
+            "tags": 1,
+        },
+        options: Some(IndexOptions {
+            background: Some(true),
+            ..Default::default()
+        }),
+    }).unwrap();
+    assert_eq!(find_ids(doc! { "tags": "t2" }), vec![2, 8]);
+
+    // the keys written by the committed chunks are removed if the build fails
+    let result = col.create_index(IndexModel {
+        keys: doc! {
+            "amount": -1,
+        },
+        options: Some(IndexOptions {
+            unique: Some(true),
+            background: Some(true),
+            ..Default::default()
+        }),
+    });
+    assert!(result.is_err());
+    assert_eq!(col.index_build_progress("amount_-1").unwrap(), None);
+    col.create_index(IndexModel {
+        keys: doc! {
+            "amount": -1,
+        },
+        options: None,
+    }).unwrap();
+    assert_eq!(find_ids(doc! { "amount": 3 }), vec![3, 7]);
+}
+
+#[test]
+fn test_background_index_with_concurrent_writer() {
+    let mut config_builder = ConfigBuilder::new();
+    config_builder.set_index_build_chunk_size(3);
+    let db = prepare_db_with_config("test-background-index-concurrent-writer", config_builder.take()).unwrap();
+    let db = std::sync::Arc::new(db);
+    let metrics = db.metrics();
+    metrics.enable();
+
+    let col = db.collection::<Document>("orders");
+    col.insert_many((0..10).map(|i| doc! {
+        "_id": i,
+        "amount": i % 4,
+    })).unwrap();
+
+    // the writer reads the collection before the index is added
+    let txn = db.start_transaction().unwrap();
+    txn.collection::<Document>("orders").insert_one(doc! { "_id": 100, "amount": 1 }).unwrap();
+
+    let build = {
+        let db = db.clone();
+        std::thread::spawn(move || {
+            db.collection::<Document>("orders").create_index(IndexModel {
+                keys: doc! {
+                    "amount": 1,
+                },
+                options: Some(IndexOptions {
+                    background: Some(true),
+                    ..Default::default()
+                }),
+            })
+        })
+    };
+
+    // the build waits for the writer
+    std::thread::sleep(Duration::from_millis(100));
+    txn.commit().unwrap();
+    build.join().unwrap().unwrap();
+
+    let mut ids: Vec<i32> = col.find(doc! { "amount": 1 })
+        .run()
+        .unwrap()
+        .map(|doc| doc.unwrap().get_i32("_id").unwrap())
+        .collect();
+    ids.sort();
+    assert_eq!(ids, vec![1, 5, 9, 100]);
+    assert_eq!(metrics.find_by_index_count(), 1);
+}
+
+#[test]
+fn test_list_indexes() {
+    vec![
+        prepare_db("test-list-indexes").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("products");
+        assert!(col.list_indexes().unwrap().is_empty());
+
+        col.insert_many(vec![
+            doc! { "_id": 1, "sku": "a", "price": 10, "colors": ["red", "blue"], "desc": "soft cotton" },
+            doc! { "_id": 2, "sku": "b", "price": 20, "colors": ["green"], "desc": "warm wool" },
+        ]).unwrap();
+
+        col.create_indexes(vec![
+            IndexModel {
+                keys: doc! { "sku": 1 },
+                options: Some(IndexOptions {
+                    unique: Some(true),
+                    ..Default::default()
+                }),
+            },
+            IndexModel {
+                keys: doc! { "colors": 1, "price": -1 },
+                options: None,
+            },
+            IndexModel {
+                keys: doc! { "desc": "text" },
+                options: Some(IndexOptions {
+                    name: Some("desc_search".into()),
+                    ..Default::default()
+                }),
+            },
+            IndexModel {
+                keys: doc! { "sku": 1 },
+                options: None,
+            },
+        ]).unwrap();
+
+        assert_eq!(col.list_index_names().unwrap(), vec!["sku_1", "colors_1_price_-1", "desc_search"]);
+        let indexes = col.list_indexes().unwrap();
+        assert_eq!(indexes[0], IndexModel {
+            keys: doc! { "sku": 1 },
+            options: Some(IndexOptions {
+                name: Some("sku_1".into()),
+                unique: Some(true),
+                ..Default::default()
+            }),
+        });
+        assert_eq!(indexes[1].keys, doc! { "colors": 1, "price": -1 });
+        assert_eq!(indexes[2].keys, doc! { "desc": "text" });
+
+        // the listed models create the same indexes
+        let other = db.collection::<Document>("products_copy");
+        other.create_indexes(indexes.clone()).unwrap();
+        assert_eq!(other.list_indexes().unwrap(), indexes);
+
+        let count = col.find(doc! { "colors": "green" }).run().unwrap().count();
+        assert_eq!(count, 1);
+        let count = col.find(doc! { "$text": { "$search": "cotton" } }).run().unwrap().count();
+        assert_eq!(count, 1);
+        assert_eq!(metrics.find_by_index_count(), 2);
+
+        let result = col.create_indexes(vec![
+            IndexModel {
+                keys: doc! { "price": 1 },
+                options: None,
+            },
+            IndexModel {
+                keys: doc! { "sku": -1 },
+                options: Some(IndexOptions {
+                    unique: Some(true),
+                    ..Default::default()
+                }),
+            },
+            IndexModel {
+                keys: doc! { "colors": "text" },
+                options: None,
+            },
+        ]);
+        assert!(matches!(result, Err(polodb_core::Error::ValidationError(_))));
+        assert_eq!(col.list_index_names().unwrap().len(), 3);
+
+        col.drop_index("desc_search").unwrap();
+        assert_eq!(col.list_index_names().unwrap(), vec!["sku_1", "colors_1_price_-1"]);
+    });
+}
+
+#[test]
+fn test_explain() {
+    vec![
+        prepare_db("test-explain").unwrap(),
+    ].iter().for_each(|db| {
+        let col = db.collection::<Document>("users");
+        let explain = col.find(doc! { "age": 10 }).explain().unwrap();
+        assert_eq!(explain.get_document("queryPlanner").unwrap().get_document("winningPlan").unwrap(), &doc! {
+            "stage": "EOF",
+        });
+
+        col.insert_many((0..20).map(|i| doc! {
+            "_id": i,
+            "age": i % 5,
+            "name": format!("user{}", i),
+        })).unwrap();
+        col.create_index(IndexModel {
+            keys: doc! { "age": 1 },
+            options: None,
+        }).unwrap();
+        col.create_index(IndexModel {
+            keys: doc! { "name": 1 },
+            options: Some(IndexOptions {
+                sparse: Some(true),
+                ..Default::default()
+            }),
+        }).unwrap();
+
+        let explain = col.find(doc! { "age": 3, "name": { "$ne": "user3" } }).explain().unwrap();
+        let query_planner = explain.get_document("queryPlanner").unwrap();
+        assert_eq!(query_planner.get_str("namespace").unwrap(), "users");
+        let winning_plan = query_planner.get_document("winningPlan").unwrap();
+        assert_eq!(winning_plan.get_str("stage").unwrap(), "IXSCAN");
+        assert_eq!(winning_plan.get_str("indexName").unwrap(), "age_1");
+        assert_eq!(winning_plan.get_document("keyPattern").unwrap(), &doc! { "age": 1 });
+        assert_eq!(winning_plan.get_document("indexBounds").unwrap(), &doc! { "age": ["[3, 3]"] });
+        assert_eq!(winning_plan.get_str("direction").unwrap(), "forward");
+        assert_eq!(winning_plan.get_document("filter").unwrap(), &doc! { "name": { "$ne": "user3" } });
+        assert_eq!(query_planner.get_array("rejectedPlans").unwrap(), &vec![
+            doc! {
+                "indexName": "name_1",
+                "reason": "the index doesn't reference all the matching documents",
+            }.into(),
+        ]);
+        assert!(explain.get_str("program").unwrap().contains("Open"));
+        assert!(explain.get("executionStats").is_none());
+
+        let explain = col.find(doc! { "age": 3 }).explain_with_stats().unwrap();
+        assert_eq!(explain.get_document("executionStats").unwrap().get_i64("nReturned").unwrap(), 4);
+        assert_eq!(explain.get_document("executionStats").unwrap().get_i64("docsExamined").unwrap(), 4);
+
+        let explain = col.find(doc! { "name": { "$gt": "user1" } }).explain_with_stats().unwrap();
+        let query_planner = explain.get_document("queryPlanner").unwrap();
+        assert_eq!(query_planner.get_document("winningPlan").unwrap().get_str("indexName").unwrap(), "name_1");
+        assert_eq!(query_planner.get_array("rejectedPlans").unwrap(), &vec![
+            doc! {
+                "indexName": "age_1",
+                "reason": "no condition of the query can be searched by the index",
+            }.into(),
+        ]);
+
+        let explain = col.find(doc! { "_id": 7, "age": 2 }).explain_with_stats().unwrap();
+        let winning_plan = explain.get_document("queryPlanner").unwrap().get_document("winningPlan").unwrap();
+        assert_eq!(winning_plan, &doc! { "stage": "IDHACK", "_id": 7 });
+        assert_eq!(explain.get_document("executionStats").unwrap().get_i64("nReturned").unwrap(), 1);
+
+        let explain = col.find(doc! { "age": { "$gte": 3 } })
+            .sort(doc! { "age": -1 })
+            .limit(2)
+            .explain_with_stats()
+            .unwrap();
+        let winning_plan = explain.get_document("queryPlanner").unwrap().get_document("winningPlan").unwrap();
+        assert_eq!(winning_plan.get_str("direction").unwrap(), "backward");
+        assert!(winning_plan.get_bool("sortSatisfied").unwrap());
+        let stats = explain.get_document("executionStats").unwrap();
+        assert_eq!(stats.get_i64("nReturned").unwrap(), 2);
+        assert_eq!(stats.get_i64("docsExamined").unwrap(), 2);
+
+        let explain = col.aggregate(vec![
+            doc! { "$skip": 15 },
+        ]).explain_with_stats().unwrap();
+        let query_planner = explain.get_document("queryPlanner").unwrap();
+        assert_eq!(query_planner.get_document("winningPlan").unwrap(), &doc! { "stage": "COLLSCAN" });
+        assert_eq!(query_planner.get_array("rejectedPlans").unwrap().len(), 2);
+        let stats = explain.get_document("executionStats").unwrap();
+        assert_eq!(stats.get_i64("nReturned").unwrap(), 5);
+        assert_eq!(stats.get_i64("docsExamined").unwrap(), 20);
+    });
+}
+
+#[test]
+fn test_plan_by_cost() {
+    vec![
+        prepare_db("test-plan-by-cost").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("orders");
+        col.insert_many((0..200).map(|i| doc! {
+            "_id": i,
+            "category": if i < 190 { "a" } else { "b" },
+            "owner": i,
+        })).unwrap();
+        col.create_index(IndexModel {
+            keys: doc! { "category": 1 },
+            options: None,
+        }).unwrap();
+        col.create_index(IndexModel {
+            keys: doc! { "owner": 1 },
+            options: None,
+        }).unwrap();
+
+        // both the indexes match one key by equality, the one with fewer keys is chosen
+        let explain = col.find(doc! { "category": "a", "owner": 7 }).explain().unwrap();
+        let query_planner = explain.get_document("queryPlanner").unwrap();
+        let winning_plan = query_planner.get_document("winningPlan").unwrap();
+        assert_eq!(winning_plan.get_str("indexName").unwrap(), "owner_1");
+        assert_eq!(winning_plan.get_i64("estimatedKeys").unwrap(), 1);
+        assert!(!winning_plan.get_bool("fromPlanCache").unwrap());
+        assert_eq!(query_planner.get_array("rejectedPlans").unwrap(), &vec![
+            doc! {
+                "indexName": "category_1",
+                "reason": "another plan is preferred",
+                "estimatedKeys": 190_i64,
+            }.into(),
+        ]);
+
+        // the query of the same shape uses the cached plan
+        let explain = col.find(doc! { "category": "b", "owner": 195 }).explain().unwrap();
+        let winning_plan = explain.get_document("queryPlanner").unwrap().get_document("winningPlan").unwrap();
+        assert_eq!(winning_plan.get_str("indexName").unwrap(), "owner_1");
+        assert!(winning_plan.get_bool("fromPlanCache").unwrap());
+
+        // the cached plan is dropped after the indexes change
+        col.create_index(IndexModel {
+            keys: doc! { "owner": 1, "category": 1 },
+            options: None,
+        }).unwrap();
+        let explain = col.find(doc! { "category": "a", "owner": 7 }).explain().unwrap();
+        let winning_plan = explain.get_document("queryPlanner").unwrap().get_document("winningPlan").unwrap();
+        assert_eq!(winning_plan.get_str("indexName").unwrap(), "owner_1_category_1");
+        assert!(!winning_plan.get_bool("fromPlanCache").unwrap());
+        col.drop_index("owner_1_category_1").unwrap();
+
+        // every branch of `$or` is searched by its own index
+        let before = metrics.find_by_index_count();
+        let result = col.find(doc! { "$or": [{ "owner": 5 }, { "category": "b" }] })
+            .run()
+            .unwrap()
+            .collect::<Result<Vec<Document>>>()
+            .unwrap();
+        assert_eq!(result.len(), 11);
+        assert_eq!(metrics.find_by_index_count(), before + 1);
+
+        // a document found by both the branches is returned once
+        let result = col.find(doc! { "$or": [{ "owner": 195 }, { "category": "b" }] })
+            .run()
+            .unwrap()
+            .collect::<Result<Vec<Document>>>()
+            .unwrap();
+        assert_eq!(result.len(), 10);
+
+        let explain = col.find(doc! { "$or": [{ "owner": 5 }, { "category": "b" }] }).explain().unwrap();
+        let query_planner = explain.get_document("queryPlanner").unwrap();
+        let winning_plan = query_planner.get_document("winningPlan").unwrap();
+        assert_eq!(winning_plan.get_str("stage").unwrap(), "OR");
+        let input_stages = winning_plan.get_array("inputStages").unwrap();
+        assert_eq!(input_stages.len(), 2);
+        assert_eq!(input_stages[0].as_document().unwrap().get_str("indexName").unwrap(), "owner_1");
+        assert_eq!(input_stages[1].as_document().unwrap().get_str("indexName").unwrap(), "category_1");
+        assert!(query_planner.get_array("rejectedPlans").unwrap().is_empty());
+
+        // a branch without any index is scanned with the collection
+        let explain = col.find(doc! { "$or": [{ "owner": 5 }, { "name": "b" }] }).explain().unwrap();
+        let winning_plan = explain.get_document("queryPlanner").unwrap().get_document("winningPlan").unwrap();
+        assert_eq!(winning_plan.get_str("stage").unwrap(), "COLLSCAN");
+    });
+}
+
+#[test]
+fn test_plan_cache_after_writes() {
+    vec![
+        prepare_db("test-plan-cache-after-writes").unwrap(),
+    ].iter().for_each(|db| {
+        let col = db.collection::<Document>("orders");
+        col.create_index(IndexModel {
+            keys: doc! { "category": 1 },
+            options: None,
+        }).unwrap();
+        col.create_index(IndexModel {
+            keys: doc! { "owner": 1 },
+            options: None,
+        }).unwrap();
+
+        let winning_plan = |query: Document| {
+            let explain = col.find(query).explain().unwrap();
+            explain.get_document("queryPlanner").unwrap().get_document("winningPlan").unwrap().clone()
+        };
+
+        // the plan is chosen when the collection is empty
+        let plan = winning_plan(doc! { "category": "a", "owner": 7 });
+        assert_eq!(plan.get_str("indexName").unwrap(), "category_1");
+        assert_eq!(plan.get_i64("estimatedKeys").unwrap(), 0);
+
+        col.insert_many((0..10).map(|i| doc! {
+            "_id": i,
+            "category": "a",
+            "owner": i,
+        })).unwrap();
+        let plan = winning_plan(doc! { "category": "a", "owner": 7 });
+        assert_eq!(plan.get_str("indexName").unwrap(), "category_1");
+        assert!(plan.get_bool("fromPlanCache").unwrap());
+
+        // the cached plan is dropped after the data grows
+        col.insert_many((10..1200).map(|i| doc! {
+            "_id": i,
+            "category": "a",
+            "owner": i,
+        })).unwrap();
+        let plan = winning_plan(doc! { "category": "a", "owner": 7 });
+        assert_eq!(plan.get_str("indexName").unwrap(), "owner_1");
+        assert!(!plan.get_bool("fromPlanCache").unwrap());
+        assert_eq!(plan.get_i64("estimatedKeys").unwrap(), 1);
+
+        // the keys are not counted for the only candidate
+        let plan = winning_plan(doc! { "owner": { "$gt": 5 } });
+        assert_eq!(plan.get_str("indexName").unwrap(), "owner_1");
+        assert!(plan.get("estimatedKeys").is_none());
+    });
+}
+
+#[test]
+fn test_hint() {
+    vec![
+        prepare_db("test-hint").unwrap(),
+    ].iter().for_each(|db| {
+        let metrics = db.metrics();
+        metrics.enable();
+
+        let col = db.collection::<Document>("orders");
+        col.insert_many((0..20).map(|i| doc! {
+            "_id": i,
+            "category": if i < 15 { "a" } else { "b" },
+            "owner": i,
+        })).unwrap();
+        col.create_index(IndexModel {
+            keys: doc! { "category": 1 },
+            options: None,
+        }).unwrap();
+        col.create_index(IndexModel {
+            keys: doc! { "owner": 1 },
+            options: None,
+        }).unwrap();
+
+        // the index of fewer keys is forbidden by the hint
+        let explain = col.find(doc! { "category": "a", "owner": 7 })
+            .hint(Hint::Name("category_1".to_string()))
+            .explain()
+            .unwrap();
+        let query_planner = explain.get_document("queryPlanner").unwrap();
+        assert_eq!(query_planner.get_document("winningPlan").unwrap().get_str("indexName").unwrap(), "category_1");
+        assert_eq!(query_planner.get_array("rejectedPlans").unwrap(), &vec![
+            doc! {
+                "indexName": "owner_1",
+                "reason": "the hint selects another plan",
+            }.into(),
+        ]);
+
+        let result = col.find(doc! { "category": "a", "owner": 7 })
+            .hint(Hint::Keys(doc! { "category": 1 }))
+            .run()
+            .unwrap()
+            .collect::<Result<Vec<Document>>>()
+            .unwrap();
+        assert_eq!(result.len(), 1);
+
+        // the whole index is scanned if no condition can be searched by it
+        let before = metrics.find_by_index_count();
+        let result = col.find(doc! { "owner": { "$gte": 1 } })
+            .sort(doc! { "owner": -1 })
+            .hint(Hint::Name("category_1".to_string()))
+            .run()
+            .unwrap()
+            .collect::<Result<Vec<Document>>>()
+            .unwrap();
+        assert_eq!(result.len(), 19);
+        assert_eq!(result[0].get_i32("owner").unwrap(), 19);
+        assert_eq!(metrics.find_by_index_count(), before + 1);
+
+        // the collection is scanned by `$natural`
+        let explain = col.find(doc! { "owner": 7 })
+            .hint(Hint::Keys(doc! { "$natural": 1 }))
+            .explain()
+            .unwrap();
+        let winning_plan = explain.get_document("queryPlanner").unwrap().get_document("winningPlan").unwrap();
+        assert_eq!(winning_plan, &doc! { "stage": "COLLSCAN" });
+
+        let result = col.aggregate(vec![
+            doc! { "$match": { "category": "b" } },
+            doc! { "$count": "count" },
+        ])
+            .hint(Hint::Name("owner_1".to_string()))
+            .run()
+            .unwrap()
+            .collect::<Result<Vec<Document>>>()
+            .unwrap();
+        assert_eq!(result, vec![doc! { "count": 5_i64 }]);
+
+        let explain = col.aggregate(vec![
+            doc! { "$skip": 15 },
+        ])
+            .hint(Hint::Keys(doc! { "owner": 1 }))
+            .explain_with_stats()
+            .unwrap();
+        let query_planner = explain.get_document("queryPlanner").unwrap();
+        assert_eq!(query_planner.get_document("winningPlan").unwrap().get_str("indexName").unwrap(), "owner_1");
+        assert_eq!(explain.get_document("executionStats").unwrap().get_i64("nReturned").unwrap(), 5);
+
+        let err = col.find(doc! { "owner": 7 })
+            .hint(Hint::Name("missing_1".to_string()))
+            .run()
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::IndexNotFound(_)));
+
+        let err = col.find(doc! { "owner": 7 })
+            .hint(Hint::Keys(doc! { "owner": -1 }))
+            .run()
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::IndexNotFound(_)));
     });
 }
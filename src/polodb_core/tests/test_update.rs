@@ -234,6 +234,52 @@ fn test_update_min() {
 
 }
 
+#[test]
+fn test_update_min_max_across_types() {
+    let db = prepare_db("test-update-min-max-across-types").unwrap();
+    let col = db.collection::<Document>("test");
+    col.insert_one(doc! {
+        "_id": 1,
+        "low": "a",
+        "high": 5,
+    }).unwrap();
+
+    // the values of different types are compared in the BSON order,
+    // the numbers are less than the strings
+    let update_result = col.update_one(doc! {
+        "_id": 1,
+    }, doc! {
+        "$min": {
+            "low": 3,
+        },
+        "$max": {
+            "high": "b",
+        },
+    }).unwrap();
+    assert_eq!(update_result.modified_count, 1);
+    let result = col.find_one(doc! {
+        "_id": 1,
+    }).unwrap().unwrap();
+    assert_eq!(result.get("low").unwrap().as_i32().unwrap(), 3);
+    assert_eq!(result.get("high").unwrap().as_str().unwrap(), "b");
+
+    col.update_one(doc! {
+        "_id": 1,
+    }, doc! {
+        "$min": {
+            "high": 1,
+        },
+        "$max": {
+            "low": "c",
+        },
+    }).unwrap();
+    let result = col.find_one(doc! {
+        "_id": 1,
+    }).unwrap().unwrap();
+    assert_eq!(result.get("low").unwrap().as_str().unwrap(), "c");
+    assert_eq!(result.get("high").unwrap().as_i32().unwrap(), 1);
+}
+
 #[test]
 fn test_update_push() {
     let db = prepare_db("test-update-push").unwrap();
@@ -21,6 +21,7 @@ use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use bson::ser::Error as BsonErr;
 use bson::ser::Result as BsonResult;
 use crate::{Error, Result};
+use crate::utils::memcomparable;
 
 pub fn stacked_key<'a, T: IntoIterator<Item = &'a Bson>>(keys: T) -> Result<Vec<u8>> {
     let mut result = Vec::<u8>::new();
@@ -110,6 +111,56 @@ pub fn stacked_key_bytes<W: Write>(writer: &mut W, key: &Bson) -> Result<()> {
     Ok(())
 }
 
+/// The flag written before a value of a descending key.
+///
+/// The bytes following the flag are the inverted bytes of the value,
+/// so the byte order of the descending keys is reversed.
+const DESCENDING_KEY_FLAG: u8 = 0xFE;
+
+/// Write the value of an index key with the order of the key.
+///
+/// The value is written in the [order-preserving encoding](super::memcomparable),
+/// so the byte order of the keys is the BSON comparison order of the values.
+pub fn index_value_bytes_with_order<W: Write>(writer: &mut W, key: &Bson, order: i8) -> Result<()> {
+    if order >= 0 {
+        return memcomparable::write_value(writer, key);
+    }
+
+    let mut buffer = Vec::<u8>::new();
+    memcomparable::write_value(&mut buffer, key)?;
+
+    writer.write_u8(DESCENDING_KEY_FLAG)?;
+    for byte in buffer {
+        writer.write_u8(!byte)?;
+    }
+
+    Ok(())
+}
+
+/// The prefix of the index keys of all the values of the type class,
+/// with the order of the key.
+pub fn class_key_prefix(class: u8, order: i8) -> Vec<u8> {
+    if order >= 0 {
+        vec![class]
+    } else {
+        vec![DESCENDING_KEY_FLAG, !class]
+    }
+}
+
+/// Return the smallest key which is greater than all the keys starting with `prefix`.
+///
+/// Return `None` if there is no such key.
+pub fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut result = prefix.to_vec();
+    while let Some(last) = result.pop() {
+        if last != u8::MAX {
+            result.push(last + 1);
+            return Some(result);
+        }
+    }
+    None
+}
+
 pub fn split_stacked_keys(buffer: &[u8]) -> Result<Vec<Bson>> {
     let mut result = Vec::<Bson>::new();
     let mut reader = buffer;
@@ -120,102 +171,106 @@ pub fn split_stacked_keys(buffer: &[u8]) -> Result<Vec<Bson>> {
             break;
         }
         let ch = ch_result.unwrap();
-        if ch == ElementType::Double as u8 {
-            let val = reader.read_f64::<BigEndian>()?;
-            result.push(Bson::Double(val));
-        } else if ch == ElementType::String as u8 {
-            let mut bytes = Vec::<u8>::new();
-            reader.read_until(0, &mut bytes)?;
-            // remove last byte of bytes
-            bytes.pop();
-            result.push(Bson::String(String::from_utf8(bytes)?));
-        } else if ch == ElementType::Boolean as u8 {
-            let val = reader.read_u8()?;
-            result.push(Bson::Boolean(if val == 0 { false } else { true }));
-        } else if ch == ElementType::Null as u8 {
-            result.push(Bson::Null);
-        } else if ch == ElementType::Int32 as u8 {
-            let val = reader.read_i32::<BigEndian>()?;
-            result.push(Bson::Int32(val));
-        } else if ch == ElementType::Int64 as u8 {
-            let val = reader.read_i64::<BigEndian>()?;
-            result.push(Bson::Int64(val));
-        } else if ch == ElementType::Timestamp as u8 {
-            let val = reader.read_u64::<BigEndian>()?;
-            let timestamp = Timestamp {
-                time: (val >> 32) as u32,
-                increment: val as u32,
+        if ch == DESCENDING_KEY_FLAG {
+            let bytes: Vec<u8> = reader.iter().map(|b| !b).collect();
+            let (ch, mut value_reader) = match bytes.split_first() {
+                Some((ch, value_reader)) => (*ch, value_reader),
+                None => return Err(Error::UnknownBsonElementType(DESCENDING_KEY_FLAG)),
             };
-            result.push(Bson::Timestamp(timestamp));
-        } else if ch == ElementType::ObjectId as u8 {
-            let mut bytes = [0u8; 12];
-            reader.read_exact(&mut bytes)?;
-            result.push(Bson::ObjectId(ObjectId::from_bytes(bytes)));
-        } else if ch == ElementType::DateTime as u8 {
-            let val = reader.read_i64::<BigEndian>()?;
-            let datetime = DateTime::from_millis(val);
-            result.push(Bson::DateTime(datetime));
-        } else if ch == ElementType::Symbol as u8 {
-            let mut bytes = Vec::<u8>::new();
-            reader.read_until(0, &mut bytes)?;
-            bytes.pop();
-            result.push(Bson::Symbol(String::from_utf8(bytes)?));
-        } else if ch == ElementType::Decimal128 as u8 {
-            let mut bytes = [0u8; 16];
-            reader.read_exact(&mut bytes)?;
-            result.push(Bson::Decimal128(Decimal128::from_bytes(bytes)));
-        } else if ch == ElementType::Undefined as u8 {
-            result.push(Bson::Undefined);
+            result.push(read_stacked_value(ch, &mut value_reader)?);
+
+            let value_len = bytes.len() - value_reader.len();
+            reader = &reader[value_len..];
         } else {
-            return Err(Error::UnknownBsonElementType(ch));
+            result.push(read_stacked_value(ch, &mut reader)?);
         }
     }
 
     Ok(result)
 }
 
+fn read_stacked_value(ch: u8, reader: &mut &[u8]) -> Result<Bson> {
+    if memcomparable::is_value_class(ch) {
+        memcomparable::read_value(ch, reader)
+    } else if ch == ElementType::Double as u8 {
+        let val = reader.read_f64::<BigEndian>()?;
+        Ok(Bson::Double(val))
+    } else if ch == ElementType::String as u8 {
+        let mut bytes = Vec::<u8>::new();
+        reader.read_until(0, &mut bytes)?;
+        // remove last byte of bytes
+        bytes.pop();
+        Ok(Bson::String(String::from_utf8(bytes)?))
+    } else if ch == ElementType::Boolean as u8 {
+        let val = reader.read_u8()?;
+        Ok(Bson::Boolean(if val == 0 { false } else { true }))
+    } else if ch == ElementType::Null as u8 {
+        Ok(Bson::Null)
+    } else if ch == ElementType::Int32 as u8 {
+        let val = reader.read_i32::<BigEndian>()?;
+        Ok(Bson::Int32(val))
+    } else if ch == ElementType::Int64 as u8 {
+        let val = reader.read_i64::<BigEndian>()?;
+        Ok(Bson::Int64(val))
+    } else if ch == ElementType::Timestamp as u8 {
+        let val = reader.read_u64::<BigEndian>()?;
+        let timestamp = Timestamp {
+            time: (val >> 32) as u32,
+            increment: val as u32,
+        };
+        Ok(Bson::Timestamp(timestamp))
+    } else if ch == ElementType::ObjectId as u8 {
+        let mut bytes = [0u8; 12];
+        reader.read_exact(&mut bytes)?;
+        Ok(Bson::ObjectId(ObjectId::from_bytes(bytes)))
+    } else if ch == ElementType::DateTime as u8 {
+        let val = reader.read_i64::<BigEndian>()?;
+        let datetime = DateTime::from_millis(val);
+        Ok(Bson::DateTime(datetime))
+    } else if ch == ElementType::Symbol as u8 {
+        let mut bytes = Vec::<u8>::new();
+        reader.read_until(0, &mut bytes)?;
+        bytes.pop();
+        Ok(Bson::Symbol(String::from_utf8(bytes)?))
+    } else if ch == ElementType::Decimal128 as u8 {
+        let mut bytes = [0u8; 16];
+        reader.read_exact(&mut bytes)?;
+        Ok(Bson::Decimal128(Decimal128::from_bytes(bytes)))
+    } else if ch == ElementType::Undefined as u8 {
+        Ok(Bson::Undefined)
+    } else {
+        Err(Error::UnknownBsonElementType(ch))
+    }
+}
+
+/// Compare two values in the BSON comparison order.
+///
+/// The numbers are compared by the values across the numeric types,
+/// the values of different types are compared by the [type classes](memcomparable::type_class).
 pub fn value_cmp(a: &Bson, b: &Bson) -> BsonResult<Ordering> {
+    if let Some(ord) = memcomparable::number_cmp(a, b) {
+        return Ok(ord);
+    }
     match (a, b) {
-        (Bson::Null, Bson::Null) => Ok(Ordering::Equal),
-        (Bson::Undefined, Bson::Undefined) => Ok(Ordering::Equal),
+        (Bson::Null | Bson::Undefined, Bson::Null | Bson::Undefined) => Ok(Ordering::Equal),
         (Bson::DateTime(d1), Bson::DateTime(d2)) => Ok(d1.cmp(d2)),
         (Bson::Boolean(b1), Bson::Boolean(b2)) => Ok(b1.cmp(b2)),
-        (Bson::Int64(i1), Bson::Int64(i2)) => Ok(i1.cmp(i2)),
-        (Bson::Int32(i1), Bson::Int32(i2)) => Ok(i1.cmp(i2)),
-        (Bson::Int64(i1), Bson::Int32(i2)) => {
-            let i2_64 = *i2 as i64;
-            Ok(i1.cmp(&i2_64))
-        },
-        (Bson::Int32(i1), Bson::Int64(i2)) => {
-            let i1_64 = *i1 as i64;
-            Ok(i1_64.cmp(i2))
-        },
-        (Bson::Double(d1), Bson::Double(d2)) => Ok(d1.total_cmp(d2)),
-        (Bson::Double(d1), Bson::Int32(d2)) => {
-            let f = *d2 as f64;
-            Ok(d1.total_cmp(&f))
-        },
-        (Bson::Double(d1), Bson::Int64(d2)) => {
-            let f = *d2 as f64;
-            Ok(d1.total_cmp(&f))
-        },
-        (Bson::Int32(i1), Bson::Double(d2)) => {
-            let f = *i1 as f64;
-            Ok(f.total_cmp(d2))
+        (Bson::Timestamp(t1), Bson::Timestamp(t2)) => {
+            Ok((t1.time, t1.increment).cmp(&(t2.time, t2.increment)))
         }
-        (Bson::Int64(i1), Bson::Double(d2)) => {
-            let f = *i1 as f64;
-            Ok(f.total_cmp(d2))
+        (Bson::Binary(b1), Bson::Binary(b2)) => {
+            let s1: u8 = b1.subtype.into();
+            let s2: u8 = b2.subtype.into();
+            Ok((b1.bytes.len(), s1, &b1.bytes).cmp(&(b2.bytes.len(), s2, &b2.bytes)))
         }
-        (Bson::Binary(b1), Bson::Binary(b2)) => Ok(b1.bytes.cmp(&b2.bytes)),
         (Bson::String(str1), Bson::String(str2)) => Ok(str1.cmp(str2)),
         (Bson::ObjectId(oid1), Bson::ObjectId(oid2)) => Ok(oid1.cmp(oid2)),
+        (Bson::MinKey, Bson::MinKey) | (Bson::MaxKey, Bson::MaxKey) => Ok(Ordering::Equal),
         _ => {
-            // compare the numeric type
-            let a_type = a.element_type() as u8;
-            let b_type = b.element_type() as u8;
-            if a_type != b_type {
-                return Ok(a_type.cmp(&b_type));
+            let a_class = memcomparable::type_class(a);
+            let b_class = memcomparable::type_class(b);
+            if a_class != b_class {
+                return Ok(a_class.cmp(&b_class));
             }
 
             Err(BsonErr::InvalidCString("Unsupported types".to_string()))
@@ -223,6 +278,77 @@ pub fn value_cmp(a: &Bson, b: &Bson) -> BsonResult<Ordering> {
     }
 }
 
+/// The numeric type code of a value, as used by the `$type` query operator.
+pub fn type_code(value: &Bson) -> i32 {
+    match value.element_type() {
+        ElementType::MinKey => -1,
+        ty => ty as i32,
+    }
+}
+
+/// The type codes selected by a `$type` alias,
+/// `"number"` selects all the numeric types.
+pub fn type_codes_of_alias(alias: &str) -> Option<Vec<i32>> {
+    let ty = match alias {
+        "double" => ElementType::Double,
+        "string" => ElementType::String,
+        "object" => ElementType::EmbeddedDocument,
+        "array" => ElementType::Array,
+        "binData" => ElementType::Binary,
+        "undefined" => ElementType::Undefined,
+        "objectId" => ElementType::ObjectId,
+        "bool" => ElementType::Boolean,
+        "date" => ElementType::DateTime,
+        "null" => ElementType::Null,
+        "regex" => ElementType::RegularExpression,
+        "dbPointer" => ElementType::DbPointer,
+        "javascript" => ElementType::JavaScriptCode,
+        "symbol" => ElementType::Symbol,
+        "javascriptWithScope" => ElementType::JavaScriptCodeWithScope,
+        "int" => ElementType::Int32,
+        "timestamp" => ElementType::Timestamp,
+        "long" => ElementType::Int64,
+        "decimal" => ElementType::Decimal128,
+        "minKey" => return Some(vec![-1]),
+        "maxKey" => ElementType::MaxKey,
+        "number" => {
+            return Some(vec![
+                ElementType::Double as i32,
+                ElementType::Int32 as i32,
+                ElementType::Int64 as i32,
+                ElementType::Decimal128 as i32,
+            ]);
+        }
+        _ => return None,
+    };
+    Some(vec![ty as i32])
+}
+
+/// The integer of a numeric value, the fraction of a double is truncated
+/// as in the `$mod` query operator.
+pub fn truncated_i64(value: &Bson) -> Option<i64> {
+    match value {
+        Bson::Int32(i) => Some(*i as i64),
+        Bson::Int64(i) => Some(*i),
+        Bson::Double(f) if *f >= i64::MIN as f64 && *f < i64::MAX as f64 => Some(f.trunc() as i64),
+        _ => None,
+    }
+}
+
+/// The integer of a numeric value without a fraction,
+/// as the values tested by the bitwise query operators.
+pub fn exact_i64(value: &Bson) -> Option<i64> {
+    match value {
+        Bson::Double(f) if f.fract() != 0.0 => None,
+        _ => truncated_i64(value),
+    }
+}
+
+/// Get the value of the path in the document.
+///
+/// The arrays on the path are traversed implicitly: a numeric key is an index of the array,
+/// otherwise the key is looked up in every document of the array,
+/// and the values found are collected into an array.
 pub fn try_get_document_value(doc: &Document, key: &str) -> Option<Bson> {
     let keys = key.split('.').collect::<Vec<&str>>();
     let keys_slice = keys.as_slice();
@@ -230,25 +356,78 @@ pub fn try_get_document_value(doc: &Document, key: &str) -> Option<Bson> {
 }
 
 fn try_get_document_by_slices(doc: &Document, keys: &[&str]) -> Option<Bson> {
-    let first = keys.first();
-    first
-        .map(|first_str| {
-            let remains = &keys[1..];
-            let value = doc.get(first_str);
-            match value {
-                Some(Bson::Document(doc)) => {
-                    try_get_document_by_slices(doc, remains)
-                }
-                Some(v) => {
-                    if remains.is_empty() {
-                        return Some(v.clone());
-                    }
-                    return None;
+    let (first, remains) = keys.split_first()?;
+    let value = doc.get(first)?;
+    try_get_value_by_slices(value, remains)
+}
+
+fn try_get_value_by_slices(value: &Bson, keys: &[&str]) -> Option<Bson> {
+    let first = match keys.first() {
+        Some(first) => first,
+        None => return Some(value.clone()),
+    };
+    match value {
+        Bson::Document(doc) => try_get_document_by_slices(doc, keys),
+        Bson::Array(arr) => {
+            if let Some(elem) = first.parse::<usize>().ok().and_then(|index| arr.get(index)) {
+                return try_get_value_by_slices(elem, &keys[1..]);
+            }
+            let values = arr
+                .iter()
+                .filter_map(|elem| match elem {
+                    Bson::Document(doc) => try_get_document_by_slices(doc, keys),
+                    _ => None,
+                })
+                .collect::<Vec<Bson>>();
+            if values.is_empty() {
+                return None;
+            }
+            Some(Bson::Array(values))
+        }
+        _ => None,
+    }
+}
+
+/// Get the values of the path in the document, which are tested by the query operators.
+///
+/// The arrays on the path are traversed like [`try_get_document_value`],
+/// but every value found in the documents of an array is a value of the path.
+/// The nested arrays are not traversed.
+pub fn get_path_values(doc: &Document, key: &str) -> Vec<Bson> {
+    let keys = key.split('.').collect::<Vec<&str>>();
+    let mut result = Vec::new();
+    if let Some(value) = doc.get(keys[0]) {
+        collect_path_values(value, &keys[1..], &mut result);
+    }
+    result
+}
+
+fn collect_path_values(value: &Bson, keys: &[&str], result: &mut Vec<Bson>) {
+    let (first, remains) = match keys.split_first() {
+        Some(pair) => pair,
+        None => {
+            result.push(value.clone());
+            return;
+        }
+    };
+    match value {
+        Bson::Document(doc) => {
+            if let Some(value) = doc.get(first) {
+                collect_path_values(value, remains, result);
+            }
+        }
+        Bson::Array(arr) => {
+            if let Some(elem) = first.parse::<usize>().ok().and_then(|index| arr.get(index)) {
+                collect_path_values(elem, remains, result);
+            }
+            for elem in arr {
+                if let Bson::Document(_) = elem {
+                    collect_path_values(elem, keys, result);
                 }
-                _ => None,
             }
-        })
-        .flatten()
+        }
+        _ => (),
+    }
 }
 
 pub fn bson_datetime_now() -> bson::datetime::DateTime {
@@ -258,9 +437,11 @@ pub fn bson_datetime_now() -> bson::datetime::DateTime {
 #[cfg(test)]
 mod tests {
     use std::cmp::Ordering;
-    use bson::{Bson, doc, Timestamp};
+    use bson::{bson, Bson, doc, Timestamp};
     use bson::oid::ObjectId;
-    use crate::utils::bson::{split_stacked_keys, stacked_key, value_cmp};
+    use crate::utils::bson::{
+        exact_i64, split_stacked_keys, stacked_key, truncated_i64, type_code, type_codes_of_alias, value_cmp,
+    };
 
     #[test]
     fn test_value_cmp() {
@@ -270,6 +451,11 @@ mod tests {
         assert_eq!(value_cmp(&Bson::Int64(2), &Bson::Int32(3)).unwrap(), Ordering::Less);
         assert_eq!(value_cmp(&Bson::Int64(2), &Bson::Int32(1)).unwrap(), Ordering::Greater);
         assert_eq!(value_cmp(&Bson::Int64(1), &Bson::Int32(1)).unwrap(), Ordering::Equal);
+        assert_eq!(value_cmp(&Bson::Double(2.5), &Bson::Int64(3)).unwrap(), Ordering::Less);
+        assert_eq!(value_cmp(&Bson::Int64(i64::MAX), &Bson::Double(i64::MAX as f64)).unwrap(), Ordering::Less);
+        // the numbers are sorted before the strings
+        assert_eq!(value_cmp(&Bson::Int32(1), &Bson::String("1".into())).unwrap(), Ordering::Less);
+        assert_eq!(value_cmp(&Bson::Null, &Bson::Int32(1)).unwrap(), Ordering::Less);
     }
 
     #[test]
@@ -281,6 +467,45 @@ mod tests {
         assert_eq!(super::try_get_document_value(&doc!{"a": { "b": 1 }}, "a.c"), None);
         assert_eq!(super::try_get_document_value(&doc!{"a": { "b": { "c": 1 }}}, "a.b.c"), Some(Bson::Int32(1)));
         assert_eq!(super::try_get_document_value(&doc!{"a": { "b": { "c": 1 }}}, "a.b.d"), None);
+        assert_eq!(super::try_get_document_value(&doc!{"a": [{ "b": 1 }, { "c": 2 }, { "b": 3 }]}, "a.b"), Some(bson!([1, 3])));
+        assert_eq!(super::try_get_document_value(&doc!{"a": [{ "b": 1 }, { "b": 3 }]}, "a.1.b"), Some(Bson::Int32(3)));
+        assert_eq!(super::try_get_document_value(&doc!{"a": [1, 2]}, "a.1"), Some(Bson::Int32(2)));
+        assert_eq!(super::try_get_document_value(&doc!{"a": [1, 2]}, "a.b"), None);
+    }
+
+    #[test]
+    fn test_get_path_values() {
+        assert_eq!(super::get_path_values(&doc!{"a": 1}, "b"), Vec::<Bson>::new());
+        assert_eq!(super::get_path_values(&doc!{"a": [1, 2]}, "a"), vec![bson!([1, 2])]);
+        assert_eq!(super::get_path_values(&doc!{"a": [{ "b": 1 }, { "b": [2, 3] }]}, "a.b"), vec![bson!(1), bson!([2, 3])]);
+        assert_eq!(super::get_path_values(&doc!{"a": [{ "b": 1 }, { "b": 2 }]}, "a.0.b"), vec![bson!(1)]);
+        assert_eq!(super::get_path_values(&doc!{"a": [[{ "b": 1 }]]}, "a.b"), Vec::<Bson>::new());
+    }
+
+    #[test]
+    fn test_type_code() {
+        assert_eq!(type_code(&Bson::Double(1.0)), 1);
+        assert_eq!(type_code(&Bson::String("a".into())), 2);
+        assert_eq!(type_code(&Bson::Int64(1)), 18);
+        assert_eq!(type_code(&Bson::MinKey), -1);
+        assert_eq!(type_code(&Bson::MaxKey), 127);
+        assert_eq!(type_codes_of_alias("int"), Some(vec![16]));
+        assert_eq!(type_codes_of_alias("number"), Some(vec![1, 16, 18, 19]));
+        assert_eq!(type_codes_of_alias("minKey"), Some(vec![-1]));
+        assert_eq!(type_codes_of_alias("float"), None);
+    }
+
+    #[test]
+    fn test_integer_values() {
+        assert_eq!(truncated_i64(&Bson::Int32(-7)), Some(-7));
+        assert_eq!(truncated_i64(&Bson::Double(-7.9)), Some(-7));
+        assert_eq!(truncated_i64(&Bson::Double(f64::NAN)), None);
+        assert_eq!(truncated_i64(&Bson::Double(1e19)), None);
+        assert_eq!(truncated_i64(&Bson::String("7".into())), None);
+        assert_eq!(exact_i64(&Bson::Int64(i64::MIN)), Some(i64::MIN));
+        assert_eq!(exact_i64(&Bson::Double(8.0)), Some(8));
+        assert_eq!(exact_i64(&Bson::Double(8.5)), None);
+        assert_eq!(exact_i64(&Bson::Double(f64::INFINITY)), None);
     }
 
     #[test]
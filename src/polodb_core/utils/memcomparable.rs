@@ -0,0 +1,649 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The order-preserving encoding of the values of the index keys.
+//!
+//! The encoded values are compared byte by byte in the same order as
+//! the BSON comparison order of MongoDB:
+//!
+//! MinKey < Null < Numbers < Strings < Object < Array < BinData
+//! < ObjectId < Boolean < Date < Timestamp < Regular Expression < MaxKey
+//!
+//! Every value starts with the byte of its type class.
+//! The class bytes never collide with the element types used by
+//! [`stacked_key_bytes`](super::bson::stacked_key_bytes),
+//! so the keys written in the legacy layout can be told apart.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use bson::{Binary, Bson, DateTime, Decimal128, Timestamp};
+use bson::oid::ObjectId;
+use bson::spec::BinarySubtype;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use crate::{Error, Result};
+
+pub const CLASS_MIN_KEY: u8 = 0x20;
+pub const CLASS_NULL: u8 = 0x21;
+pub const CLASS_NUMBER: u8 = 0x22;
+pub const CLASS_STRING: u8 = 0x23;
+pub const CLASS_OBJECT: u8 = 0x24;
+pub const CLASS_ARRAY: u8 = 0x25;
+pub const CLASS_BINARY: u8 = 0x26;
+pub const CLASS_OBJECT_ID: u8 = 0x27;
+pub const CLASS_BOOLEAN: u8 = 0x28;
+pub const CLASS_DATE_TIME: u8 = 0x29;
+pub const CLASS_TIMESTAMP: u8 = 0x2A;
+pub const CLASS_REGEX: u8 = 0x2B;
+pub const CLASS_OTHER: u8 = 0x2C;
+pub const CLASS_MAX_KEY: u8 = 0x2F;
+
+/// The type class of the value in the BSON comparison order.
+///
+/// The values of different classes are ordered by the classes,
+/// for example, `Int32`, `Int64`, `Double` and `Decimal128` are all numbers.
+pub fn type_class(value: &Bson) -> u8 {
+    match value {
+        Bson::MinKey => CLASS_MIN_KEY,
+        Bson::Null | Bson::Undefined => CLASS_NULL,
+        Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) | Bson::Decimal128(_) => CLASS_NUMBER,
+        Bson::String(_) | Bson::Symbol(_) => CLASS_STRING,
+        Bson::Document(_) => CLASS_OBJECT,
+        Bson::Array(_) => CLASS_ARRAY,
+        Bson::Binary(_) => CLASS_BINARY,
+        Bson::ObjectId(_) => CLASS_OBJECT_ID,
+        Bson::Boolean(_) => CLASS_BOOLEAN,
+        Bson::DateTime(_) => CLASS_DATE_TIME,
+        Bson::Timestamp(_) => CLASS_TIMESTAMP,
+        Bson::RegularExpression(_) => CLASS_REGEX,
+        Bson::MaxKey => CLASS_MAX_KEY,
+        _ => CLASS_OTHER,
+    }
+}
+
+/// Check whether the byte is the class of an encoded value.
+#[inline]
+pub fn is_value_class(ch: u8) -> bool {
+    (CLASS_MIN_KEY..=CLASS_MAX_KEY).contains(&ch)
+}
+
+pub fn write_value<W: Write>(writer: &mut W, value: &Bson) -> Result<()> {
+    match value {
+        Bson::MinKey | Bson::MaxKey | Bson::Null | Bson::Undefined => {
+            writer.write_u8(type_class(value))?;
+        }
+        Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) | Bson::Decimal128(_) => {
+            writer.write_u8(CLASS_NUMBER)?;
+            writer.write_all(&number_key(value).unwrap())?;
+        }
+        Bson::String(str) | Bson::Symbol(str) => {
+            writer.write_u8(CLASS_STRING)?;
+            // escape the zeros, so the end of the string is sorted before any char
+            for byte in str.as_bytes() {
+                writer.write_u8(*byte)?;
+                if *byte == 0 {
+                    writer.write_u8(u8::MAX)?;
+                }
+            }
+            writer.write_all(&[0, 0])?;
+        }
+        Bson::Array(arr) => {
+            // the elements are compared one by one, the end of the array
+            // is sorted before any element, so a prefix is sorted first
+            writer.write_u8(CLASS_ARRAY)?;
+            for element in arr {
+                write_value(writer, element)?;
+            }
+            writer.write_u8(0)?;
+        }
+        Bson::Binary(bin) => {
+            // MongoDB compares the length first, then the subtype
+            writer.write_u8(CLASS_BINARY)?;
+            writer.write_u32::<BigEndian>(bin.bytes.len() as u32)?;
+            writer.write_u8(bin.subtype.into())?;
+            writer.write_all(&bin.bytes)?;
+        }
+        Bson::ObjectId(oid) => {
+            writer.write_u8(CLASS_OBJECT_ID)?;
+            writer.write_all(&oid.bytes())?;
+        }
+        Bson::Boolean(bl) => {
+            writer.write_u8(CLASS_BOOLEAN)?;
+            writer.write_u8(*bl as u8)?;
+        }
+        Bson::DateTime(dt) => {
+            writer.write_u8(CLASS_DATE_TIME)?;
+            writer.write_u64::<BigEndian>(sortable_i64(dt.timestamp_millis()))?;
+        }
+        Bson::Timestamp(ts) => {
+            writer.write_u8(CLASS_TIMESTAMP)?;
+            writer.write_u32::<BigEndian>(ts.time)?;
+            writer.write_u32::<BigEndian>(ts.increment)?;
+        }
+        _ => {
+            let val = format!("{:?}", value);
+            return Err(Error::NotAValidKeyType(val))
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a value following the class byte.
+///
+/// The numbers are read with their exact values, but the original numeric type
+/// is not kept in the key. A number equal to a double is read as `Int64` if it's
+/// an integer, otherwise as `Double`. The other numbers, which are the big integers
+/// and the decimals not representable by a double, are read as `Int64` if they are
+/// integers in the range of i64, otherwise as `Decimal128`, and the scale of
+/// a decimal is not kept, for example, `4.20` is read as `4.2`.
+pub fn read_value(ch: u8, reader: &mut &[u8]) -> Result<Bson> {
+    match ch {
+        CLASS_MIN_KEY => Ok(Bson::MinKey),
+        CLASS_MAX_KEY => Ok(Bson::MaxKey),
+        CLASS_NULL => Ok(Bson::Null),
+        CLASS_NUMBER => {
+            let approx = reader.read_u64::<BigEndian>()?;
+            match reader.read_u8()? {
+                EXACT_EQUAL => Ok(read_number(approx)),
+                EXACT_BELOW | EXACT_ABOVE => ExactDecimal::read(reader)?.into_bson(),
+                _ => Err(Error::UnknownBsonElementType(ch)),
+            }
+        }
+        CLASS_STRING => {
+            let mut bytes = Vec::<u8>::new();
+            loop {
+                let byte = reader.read_u8()?;
+                if byte != 0 {
+                    bytes.push(byte);
+                    continue;
+                }
+                match reader.read_u8()? {
+                    0 => break,
+                    u8::MAX => bytes.push(0),
+                    _ => return Err(Error::UnknownBsonElementType(ch)),
+                }
+            }
+            Ok(Bson::String(String::from_utf8(bytes)?))
+        }
+        CLASS_ARRAY => {
+            let mut arr = Vec::new();
+            loop {
+                let class = reader.read_u8()?;
+                if class == 0 {
+                    break;
+                }
+                arr.push(read_value(class, reader)?);
+            }
+            Ok(Bson::Array(arr))
+        }
+        CLASS_BINARY => {
+            let len = reader.read_u32::<BigEndian>()? as usize;
+            let subtype = BinarySubtype::from(reader.read_u8()?);
+            let mut bytes = vec![0u8; len];
+            reader.read_exact(&mut bytes)?;
+            Ok(Bson::Binary(Binary { subtype, bytes }))
+        }
+        CLASS_OBJECT_ID => {
+            let mut bytes = [0u8; 12];
+            reader.read_exact(&mut bytes)?;
+            Ok(Bson::ObjectId(ObjectId::from_bytes(bytes)))
+        }
+        CLASS_BOOLEAN => {
+            let val = reader.read_u8()?;
+            Ok(Bson::Boolean(val != 0))
+        }
+        CLASS_DATE_TIME => {
+            let val = reader.read_u64::<BigEndian>()? ^ SIGN_BIT_64;
+            Ok(Bson::DateTime(DateTime::from_millis(val as i64)))
+        }
+        CLASS_TIMESTAMP => {
+            let time = reader.read_u32::<BigEndian>()?;
+            let increment = reader.read_u32::<BigEndian>()?;
+            Ok(Bson::Timestamp(Timestamp { time, increment }))
+        }
+        _ => Err(Error::UnknownBsonElementType(ch)),
+    }
+}
+
+/// Compare two numbers of any numeric types.
+///
+/// NaN is equal to NaN and less than the other numbers, the same as MongoDB.
+/// Return `None` if any of the values is not a number.
+pub fn number_cmp(a: &Bson, b: &Bson) -> Option<Ordering> {
+    Some(number_key(a)?.cmp(&number_key(b)?))
+}
+
+const SIGN_BIT_64: u64 = 1 << 63;
+const SIGN_BIT_32: u32 = 1 << 31;
+
+const EXACT_BELOW: u8 = 0;
+const EXACT_EQUAL: u8 = 1;
+const EXACT_ABOVE: u8 = 2;
+
+// A number is encoded as the nearest double, followed by the position
+// of the exact value relative to the double.
+// The numbers equal to the double, including all the doubles, end with `EXACT_EQUAL`.
+// The big integers and the decimals which can not be represented by a double exactly
+// end with `EXACT_BELOW` or `EXACT_ABOVE`, followed by the exact value in decimal.
+//
+// The rounding to the nearest double keeps the order of the numbers,
+// so the numbers are sorted by the double first, and then by the exact value.
+fn number_key(value: &Bson) -> Option<Vec<u8>> {
+    let (approx, exact) = match value {
+        Bson::Int32(v) => integer_parts(*v as i64),
+        Bson::Int64(v) => integer_parts(*v),
+        Bson::Double(v) => (*v, None),
+        Bson::Decimal128(v) => decimal_parts(v),
+        _ => return None,
+    };
+
+    let mut result = Vec::with_capacity(9);
+    result.extend_from_slice(&sortable_f64(approx).to_be_bytes());
+    match exact {
+        None => result.push(EXACT_EQUAL),
+        Some((ord, exact)) => {
+            result.push(if ord == Ordering::Less { EXACT_BELOW } else { EXACT_ABOVE });
+            exact.write(&mut result);
+        }
+    }
+    Some(result)
+}
+
+// The nearest double of the number, and the exact value if it's not equal to the double.
+type NumberParts = (f64, Option<(Ordering, ExactDecimal)>);
+
+fn integer_parts(value: i64) -> NumberParts {
+    let approx = value as f64;
+    match (value as i128).cmp(&(approx as i128)) {
+        Ordering::Equal => (approx, None),
+        ord => (approx, Some((ord, ExactDecimal::from_integer(value)))),
+    }
+}
+
+// The value of a Decimal128 in the BID encoding.
+fn decimal_parts(value: &Decimal128) -> NumberParts {
+    let bits = u128::from_le_bytes(value.bytes());
+    let negative = bits >> 127 == 1;
+
+    let (coefficient, exponent) = if (bits >> 125) & 0b11 == 0b11 {
+        match (bits >> 122) & 0b11111 {
+            0b11110 => {
+                return (if negative { f64::NEG_INFINITY } else { f64::INFINITY }, None);
+            }
+            0b11111 => return (f64::NAN, None),
+            // the coefficient is out of range, the value is zero
+            _ => (0, 0),
+        }
+    } else {
+        let exponent = ((bits >> 113) & 0x3FFF) as i32 - 6176;
+        let coefficient = bits & ((1u128 << 113) - 1);
+        if coefficient >= 10u128.pow(34) {
+            (0, 0)
+        } else {
+            (coefficient, exponent)
+        }
+    };
+
+    if coefficient == 0 {
+        return (0.0, None);
+    }
+
+    if (0..=18).contains(&exponent) {
+        if let Some(integer) = coefficient.checked_mul(10u128.pow(exponent as u32)) {
+            let integer = i128::try_from(integer).ok()
+                .map(|integer| if negative { -integer } else { integer })
+                .and_then(|integer| i64::try_from(integer).ok());
+            if let Some(integer) = integer {
+                return integer_parts(integer);
+            }
+        }
+    }
+
+    let sign = if negative { "-" } else { "" };
+    let approx = format!("{}{}e{}", sign, coefficient, exponent).parse::<f64>().unwrap_or(f64::NAN);
+    let exact = ExactDecimal::new(negative, &coefficient.to_string(), exponent);
+    match exact.cmp_f64(approx) {
+        Ordering::Equal => (approx, None),
+        ord => (approx, Some((ord, exact))),
+    }
+}
+
+// Build a Decimal128 of `coefficient * 10^exponent` in the BID encoding.
+fn decimal_from_parts(coefficient: u128, exponent: i32, negative: bool) -> Decimal128 {
+    let mut bits = coefficient | (((exponent + 6176) as u128) << 113);
+    if negative {
+        bits |= 1 << 127;
+    }
+    Decimal128::from_bytes(bits.to_le_bytes())
+}
+
+// A non-zero number in the scientific notation, `d0.d1d2... * 10^exponent`,
+// without the trailing zeros of the digits.
+#[derive(Debug, PartialEq, Eq)]
+struct ExactDecimal {
+    negative: bool,
+    digits: Vec<u8>,
+    exponent: i32,
+}
+
+impl ExactDecimal {
+
+    // `digits * 10^exponent`
+    fn new(negative: bool, digits: &str, exponent: i32) -> ExactDecimal {
+        let digits = digits.trim_start_matches('0');
+        let exponent = exponent + digits.len() as i32 - 1;
+        let digits = digits.trim_end_matches('0')
+            .bytes()
+            .map(|ch| ch - b'0')
+            .collect();
+        ExactDecimal { negative, digits, exponent }
+    }
+
+    fn from_integer(value: i64) -> ExactDecimal {
+        ExactDecimal::new(value < 0, &value.unsigned_abs().to_string(), 0)
+    }
+
+    // The exact value of a finite non-zero double,
+    // which has at most 767 significant digits.
+    fn from_f64(value: f64) -> ExactDecimal {
+        let formatted = format!("{:.767e}", value.abs());
+        let (mantissa, exponent) = formatted.split_once('e').unwrap();
+        let exponent = exponent.parse::<i32>().unwrap();
+        let digits = mantissa.replace('.', "");
+        ExactDecimal::new(value < 0.0, &digits, exponent - (digits.len() as i32 - 1))
+    }
+
+    fn cmp_f64(&self, value: f64) -> Ordering {
+        if value.is_infinite() {
+            return if value > 0.0 { Ordering::Less } else { Ordering::Greater };
+        }
+        if value == 0.0 {
+            return if self.negative { Ordering::Less } else { Ordering::Greater };
+        }
+        self.cmp(&ExactDecimal::from_f64(value))
+    }
+
+    // The magnitudes of the negative numbers are written with the bits flipped,
+    // and the digits end with zero, so a shorter number is sorted first.
+    fn write(&self, buf: &mut Vec<u8>) {
+        let mask = if self.negative { u8::MAX } else { 0 };
+        buf.push(if self.negative { 0 } else { 1 });
+        for byte in ((self.exponent as u32) ^ SIGN_BIT_32).to_be_bytes().iter() {
+            buf.push(byte ^ mask);
+        }
+        for digit in &self.digits {
+            buf.push((digit + 1) ^ mask);
+        }
+        buf.push(mask);
+    }
+
+    fn read(reader: &mut &[u8]) -> Result<ExactDecimal> {
+        let negative = reader.read_u8()? == 0;
+        let mask = if negative { u8::MAX } else { 0 };
+        let mut exponent = [0u8; 4];
+        reader.read_exact(&mut exponent)?;
+        let exponent = u32::from_be_bytes(exponent.map(|byte| byte ^ mask)) ^ SIGN_BIT_32;
+        let mut digits = Vec::new();
+        loop {
+            match reader.read_u8()? ^ mask {
+                0 => break,
+                byte @ 1..=10 => digits.push(byte - 1),
+                _ => return Err(Error::UnknownBsonElementType(CLASS_NUMBER)),
+            }
+        }
+        Ok(ExactDecimal { negative, digits, exponent: exponent as i32 })
+    }
+
+    fn into_bson(self) -> Result<Bson> {
+        let coefficient = self.digits.iter()
+            .try_fold(0u128, |acc, digit| acc.checked_mul(10)?.checked_add(*digit as u128))
+            .filter(|coefficient| *coefficient < 10u128.pow(34))
+            .ok_or(Error::UnknownBsonElementType(CLASS_NUMBER))?;
+        let exponent = self.exponent - (self.digits.len() as i32 - 1);
+
+        if (0..=18).contains(&exponent) {
+            let integer = coefficient.checked_mul(10u128.pow(exponent as u32))
+                .and_then(|integer| i128::try_from(integer).ok())
+                .map(|integer| if self.negative { -integer } else { integer })
+                .and_then(|integer| i64::try_from(integer).ok());
+            if let Some(integer) = integer {
+                return Ok(Bson::Int64(integer));
+            }
+        }
+
+        Ok(Bson::Decimal128(decimal_from_parts(coefficient, exponent, self.negative)))
+    }
+
+}
+
+impl PartialOrd for ExactDecimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ExactDecimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let magnitude = self.exponent.cmp(&other.exponent)
+            .then_with(|| self.digits.cmp(&other.digits));
+        match (self.negative, other.negative) {
+            (false, false) => magnitude,
+            (true, true) => magnitude.reverse(),
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+        }
+    }
+}
+
+// Flip the bits of the double, so the bytes are in the order of the values.
+// All the NaNs are encoded as zero, which is less than the negative infinity.
+fn sortable_f64(value: f64) -> u64 {
+    if value.is_nan() {
+        return 0;
+    }
+    // -0.0 is equal to 0.0
+    let value = if value == 0.0 { 0.0 } else { value };
+    let bits = value.to_bits();
+    if bits & SIGN_BIT_64 != 0 {
+        !bits
+    } else {
+        bits | SIGN_BIT_64
+    }
+}
+
+fn read_number(approx: u64) -> Bson {
+    if approx == 0 {
+        return Bson::Double(f64::NAN);
+    }
+    let bits = if approx & SIGN_BIT_64 != 0 {
+        approx & !SIGN_BIT_64
+    } else {
+        !approx
+    };
+    let value = f64::from_bits(bits);
+
+    // 2^63 is not in the range of i64
+    if value.fract() == 0.0 && value >= i64::MIN as f64 && value < i64::MAX as f64 {
+        return Bson::Int64(value as i64);
+    }
+
+    Bson::Double(value)
+}
+
+#[inline]
+fn sortable_i64(value: i64) -> u64 {
+    (value as u64) ^ SIGN_BIT_64
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Binary, Bson};
+    use bson::spec::BinarySubtype;
+    use super::{decimal_from_parts as decimal, read_value, write_value};
+
+    fn encode(value: &Bson) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_value(&mut buf, value).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_numbers_in_order() {
+        let values = vec![
+            Bson::Double(f64::NAN),
+            Bson::Double(f64::NEG_INFINITY),
+            Bson::Int64(i64::MIN),
+            Bson::Int64(i64::MIN + 1),
+            Bson::Double(-1e10),
+            Bson::Int32(-3),
+            Bson::Double(-2.5),
+            Bson::Decimal128(decimal(15, -1, true)),
+            Bson::Int64(-1),
+            Bson::Double(-0.5),
+            Bson::Int32(0),
+            Bson::Double(0.5),
+            Bson::Decimal128(decimal(15, -1, false)),
+            Bson::Int64(2),
+            Bson::Double(2.5),
+            Bson::Int32(3),
+            Bson::Int64(1 << 53),
+            Bson::Int64((1 << 53) + 1),
+            Bson::Int64(i64::MAX - 1),
+            Bson::Int64(i64::MAX),
+            Bson::Double(9223372036854775808.0),
+            Bson::Double(f64::INFINITY),
+        ];
+
+        for pair in values.windows(2) {
+            assert!(encode(&pair[0]) < encode(&pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_decimals_equal_as_double_in_order() {
+        // the numbers of every group have the same nearest double
+        let groups = vec![
+            vec![
+                Bson::Decimal128(decimal(1000000000000000055511151231257828, -34, true)),
+                Bson::Double(-0.1),
+                Bson::Decimal128(decimal(1000000000000000000000000000000001, -34, true)),
+                Bson::Decimal128(decimal(1, -1, true)),
+            ],
+            vec![
+                Bson::Decimal128(decimal(1, -400, true)),
+                Bson::Int32(0),
+                Bson::Decimal128(decimal(1, -400, false)),
+                Bson::Decimal128(decimal(2, -400, false)),
+            ],
+            vec![
+                Bson::Decimal128(decimal(1, -1, false)),
+                Bson::Decimal128(decimal(1000000000000000000000000000000001, -34, false)),
+                Bson::Double(0.1),
+                Bson::Decimal128(decimal(1000000000000000055511151231257828, -34, false)),
+            ],
+            vec![
+                Bson::Int64(i64::MAX),
+                Bson::Decimal128(decimal(92233720368547758075, -1, false)),
+                Bson::Double(9223372036854775808.0),
+            ],
+            vec![
+                Bson::Decimal128(decimal(1, 400, false)),
+                Bson::Decimal128(decimal(1, 401, false)),
+                Bson::Double(f64::INFINITY),
+            ],
+        ];
+
+        let values = groups.concat();
+        for pair in values.windows(2) {
+            assert!(encode(&pair[0]) < encode(&pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+
+        for group in groups {
+            for pair in group.windows(2) {
+                assert_eq!(encode(&pair[0])[..9], encode(&pair[1])[..9], "{} ~ {}", pair[0], pair[1]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_equal_numbers() {
+        let key = encode(&Bson::Int32(42));
+        assert_eq!(encode(&Bson::Int64(42)), key);
+        assert_eq!(encode(&Bson::Double(42.0)), key);
+        assert_eq!(encode(&Bson::Decimal128(decimal(42, 0, false))), key);
+        assert_eq!(encode(&Bson::Decimal128(decimal(4200, -2, false))), key);
+        assert_eq!(encode(&Bson::Double(-0.0)), encode(&Bson::Int32(0)));
+    }
+
+    #[test]
+    fn test_classes_in_order() {
+        let values = vec![
+            Bson::MinKey,
+            Bson::Null,
+            Bson::Int64(i64::MAX),
+            Bson::String("".to_string()),
+            Bson::String("a".to_string()),
+            Bson::String("a\0b".to_string()),
+            Bson::String("a\x01".to_string()),
+            Bson::Array(vec![]),
+            Bson::Array(vec![Bson::Null]),
+            Bson::Array(vec![Bson::Int64(1)]),
+            Bson::Array(vec![Bson::Int64(1), Bson::String("a".to_string())]),
+            Bson::Array(vec![Bson::Int64(1), Bson::Array(vec![])]),
+            Bson::Array(vec![Bson::Int64(2)]),
+            Bson::Binary(Binary { subtype: BinarySubtype::Generic, bytes: vec![0xFF] }),
+            Bson::Binary(Binary { subtype: BinarySubtype::Generic, bytes: vec![0, 0] }),
+            Bson::ObjectId(bson::oid::ObjectId::new()),
+            Bson::Boolean(false),
+            Bson::Boolean(true),
+            Bson::DateTime(bson::DateTime::from_millis(-1)),
+            Bson::DateTime(bson::DateTime::from_millis(0)),
+            Bson::Timestamp(bson::Timestamp { time: 1, increment: 2 }),
+            Bson::MaxKey,
+        ];
+
+        for pair in values.windows(2) {
+            assert!(encode(&pair[0]) < encode(&pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+
+        for value in values {
+            let key = encode(&value);
+            let mut reader = &key[1..];
+            assert_eq!(read_value(key[0], &mut reader).unwrap(), value);
+            assert!(reader.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_read_numbers() {
+        let read = |value: Bson| {
+            let key = encode(&value);
+            let mut reader = &key[1..];
+            read_value(key[0], &mut reader).unwrap()
+        };
+        assert_eq!(read(Bson::Int32(-7)), Bson::Int64(-7));
+        assert_eq!(read(Bson::Int64(i64::MAX)), Bson::Int64(i64::MAX));
+        assert_eq!(read(Bson::Int64(i64::MIN)), Bson::Int64(i64::MIN));
+        assert_eq!(read(Bson::Double(2.5)), Bson::Double(2.5));
+        assert!(read(Bson::Double(f64::NAN)).as_f64().unwrap().is_nan());
+        assert_eq!(read(Bson::Decimal128(decimal(4200, -2, false))), Bson::Int64(42));
+        assert_eq!(read(Bson::Decimal128(decimal(50, -2, false))), Bson::Double(0.5));
+        assert_eq!(read(Bson::Decimal128(decimal(10, -2, true))), Bson::Decimal128(decimal(1, -1, true)));
+        assert_eq!(read(Bson::Decimal128(decimal(1, 400, false))), Bson::Decimal128(decimal(1, 400, false)));
+        assert_eq!(read(Bson::Decimal128(decimal(92233720368547758075, -1, false))),
+                   Bson::Decimal128(decimal(92233720368547758075, -1, false)));
+    }
+
+}
@@ -15,4 +15,5 @@
 pub(crate) mod file_lock;
 
 pub(crate) mod bson;
+pub(crate) mod memcomparable;
 pub mod str;
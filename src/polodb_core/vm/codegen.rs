@@ -11,17 +11,20 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
 
 
 use super::label::{JumpTableRecord, Label, LabelSlot};
 use crate::coll::collection_info::CollectionSpecification;
-use crate::errors::{mk_invalid_query_field};
-use crate::index::INDEX_PREFIX;
+use crate::errors::{mk_invalid_query_field, FieldTypeUnexpectedStruct};
+use crate::index::IndexHelper;
 use crate::vm::op::DbOp;
+use crate::vm::query_planner::{IndexScan, PlanContext, QueryPlan, QueryPlanner};
 use crate::vm::subprogram::SubProgramIndexItem;
 use crate::vm::SubProgram;
 use crate::{Error, Result};
-use bson::spec::{BinarySubtype, ElementType};
+use bson::spec::BinarySubtype;
 use bson::{Array, Binary, Bson, Document};
 use crate::vm::aggregation_codegen_context::{AggregationCodeGenContext, PipelineItem};
 use crate::vm::global_variable::{GlobalVariable, GlobalVariableSlot};
@@ -29,16 +32,21 @@ use crate::vm::operators::OpRegistry;
 use crate::vm::update_operators::{IncOperator, MaxOperator, MinOperator, MulOperator, PopOperator, PushOperator, RenameOperator, SetOperator, UnsetOperator, UpdateOperator};
 use crate::vm::vm_add_fields::VmFuncAddFields;
 use crate::vm::vm_count::VmFuncCount;
+use crate::vm::vm_expr::VmFuncExpr;
 use crate::vm::vm_external_func::VmExternalFunc;
 use crate::vm::vm_group::VmFuncGroup;
 use crate::vm::vm_limit::VmFuncLimit;
 use crate::vm::vm_skip::VmFuncSkip;
 use crate::vm::vm_sort::VmFuncSort;
 use crate::vm::vm_unset::VmFuncUnset;
+use crate::vm::vm_project::VmFuncProject;
 
 const JUMP_TABLE_DEFAULT_SIZE: usize = 8;
 const PATH_DEFAULT_SIZE: usize = 8;
 
+/// Generate the code before the cursor is closed.
+pub(super) type BeforeCloseCallback = Box<dyn FnOnce(&mut Codegen) -> Result<()>>;
+
 pub(super) struct Codegen {
     program: Box<SubProgram>,
     jump_table: Vec<JumpTableRecord>,
@@ -46,6 +54,9 @@ pub(super) struct Codegen {
     is_write: bool,
     paths: Vec<String>,
     op_registry: OpRegistry,
+    sort_memory_limit: usize,
+    sort_spill_dir: PathBuf,
+    plan_context: Option<PlanContext>,
 }
 
 impl Codegen {
@@ -57,9 +68,28 @@ impl Codegen {
             is_write,
             paths: Vec::with_capacity(PATH_DEFAULT_SIZE),
             op_registry: OpRegistry,
+            sort_memory_limit: crate::config::SORT_MEMORY_LIMIT,
+            sort_spill_dir: std::env::temp_dir(),
+            plan_context: None,
         }
     }
 
+    pub(super) fn with_sort_memory_limit(mut self, limit: usize) -> Codegen {
+        self.sort_memory_limit = limit;
+        self
+    }
+
+    pub(super) fn with_sort_spill_dir(mut self, dir: &Path) -> Codegen {
+        self.sort_spill_dir = dir.to_path_buf();
+        self
+    }
+
+    /// The queries are planned with the estimates of the storage and the plan cache.
+    pub(super) fn with_plan_context(mut self, context: Option<&PlanContext>) -> Codegen {
+        self.plan_context = context.cloned();
+        self
+    }
+
     fn unify_labels(&mut self) {
         for record in &self.jump_table {
             let pos = (record.begin_loc + record.offset) as usize;
@@ -145,6 +175,7 @@ impl Codegen {
         pkey: Bson,
         query: &Document,
         result_callback: F,
+        before_close: Option<BeforeCloseCallback>,
     ) -> Result<()>
     where
         F: FnOnce(&mut Codegen) -> Result<()>,
@@ -161,27 +192,38 @@ impl Codegen {
 
         self.emit_label(close_label);
         self.emit(DbOp::Pop);
+
+        // the stages of the pipeline are completed as in the scan
+        if let Some(before_close) = before_close {
+            before_close(self)?;
+        }
+
         self.emit(DbOp::Close);
         self.emit(DbOp::Halt);
 
         self.emit_label(result_label);
-        for (key, value) in query.iter() {
-            if key == "_id" {
-                continue;
-            }
 
-            let key_static_id = self.push_static(Bson::String(key.clone()));
-            let value_static_id = self.push_static(value.clone());
+        // test the other conditions of the document found
+        let mut remains = query.clone();
+        remains.remove("_id");
+        if !remains.is_empty() {
+            let compare_fun = self.new_label();
+            let compare_fun_clean = self.new_label();
+            let matched_label = self.new_label();
 
-            self.emit_goto2(DbOp::GetField, key_static_id, close_label); // push a value1
-            self.emit_push_value(value_static_id); // push a value2
-
-            self.emit(DbOp::Equal);
-            // if not equal，go to next
+            self.emit(DbOp::Dup);
+            self.emit_goto(DbOp::Call, compare_fun);
+            self.emit_u32(1);
             self.emit_goto(DbOp::IfFalse, close_label);
+            self.emit_goto(DbOp::Goto, matched_label);
+
+            self.emit_label_with_name(compare_fun, "compare_function");
+            self.emit_standard_query_doc(&remains, matched_label, compare_fun_clean)?;
+
+            self.emit_label_with_name(compare_fun_clean, "compare_function_clean");
+            self.emit_ret(0);
 
-            self.emit(DbOp::Pop); // pop a value2
-            self.emit(DbOp::Pop); // pop a value1
+            self.emit_label(matched_label);
         }
 
         result_callback(self)?;
@@ -196,28 +238,98 @@ impl Codegen {
         col_spec: &CollectionSpecification,
         query: &Document,
         result_callback: F,
-        before_close: Option<Box<dyn FnOnce(&mut Codegen) -> Result<()>>>,
+        before_close: Option<BeforeCloseCallback>,
         is_many: bool,
     ) -> Result<()>
     where
         F: FnOnce(&mut Codegen) -> Result<()>,
     {
-        let try_pkey_result = self.try_query_by_pkey(col_spec, query, result_callback)?;
-        if try_pkey_result.is_none() {
-            return Ok(());
-        }
-
-        let result_callback: F = try_pkey_result.unwrap();
+        let plan = QueryPlanner::new(col_spec, self.is_write)
+            .with_context(self.plan_context.as_ref())
+            .plan(query)?;
+        self.emit_query_layout_with_plan(
+            col_spec,
+            plan,
+            query,
+            result_callback,
+            before_close,
+            is_many,
+        )
+    }
 
-        let try_index_result = self.try_query_by_index(col_spec, query, result_callback)?;
-        if try_index_result.is_none() {
-            return Ok(());
+    /// Keep the explanation of the first plan of the program.
+    pub(super) fn record_query_plan(&mut self, col_spec: &CollectionSpecification, plan: &QueryPlan, query: &Document) {
+        if self.program.query_plan.is_none() {
+            let planner = QueryPlanner::new(col_spec, self.is_write)
+                .with_context(self.plan_context.as_ref());
+            self.program.query_plan = Some(planner.explain(plan, query));
         }
+    }
 
-        self.emit_open(col_spec._id.clone().into());
+    pub(super) fn emit_query_layout_with_plan<F>(
+        &mut self,
+        col_spec: &CollectionSpecification,
+        plan: QueryPlan,
+        query: &Document,
+        result_callback: F,
+        before_close: Option<BeforeCloseCallback>,
+        is_many: bool,
+    ) -> Result<()>
+    where
+        F: FnOnce(&mut Codegen) -> Result<()>,
+    {
+        self.record_query_plan(col_spec, &plan, query);
 
-        let result_callback: F = try_index_result.unwrap();
+        match plan {
+            QueryPlan::PrimaryKey(pkey) => {
+                self.emit_open(col_spec._id.clone().into());
+                self.emit_query_layout_has_pkey(pkey, query, result_callback, before_close)
+            }
+            QueryPlan::IndexScan(plan) => {
+                let prefix_bytes = IndexHelper::make_index_prefix(
+                    col_spec._id.as_str(),
+                    plan.scan.index_name.as_str(),
+                )?;
+                self.emit_open(Bson::Binary(Binary {
+                    subtype: BinarySubtype::Generic,
+                    bytes: prefix_bytes,
+                }));
+
+                let index_scan_id = self.push_index_scan(plan.scan);
+                self.emit_scan_layout(
+                    Some(index_scan_id),
+                    &plan.remain_query,
+                    result_callback,
+                    before_close,
+                    is_many,
+                )
+            }
+            QueryPlan::CollectionScan => {
+                self.emit_open(col_spec._id.clone().into());
+                self.emit_scan_layout(
+                    None,
+                    query,
+                    result_callback,
+                    before_close,
+                    is_many,
+                )
+            }
+        }
+    }
 
+    // Scan the collection, or the index if `index_scan_id` is passed,
+    // and give out the documents matching the query.
+    fn emit_scan_layout<F>(
+        &mut self,
+        index_scan_id: Option<u32>,
+        query: &Document,
+        result_callback: F,
+        before_close: Option<BeforeCloseCallback>,
+        is_many: bool,
+    ) -> Result<()>
+    where
+        F: FnOnce(&mut Codegen) -> Result<()>,
+    {
         let compare_fun = self.new_label();
         let compare_fun_clean = self.new_label();
         let compare_label = self.new_label();
@@ -226,12 +338,18 @@ impl Codegen {
         let not_found_label = self.new_label();
         let close_label = self.new_label();
 
-        self.emit_goto(DbOp::Rewind, close_label);
+        match index_scan_id {
+            Some(index_scan_id) => self.emit_goto2(DbOp::FindByIndex, index_scan_id, close_label),
+            None => self.emit_goto(DbOp::Rewind, close_label),
+        }
 
         self.emit_goto(DbOp::Goto, compare_label);
 
         self.emit_label(next_label);
-        self.emit_goto(DbOp::Next, compare_label);
+        match index_scan_id {
+            Some(_) => self.emit_goto(DbOp::NextIndexValue, compare_label),
+            None => self.emit_goto(DbOp::Next, compare_label),
+        }
 
         // <==== close cursor
         self.emit_label_with_name(close_label, "close");
@@ -282,139 +400,6 @@ impl Codegen {
         Ok(())
     }
 
-    fn try_query_by_pkey<F>(
-        &mut self,
-        col_spec: &CollectionSpecification,
-        query: &Document,
-        result_callback: F,
-    ) -> Result<Option<F>>
-    where
-        F: FnOnce(&mut Codegen) -> Result<()>,
-    {
-        if let Some(id_value) = query.get("_id") {
-            if id_value.element_type() != ElementType::EmbeddedDocument {
-                self.emit_open(col_spec._id.clone().into());
-                self.emit_query_layout_has_pkey(id_value.clone(), query, result_callback)?;
-                return Ok(None);
-            }
-        }
-
-        Ok(Some(result_callback))
-    }
-
-    fn try_query_by_index<F>(
-        &mut self,
-        col_spec: &CollectionSpecification,
-        query: &Document,
-        result_callback: F,
-    ) -> Result<Option<F>>
-    where
-        F: FnOnce(&mut Codegen) -> Result<()>,
-    {
-        if self.is_write {
-            return Ok(Some(result_callback));
-        }
-
-        let index_meta = &col_spec.indexes;
-        for (index_name, index_info) in index_meta {
-            let (key, _order) = index_info.keys.iter().next().unwrap();
-            // the key is ellipse representation, such as "a.b.c"
-            // the query is supposed to be ellipse too, such as
-            // { "a.b.c": 1 }
-            let test_result = query.get(key);
-            if let Some(query_doc) = test_result {
-                if query_doc.element_type() != ElementType::EmbeddedDocument {
-                    let mut remain_query = query.clone();
-                    remain_query.remove(key);
-
-                    self.indeed_emit_query_by_index(
-                        col_spec._id.as_str(),
-                        index_name.as_str(),
-                        query_doc,
-                        &remain_query,
-                        result_callback,
-                    )?;
-                    return Ok(None);
-                }
-            }
-        }
-
-        Ok(Some(result_callback))
-    }
-
-    fn indeed_emit_query_by_index<F>(
-        &mut self,
-        col_name: &str,
-        index_name: &str,
-        query_value: &Bson,
-        remain_query: &Document,
-        result_callback: F,
-    ) -> Result<()>
-    where
-        F: FnOnce(&mut Codegen) -> Result<()>,
-    {
-        let prefix_bytes = {
-            let b_prefix = Bson::String(INDEX_PREFIX.to_string());
-            let b_col_name = Bson::String(col_name.to_string());
-            let b_index_name = &Bson::String(index_name.to_string());
-
-            let buf: Vec<&Bson> = vec![&b_prefix, &b_col_name, &b_index_name];
-            crate::utils::bson::stacked_key(buf)?
-        };
-
-        self.emit_open(Bson::Binary(Binary {
-            subtype: BinarySubtype::Generic,
-            bytes: prefix_bytes,
-        }));
-
-        let close_label = self.new_label();
-        let result_label = self.new_label();
-        let next_label = self.new_label();
-
-        let value_id = self.push_static(query_value.clone());
-        self.emit_push_value(value_id);
-
-        let col_name_id = self.push_static(Bson::String(col_name.to_string()));
-        self.emit_push_value(col_name_id);
-
-        self.emit_goto(DbOp::FindByIndex, close_label);
-
-        self.emit_goto(DbOp::Goto, result_label);
-
-        self.emit_label(next_label);
-        self.emit_goto(DbOp::NextIndexValue, result_label);
-
-        self.emit_label(close_label);
-
-        self.emit(DbOp::Pop); // pop the collection name
-        self.emit(DbOp::Pop); // pop the query value
-
-        self.emit(DbOp::Close);
-        self.emit(DbOp::Halt);
-
-        self.emit_label(result_label);
-        for (key, value) in remain_query.iter() {
-            let key_static_id = self.push_static(Bson::String(key.clone()));
-            let value_static_id = self.push_static(value.clone());
-
-            self.emit_goto2(DbOp::GetField, key_static_id, close_label); // push a value1
-            self.emit_push_value(value_static_id); // push a value2
-
-            self.emit(DbOp::Equal);
-            // if not equal，go to next
-            self.emit_goto(DbOp::IfFalse, close_label);
-
-            self.emit(DbOp::Pop); // pop a value2
-            self.emit(DbOp::Pop); // pop a value1
-        }
-
-        result_callback(self)?;
-
-        self.emit_goto(DbOp::Goto, next_label);
-
-        Ok(())
-    }
-
     fn emit_standard_query_doc(
         &mut self,
         query_doc: &Document,
@@ -478,19 +463,14 @@ impl Codegen {
         Ok(())
     }
 
-    fn emit_logic_or(
-        &mut self,
-        arr: &Array,
-        ret_label: Label,
-    ) -> Result<()> {
-        let cmp_label = self.new_label();
-        self.emit_goto(DbOp::Goto, cmp_label);
-
+    // Every item of the array is compiled to a function
+    // which returns whether the document matches the item.
+    fn emit_logic_branches(&mut self, op_name: &str, arr: &Array) -> Result<Vec<Label>> {
         let mut functions = Vec::<Label>::new();
         for (index, item_doc_value) in arr.iter().enumerate() {
             let path_msg = format!("[{}]", index);
             crate::path_hint!(self, path_msg, {
-                let item_doc = crate::try_unwrap_document!("$or", item_doc_value);
+                let item_doc = crate::try_unwrap_document!(op_name, item_doc_value);
 
                 let query_label = self.new_label();
                 let ret_label = self.new_label();
@@ -508,18 +488,58 @@ impl Codegen {
                 functions.push(query_label);
             });
         }
+        Ok(functions)
+    }
+
+    fn emit_logic_or(
+        &mut self,
+        arr: &Array,
+        not_found_label: Label,
+    ) -> Result<()> {
+        let cmp_label = self.new_label();
+        self.emit_goto(DbOp::Goto, cmp_label);
+
+        let functions = self.emit_logic_branches("$or", arr)?;
+
+        let matched_label = self.new_label();
+        self.emit_label(cmp_label);
+        for fun in functions {
+            self.emit_goto(DbOp::Call, fun);
+            self.emit_u32(0);
+            self.emit_goto(DbOp::IfTrue, matched_label);
+        }
+        // none of the items matches
+        self.emit_goto(DbOp::Goto, not_found_label);
+
+        self.emit_label(matched_label);
+
+        Ok(())
+    }
+
+    fn emit_logic_nor(
+        &mut self,
+        arr: &Array,
+        not_found_label: Label,
+    ) -> Result<()> {
+        let cmp_label = self.new_label();
+        self.emit_goto(DbOp::Goto, cmp_label);
+
+        let functions = self.emit_logic_branches("$nor", arr)?;
 
         self.emit_label(cmp_label);
         for fun in functions {
             self.emit_goto(DbOp::Call, fun);
             self.emit_u32(0);
-            self.emit_goto(DbOp::IfTrue, ret_label);
+            // any item matches, go to next
+            self.emit(DbOp::Not);
+            self.emit_goto(DbOp::IfFalse, not_found_label);
         }
 
         Ok(())
     }
 
-    // case1: "$and" | "$or" -> [ Document ]
+    // case1: "$and" | "$or" | "$nor" -> [ Document ]
+    // case2: "$expr" -> Expression
     // case3: "_id" -> Document
     fn emit_query_tuple(
         &mut self,
@@ -547,6 +567,28 @@ impl Codegen {
                     )?;
                 }
 
+                "$nor" => {
+                    let sub_arr = crate::try_unwrap_array!("$nor", value);
+                    self.emit_logic_nor(
+                        sub_arr.as_ref(),
+                        not_found_label,
+                    )?;
+                }
+
+                // the expression is evaluated on the document on the top of the stack
+                "$expr" => {
+                    let external_func = VmFuncExpr::compile(
+                        &mut self.paths,
+                        self.op_registry.clone(),
+                        value,
+                    )?;
+                    let external_func_id = self.push_external_func(external_func);
+                    self.emit(DbOp::Dup);
+                    self.emit_call_external_func_id(external_func_id, 1);
+                    self.emit(DbOp::Pop);
+                    self.emit_goto(DbOp::IfFalse, not_found_label);
+                }
+
                 _ => {
                     return Err(Error::InvalidField(mk_invalid_query_field(
                         self.last_key().into(),
@@ -565,52 +607,171 @@ impl Codegen {
                     );
                 }
 
-                Bson::Array(_) => {
-                    return Err(Error::InvalidField(mk_invalid_query_field(
-                        self.last_key().into(),
-                        self.gen_path(),
-                    )))
+                // the value is compared with the values of the path and their elements
+                _ => {
+                    return self.emit_query_tuple_document_kv(
+                        key,
+                        false,
+                        not_found_label,
+                        "$eq",
+                        value,
+                    );
                 }
+            }
+        }
+        Ok(())
+    }
 
-                _ => {
-                    let key_static_id = self.push_static(key.into());
-                    self.emit_goto2(DbOp::GetField, key_static_id, not_found_label);
+    fn emit_logical(&mut self, op: DbOp, is_in_not: bool) {
+        self.emit(op);
+        if is_in_not {
+            self.emit(DbOp::Not);
+        }
+    }
 
-                    let value_static_id = self.push_static(value.clone());
-                    self.emit_push_value(value_static_id); // push a value2
+    // The empty key is the value on the top of the stack,
+    // e.g. the element of the array in `$elemMatch`.
+    fn emit_query_tuple_document_kv(
+        &mut self,
+        key: &str,
+        is_in_not: bool,
+        not_found_label: Label,
+        sub_key: &str,
+        sub_value: &Bson,
+    ) -> Result<()> {
+        match sub_key {
+            "$exists" => {
+                let exists = match sub_value {
+                    Bson::Boolean(b) => *b,
+                    Bson::Int32(i) => *i != 0,
+                    Bson::Int64(i) => *i != 0,
+                    Bson::Double(f) => *f != 0.0,
+                    _ => {
+                        return Err(Error::InvalidField(mk_invalid_query_field(
+                            self.last_key().into(),
+                            self.gen_path(),
+                        )))
+                    }
+                };
+                let found_matches = exists != is_in_not;
 
-                    self.emit(DbOp::Equal);
-                    // if not equal，go to next
+                if key.is_empty() {
+                    self.emit(DbOp::StoreR0_2);
+                    self.emit_u8(found_matches as u8);
                     self.emit_goto(DbOp::IfFalse, not_found_label);
-
-                    self.emit(DbOp::Pop); // pop a value2
-                    self.emit(DbOp::Pop); // pop a value1
+                    return Ok(());
                 }
+
+                let missing_label = self.new_label();
+                let next_label = self.new_label();
+
+                let path_id = self.push_static(key.into());
+                self.emit_goto2(DbOp::GetPathValues, path_id, missing_label);
+                self.emit(DbOp::Pop);
+                self.emit(DbOp::StoreR0_2);
+                self.emit_u8(found_matches as u8);
+                self.emit_goto(DbOp::Goto, next_label);
+
+                self.emit_label(missing_label);
+                self.emit(DbOp::StoreR0_2);
+                self.emit_u8(!found_matches as u8);
+
+                self.emit_label(next_label);
+                self.emit_goto(DbOp::IfFalse, not_found_label);
+            }
+
+            "$not" => {
+                let doc = match sub_value {
+                    Bson::Document(doc) => doc,
+                    _ => {
+                        return Err(Error::InvalidField(mk_invalid_query_field(
+                            self.last_key().into(),
+                            self.gen_path(),
+                        )))
+                    }
+                };
+
+                crate::path_hint!(self, "$not".to_string(), {
+                    self.emit_query_tuple_document(key, doc, !is_in_not, not_found_label)?;
+                });
+            }
+
+            _ if key.is_empty() => {
+                self.emit_value_condition(is_in_not, not_found_label, sub_key, sub_value)?;
+            }
+
+            _ => {
+                self.emit_path_condition(key, is_in_not, not_found_label, sub_key, sub_value)?;
             }
         }
+
         Ok(())
     }
 
-    fn recursively_get_field(&mut self, key: &str, get_field_failed_label: Label) -> usize {
-        let slices: Vec<&str> = key.split('.').collect();
-        for slice in &slices {
-            let str_ref: &str = slice;
-            let current_stat_id = self.push_static(str_ref.into());
-            self.emit_goto2(DbOp::GetField, current_stat_id, get_field_failed_label);
+    // The condition matches if any value of the path matches the operator.
+    //
+    // The operators on the arrays test the values of the path,
+    // the others test the elements of the arrays as well.
+    fn emit_path_condition(
+        &mut self,
+        key: &str,
+        is_in_not: bool,
+        not_found_label: Label,
+        sub_key: &str,
+        sub_value: &Bson,
+    ) -> Result<()> {
+        let (op, is_negated) = match sub_key {
+            "$ne" => ("$eq", true),
+            "$nin" => ("$in", true),
+            _ => (sub_key, false),
+        };
+        let on_arrays = matches!(op, "$size" | "$elemMatch" | "$all");
+
+        let elem_fun = self.emit_function(|codegen| {
+            let ret_label = codegen.new_label();
+            codegen.emit_value_condition(false, ret_label, op, sub_value)?;
+            codegen.emit_label(ret_label);
+            codegen.emit_ret(0);
+            Ok(())
+        })?;
+
+        // A missing field fails the condition except for `$ne` and `$nin`,
+        // it's the other way around in `$not`.
+        let is_inverted = is_negated != is_in_not;
+        let missing_label = if is_inverted {
+            self.new_label()
+        } else {
+            not_found_label
+        };
+
+        let path_id = self.push_static(key.into());
+        self.emit_goto2(DbOp::GetPathValues, path_id, missing_label);
+        if !on_arrays {
+            self.emit(DbOp::ExpandArrays);
         }
-        slices.len()
-    }
+        self.emit_any_element(elem_fun);
+        self.emit(DbOp::Pop);
 
-    fn emit_logical(&mut self, op: DbOp, is_in_not: bool) {
-        self.emit(op);
-        if is_in_not {
+        if is_inverted {
+            let next_label = self.new_label();
             self.emit(DbOp::Not);
+            self.emit_goto(DbOp::Goto, next_label);
+
+            self.emit_label(missing_label);
+            self.emit(DbOp::StoreR0_2);
+            self.emit_u8(1);
+
+            self.emit_label(next_label);
         }
+
+        self.emit_goto(DbOp::IfFalse, not_found_label);
+
+        Ok(())
     }
 
-    fn emit_query_tuple_document_kv(
+    // The condition of the operator on the value on the top of the stack.
+    fn emit_value_condition(
         &mut self,
-        key: &str,
         is_in_not: bool,
         not_found_label: Label,
         sub_key: &str,
@@ -618,8 +779,6 @@ impl Codegen {
     ) -> Result<()> {
         match sub_key {
             "$eq" => {
-                let field_size = self.recursively_get_field(key, not_found_label);
-
                 let stat_val_id = self.push_static(sub_value.clone());
                 self.emit_push_value(stat_val_id);
                 self.emit_logical(DbOp::Equal, is_in_not);
@@ -627,34 +786,27 @@ impl Codegen {
                 // if not equal，go to next
                 self.emit_goto(DbOp::IfFalse, not_found_label);
 
-                self.emit(DbOp::Pop2);
-                self.emit_u32((field_size + 1) as u32);
+                self.emit(DbOp::Pop);
             }
 
             "$gt" => {
-                let field_size = self.recursively_get_field(key, not_found_label);
-
                 let stat_val_id = self.push_static(sub_value.clone());
                 self.emit_push_value(stat_val_id);
                 self.emit_logical(DbOp::Greater, is_in_not);
 
                 self.emit_goto(DbOp::IfFalse, not_found_label);
 
-                self.emit(DbOp::Pop2);
-                self.emit_u32((field_size + 1) as u32);
+                self.emit(DbOp::Pop);
             }
 
             "$gte" => {
-                let field_size = self.recursively_get_field(key, not_found_label);
-
                 let stat_val_id = self.push_static(sub_value.clone());
                 self.emit_push_value(stat_val_id);
                 self.emit_logical(DbOp::GreaterEqual, is_in_not);
 
                 self.emit_goto(DbOp::IfFalse, not_found_label);
 
-                self.emit(DbOp::Pop2);
-                self.emit_u32((field_size + 1) as u32);
+                self.emit(DbOp::Pop);
             }
 
             // check the value is array
@@ -669,34 +821,26 @@ impl Codegen {
                     }
                 }
 
-                let field_size = self.recursively_get_field(key, not_found_label);
-
                 let stat_val_id = self.push_static(sub_value.clone());
                 self.emit_push_value(stat_val_id);
                 self.emit_logical(DbOp::In, is_in_not);
 
                 self.emit_goto(DbOp::IfFalse, not_found_label);
 
-                self.emit(DbOp::Pop2);
-                self.emit_u32((field_size + 1) as u32);
+                self.emit(DbOp::Pop);
             }
 
             "$lt" => {
-                let field_size = self.recursively_get_field(key, not_found_label);
-
                 let stat_val_id = self.push_static(sub_value.clone());
                 self.emit_push_value(stat_val_id);
                 self.emit_logical(DbOp::Less, is_in_not);
 
                 self.emit_goto(DbOp::IfFalse, not_found_label);
 
-                self.emit(DbOp::Pop2);
-                self.emit_u32((field_size + 1) as u32);
+                self.emit(DbOp::Pop);
             }
 
             "$lte" => {
-                let field_size = self.recursively_get_field(key, not_found_label);
-
                 let stat_val_id = self.push_static(sub_value.clone());
                 self.emit_push_value(stat_val_id);
                 self.emit_logical(DbOp::LessEqual, is_in_not);
@@ -704,22 +848,18 @@ impl Codegen {
                 // less
                 self.emit_goto(DbOp::IfFalse, not_found_label);
 
-                self.emit(DbOp::Pop2);
-                self.emit_u32((field_size + 1) as u32);
+                self.emit(DbOp::Pop);
             }
 
             "$ne" => {
-                let field_size = self.recursively_get_field(key, not_found_label);
-
                 let stat_val_id = self.push_static(sub_value.clone());
                 self.emit_push_value(stat_val_id);
-                self.emit_logical(DbOp::Equal, is_in_not);
+                self.emit_logical(DbOp::Equal, !is_in_not);
 
                 // if equal，go to next
                 self.emit_goto(DbOp::IfFalse, not_found_label);
 
-                self.emit(DbOp::Pop2);
-                self.emit_u32((field_size + 1) as u32);
+                self.emit(DbOp::Pop);
             }
 
             "$nin" => {
@@ -733,16 +873,13 @@ impl Codegen {
                     }
                 }
 
-                let field_size = self.recursively_get_field(key, not_found_label);
-
                 let stat_val_id = self.push_static(sub_value.clone());
                 self.emit_push_value(stat_val_id);
-                self.emit_logical(DbOp::In, is_in_not);
+                self.emit_logical(DbOp::In, !is_in_not);
 
-                self.emit_goto(DbOp::IfTrue, not_found_label);
+                self.emit_goto(DbOp::IfFalse, not_found_label);
 
-                self.emit(DbOp::Pop2);
-                self.emit_u32((field_size + 1) as u32);
+                self.emit(DbOp::Pop);
             }
 
             "$size" => {
@@ -756,7 +893,17 @@ impl Codegen {
                     }
                 };
 
-                let field_size = self.recursively_get_field(key, not_found_label);
+                let not_array_label = self.new_label();
+                let next_label = self.new_label();
+
+                let array_type_id = self.push_static(Bson::Array(vec![
+                    Bson::Int32(crate::utils::bson::type_code(&Bson::Array(vec![]))),
+                ]));
+                self.emit_push_value(array_type_id);
+                self.emit(DbOp::IsType);
+                self.emit(DbOp::Pop);
+                self.emit_goto(DbOp::IfFalse, not_array_label);
+
                 self.emit(DbOp::ArraySize);
 
                 let expect_size_stat_id = self.push_static(Bson::from(expected_size));
@@ -764,10 +911,17 @@ impl Codegen {
 
                 self.emit_logical(DbOp::Equal, is_in_not);
 
-                self.emit_goto(DbOp::IfFalse, not_found_label);
-
                 self.emit(DbOp::Pop2);
-                self.emit_u32((field_size + 1) as u32);
+                self.emit_u32(2);
+                self.emit_goto(DbOp::Goto, next_label);
+
+                // the values other than arrays have no size
+                self.emit_label(not_array_label);
+                self.emit(DbOp::StoreR0_2);
+                self.emit_u8(is_in_not as u8);
+
+                self.emit_label(next_label);
+                self.emit_goto(DbOp::IfFalse, not_found_label);
             }
 
             "$regex" => {
@@ -781,8 +935,6 @@ impl Codegen {
                     }
                 }
 
-                let field_size = self.recursively_get_field(key, not_found_label);
-
                 let stat_val_id = self.push_static(sub_value.clone());
                 self.emit_push_value(stat_val_id);
 
@@ -791,12 +943,58 @@ impl Codegen {
                 // if not equal，go to next
                 self.emit_goto(DbOp::IfFalse, not_found_label);
 
-                self.emit(DbOp::Pop2);
-                self.emit_u32((field_size + 1) as u32);
+                self.emit(DbOp::Pop);
             }
 
-            "$not" => {
-                let doc = match sub_value {
+            "$type" => {
+                let type_codes = self.parse_type_codes(sub_value)?;
+
+                let stat_val_id = self.push_static(Bson::Array(
+                    type_codes.into_iter().map(Bson::Int32).collect(),
+                ));
+                self.emit_push_value(stat_val_id);
+                self.emit_logical(DbOp::IsType, is_in_not);
+
+                self.emit_goto(DbOp::IfFalse, not_found_label);
+
+                self.emit(DbOp::Pop);
+            }
+
+            "$mod" => {
+                let (divisor, remainder) = self.parse_mod_operand(sub_value)?;
+
+                let stat_val_id = self.push_static(Bson::Array(vec![
+                    Bson::Int64(divisor),
+                    Bson::Int64(remainder),
+                ]));
+                self.emit_push_value(stat_val_id);
+                self.emit_logical(DbOp::Mod, is_in_not);
+
+                self.emit_goto(DbOp::IfFalse, not_found_label);
+
+                self.emit(DbOp::Pop);
+            }
+
+            "$bitsAllSet" | "$bitsAnySet" | "$bitsAllClear" | "$bitsAnyClear" => {
+                let mask = self.parse_bit_mask(sub_key, sub_value)?;
+                let op = match sub_key {
+                    "$bitsAllSet" => DbOp::BitsAllSet,
+                    "$bitsAnySet" => DbOp::BitsAnySet,
+                    "$bitsAllClear" => DbOp::BitsAllClear,
+                    _ => DbOp::BitsAnyClear,
+                };
+
+                let stat_val_id = self.push_static(Bson::Int64(mask));
+                self.emit_push_value(stat_val_id);
+                self.emit_logical(op, is_in_not);
+
+                self.emit_goto(DbOp::IfFalse, not_found_label);
+
+                self.emit(DbOp::Pop);
+            }
+
+            "$elemMatch" => {
+                let query = match sub_value {
                     Bson::Document(doc) => doc,
                     _ => {
                         return Err(Error::InvalidField(mk_invalid_query_field(
@@ -806,9 +1004,24 @@ impl Codegen {
                     }
                 };
 
-                crate::path_hint!(self, "$not".to_string(), {
-                    self.emit_query_tuple_document(key, doc, !is_in_not, not_found_label)?;
-                });
+                let elem_fun = self.emit_elem_match_function(query)?;
+                let fun = self.emit_any_element_function(elem_fun)?;
+                self.emit_value_predicate(fun, is_in_not, not_found_label);
+            }
+
+            "$all" => {
+                let arr = match sub_value {
+                    Bson::Array(arr) => arr,
+                    _ => {
+                        return Err(Error::InvalidField(mk_invalid_query_field(
+                            self.last_key().into(),
+                            self.gen_path(),
+                        )))
+                    }
+                };
+
+                let fun = self.emit_all_function(arr)?;
+                self.emit_value_predicate(fun, is_in_not, not_found_label);
             }
 
             _ => {
@@ -818,9 +1031,258 @@ impl Codegen {
                 )))
             }
         }
+
         Ok(())
     }
 
+    // Call the function with the value on the top of the stack,
+    // the function returns whether the value matches.
+    fn emit_value_predicate(&mut self, fun: Label, is_in_not: bool, not_found_label: Label) {
+        self.emit(DbOp::Dup);
+        self.emit_goto(DbOp::Call, fun);
+        self.emit_u32(1);
+        if is_in_not {
+            self.emit(DbOp::Not);
+        }
+
+        self.emit_goto(DbOp::IfFalse, not_found_label);
+    }
+
+    // Emit a function out of the current flow of the code.
+    fn emit_function<F>(&mut self, body: F) -> Result<Label>
+    where
+        F: FnOnce(&mut Codegen) -> Result<()>,
+    {
+        let fun = self.new_label();
+        let skip_label = self.new_label();
+
+        self.emit_goto(DbOp::Goto, skip_label);
+        self.emit_label(fun);
+        body(self)?;
+        self.emit_label(skip_label);
+
+        Ok(fun)
+    }
+
+    // Test whether any element of the array on the top of the stack matches
+    // the element function, the array is kept on the stack.
+    fn emit_any_element(&mut self, elem_fun: Label) {
+        let loop_label = self.new_label();
+        let done_label = self.new_label();
+
+        let index_id = self.push_static(Bson::Int64(0));
+        self.emit_push_value(index_id);
+
+        self.emit_label(loop_label);
+        self.emit_goto(DbOp::NextElement, done_label);
+        self.emit_goto(DbOp::Call, elem_fun);
+        self.emit_u32(1);
+        self.emit_goto(DbOp::IfFalse, loop_label);
+
+        self.emit_label(done_label);
+        self.emit(DbOp::Pop);
+    }
+
+    // The function takes an array, and returns whether
+    // any element of the array matches the element function.
+    fn emit_any_element_function(&mut self, elem_fun: Label) -> Result<Label> {
+        self.emit_function(|codegen| {
+            codegen.emit_any_element(elem_fun);
+            codegen.emit_ret(0);
+            Ok(())
+        })
+    }
+
+    // The function takes an element of the array and returns whether it matches the query.
+    //
+    // The query is a list of operators applied on the element, e.g. `{ "$gte": 80, "$lt": 85 }`,
+    // or a query on the fields of the element.
+    fn emit_elem_match_function(&mut self, query: &Document) -> Result<Label> {
+        let is_operator_list = !query.is_empty() && query.keys().all(|key| {
+            key.starts_with('$') && !matches!(key.as_str(), "$and" | "$or" | "$nor")
+        });
+
+        self.emit_function(|codegen| {
+            let ret_label = codegen.new_label();
+
+            if is_operator_list {
+                codegen.emit_query_tuple_document("", query, false, ret_label)?;
+            } else {
+                let doc_type_id = codegen.push_static(Bson::Array(vec![
+                    Bson::Int32(crate::utils::bson::type_code(&Bson::Document(Document::new()))),
+                ]));
+                codegen.emit_push_value(doc_type_id);
+                codegen.emit(DbOp::IsType);
+                codegen.emit(DbOp::Pop);
+                codegen.emit_goto(DbOp::IfFalse, ret_label);
+
+                codegen.emit_standard_query_doc(query, ret_label, ret_label)?;
+            }
+
+            codegen.emit_label(ret_label);
+            codegen.emit_ret(0);
+            Ok(())
+        })
+    }
+
+    // The function takes the value of the field,
+    // and returns whether it contains all the items.
+    //
+    // An item is contained if it equals to the value or an element of the array,
+    // `{ "$elemMatch": ... }` items are matched against the elements.
+    fn emit_all_function(&mut self, items: &Array) -> Result<Label> {
+        let mut item_funs = Vec::with_capacity(items.len());
+        for (index, item) in items.iter().enumerate() {
+            crate::path_hint!(self, format!("[{}]", index), {
+                let elem_match = match item {
+                    Bson::Document(doc) if doc.keys().any(|key| key.starts_with('$')) => {
+                        match doc.get_document("$elemMatch") {
+                            Ok(query) if doc.len() == 1 => Some(query),
+                            _ => {
+                                return Err(Error::InvalidField(mk_invalid_query_field(
+                                    self.last_key().into(),
+                                    self.gen_path(),
+                                )))
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+
+                let (item_id, elem_fun) = match elem_match {
+                    Some(query) => (None, self.emit_elem_match_function(query)?),
+                    None => {
+                        let item_id = self.push_static(item.clone());
+                        let elem_fun = self.emit_function(|codegen| {
+                            codegen.emit_push_value(item_id);
+                            codegen.emit(DbOp::Equal);
+                            codegen.emit_ret(0);
+                            Ok(())
+                        })?;
+                        (Some(item_id), elem_fun)
+                    }
+                };
+                let any_fun = self.emit_any_element_function(elem_fun)?;
+                item_funs.push((item_id, any_fun));
+            });
+        }
+
+        self.emit_function(|codegen| {
+            let ret_label = codegen.new_label();
+
+            for (item_id, any_fun) in item_funs.iter() {
+                let next_label = codegen.new_label();
+
+                if let Some(item_id) = item_id {
+                    codegen.emit(DbOp::Dup);
+                    codegen.emit_push_value(*item_id);
+                    codegen.emit(DbOp::Equal);
+                    codegen.emit(DbOp::Pop2);
+                    codegen.emit_u32(2);
+                    codegen.emit_goto(DbOp::IfTrue, next_label);
+                }
+
+                codegen.emit(DbOp::Dup);
+                codegen.emit_goto(DbOp::Call, *any_fun);
+                codegen.emit_u32(1);
+                codegen.emit_goto(DbOp::IfFalse, ret_label);
+
+                codegen.emit_label(next_label);
+            }
+
+            // an empty `$all` matches nothing
+            codegen.emit(DbOp::StoreR0_2);
+            codegen.emit_u8(!item_funs.is_empty() as u8);
+
+            codegen.emit_label(ret_label);
+            codegen.emit_ret(0);
+            Ok(())
+        })
+    }
+
+    // The operand of `$type` is an alias, a type code or an array of them.
+    fn parse_type_codes(&self, value: &Bson) -> Result<Vec<i32>> {
+        let codes = match value {
+            Bson::Array(arr) => {
+                let mut result = Vec::with_capacity(arr.len());
+                for item in arr {
+                    result.extend(self.parse_type_codes(item)?);
+                }
+                Some(result)
+            }
+            Bson::String(alias) => crate::utils::bson::type_codes_of_alias(alias),
+            Bson::Int32(code) => Some(vec![*code]),
+            Bson::Int64(code) => i32::try_from(*code).ok().map(|code| vec![code]),
+            Bson::Double(code) if code.fract() == 0.0 => Some(vec![*code as i32]),
+            _ => None,
+        };
+        codes.ok_or_else(|| Error::InvalidField(mk_invalid_query_field(
+            self.last_key().into(),
+            self.gen_path(),
+        )))
+    }
+
+    // The operand of `$mod` is `[divisor, remainder]`,
+    // the fractions of the doubles are truncated.
+    fn parse_mod_operand(&self, value: &Bson) -> Result<(i64, i64)> {
+        let arr = crate::try_unwrap_array!("$mod", value);
+        if arr.len() != 2 {
+            return Err(Error::ValidationError("$mod takes exactly 2 arguments".to_string()));
+        }
+        let mut numbers = Vec::with_capacity(2);
+        for item in arr {
+            let number = crate::utils::bson::truncated_i64(item).ok_or_else(|| {
+                FieldTypeUnexpectedStruct {
+                    field_name: "$mod".into(),
+                    expected_ty: "Number".into(),
+                    actual_ty: format!("{}", item),
+                }
+            })?;
+            numbers.push(number);
+        }
+        if numbers[0] == 0 {
+            return Err(Error::ValidationError("the divisor of $mod cannot be 0".to_string()));
+        }
+        Ok((numbers[0], numbers[1]))
+    }
+
+    // The operand of the bitwise operators is an array of bit positions
+    // or a non-negative bitmask.
+    //
+    // The integers are sign-extended, so the positions above 63
+    // are the same as the sign bit.
+    fn parse_bit_mask(&self, op_name: &str, value: &Bson) -> Result<i64> {
+        let to_integer = |item: &Bson| -> Result<i64> {
+            let number = crate::utils::bson::exact_i64(item).ok_or_else(|| {
+                FieldTypeUnexpectedStruct {
+                    field_name: op_name.into(),
+                    expected_ty: "Integer".into(),
+                    actual_ty: format!("{}", item),
+                }
+            })?;
+            if number < 0 {
+                return Err(Error::ValidationError(format!("{} takes non-negative integers", op_name)));
+            }
+            Ok(number)
+        };
+        match value {
+            Bson::Array(positions) => {
+                let mut mask = 0i64;
+                for position in positions {
+                    let position = to_integer(position)?;
+                    mask |= 1i64 << position.min(63);
+                }
+                Ok(mask)
+            }
+            Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) => to_integer(value),
+            _ => Err(FieldTypeUnexpectedStruct {
+                field_name: op_name.into(),
+                expected_ty: "Array or Integer".into(),
+                actual_ty: format!("{}", value),
+            }.into()),
+        }
+    }
+
     // very complex query document
     fn emit_query_tuple_document(
         &mut self,
@@ -854,8 +1316,9 @@ impl Codegen {
         }
         let next_label = self.new_label();
 
-        for stage_item in &ctx.items {
-            self.emit_goto(DbOp::Call, stage_item.next_label);
+        // the stages are chained, every stage calls the next one with its output
+        if let Some(first_stage) = ctx.items.first() {
+            self.emit_goto(DbOp::Call, first_stage.next_label);
             self.emit_u32(1);
         }
 
@@ -944,7 +1407,14 @@ impl Codegen {
                     }
                     "$sort" => {
                         let next_fun = ctx.items[index + 1].next_label;
-                        let external_func: Box<dyn VmExternalFunc> = VmFuncSort::compile(&mut self.paths, value)?;
+                        let top_k = Codegen::sort_top_k(&pipeline[index + 1..]);
+                        let external_func: Box<dyn VmExternalFunc> = VmFuncSort::compile(
+                            &mut self.paths,
+                            value,
+                            self.sort_memory_limit,
+                            &self.sort_spill_dir,
+                            top_k,
+                        )?;
                         self.emit_external_func(external_func, stage_ctx_item, next_fun);
                     }
                     "$addFields" => {
@@ -961,6 +1431,14 @@ impl Codegen {
                         let external_func: Box<dyn VmExternalFunc> = VmFuncUnset::compile(&mut self.paths, value)?;
                         self.emit_external_func(external_func, stage_ctx_item, next_fun);
                     }
+                    "$project" => {
+                        let next_fun = ctx.items[index + 1].next_label;
+                        let external_func: Box<dyn VmExternalFunc> = VmFuncProject::compile(&mut self.paths, value)?;
+                        self.emit_external_func(external_func, stage_ctx_item, next_fun);
+                    }
+                    "$vectorSearch" => {
+                        return Err(Error::ValidationError("$vectorSearch must be the first stage of the pipeline".into()));
+                    }
                     _ => {
                         return Err(Error::UnknownAggregationOperation(key.clone()));
                     }
@@ -971,10 +1449,39 @@ impl Codegen {
         Ok(())
     }
 
+    // If the $sort is followed by a $limit (after an optional $skip),
+    // only the first `skip + limit` documents need to be sorted.
+    fn sort_top_k(following: &[Document]) -> Option<usize> {
+        fn stage_count(stage: Option<&Document>, name: &str) -> Option<usize> {
+            let stage = stage?;
+            if stage.len() != 1 {
+                return None;
+            }
+            match stage.get(name)? {
+                Bson::Int32(val) if *val >= 0 => Some(*val as usize),
+                Bson::Int64(val) if *val >= 0 => Some(*val as usize),
+                _ => None,
+            }
+        }
+
+        let mut iter = following.iter();
+        let mut stage = iter.next();
+        let skip = match stage_count(stage, "$skip") {
+            Some(skip) => {
+                stage = iter.next();
+                skip
+            }
+            None => 0,
+        };
+        let limit = stage_count(stage, "$limit")?;
+        skip.checked_add(limit)
+    }
+
     fn emit_external_func(&mut self, external_func: Box<dyn VmExternalFunc>, stage_ctx_item: &PipelineItem, next_fun: Label) {
         let external_func_id = self.push_external_func(external_func);
         let go_next = self.new_label();
         let loop_next = self.new_label();
+        let is_null = self.new_label();
 
         // $count_next =>
         self.emit_label(stage_ctx_item.next_label);
@@ -987,7 +1494,12 @@ impl Codegen {
         self.emit(DbOp::Pop);
         self.emit_ret(0);
 
+        // The null marks the end of the input,
+        // it's not passed to the next stage because every stage is completed
+        // by the code before closing.
         self.emit_label(go_next);
+        self.emit(DbOp::EqualNull);
+        self.emit_goto(DbOp::IfTrue, is_null);
         self.emit_goto(DbOp::Call, next_fun);
         self.emit_u32(1);
         self.emit(DbOp::Pop);
@@ -998,6 +1510,9 @@ impl Codegen {
         self.emit_goto(DbOp::IfFalse, loop_next);
 
         self.emit_ret(0);
+
+        self.emit_label(is_null);
+        self.emit_ret(0);
     }
 
     fn emit_call_external_func_id(&mut self, external_func_id: u32, param_size: usize) {
@@ -1197,6 +1712,12 @@ impl Codegen {
         pos
     }
 
+    pub(super) fn push_index_scan(&mut self, index_scan: IndexScan) -> u32 {
+        let pos = self.program.index_scans.len() as u32;
+        self.program.index_scans.push(index_scan);
+        pos
+    }
+
     pub(super) fn emit_push_value(&mut self, static_id: u32) {
         self.emit(DbOp::PushValue);
         let bytes = static_id.to_le_bytes();
@@ -27,8 +27,14 @@ mod vm_skip;
 mod vm_sort;
 mod vm_limit;
 mod vm_unset;
+mod vm_project;
 mod vm_add_fields;
+mod vm_expr;
 mod update_operators;
+mod query_planner;
+mod plan_cache;
 
 pub(crate) use subprogram::SubProgram;
 pub(crate) use vm::{VM, VmState};
+pub(crate) use plan_cache::PlanCache;
+pub(crate) use query_planner::PlanContext;
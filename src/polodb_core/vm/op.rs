@@ -14,6 +14,7 @@
 
 use std::cmp::Ordering;
 use bson::Bson;
+use crate::utils::memcomparable::type_class;
 
 #[repr(u8)]
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -65,10 +66,13 @@ pub enum DbOp {
     // op1. location: 4 bytes
     FindByPrimaryKey,
 
-    // reset the cursor pointer to the element
+    // start to scan the ranges of the index,
+    // push the first document found to the stack
+    // if the item can not be found, jump to the location
     //
-    // 5 bytes
-    // op1. location: 4 bytes
+    // 9 bytes
+    // op1. index scan id: 4 bytes
+    // op2. location: 4 bytes
     FindByIndex,
 
     // next element of the cursor
@@ -137,6 +141,23 @@ pub enum DbOp {
     // op2. location: 4bytes
     GetField,
 
+    // get the values of the path in the document on the top of the stack,
+    // the arrays on the path are traversed,
+    // push the array of the values to the stack
+    //
+    // if no value is found, goto op2
+    //
+    // 9 bytes
+    // op1. path_index: 4bytes
+    // op2. location: 4bytes
+    GetPathValues,
+
+    // add the elements of the arrays in the array on the top of the stack
+    // to the array, following the arrays
+    //
+    // 1 byte
+    ExpandArrays,
+
     // remove the field
     //
     // 5 bytes
@@ -223,6 +244,38 @@ pub enum DbOp {
 
     EqualNull,
 
+    // check if the type of top-2 is one of
+    // the type codes in the array of top-1
+    // the result is stored in r0
+    //
+    // 1 byte
+    IsType,
+
+    // check if the integer of top-2 divided by top-1[0] leaves
+    // the remainder top-1[1], the fraction of a double is truncated
+    // the result is stored in r0
+    //
+    // 1 byte
+    Mod,
+
+    // check the bits of the integer of top-2 masked by top-1
+    // the result is stored in r0
+    //
+    // 1 byte
+    BitsAllSet,
+    BitsAnySet,
+    BitsAllClear,
+    BitsAnyClear,
+
+    // iterate the array of top-2, top-1 is the index of the next element
+    // push the next element to the stack and increase the index
+    //
+    // if there is no next element, set r0 to false and goto op1
+    //
+    // 5 bytes
+    // op1. location: 4 bytes
+    NextElement,
+
     // open a cursor with op0 as root_pid
     //
     // 5 bytes
@@ -317,3 +370,19 @@ pub(crate) fn generic_cmp(op: DbOp, val1: &Bson, val2: &Bson) -> crate::Result<b
     );
     Ok(result)
 }
+
+/// Compare the values for the comparison operators of the queries.
+///
+/// Unlike [`generic_cmp`] following the BSON comparison order,
+/// the values of different types are not compared by the range operators,
+/// e.g. `{ $gt: 1 }` doesn't match a string.
+pub(crate) fn query_cmp(op: DbOp, val1: &Bson, val2: &Bson) -> crate::Result<bool> {
+    if op != DbOp::Equal && type_class(val1) != type_class(val2) {
+        return Ok(false);
+    }
+    match generic_cmp(op, val1, val2) {
+        // the documents and the arrays are only compared for equality
+        Err(_) if op == DbOp::Equal => Ok(val1 == val2),
+        result => result,
+    }
+}
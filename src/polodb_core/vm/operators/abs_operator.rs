@@ -89,7 +89,7 @@ impl VmOperator for AbsOperator {
                 Self::bson_abs(op.next(input)),
             OperatorExpr::Alias(ref field_name) => {
                 let unwrap = match input {
-                    Bson::Document(doc) => doc.get(field_name).cloned(),
+                    Bson::Document(doc) => crate::utils::bson::try_get_document_value(doc, field_name),
                     _ => None,
                 }.unwrap_or(Bson::Null);
                 Self::bson_abs(unwrap)
@@ -0,0 +1,148 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::convert::TryFrom;
+use bson::Bson;
+use crate::vm::operators::{OpRegistry, OperatorExpr, VmOperator};
+use crate::{Error, Result};
+
+#[derive(Clone, Copy, PartialEq)]
+enum ArithmeticOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+/// The arithmetic operators of the expressions, `$add`, `$subtract`, `$multiply` and `$divide`.
+///
+/// The result is an integer if all the arguments are integers, except for `$divide`,
+/// it's null if any argument is not a number, or the divisor is zero.
+pub(crate) struct ArithmeticOperator {
+    op: ArithmeticOp,
+    args: Vec<OperatorExpr>,
+}
+
+impl ArithmeticOperator {
+
+    pub(crate) fn compile(paths: &mut Vec<String>, registry: OpRegistry, name: &str, v: &Bson) -> Result<Box<dyn VmOperator>> {
+        let op = match name {
+            "$add" => ArithmeticOp::Add,
+            "$subtract" => ArithmeticOp::Subtract,
+            "$multiply" => ArithmeticOp::Multiply,
+            "$divide" => ArithmeticOp::Divide,
+            _ => return Err(Error::UnknownAggregationOperation(name.to_string())),
+        };
+        let args = match v {
+            Bson::Array(args) => args,
+            _ => return Err(Error::ValidationError(format!("{} takes an array of arguments", name))),
+        };
+        let is_binary = matches!(op, ArithmeticOp::Subtract | ArithmeticOp::Divide);
+        if is_binary && args.len() != 2 {
+            return Err(Error::ValidationError(format!("{} takes exactly 2 arguments", name)));
+        }
+        let args = args
+            .iter()
+            .map(|arg| OperatorExpr::compile(paths, &registry, arg))
+            .collect::<Result<Vec<OperatorExpr>>>()?;
+        Ok(Box::new(ArithmeticOperator {
+            op,
+            args,
+        }))
+    }
+
+    fn fold_i64(&self, values: &[i64]) -> Option<i64> {
+        let (first, remains) = values.split_first()?;
+        remains.iter().try_fold(*first, |acc, value| match self.op {
+            ArithmeticOp::Add => acc.checked_add(*value),
+            ArithmeticOp::Subtract => acc.checked_sub(*value),
+            ArithmeticOp::Multiply => acc.checked_mul(*value),
+            ArithmeticOp::Divide => None,
+        })
+    }
+
+    fn fold_f64(&self, values: &[f64]) -> Option<f64> {
+        let (first, remains) = values.split_first()?;
+        remains.iter().try_fold(*first, |acc, value| match self.op {
+            ArithmeticOp::Add => Some(acc + value),
+            ArithmeticOp::Subtract => Some(acc - value),
+            ArithmeticOp::Multiply => Some(acc * value),
+            ArithmeticOp::Divide if *value == 0.0 => None,
+            ArithmeticOp::Divide => Some(acc / value),
+        })
+    }
+
+    fn apply(&self, values: &[Bson]) -> Option<Bson> {
+        let mut has_double = false;
+        let mut has_long = false;
+        for value in values {
+            match value {
+                Bson::Int32(_) => (),
+                Bson::Int64(_) => has_long = true,
+                Bson::Double(_) => has_double = true,
+                _ => return None,
+            }
+        }
+
+        if !has_double && self.op != ArithmeticOp::Divide {
+            let integers = values
+                .iter()
+                .map(|value| match value {
+                    Bson::Int32(i) => *i as i64,
+                    Bson::Int64(i) => *i,
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<i64>>();
+            // the overflowed integers are computed as doubles
+            if let Some(result) = self.fold_i64(&integers) {
+                if !has_long {
+                    if let Ok(result) = i32::try_from(result) {
+                        return Some(Bson::Int32(result));
+                    }
+                }
+                return Some(Bson::Int64(result));
+            }
+        }
+
+        let numbers = values
+            .iter()
+            .map(|value| match value {
+                Bson::Int32(i) => *i as f64,
+                Bson::Int64(i) => *i as f64,
+                Bson::Double(f) => *f,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<f64>>();
+        self.fold_f64(&numbers).map(Bson::Double)
+    }
+
+}
+
+impl VmOperator for ArithmeticOperator {
+    fn initial_value(&self) -> Bson {
+        Bson::Null
+    }
+
+    fn next(&self, input: &Bson) -> Bson {
+        let values = self.args
+            .iter()
+            .map(|arg| arg.evaluate(input))
+            .collect::<Vec<Bson>>();
+        self.apply(&values).unwrap_or(Bson::Null)
+    }
+
+    fn complete(&self) -> Bson {
+        Bson::Null
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Ordering;
+use bson::Bson;
+use crate::vm::operators::{OpRegistry, OperatorExpr, VmOperator};
+use crate::{Error, Result};
+
+#[derive(Clone, Copy)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Cmp,
+}
+
+/// The comparison of two expressions, e.g. `{ "$gt": ["$spent", "$budget"] }`.
+///
+/// Unlike the query operators, the values of different types
+/// are compared in the BSON comparison order.
+pub(crate) struct CompareOperator {
+    op: CompareOp,
+    lhs: OperatorExpr,
+    rhs: OperatorExpr,
+}
+
+impl CompareOperator {
+
+    pub(crate) fn compile(paths: &mut Vec<String>, registry: OpRegistry, name: &str, v: &Bson) -> Result<Box<dyn VmOperator>> {
+        let op = match name {
+            "$eq" => CompareOp::Eq,
+            "$ne" => CompareOp::Ne,
+            "$gt" => CompareOp::Gt,
+            "$gte" => CompareOp::Gte,
+            "$lt" => CompareOp::Lt,
+            "$lte" => CompareOp::Lte,
+            "$cmp" => CompareOp::Cmp,
+            _ => return Err(Error::UnknownAggregationOperation(name.to_string())),
+        };
+        let args = match v {
+            Bson::Array(args) if args.len() == 2 => args,
+            _ => return Err(Error::ValidationError(format!("{} takes exactly 2 arguments", name))),
+        };
+        let lhs = OperatorExpr::compile(paths, &registry, &args[0])?;
+        let rhs = OperatorExpr::compile(paths, &registry, &args[1])?;
+        Ok(Box::new(CompareOperator {
+            op,
+            lhs,
+            rhs,
+        }))
+    }
+
+    // The documents and the arrays are only compared for equality.
+    fn compare(a: &Bson, b: &Bson) -> Option<Ordering> {
+        match crate::utils::bson::value_cmp(a, b) {
+            Ok(ord) => Some(ord),
+            Err(_) if a == b => Some(Ordering::Equal),
+            Err(_) => None,
+        }
+    }
+
+}
+
+impl VmOperator for CompareOperator {
+    fn initial_value(&self) -> Bson {
+        Bson::Null
+    }
+
+    fn next(&self, input: &Bson) -> Bson {
+        let lhs = self.lhs.evaluate(input);
+        let rhs = self.rhs.evaluate(input);
+        let ord = CompareOperator::compare(&lhs, &rhs);
+        let result = match self.op {
+            CompareOp::Eq => ord == Some(Ordering::Equal),
+            CompareOp::Ne => ord != Some(Ordering::Equal),
+            CompareOp::Gt => ord == Some(Ordering::Greater),
+            CompareOp::Gte => matches!(ord, Some(Ordering::Greater | Ordering::Equal)),
+            CompareOp::Lt => ord == Some(Ordering::Less),
+            CompareOp::Lte => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+            CompareOp::Cmp => {
+                return match ord {
+                    Some(ord) => Bson::Int32(ord as i32),
+                    None => Bson::Null,
+                };
+            }
+        };
+        Bson::Boolean(result)
+    }
+
+    fn complete(&self) -> Bson {
+        Bson::Null
+    }
+}
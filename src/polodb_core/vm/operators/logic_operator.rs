@@ -0,0 +1,78 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bson::Bson;
+use crate::vm::operators::{is_truthy, OpRegistry, OperatorExpr, VmOperator};
+use crate::{Error, Result};
+
+#[derive(Clone, Copy, PartialEq)]
+enum LogicOp {
+    And,
+    Or,
+    Not,
+}
+
+/// The boolean operators of the expressions, `$and`, `$or` and `$not`.
+pub(crate) struct LogicOperator {
+    op: LogicOp,
+    args: Vec<OperatorExpr>,
+}
+
+impl LogicOperator {
+
+    pub(crate) fn compile(paths: &mut Vec<String>, registry: OpRegistry, name: &str, v: &Bson) -> Result<Box<dyn VmOperator>> {
+        let op = match name {
+            "$and" => LogicOp::And,
+            "$or" => LogicOp::Or,
+            "$not" => LogicOp::Not,
+            _ => return Err(Error::UnknownAggregationOperation(name.to_string())),
+        };
+        // `{ "$not": expr }` is the same as `{ "$not": [expr] }`
+        let args = match v {
+            Bson::Array(args) => args
+                .iter()
+                .map(|arg| OperatorExpr::compile(paths, &registry, arg))
+                .collect::<Result<Vec<OperatorExpr>>>()?,
+            _ => vec![OperatorExpr::compile(paths, &registry, v)?],
+        };
+        if op == LogicOp::Not && args.len() != 1 {
+            return Err(Error::ValidationError("$not takes exactly 1 argument".to_string()));
+        }
+        Ok(Box::new(LogicOperator {
+            op,
+            args,
+        }))
+    }
+
+}
+
+impl VmOperator for LogicOperator {
+    fn initial_value(&self) -> Bson {
+        Bson::Null
+    }
+
+    fn next(&self, input: &Bson) -> Bson {
+        let mut values = self.args.iter().map(|arg| is_truthy(&arg.evaluate(input)));
+        let result = match self.op {
+            LogicOp::And => values.all(|value| value),
+            LogicOp::Or => values.any(|value| value),
+            LogicOp::Not => !values.all(|value| value),
+        };
+        Bson::Boolean(result)
+    }
+
+    fn complete(&self) -> Bson {
+        Bson::Null
+    }
+}
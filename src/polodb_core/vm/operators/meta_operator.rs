@@ -0,0 +1,61 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bson::Bson;
+use crate::index::{TEXT_SCORE_FIELD, VECTOR_SEARCH_SCORE_FIELD};
+use crate::vm::operators::VmOperator;
+use crate::{Error, Result};
+
+/// `{ "$meta": "textScore" }`, the score of the document found by `$text`,
+/// or `{ "$meta": "vectorSearchScore" }`, the score of the document found by `$vectorSearch`.
+pub(crate) struct MetaOperator {
+    field: &'static str,
+}
+
+impl MetaOperator {
+
+    pub(crate) fn compile(v: &Bson) -> Result<Box<dyn VmOperator>> {
+        match v.as_str().and_then(MetaOperator::meta_field) {
+            Some(field) => Ok(Box::new(MetaOperator { field })),
+            None => Err(Error::UnknownAggregationOperation("$meta".to_string())),
+        }
+    }
+
+    /// The reserved field holding the metadata of the name.
+    pub(crate) fn meta_field(name: &str) -> Option<&'static str> {
+        match name {
+            "textScore" => Some(TEXT_SCORE_FIELD),
+            "vectorSearchScore" => Some(VECTOR_SEARCH_SCORE_FIELD),
+            _ => None,
+        }
+    }
+
+}
+
+impl VmOperator for MetaOperator {
+    fn initial_value(&self) -> Bson {
+        Bson::Null
+    }
+
+    fn next(&self, input: &Bson) -> Bson {
+        match input {
+            Bson::Document(doc) => doc.get(self.field).cloned(),
+            _ => None,
+        }.unwrap_or(Bson::Null)
+    }
+
+    fn complete(&self) -> Bson {
+        Bson::Null
+    }
+}
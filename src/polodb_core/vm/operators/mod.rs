@@ -1,8 +1,13 @@
 mod sum_operator;
 mod op_registry;
 mod abs_operator;
+mod meta_operator;
+mod compare_operator;
+mod logic_operator;
+mod arithmetic_operator;
 
 use bson::Bson;
+use crate::Result;
 
 pub(crate) trait VmOperator {
 
@@ -20,6 +25,54 @@ pub(crate) enum OperatorExpr {
     Alias(String),
 }
 
+impl OperatorExpr {
+
+    /// Compile an argument of an expression,
+    /// which is a field path like `"$age"`, an operator or a constant.
+    pub(crate) fn compile(paths: &mut Vec<String>, registry: &OpRegistry, v: &Bson) -> Result<OperatorExpr> {
+        let expr = match v {
+            Bson::String(field_name) if field_name.starts_with('$') => {
+                OperatorExpr::Alias(field_name[1..].to_string())
+            }
+            Bson::Document(doc) if doc.keys().next().is_some_and(|key| key.starts_with('$')) => {
+                OperatorExpr::Expr(registry.compile_doc(paths, doc)?)
+            }
+            _ => OperatorExpr::Constant(v.clone()),
+        };
+        Ok(expr)
+    }
+
+    /// The value of the expression on the input document.
+    pub(crate) fn evaluate(&self, input: &Bson) -> Bson {
+        match self {
+            OperatorExpr::Constant(v) => v.clone(),
+            OperatorExpr::Expr(op) => op.next(input),
+            OperatorExpr::Alias(field_name) => match input {
+                Bson::Document(doc) => crate::utils::bson::try_get_document_value(doc, field_name),
+                _ => None,
+            }.unwrap_or(Bson::Null),
+        }
+    }
+
+}
+
+/// Whether the value is true in a boolean expression,
+/// false, null, undefined and zero are false.
+pub(crate) fn is_truthy(value: &Bson) -> bool {
+    match value {
+        Bson::Boolean(b) => *b,
+        Bson::Null | Bson::Undefined => false,
+        Bson::Int32(i) => *i != 0,
+        Bson::Int64(i) => *i != 0,
+        Bson::Double(f) => *f != 0.0,
+        _ => true,
+    }
+}
+
 pub(crate) use sum_operator::SumOperator;
 pub(crate) use abs_operator::AbsOperator;
+pub(crate) use meta_operator::MetaOperator;
+pub(crate) use compare_operator::CompareOperator;
+pub(crate) use logic_operator::LogicOperator;
+pub(crate) use arithmetic_operator::ArithmeticOperator;
 pub(crate) use op_registry::OpRegistry;
@@ -15,7 +15,7 @@
 use bson::{Bson, Document};
 use crate::{Error, Result};
 use crate::errors::mk_invalid_aggregate_field;
-use crate::vm::operators::{AbsOperator, SumOperator, VmOperator};
+use crate::vm::operators::{AbsOperator, ArithmeticOperator, CompareOperator, LogicOperator, MetaOperator, SumOperator, VmOperator};
 
 // Reference: https://www.mongodb.com/docs/manual/reference/operator/aggregation/
 #[derive(Clone)]
@@ -41,6 +41,14 @@ impl OpRegistry {
             match op_name.as_str() {
                 "$sum" => SumOperator::compile(op_value),
                 "$abs" => AbsOperator::compile(paths, self.clone(), op_value)?,
+                "$meta" => MetaOperator::compile(op_value)?,
+                "$eq" | "$ne" | "$gt" | "$gte" | "$lt" | "$lte" | "$cmp" => {
+                    CompareOperator::compile(paths, self.clone(), op_name, op_value)?
+                }
+                "$and" | "$or" | "$not" => LogicOperator::compile(paths, self.clone(), op_name, op_value)?,
+                "$add" | "$subtract" | "$multiply" | "$divide" => {
+                    ArithmeticOperator::compile(paths, self.clone(), op_name, op_value)?
+                }
                 _ => {
                     let invalid_err = mk_invalid_aggregate_field(paths);
                     return Err(Error::InvalidField(invalid_err))
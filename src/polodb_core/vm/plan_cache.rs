@@ -0,0 +1,170 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use bson::{Bson, Document};
+use crate::coll::collection_info::CollectionSpecification;
+
+/// The entries are cleared when the cache is full.
+const PLAN_CACHE_CAPACITY: usize = 1024;
+
+/// The documents written to the collection after an entry is cached,
+/// the entry is ignored after that, because the data may be changed a lot.
+const PLAN_CACHE_WRITES_LIMIT: u64 = 1000;
+
+/// The indexes chosen for the shapes of the queries,
+/// so the candidate plans of a query are not estimated again.
+///
+/// An entry is ignored after the indexes of the collection change,
+/// or after `PLAN_CACHE_WRITES_LIMIT` documents are written to the collection.
+pub(crate) struct PlanCache {
+    entries: Mutex<HashMap<(String, String), CachedPlan>>,
+    /// The documents written to every collection since the database is opened.
+    writes: Mutex<HashMap<String, u64>>,
+}
+
+struct CachedPlan {
+    indexes_signature: String,
+    /// The chosen index, or the indexes of the branches of `$or`.
+    index_names: Vec<String>,
+    /// The writes of the collection when the entry is cached.
+    writes: u64,
+}
+
+impl PlanCache {
+
+    pub fn new() -> PlanCache {
+        PlanCache {
+            entries: Mutex::new(HashMap::new()),
+            writes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, col_spec: &CollectionSpecification, shape: &str) -> Option<Vec<String>> {
+        let writes = self.writes(&col_spec._id);
+        let entries = self.entries.lock().ok()?;
+        let cached = entries.get(&(col_spec._id.clone(), shape.to_string()))?;
+        if cached.indexes_signature != indexes_signature(col_spec) {
+            return None;
+        }
+        if writes.saturating_sub(cached.writes) >= PLAN_CACHE_WRITES_LIMIT {
+            return None;
+        }
+        Some(cached.index_names.clone())
+    }
+
+    pub fn insert(&self, col_spec: &CollectionSpecification, shape: String, index_names: Vec<String>) {
+        let writes = self.writes(&col_spec._id);
+        let mut entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        if entries.len() >= PLAN_CACHE_CAPACITY {
+            entries.clear();
+        }
+        entries.insert((col_spec._id.clone(), shape), CachedPlan {
+            indexes_signature: indexes_signature(col_spec),
+            index_names,
+            writes,
+        });
+    }
+
+    /// Count the documents inserted, updated or deleted in the collection.
+    pub fn record_writes(&self, col_name: &str, count: u64) {
+        if count == 0 {
+            return;
+        }
+        if let Ok(mut writes) = self.writes.lock() {
+            let col_writes = writes.entry(col_name.to_string()).or_insert(0);
+            *col_writes = col_writes.saturating_add(count);
+        }
+    }
+
+    fn writes(&self, col_name: &str) -> u64 {
+        self.writes.lock()
+            .ok()
+            .and_then(|writes| writes.get(col_name).copied())
+            .unwrap_or(0)
+    }
+
+}
+
+// The indexes and the states affecting the plans,
+// an index dropped and created again with other keys changes the signature too.
+fn indexes_signature(col_spec: &CollectionSpecification) -> String {
+    let mut signature = String::new();
+    for (index_name, index_info) in &col_spec.indexes {
+        signature.push_str(index_name);
+        signature.push_str(&format!("{:?}", index_info.keys));
+        signature.push(if index_info.multikey { '*' } else { ' ' });
+        signature.push(if index_info.is_building() { '~' } else { ' ' });
+        signature.push(',');
+    }
+    signature
+}
+
+/// The shape of the query and the sort, the values are replaced by their types,
+/// for example `{ "age": { "$gt": 18 } }` is `{age:{$gt:Int32,},}`.
+pub(crate) fn query_shape(query: &Document, sort: Option<&Document>) -> String {
+    let mut shape = String::new();
+    document_shape(&mut shape, query);
+    if let Some(sort) = sort {
+        shape.push_str(" sort ");
+        shape.push_str(&sort.to_string());
+    }
+    shape
+}
+
+fn document_shape(shape: &mut String, doc: &Document) {
+    shape.push('{');
+    for (key, value) in doc {
+        shape.push_str(key);
+        shape.push(':');
+        value_shape(shape, value);
+        shape.push(',');
+    }
+    shape.push('}');
+}
+
+fn value_shape(shape: &mut String, value: &Bson) {
+    match value {
+        Bson::Document(doc) => document_shape(shape, doc),
+        Bson::Array(arr) => {
+            shape.push('[');
+            for item in arr {
+                value_shape(shape, item);
+                shape.push(',');
+            }
+            shape.push(']');
+        }
+        _ => shape.push_str(&format!("{:?}", value.element_type())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+    use super::query_shape;
+
+    #[test]
+    fn test_query_shape() {
+        let shape = query_shape(&doc! { "age": { "$gt": 18 }, "name": "a" }, None);
+        assert_eq!(shape, "{age:{$gt:Int32,},name:String,}");
+        assert_eq!(shape, query_shape(&doc! { "age": { "$gt": 30 }, "name": "b" }, None));
+        assert_ne!(shape, query_shape(&doc! { "age": { "$lt": 18 }, "name": "a" }, None));
+        assert_ne!(shape, query_shape(&doc! { "age": { "$gt": 18 }, "name": "a" }, Some(&doc! { "age": 1 })));
+    }
+
+}
@@ -0,0 +1,1324 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::fmt;
+use std::ops::Bound;
+use bson::{doc, Bson, Document};
+use bson::spec::ElementType;
+use crate::coll::collection_info::{CollectionSpecification, IndexInfo, IndexType};
+use crate::index::{covers_path, hash_value, GeoPoint, GeoQuery, IndexHelper, IndexKeyRange, PartialFilter, TextSearch, VectorSearch, VectorSimilarity};
+use std::sync::Arc;
+use crate::{Error, Result};
+use crate::options::Hint;
+use crate::transaction::TransactionInner;
+use crate::vm::plan_cache::{query_shape, PlanCache};
+
+/// The way to find the documents matching a query.
+pub(crate) enum QueryPlan {
+    /// Find the only document by the primary key.
+    PrimaryKey(Bson),
+    /// Scan the keys of an index.
+    IndexScan(Box<IndexScanPlan>),
+    /// Scan all the documents of the collection.
+    CollectionScan,
+}
+
+impl QueryPlan {
+
+    /// `COLLSCAN`, `IDHACK` for the primary key, `IXSCAN`, or `OR` for a union of indexes.
+    pub fn explain(&self) -> Document {
+        match self {
+            QueryPlan::PrimaryKey(pkey) => doc! {
+                "stage": "IDHACK",
+                "_id": pkey.clone(),
+            },
+            QueryPlan::IndexScan(plan) => {
+                let mut result = plan.explain();
+                if let Some(keys) = plan.estimate.keys {
+                    result.insert("estimatedKeys", keys as i64);
+                }
+                result.insert("fromPlanCache", plan.estimate.from_cache);
+                result
+            }
+            QueryPlan::CollectionScan => doc! {
+                "stage": "COLLSCAN",
+            },
+        }
+    }
+
+}
+
+pub(crate) struct IndexScanPlan {
+    pub scan: IndexScan,
+    /// The conditions of the query which are not covered by the index.
+    pub remain_query: Document,
+    /// The documents are returned in the order of the requested sort.
+    pub sort_satisfied: bool,
+    pub estimate: PlanEstimate,
+    /// The plans of the branches of `$or` if the scan is the union of them,
+    /// the ranges of the scan are the ranges of all the branches.
+    pub union: Vec<IndexScanPlan>,
+}
+
+impl IndexScanPlan {
+
+    // A union of the indexes is explained as the `OR` stage of the scans of its branches.
+    fn explain(&self) -> Document {
+        if !self.union.is_empty() {
+            let input_stages = self.union
+                .iter()
+                .map(|branch| Bson::Document(branch.explain()))
+                .collect::<Vec<Bson>>();
+            return doc! {
+                "stage": "OR",
+                "inputStages": input_stages,
+                "filter": self.remain_query.clone(),
+            };
+        }
+
+        let scan = &self.scan;
+        let mut result = doc! {
+            "stage": "IXSCAN",
+            "indexName": scan.index_name.clone(),
+            "indexBounds": scan.bounds.clone(),
+            "direction": if scan.reverse { "backward" } else { "forward" },
+            "isMultiKey": scan.multikey,
+            "sortSatisfied": self.sort_satisfied,
+        };
+        if scan.text.is_some() {
+            result.insert("search", "text");
+        } else if let Some(geo) = &scan.geo {
+            result.insert("search", if geo.query.is_near() { "near" } else { "geo" });
+        } else if scan.vector.is_some() {
+            result.insert("search", "vector");
+        }
+        if !self.remain_query.is_empty() {
+            result.insert("filter", self.remain_query.clone());
+        }
+        result
+    }
+
+}
+
+/// The cost of the plan estimated by the planner.
+#[derive(Default)]
+pub(crate) struct PlanEstimate {
+    /// The keys in the ranges of the index, it's counted up to `PLAN_SAMPLE_LIMIT`,
+    /// and not counted if the plan is the only candidate.
+    pub keys: Option<u64>,
+    /// The plan is chosen for the same shape of query before.
+    pub from_cache: bool,
+    /// The indexes of the other candidates and their estimated keys.
+    pub rejected: Vec<(String, Option<u64>)>,
+}
+
+/// The keys counted for a candidate plan at most,
+/// the candidates with more keys are considered equally expensive.
+const PLAN_SAMPLE_LIMIT: u64 = 1000;
+
+/// The storage and the plan cache used to choose among the candidate plans,
+/// the candidates are ranked by the heuristics without it.
+#[derive(Clone)]
+pub(crate) struct PlanContext {
+    pub txn: TransactionInner,
+    pub cache: Arc<PlanCache>,
+    /// The plan required by the user, the plan is not cached.
+    pub hint: Option<Hint>,
+}
+
+// The hint resolved in the collection.
+enum HintedPlan<'a> {
+    CollectionScan,
+    Index(&'a String, &'a IndexInfo),
+}
+
+// A plan searching the query by an index, or by a union of indexes.
+struct Candidate {
+    plan: IndexScanPlan,
+    /// The kind of the index, the leading keys matched by equality,
+    /// whether the following key has a range condition, and whether the sort is satisfied.
+    rank: (u8, usize, bool, bool),
+    index_names: Vec<String>,
+}
+
+// Count the keys in the ranges until the limit.
+fn count_keys(txn: &TransactionInner, ranges: &[IndexKeyRange], limit: u64) -> Result<u64> {
+    let kv_cursor = txn.rocksdb_txn.new_iterator();
+    let mut count: u64 = 0;
+    for range in ranges {
+        kv_cursor.seek(&range.lower);
+        while count < limit && kv_cursor.valid() {
+            let key = kv_cursor.copy_key_arc()?;
+            if !range.contains(key.as_ref()) {
+                break;
+            }
+            count += 1;
+            kv_cursor.next();
+        }
+        if count >= limit {
+            break;
+        }
+    }
+    Ok(count)
+}
+
+/// The ranges of an index to scan.
+pub(crate) struct IndexScan {
+    pub index_name: String,
+    pub ranges: Vec<IndexKeyRange>,
+    pub reverse: bool,
+    /// A document may be found more than once by a multikey index.
+    pub multikey: bool,
+    /// The bounds of every key of the index,
+    /// in the format of `{ "age": ["[32, 32]"] }`.
+    pub bounds: Document,
+    /// Set if the index is a text index searched by `$text`,
+    /// the ranges are the keys of the searched terms.
+    pub text: Option<Box<TextScan>>,
+    /// Set if the index is a 2dsphere index searched by a geospatial query,
+    /// the ranges are the cells containing the matching points.
+    pub geo: Option<Box<GeoScan>>,
+    /// Set if the index is a vector index searched by `$vectorSearch`,
+    /// the lists of the index are scanned instead of the ranges.
+    pub vector: Option<Box<VectorScan>>,
+}
+
+/// The search of the nearest vectors in a vector index.
+pub(crate) struct VectorScan {
+    pub index_prefix: Vec<u8>,
+    pub query_vector: Vec<f64>,
+    pub similarity: VectorSimilarity,
+    pub num_candidates: usize,
+}
+
+/// The geospatial query on the key of a 2dsphere index.
+pub(crate) struct GeoScan {
+    pub key: String,
+    pub query: GeoQuery,
+}
+
+impl GeoScan {
+
+    /// Test the point of the document,
+    /// return the distance for `$near`, or 0 if the point matches the others.
+    pub fn test(&self, doc: &Document) -> Option<f64> {
+        let value = crate::utils::bson::try_get_document_value(doc, &self.key)?;
+        let point = GeoPoint::from_bson(&value)?;
+        self.query.test(&point)
+    }
+
+}
+
+/// The search of a `$text` query in a text index.
+pub(crate) struct TextScan {
+    pub search: TextSearch,
+    /// The keys of the negated terms,
+    /// the documents found by them are excluded.
+    pub negated_ranges: Vec<IndexKeyRange>,
+}
+
+impl fmt::Display for IndexScan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\", {}", self.index_name, self.bounds)?;
+        if self.text.is_some() {
+            write!(f, ", text")?;
+        }
+        if self.geo.as_ref().map(|geo| geo.query.is_near()).unwrap_or(false) {
+            write!(f, ", near")?;
+        }
+        if self.vector.is_some() {
+            write!(f, ", vector")?;
+        }
+        if self.reverse {
+            write!(f, ", reverse")?;
+        }
+        if self.multikey {
+            write!(f, ", multikey")?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct QueryPlanner<'a> {
+    col_spec: &'a CollectionSpecification,
+    is_write: bool,
+    sort: Option<&'a Document>,
+    context: Option<&'a PlanContext>,
+}
+
+impl<'a> QueryPlanner<'a> {
+
+    pub fn new(col_spec: &'a CollectionSpecification, is_write: bool) -> QueryPlanner<'a> {
+        QueryPlanner {
+            col_spec,
+            is_write,
+            sort: None,
+            context: None,
+        }
+    }
+
+    /// The sort requested after the query,
+    /// an index returning the documents in this order is preferred.
+    pub fn with_sort(mut self, sort: Option<&'a Document>) -> QueryPlanner<'a> {
+        self.sort = sort;
+        self
+    }
+
+    /// The candidate plans are estimated by the keys in the storage,
+    /// and the chosen plans are cached.
+    pub fn with_context(mut self, context: Option<&'a PlanContext>) -> QueryPlanner<'a> {
+        self.context = context;
+        self
+    }
+
+    pub fn plan(&self, query: &Document) -> Result<QueryPlan> {
+        let hinted = self.resolve_hint()?;
+
+        if let Some(search) = query.get("$text") {
+            let plan = self.plan_text_search(search, query)?;
+            QueryPlanner::check_search_hint(hinted.as_ref(), &plan)?;
+            return Ok(QueryPlan::IndexScan(Box::new(plan)));
+        }
+
+        if let Some((key, op)) = QueryPlanner::find_geo_condition(query) {
+            let plan = self.plan_geo_search(key, op, query)?;
+            QueryPlanner::check_search_hint(hinted.as_ref(), &plan)?;
+            return Ok(QueryPlan::IndexScan(Box::new(plan)));
+        }
+
+        match hinted {
+            Some(HintedPlan::CollectionScan) => return Ok(QueryPlan::CollectionScan),
+            Some(HintedPlan::Index(index_name, index_info)) => {
+                let plan = self.plan_hinted_index(index_name, index_info, query)?;
+                return Ok(QueryPlan::IndexScan(Box::new(plan)));
+            }
+            None => (),
+        }
+
+        if let Some(id_value) = query.get("_id") {
+            if id_value.element_type() != ElementType::EmbeddedDocument {
+                return Ok(QueryPlan::PrimaryKey(id_value.clone()));
+            }
+        }
+
+        // the write operations modify the documents by the cursor of the collection
+        if self.is_write {
+            return Ok(QueryPlan::CollectionScan);
+        }
+
+        match self.plan_index(query)? {
+            Some(plan) => Ok(QueryPlan::IndexScan(Box::new(plan))),
+            None => Ok(QueryPlan::CollectionScan),
+        }
+    }
+
+    // The plan required by the hint of the query,
+    // the hinted index must exist in the collection.
+    fn resolve_hint(&self) -> Result<Option<HintedPlan<'a>>> {
+        let hint = match self.context.and_then(|context| context.hint.as_ref()) {
+            Some(hint) => hint,
+            None => return Ok(None),
+        };
+        let hinted = match hint {
+            Hint::Keys(keys) if keys.contains_key("$natural") => return Ok(Some(HintedPlan::CollectionScan)),
+            Hint::Keys(keys) => self.col_spec.indexes
+                .iter()
+                .find(|(index_name, index_info)| {
+                    QueryPlanner::is_same_key_pattern(&index_info.to_index_model(index_name).keys, keys)
+                })
+                .ok_or_else(|| Error::IndexNotFound(keys.to_string()))?,
+            Hint::Name(name) => self.col_spec.indexes
+                .get_key_value(name)
+                .ok_or_else(|| Error::IndexNotFound(name.clone()))?,
+        };
+        Ok(Some(HintedPlan::Index(hinted.0, hinted.1)))
+    }
+
+    // The orders of the keys are compared by their numeric values,
+    // the drivers may send them in any numeric type.
+    fn is_same_key_pattern(keys: &Document, hint: &Document) -> bool {
+        let numeric_order = |order: &Bson| match order {
+            Bson::Int32(order) => Some(*order as f64),
+            Bson::Int64(order) => Some(*order as f64),
+            Bson::Double(order) => Some(*order),
+            _ => None,
+        };
+        keys.len() == hint.len() && keys.iter().zip(hint).all(|((key, order), (hint_key, hint_order))| {
+            key == hint_key && match (numeric_order(order), numeric_order(hint_order)) {
+                (Some(order), Some(hint_order)) => order == hint_order,
+                _ => order == hint_order,
+            }
+        })
+    }
+
+    // The `$text`, geo and vector queries can only be searched by their own indexes.
+    fn check_search_hint(hinted: Option<&HintedPlan>, plan: &IndexScanPlan) -> Result<()> {
+        match hinted {
+            Some(HintedPlan::Index(index_name, _)) if *index_name == &plan.scan.index_name => Ok(()),
+            Some(_) => Err(Error::BadHint(format!(
+                "the query must be searched by the index '{}'",
+                plan.scan.index_name,
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // Search the query by the hinted index,
+    // every document is referenced by a regular index,
+    // so the whole index is scanned if no condition can be searched by it.
+    fn plan_hinted_index(&self, index_name: &str, index_info: &IndexInfo, query: &Document) -> Result<IndexScanPlan> {
+        if let Some(candidate) = self.index_candidate(index_name, index_info, query)? {
+            return Ok(candidate.plan);
+        }
+
+        let usable = QueryPlanner::is_index_usable(index_info, query);
+        if index_info.index_type != IndexType::Regular || !usable {
+            let reason = self.unusable_reason(index_info, query)
+                .unwrap_or("no condition of the query can be searched by the index");
+            return Err(Error::BadHint(format!("index '{}': {}", index_name, reason)));
+        }
+
+        let mut bounds = Document::new();
+        for (key, order) in &index_info.keys {
+            let full_range = if *order > 0 { "[MinKey, MaxKey]" } else { "[MaxKey, MinKey]" };
+            bounds.insert(key.clone(), vec![Bson::String(full_range.to_string())]);
+        }
+        let prefix = IndexHelper::make_index_prefix(&self.col_spec._id, index_name)?;
+
+        Ok(IndexScanPlan {
+            scan: IndexScan {
+                index_name: index_name.to_string(),
+                ranges: vec![IndexKeyRange::with_prefix(prefix)],
+                reverse: false,
+                multikey: index_info.multikey,
+                bounds,
+                text: None,
+                geo: None,
+                vector: None,
+            },
+            remain_query: query.clone(),
+            sort_satisfied: false,
+            estimate: PlanEstimate::default(),
+            union: Vec::new(),
+        })
+    }
+
+    // Choose the cheapest one of the candidate index scans,
+    // the keys in the ranges of every candidate are counted in the storage
+    // if the context is given, otherwise the candidates are ranked by the heuristics.
+    fn plan_index(&self, query: &Document) -> Result<Option<IndexScanPlan>> {
+        let shape = self.context.map(|_| query_shape(query, self.sort));
+        if let (Some(context), Some(shape)) = (self.context, shape.as_ref()) {
+            if let Some(index_names) = context.cache.get(self.col_spec, shape) {
+                if let Some(mut candidate) = self.plan_cached(query, &index_names)? {
+                    candidate.plan.estimate.from_cache = true;
+                    return Ok(Some(candidate.plan));
+                }
+            }
+        }
+
+        let mut candidates = self.index_candidates(query)?;
+        if let Some(union) = self.plan_or_union(query)? {
+            candidates.push(union);
+        }
+
+        let best_index = match self.choose_candidate(&mut candidates)? {
+            Some(best_index) => best_index,
+            None => return Ok(None),
+        };
+        let mut best = candidates.swap_remove(best_index);
+        best.plan.estimate.rejected = candidates
+            .iter()
+            .filter(|candidate| candidate.plan.union.is_empty())
+            .map(|candidate| (candidate.plan.scan.index_name.clone(), candidate.plan.estimate.keys))
+            .collect();
+
+        if let (Some(context), Some(shape)) = (self.context, shape) {
+            if !candidates.is_empty() {
+                context.cache.insert(self.col_spec, shape, best.index_names.clone());
+            }
+        }
+
+        Ok(Some(best.plan))
+    }
+
+    // Return the position of the best candidate,
+    // the one with the fewest keys, then the one with the highest rank.
+    // The keys are not counted if there is only one candidate.
+    fn choose_candidate(&self, candidates: &mut [Candidate]) -> Result<Option<usize>> {
+        if candidates.len() <= 1 {
+            return Ok(if candidates.is_empty() { None } else { Some(0) });
+        }
+        if let Some(context) = self.context {
+            // a candidate is not counted further once it has more keys than the best one
+            let mut limit = PLAN_SAMPLE_LIMIT;
+            for candidate in candidates.iter_mut() {
+                let keys = count_keys(&context.txn, &candidate.plan.scan.ranges, limit)?;
+                candidate.plan.estimate.keys = Some(keys);
+                limit = limit.min(keys + 1);
+            }
+        }
+
+        let mut best: Option<usize> = None;
+        for (index, candidate) in candidates.iter().enumerate() {
+            let is_better = match best {
+                Some(best) => {
+                    let best = &candidates[best];
+                    match candidate.plan.estimate.keys.cmp(&best.plan.estimate.keys) {
+                        std::cmp::Ordering::Less => true,
+                        std::cmp::Ordering::Equal => candidate.rank > best.rank,
+                        std::cmp::Ordering::Greater => false,
+                    }
+                }
+                None => true,
+            };
+            if is_better {
+                best = Some(index);
+            }
+        }
+
+        Ok(best)
+    }
+
+    // The index scans of the indexes which can search the query.
+    fn index_candidates(&self, query: &Document) -> Result<Vec<Candidate>> {
+        let mut candidates = Vec::new();
+        for (index_name, index_info) in &self.col_spec.indexes {
+            if let Some(candidate) = self.index_candidate(index_name, index_info, query)? {
+                candidates.push(candidate);
+            }
+        }
+        Ok(candidates)
+    }
+
+    fn index_candidate(&self, index_name: &str, index_info: &IndexInfo, query: &Document) -> Result<Option<Candidate>> {
+        if !QueryPlanner::is_index_usable(index_info, query) {
+            return Ok(None);
+        }
+        match index_info.index_type {
+            IndexType::Regular => self.regular_candidate(index_name, index_info, query),
+            IndexType::Hashed => self.hashed_candidate(index_name, index_info, query),
+            IndexType::Wildcard => self.wildcard_candidate(index_name, index_info, query),
+            _ => Ok(None),
+        }
+    }
+
+    // Plan the query by the indexes chosen for the same shape of query before,
+    // return `None` if any of them can't search the query.
+    fn plan_cached(&self, query: &Document, index_names: &[String]) -> Result<Option<Candidate>> {
+        let candidate_of = |index_name: &String, query: &Document| -> Result<Option<Candidate>> {
+            match self.col_spec.indexes.get(index_name) {
+                Some(index_info) => self.index_candidate(index_name, index_info, query),
+                None => Ok(None),
+            }
+        };
+
+        if index_names.len() == 1 {
+            return candidate_of(&index_names[0], query);
+        }
+
+        let branches = match QueryPlanner::or_branches(query) {
+            Some(branches) if branches.len() == index_names.len() => branches,
+            _ => return Ok(None),
+        };
+        let mut branch_candidates = Vec::with_capacity(branches.len());
+        for (branch, index_name) in branches.iter().zip(index_names) {
+            match candidate_of(index_name, branch)? {
+                Some(candidate) => branch_candidates.push(candidate),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(QueryPlanner::union_candidate(branch_candidates, query)))
+    }
+
+    // The branches of the `$or` of the query, if all of them are documents.
+    fn or_branches(query: &Document) -> Option<Vec<&Document>> {
+        let branches = query.get_array("$or").ok()?;
+        if branches.is_empty() {
+            return None;
+        }
+        branches.iter().map(|branch| branch.as_document()).collect()
+    }
+
+    // Every branch of `$or` is searched by its own index,
+    // the keys of all the branches are scanned and the documents are deduplicated,
+    // then the whole query is tested on them.
+    fn plan_or_union(&self, query: &Document) -> Result<Option<Candidate>> {
+        let branches = match QueryPlanner::or_branches(query) {
+            Some(branches) => branches,
+            None => return Ok(None),
+        };
+
+        let mut branch_candidates = Vec::with_capacity(branches.len());
+        for branch in branches {
+            let mut candidates = self.index_candidates(branch)?;
+            let best_index = match self.choose_candidate(&mut candidates)? {
+                Some(best_index) => best_index,
+                // a branch can't be searched by any index
+                None => return Ok(None),
+            };
+            branch_candidates.push(candidates.swap_remove(best_index));
+        }
+
+        Ok(Some(QueryPlanner::union_candidate(branch_candidates, query)))
+    }
+
+    fn union_candidate(branch_candidates: Vec<Candidate>, query: &Document) -> Candidate {
+        let mut ranges = Vec::new();
+        let mut index_names = Vec::with_capacity(branch_candidates.len());
+        let mut bounds = Vec::<Bson>::with_capacity(branch_candidates.len());
+        let mut branches = Vec::with_capacity(branch_candidates.len());
+        for candidate in branch_candidates {
+            let scan = &candidate.plan.scan;
+            ranges.extend(scan.ranges.iter().cloned());
+            index_names.push(scan.index_name.clone());
+            bounds.push(Bson::Document(doc! {
+                scan.index_name.clone(): scan.bounds.clone(),
+            }));
+            branches.push(candidate.plan);
+        }
+
+        Candidate {
+            plan: IndexScanPlan {
+                scan: IndexScan {
+                    // the cursor is opened by the first index,
+                    // the ranges of the other indexes are sought by the same cursor
+                    index_name: index_names[0].clone(),
+                    ranges,
+                    reverse: false,
+                    // a document may be found by more than one branch
+                    multikey: true,
+                    bounds: doc! {
+                        "$or": bounds,
+                    },
+                    text: None,
+                    geo: None,
+                    vector: None,
+                },
+                remain_query: query.clone(),
+                sort_satisfied: false,
+                estimate: PlanEstimate::default(),
+                union: branches,
+            },
+            // a single index is preferred by the heuristics
+            rank: (0, 0, false, false),
+            index_names,
+        }
+    }
+
+    // A hashed index searches the hashes of the values of `$eq` or `$in`,
+    // the condition is kept in the query, because the different values may have the same hash.
+    fn hashed_candidate(&self, index_name: &str, index_info: &IndexInfo, query: &Document) -> Result<Option<Candidate>> {
+        let (key, _) = index_info.keys.first().expect("a hashed index has a key");
+        let values = match query.get(key).and_then(QueryPlanner::equality_values) {
+            Some(values) => values,
+            None => return Ok(None),
+        };
+        let hashes = match values.iter().map(hash_value).collect::<Result<Vec<i64>>>() {
+            Ok(hashes) => hashes,
+            Err(_) => return Ok(None),
+        };
+
+        let index_prefix = IndexHelper::make_index_prefix(&self.col_spec._id, index_name)?;
+        let mut ranges = Vec::with_capacity(hashes.len());
+        for hash in &hashes {
+            let mut prefix = index_prefix.clone();
+            IndexHelper::append_index_values(&mut prefix, index_info, &[Bson::Int64(*hash)])?;
+            ranges.push(IndexKeyRange::with_prefix(prefix));
+        }
+
+        let mut bounds = Document::new();
+        bounds.insert(key.clone(), hashes.iter().map(|hash| Bson::String(format!("[{}, {}]", hash, hash))).collect::<Vec<Bson>>());
+
+        Ok(Some(Candidate {
+            plan: IndexScanPlan {
+                scan: IndexScan {
+                    index_name: index_name.to_string(),
+                    ranges: IndexKeyRange::normalize(ranges),
+                    reverse: false,
+                    multikey: false,
+                    bounds,
+                    text: None,
+                    geo: None,
+                    vector: None,
+                },
+                remain_query: query.clone(),
+                sort_satisfied: false,
+                estimate: PlanEstimate::default(),
+                union: Vec::new(),
+            },
+            rank: (2, 0, false, false),
+            index_names: vec![index_name.to_string()],
+        }))
+    }
+
+    // The values of an equality condition, such as `"a"`, `{ "$eq": "a" }` or `{ "$in": ["a", "b"] }`.
+    fn equality_values(value: &Bson) -> Option<Vec<Bson>> {
+        let doc = match value {
+            Bson::Document(doc) if doc.keys().any(|key| key.starts_with('$')) => doc,
+            Bson::Document(_) | Bson::Array(_) => return None,
+            _ => return Some(vec![value.clone()]),
+        };
+        if let Some(value) = doc.get("$eq") {
+            return match value {
+                Bson::Document(_) | Bson::Array(_) => None,
+                _ => Some(vec![value.clone()]),
+            };
+        }
+        doc.get_array("$in").ok().cloned()
+    }
+
+    // The condition on the path which can be searched by the wildcard index of the root.
+    fn wildcard_range(root: &str, path: &str, value: &Bson) -> Option<RangeCondition> {
+        if !covers_path(root, path) {
+            return None;
+        }
+        let range = match value {
+            Bson::Document(_) => RangeCondition::parse(value),
+            _ if QueryPlanner::is_index_equality_value(value) => {
+                RangeCondition::points(std::slice::from_ref(value))
+            }
+            _ => None,
+        }?;
+        if range.is_searchable_for_elements() {
+            Some(range)
+        } else {
+            None
+        }
+    }
+
+    // A wildcard index searches a path under its root by `$eq`, `$in` or a range,
+    // the values of an array are indexed with the path of the array,
+    // so a range with both the bounds is not searched, an element may match one bound,
+    // and another element may match the other one.
+    fn wildcard_candidate(&self, index_name: &str, index_info: &IndexInfo, query: &Document) -> Result<Option<Candidate>> {
+        let root = index_info.wildcard_root().expect("a wildcard index has a root");
+
+        let mut best: Option<(&String, RangeCondition)> = None;
+        for (path, value) in query {
+            let range = match QueryPlanner::wildcard_range(root, path, value) {
+                Some(range) => range,
+                None => continue,
+            };
+            let is_better = match &best {
+                Some((_, best_range)) => range.is_points() && !best_range.is_points(),
+                None => true,
+            };
+            if is_better {
+                best = Some((path, range));
+            }
+        }
+
+        let (path, range) = match best {
+            Some(best) => best,
+            None => return Ok(None),
+        };
+
+        let mut prefix = IndexHelper::make_index_prefix(&self.col_spec._id, index_name)?;
+        crate::utils::bson::index_value_bytes_with_order(&mut prefix, &Bson::String(path.clone()), 1)?;
+
+        // the entries of the equal value are exactly the matched documents,
+        // the other conditions are tested with the whole array again
+        let mut remain_query = query.clone();
+        if !matches!(query.get(path), Some(Bson::Document(_))) {
+            remain_query.remove(path);
+        }
+
+        let mut bounds = Document::new();
+        bounds.insert("$_path", vec![Bson::String(format!("[{:?}, {:?}]", path, path))]);
+        bounds.insert(path.clone(), range.bounds(1));
+
+        let rank = (1, 0, range.is_points(), false);
+        Ok(Some(Candidate {
+            plan: IndexScanPlan {
+                scan: IndexScan {
+                    index_name: index_name.to_string(),
+                    ranges: range.key_ranges(&prefix, 1)?,
+                    reverse: false,
+                    // a document may have the entries of the same path and different values
+                    multikey: true,
+                    bounds,
+                    text: None,
+                    geo: None,
+                    vector: None,
+                },
+                remain_query,
+                sort_satisfied: false,
+                estimate: PlanEstimate::default(),
+                union: Vec::new(),
+            },
+            rank,
+            index_names: vec![index_name.to_string()],
+        }))
+    }
+
+    // The index is ranked by the number of the leading keys matched by equality,
+    // then by the range condition on the key following them,
+    // and then by whether it satisfies the sort.
+    fn regular_candidate(&self, index_name: &str, index_info: &IndexInfo, query: &Document) -> Result<Option<Candidate>> {
+        let eq_len = QueryPlanner::index_prefix_len(index_info, query);
+        // the range conditions are tested with the whole array again,
+        // and the elements are not in the order of the documents,
+        // so a multikey index only searches the equal elements
+        let (range, sort_direction) = if index_info.multikey {
+            (None, None)
+        } else {
+            let range = index_info.keys
+                .get_index(eq_len)
+                .and_then(|(key, _)| query.get(key))
+                .and_then(RangeCondition::parse);
+            (range, self.sort_direction(index_info, eq_len))
+        };
+        if eq_len == 0 && range.is_none() && sort_direction.is_none() {
+            return Ok(None);
+        }
+
+        let mut prefix = IndexHelper::make_index_prefix(&self.col_spec._id, index_name)?;
+        let mut values = Vec::<Bson>::with_capacity(eq_len);
+        let mut remain_query = query.clone();
+        let mut bounds = Document::new();
+        for (index, (key, order)) in index_info.keys.iter().enumerate() {
+            if index < eq_len {
+                // the key is ellipse representation, such as "a.b.c"
+                // the query is supposed to be ellipse too, such as
+                // { "a.b.c": 1 }
+                let value = remain_query.remove(key).unwrap();
+                bounds.insert(key.clone(), vec![Bson::String(format!("[{}, {}]", value, value))]);
+                values.push(value);
+            } else if index == eq_len && range.is_some() {
+                // the condition is kept in the query to test the values again
+                let range = range.as_ref().unwrap();
+                bounds.insert(key.clone(), range.bounds(*order));
+            } else {
+                let full_range = if *order > 0 { "[MinKey, MaxKey]" } else { "[MaxKey, MinKey]" };
+                bounds.insert(key.clone(), vec![Bson::String(full_range.to_string())]);
+            }
+        }
+        IndexHelper::append_index_values(&mut prefix, index_info, &values)?;
+
+        let ranges = match &range {
+            Some(range) => {
+                let order = index_info.keys[eq_len];
+                range.key_ranges(&prefix, order)?
+            }
+            None => vec![IndexKeyRange::with_prefix(prefix)],
+        };
+
+        let rank = (3, eq_len, range.is_some(), sort_direction.is_some());
+        Ok(Some(Candidate {
+            plan: IndexScanPlan {
+                scan: IndexScan {
+                    index_name: index_name.to_string(),
+                    ranges,
+                    reverse: sort_direction.unwrap_or(false),
+                    multikey: index_info.multikey,
+                    bounds,
+                    text: None,
+                    geo: None,
+                    vector: None,
+                },
+                remain_query,
+                sort_satisfied: sort_direction.is_some(),
+                estimate: PlanEstimate::default(),
+                union: Vec::new(),
+            },
+            rank,
+            index_names: vec![index_name.to_string()],
+        }))
+    }
+
+    // The `$text` query can only be served by the text index of the collection,
+    // the other conditions of the query are tested on the documents found.
+    fn plan_text_search(&self, search: &Bson, query: &Document) -> Result<IndexScanPlan> {
+        if self.is_write {
+            return Err(Error::ValidationError("$text is not supported by the write operations".into()));
+        }
+
+        let (index_name, index_info) = self.col_spec.indexes
+            .iter()
+            .find(|(_, index_info)| index_info.is_text() && !index_info.is_building())
+            .ok_or_else(|| Error::ValidationError("text index required for $text query".into()))?;
+        let search = TextSearch::parse(search, index_info)?;
+
+        let term_ranges = |terms: &[String]| -> Result<Vec<IndexKeyRange>> {
+            let mut ranges = Vec::with_capacity(terms.len());
+            for term in terms {
+                let prefix = IndexHelper::make_index_key(
+                    &self.col_spec._id,
+                    index_name,
+                    index_info,
+                    &[Bson::String(term.clone())],
+                    None,
+                )?;
+                ranges.push(IndexKeyRange::with_prefix(prefix));
+            }
+            Ok(IndexKeyRange::normalize(ranges))
+        };
+        let ranges = term_ranges(&search.terms)?;
+        let negated_ranges = term_ranges(&search.negated_terms)?;
+
+        let mut remain_query = query.clone();
+        remain_query.remove("$text");
+
+        Ok(IndexScanPlan {
+            scan: IndexScan {
+                index_name: index_name.clone(),
+                ranges,
+                reverse: false,
+                multikey: false,
+                bounds: search.bounds(),
+                text: Some(Box::new(TextScan {
+                    search,
+                    negated_ranges,
+                })),
+                geo: None,
+                vector: None,
+            },
+            remain_query,
+            sort_satisfied: false,
+            estimate: PlanEstimate::default(),
+            union: Vec::new(),
+        })
+    }
+
+    // Find the key with a geospatial operator, such as `{ "location": { "$near": ... } }`.
+    fn find_geo_condition(query: &Document) -> Option<(&str, &str)> {
+        query.iter().find_map(|(key, value)| {
+            let doc = value.as_document()?;
+            let op = doc.keys().find(|op| GeoQuery::is_geo_operator(op))?;
+            Some((key.as_str(), op.as_str()))
+        })
+    }
+
+    // The geospatial queries can only be served by the 2dsphere index of the key,
+    // the cells of the index are scanned and the points are tested again.
+    fn plan_geo_search(&self, key: &str, op: &str, query: &Document) -> Result<IndexScanPlan> {
+        if self.is_write {
+            return Err(Error::ValidationError(format!("{} is not supported by the write operations", op)));
+        }
+
+        let (index_name, _) = self.col_spec.indexes
+            .iter()
+            .find(|(_, index_info)| {
+                index_info.is_2dsphere() && !index_info.is_building() && index_info.keys.contains_key(key)
+            })
+            .ok_or_else(|| Error::ValidationError(format!("2dsphere index required for {} query", op)))?;
+
+        let mut remain_query = query.clone();
+        let mut condition = remain_query.get_document(key).unwrap().clone();
+        let geo_query = GeoQuery::parse(op, condition.get(op).unwrap())?;
+        condition.remove(op);
+        if condition.is_empty() {
+            remain_query.remove(key);
+        } else {
+            remain_query.insert(key, condition);
+        }
+
+        let prefix = IndexHelper::make_index_prefix(&self.col_spec._id, index_name)?;
+        let ranges = match geo_query.cell_ranges() {
+            Some(cell_ranges) => {
+                let mut ranges = Vec::with_capacity(cell_ranges.len());
+                for (lower, upper) in cell_ranges {
+                    let lower = Bson::Int64(lower);
+                    let upper = Bson::Int64(upper);
+                    let range = IndexKeyRange::between(&prefix, Bound::Included(&lower), Bound::Excluded(&upper), 1)?;
+                    ranges.extend(range.expect("the cells are searchable"));
+                }
+                IndexKeyRange::normalize(ranges)
+            }
+            None => vec![IndexKeyRange::with_prefix(prefix)],
+        };
+
+        let mut bounds = Document::new();
+        bounds.insert(key, vec![Bson::String(geo_query.to_string())]);
+
+        Ok(IndexScanPlan {
+            scan: IndexScan {
+                index_name: index_name.clone(),
+                ranges,
+                reverse: false,
+                multikey: false,
+                bounds,
+                text: None,
+                geo: Some(Box::new(GeoScan {
+                    key: key.to_string(),
+                    query: geo_query,
+                })),
+                vector: None,
+            },
+            remain_query,
+            sort_satisfied: false,
+            estimate: PlanEstimate::default(),
+            union: Vec::new(),
+        })
+    }
+
+    /// Plan the `$vectorSearch` stage by the vector index of the path,
+    /// the filter of the stage is the remaining query.
+    pub fn plan_vector_search(&self, search: &VectorSearch) -> Result<IndexScanPlan> {
+        let (index_name, index_info) = self.col_spec.indexes
+            .iter()
+            .find(|(index_name, index_info)| {
+                index_info.is_vector()
+                    && !index_info.is_building()
+                    && index_info.keys.contains_key(&search.path)
+                    && search.index.as_ref().map(|name| name == *index_name).unwrap_or(true)
+            })
+            .ok_or_else(|| Error::ValidationError(format!("vector index required for $vectorSearch of the path: {}", search.path)))?;
+
+        if let Some(dimensions) = index_info.vector_dimensions() {
+            if dimensions as usize != search.query_vector.len() {
+                return Err(Error::ValidationError(format!(
+                    "the query vector has {} dimensions, but the index has {}",
+                    search.query_vector.len(),
+                    dimensions,
+                )));
+            }
+        }
+
+        let mut bounds = Document::new();
+        bounds.insert(search.path.clone(), vec![Bson::String(format!("numCandidates: {}", search.num_candidates))]);
+
+        let plan = IndexScanPlan {
+            scan: IndexScan {
+                index_name: index_name.clone(),
+                ranges: Vec::new(),
+                reverse: false,
+                multikey: false,
+                bounds,
+                text: None,
+                geo: None,
+                vector: Some(Box::new(VectorScan {
+                    index_prefix: IndexHelper::make_index_prefix(&self.col_spec._id, index_name)?,
+                    query_vector: search.query_vector.clone(),
+                    similarity: index_info.vector_similarity(),
+                    num_candidates: search.num_candidates,
+                })),
+            },
+            remain_query: search.filter.clone(),
+            sort_satisfied: false,
+            estimate: PlanEstimate::default(),
+            union: Vec::new(),
+        };
+        QueryPlanner::check_search_hint(self.resolve_hint()?.as_ref(), &plan)?;
+
+        Ok(plan)
+    }
+
+    /// Explain the plan of the query, the indexes not used by the plan
+    /// are listed in the rejected plans with the reasons.
+    pub fn explain(&self, plan: &QueryPlan, query: &Document) -> Document {
+        let (used_index, used_indexes, estimate) = match plan {
+            QueryPlan::IndexScan(plan) if plan.union.is_empty() => {
+                (Some(plan.scan.index_name.as_str()), vec![plan.scan.index_name.as_str()], Some(&plan.estimate))
+            }
+            QueryPlan::IndexScan(plan) => {
+                let used_indexes = plan.union
+                    .iter()
+                    .map(|branch| branch.scan.index_name.as_str())
+                    .collect();
+                (None, used_indexes, Some(&plan.estimate))
+            }
+            _ => (None, Vec::new(), None),
+        };
+
+        let is_hinted = self.context.map(|context| context.hint.is_some()).unwrap_or(false);
+        let mut rejected_plans = Vec::<Bson>::new();
+        for (index_name, index_info) in &self.col_spec.indexes {
+            if used_indexes.contains(&index_name.as_str()) {
+                continue;
+            }
+            let reason = self.unusable_reason(index_info, query).unwrap_or(if is_hinted {
+                "the hint selects another plan"
+            } else {
+                "another plan is preferred"
+            });
+            let mut rejected_plan = doc! {
+                "indexName": index_name.clone(),
+                "reason": reason,
+            };
+            let keys = estimate
+                .and_then(|estimate| estimate.rejected.iter().find(|(name, _)| name == index_name))
+                .and_then(|(_, keys)| *keys);
+            if let Some(keys) = keys {
+                rejected_plan.insert("estimatedKeys", keys as i64);
+            }
+            rejected_plans.push(Bson::Document(rejected_plan));
+        }
+
+        let mut winning_plan = plan.explain();
+        if let Some(index_info) = used_index.and_then(|index_name| self.col_spec.indexes.get(index_name)) {
+            winning_plan.insert("keyPattern", index_info.to_index_model(used_index.unwrap()).keys);
+        }
+
+        doc! {
+            "winningPlan": winning_plan,
+            "rejectedPlans": rejected_plans,
+        }
+    }
+
+    // The reason why the index can't search the query.
+    fn unusable_reason(&self, index_info: &IndexInfo, query: &Document) -> Option<&'static str> {
+        if index_info.is_building() {
+            Some("the index is being built")
+        } else if !QueryPlanner::is_index_usable(index_info, query) {
+            Some("the index doesn't reference all the matching documents")
+        } else if !self.is_index_applicable(index_info, query) {
+            Some("no condition of the query can be searched by the index")
+        } else {
+            None
+        }
+    }
+
+    // Whether the index can search any condition of the query or the sort,
+    // the usability of a sparse or partial index is tested separately.
+    fn is_index_applicable(&self, index_info: &IndexInfo, query: &Document) -> bool {
+        let (first_key, _) = match index_info.keys.first() {
+            Some(first) => first,
+            None => return false,
+        };
+        match index_info.index_type {
+            IndexType::Regular => {
+                let eq_len = QueryPlanner::index_prefix_len(index_info, query);
+                let has_range = !index_info.multikey && index_info.keys
+                    .get_index(eq_len)
+                    .and_then(|(key, _)| query.get(key))
+                    .and_then(RangeCondition::parse)
+                    .is_some();
+                eq_len > 0 || has_range || self.sort_direction(index_info, eq_len).is_some()
+            }
+            IndexType::Text => query.contains_key("$text"),
+            IndexType::Geo2dSphere => QueryPlanner::find_geo_condition(query)
+                .map(|(key, _)| key == first_key)
+                .unwrap_or(false),
+            // only searched by the `$vectorSearch` stage
+            IndexType::Vector => false,
+            IndexType::Hashed => query.get(first_key).and_then(QueryPlanner::equality_values).is_some(),
+            IndexType::Wildcard => {
+                let root = index_info.wildcard_root().expect("a wildcard index has a root");
+                query.iter().any(|(path, value)| QueryPlanner::wildcard_range(root, path, value).is_some())
+            }
+        }
+    }
+
+    /// A sparse or partial index doesn't reference all the documents,
+    /// it can only be used if the documents matching the query are referenced.
+    /// An index being built doesn't reference the documents not scanned yet.
+    fn is_index_usable(index_info: &IndexInfo, query: &Document) -> bool {
+        if index_info.is_building() {
+            return false;
+        }
+
+        if index_info.is_sparse() {
+            let has_key = index_info.keys
+                .keys()
+                .any(|key| PartialFilter::exists(key).is_implied_by(query));
+            if !has_key {
+                return false;
+            }
+        }
+
+        match index_info.partial_filter_expression() {
+            Some(filter) => PartialFilter::parse(filter)
+                .map(|filter| filter.is_implied_by(query))
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// Return how many leading keys of the index are matched
+    /// by the equality conditions of the query.
+    fn index_prefix_len(index_info: &IndexInfo, query: &Document) -> usize {
+        index_info.keys
+            .keys()
+            .take_while(|key| {
+                query.get(key.as_str()).map(QueryPlanner::is_index_equality_value).unwrap_or(false)
+            })
+            .count()
+    }
+
+    // A missing field is indexed as null,
+    // so null can not be searched by the index.
+    #[inline]
+    fn is_index_equality_value(value: &Bson) -> bool {
+        !matches!(
+            value.element_type(),
+            ElementType::EmbeddedDocument | ElementType::Array | ElementType::Null
+        )
+    }
+
+    /// Check whether the index returns the documents in the order of the sort,
+    /// after the leading keys are matched by equality.
+    ///
+    /// Return `Some(true)` if the index should be scanned in reverse order.
+    fn sort_direction(&self, index_info: &IndexInfo, eq_len: usize) -> Option<bool> {
+        let sort = self.sort?;
+        if sort.is_empty() {
+            return None;
+        }
+        let eq_keys: Vec<&String> = index_info.keys.keys().take(eq_len).collect();
+
+        let mut index_keys = index_info.keys.iter().skip(eq_len);
+        let mut reverse: Option<bool> = None;
+        for (sort_key, sort_order) in sort {
+            let sort_order = match sort_order {
+                Bson::Int32(1) | Bson::Int64(1) => 1,
+                Bson::Int32(-1) | Bson::Int64(-1) => -1,
+                _ => return None,
+            };
+
+            // the keys matched by equality have only one value
+            if reverse.is_none() && eq_keys.contains(&sort_key) {
+                continue;
+            }
+
+            let (index_key, index_order) = index_keys.next()?;
+            if index_key != sort_key {
+                return None;
+            }
+
+            let key_reverse = sort_order != *index_order;
+            if *reverse.get_or_insert(key_reverse) != key_reverse {
+                return None;
+            }
+        }
+
+        Some(reverse.unwrap_or(false))
+    }
+
+}
+
+/// The condition of the query on a key which can be searched by ranges of the index.
+struct RangeCondition {
+    /// The intervals of the values, a value of `$in` is an interval with the same bounds.
+    intervals: Vec<(Bound<Bson>, Bound<Bson>)>,
+}
+
+impl RangeCondition {
+
+    // The conditions on the same key are combined by "and",
+    // so any of them can be used to search the index,
+    // the other conditions are tested after the documents are found.
+    fn parse(value: &Bson) -> Option<RangeCondition> {
+        let doc = match value {
+            Bson::Document(doc) => doc,
+            _ => return None,
+        };
+
+        let mut lower = Bound::Unbounded;
+        let mut upper = Bound::Unbounded;
+        for (op, value) in doc {
+            match op.as_str() {
+                "$eq" => {
+                    if let Some(condition) = RangeCondition::points(std::slice::from_ref(value)) {
+                        return Some(condition);
+                    }
+                }
+                "$in" => {
+                    if let Some(condition) = RangeCondition::points(value.as_array()?) {
+                        return Some(condition);
+                    }
+                }
+                "$gt" if matches!(lower, Bound::Unbounded) => lower = Bound::Excluded(value.clone()),
+                "$gte" if matches!(lower, Bound::Unbounded) => lower = Bound::Included(value.clone()),
+                "$lt" if matches!(upper, Bound::Unbounded) => upper = Bound::Excluded(value.clone()),
+                "$lte" if matches!(upper, Bound::Unbounded) => upper = Bound::Included(value.clone()),
+                _ => (),
+            }
+        }
+
+        if !IndexKeyRange::is_searchable(lower.as_ref(), upper.as_ref()) {
+            return None;
+        }
+
+        Some(RangeCondition {
+            intervals: vec![(lower, upper)],
+        })
+    }
+
+    fn is_points(&self) -> bool {
+        self.intervals.iter().all(|(lower, upper)| matches!(lower, Bound::Included(_)) && lower == upper)
+    }
+
+    /// The missing values are not indexed by a wildcard index,
+    /// so null is not searched, and the range with both the bounds is not searched
+    /// because of the elements of the arrays.
+    fn is_searchable_for_elements(&self) -> bool {
+        let has_null = self.intervals.iter().any(|(lower, upper)| {
+            matches!(lower, Bound::Included(Bson::Null)) || matches!(upper, Bound::Included(Bson::Null))
+        });
+        if has_null {
+            return false;
+        }
+        self.is_points() || self.intervals.iter().all(|(lower, upper)| {
+            matches!(lower, Bound::Unbounded) || matches!(upper, Bound::Unbounded)
+        })
+    }
+
+    fn points(values: &[Bson]) -> Option<RangeCondition> {
+        let mut intervals = Vec::with_capacity(values.len());
+        for value in values {
+            if !IndexKeyRange::is_searchable(Bound::Included(value), Bound::Included(value)) {
+                return None;
+            }
+            intervals.push((Bound::Included(value.clone()), Bound::Included(value.clone())));
+        }
+        Some(RangeCondition {
+            intervals,
+        })
+    }
+
+    fn key_ranges(&self, prefix: &[u8], order: i8) -> Result<Vec<IndexKeyRange>> {
+        let mut ranges = Vec::new();
+        for (lower, upper) in &self.intervals {
+            let interval_ranges = IndexKeyRange::between(prefix, lower.as_ref(), upper.as_ref(), order)?;
+            ranges.extend(interval_ranges.expect("the bounds have been checked"));
+        }
+        Ok(IndexKeyRange::normalize(ranges))
+    }
+
+    /// The bounds in the format of the explain output, such as `(30, MaxKey]`.
+    fn bounds(&self, order: i8) -> Vec<Bson> {
+        self.intervals
+            .iter()
+            .map(|(lower, upper)| {
+                // the bounds of a descending key are reversed
+                let bound = if order > 0 {
+                    format!("{}, {}", start_bound_str(lower, "MinKey"), end_bound_str(upper, "MaxKey"))
+                } else {
+                    format!("{}, {}", start_bound_str(upper, "MaxKey"), end_bound_str(lower, "MinKey"))
+                };
+                Bson::String(bound)
+            })
+            .collect()
+    }
+
+}
+
+fn start_bound_str(bound: &Bound<Bson>, unbounded: &str) -> String {
+    match bound {
+        Bound::Included(value) => format!("[{}", value),
+        Bound::Excluded(value) => format!("({}", value),
+        Bound::Unbounded => format!("[{}", unbounded),
+    }
+}
+
+fn end_bound_str(bound: &Bound<Bson>, unbounded: &str) -> String {
+    match bound {
+        Bound::Included(value) => format!("{}]", value),
+        Bound::Excluded(value) => format!("{})", value),
+        Bound::Unbounded => format!("{}]", unbounded),
+    }
+}
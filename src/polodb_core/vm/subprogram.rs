@@ -18,14 +18,16 @@ use super::op::DbOp;
 use crate::coll::collection_info::{CollectionSpecification, IndexInfo};
 use crate::utils::str::escape_binary_to_string;
 use crate::vm::codegen::Codegen;
-use crate::{Result};
-use bson::{Bson, Document};
+use crate::{Config, Result};
+use bson::{doc, Bson, Document};
 use indexmap::IndexMap;
 use std::fmt;
 use std::rc::Rc;
 use crate::errors::FieldTypeUnexpectedStruct;
 use crate::vm::aggregation_codegen_context::AggregationCodeGenContext;
 use crate::vm::global_variable::GlobalVariableSlot;
+use crate::index::VectorSearch;
+use crate::vm::query_planner::{IndexScan, PlanContext, QueryPlan, QueryPlanner};
 use crate::vm::update_operators::UpdateOperator;
 use crate::vm::vm_external_func::VmExternalFunc;
 
@@ -40,8 +42,12 @@ pub(crate) struct SubProgram {
     pub(super) global_variables: Vec<GlobalVariableSlot>,
     pub(super) label_slots: Vec<LabelSlot>,
     pub(super) index_infos: Vec<SubProgramIndexItem>,
+    pub(super) index_scans: Vec<IndexScan>,
     pub(crate) external_funcs: Vec<Box<dyn VmExternalFunc>>,
     pub(crate) update_operators: Vec<Box<dyn UpdateOperator>>,
+    /// The explanation of the plan of the query,
+    /// in the format of `{ "winningPlan": ..., "rejectedPlans": [...] }`.
+    pub(crate) query_plan: Option<Document>,
 }
 
 impl SubProgram {
@@ -52,8 +58,10 @@ impl SubProgram {
             global_variables: Vec::with_capacity(16),
             label_slots: Vec::with_capacity(32),
             index_infos: Vec::new(),
+            index_scans: Vec::new(),
             external_funcs: Vec::new(),
             update_operators: Vec::new(),
+            query_plan: None,
         }
     }
 
@@ -70,11 +78,23 @@ impl SubProgram {
         query: &Document,
         skip_annotation: bool,
     ) -> Result<SubProgram> {
-        if query.is_empty() {
+        SubProgram::compile_query_with_context(col_spec, query, None, skip_annotation)
+    }
+
+    /// Compile the query planned with the estimates of the storage and the plan cache.
+    pub(crate) fn compile_query_with_context(
+        col_spec: &CollectionSpecification,
+        query: &Document,
+        plan_context: Option<&PlanContext>,
+        skip_annotation: bool,
+    ) -> Result<SubProgram> {
+        // the hinted index is scanned even if the query is empty
+        if query.is_empty() && !SubProgram::has_hint(plan_context) {
             return SubProgram::compile_query_all(col_spec, skip_annotation);
         }
 
-        let mut codegen = Codegen::new(skip_annotation, false);
+        let mut codegen = Codegen::new(skip_annotation, false)
+            .with_plan_context(plan_context);
 
         codegen.emit_query_layout(
             col_spec,
@@ -229,7 +249,9 @@ impl SubProgram {
         col_spec: &CollectionSpecification,
         skip_annotation: bool,
     ) -> Result<SubProgram> {
-        SubProgram::compile_query_all_by_name(col_spec.name(), skip_annotation)
+        let mut program = SubProgram::compile_query_all_by_name(col_spec.name(), skip_annotation)?;
+        program.query_plan = Some(QueryPlanner::new(col_spec, false).explain(&QueryPlan::CollectionScan, &Document::new()));
+        Ok(program)
     }
 
     pub(crate) fn compile_query_all_by_name(
@@ -267,25 +289,65 @@ impl SubProgram {
     pub(crate) fn compile_aggregate(
         col_spec: &CollectionSpecification,
         pipeline: impl IntoIterator<Item = Document>,
+        config: &Config,
+        plan_context: Option<&PlanContext>,
         skip_annotation: bool,
     ) -> Result<SubProgram> {
-        let pipeline_vec: Vec<Document> = pipeline.into_iter().collect();
+        let mut pipeline_vec: Vec<Document> = pipeline.into_iter().collect();
+        // the documents of the first stage are found by the hinted plan
+        if SubProgram::has_hint(plan_context) {
+            let has_query = pipeline_vec
+                .first()
+                .map(|first| first.len() == 1 && (first.contains_key("$match") || first.contains_key("$vectorSearch")))
+                .unwrap_or(false);
+            if !has_query {
+                pipeline_vec.insert(0, doc! { "$match": {} });
+            }
+        }
         if pipeline_vec.is_empty() {
             return SubProgram::compile_query_all(col_spec, skip_annotation);
         }
 
         let first = pipeline_vec.first().unwrap();
         if first.len() == 1 && first.contains_key("$match") {
-            return SubProgram::compile_aggregate_with_match(col_spec, pipeline_vec, skip_annotation);
+            return SubProgram::compile_aggregate_with_match(col_spec, pipeline_vec, config, plan_context, skip_annotation);
         }
 
-        let mut codegen = Codegen::new(skip_annotation, false);
+        if first.len() == 1 && first.contains_key("$vectorSearch") {
+            return SubProgram::compile_aggregate_with_vector_search(col_spec, pipeline_vec, config, plan_context, skip_annotation);
+        }
+
+        // If the first pipeline is $sort, try to scan the index in the order of the sort.
+        if let Some(sort) = SubProgram::sort_stage(first) {
+            let query_doc = Document::new();
+            let plan = QueryPlanner::new(col_spec, false)
+                .with_sort(Some(sort))
+                .with_context(plan_context)
+                .plan(&query_doc)?;
+            if SubProgram::is_sort_satisfied(&plan) {
+                return SubProgram::compile_aggregate_with_plan(
+                    col_spec,
+                    plan,
+                    &query_doc,
+                    &pipeline_vec[1..],
+                    config,
+                    plan_context,
+                    skip_annotation,
+                );
+            }
+        }
+
+        let mut codegen = Codegen::new(skip_annotation, false)
+            .with_sort_memory_limit(config.sort_memory_limit)
+            .with_sort_spill_dir(&config.sort_spill_dir);
         let result_label = codegen.new_label();
         let next_label = codegen.new_label();
         let close_label = codegen.new_label();
 
         let mut ctx = AggregationCodeGenContext::default();
 
+        codegen.record_query_plan(col_spec, &QueryPlan::CollectionScan, &Document::new());
+
         // set up the slots for the aggregation pipeline
         codegen.emit_aggregation_before_query(&mut ctx, &pipeline_vec)?;
 
@@ -316,9 +378,10 @@ impl SubProgram {
     pub(crate) fn compile_aggregate_with_match(
         col_spec: &CollectionSpecification,
         pipeline_vec: Vec<Document>,
+        config: &Config,
+        plan_context: Option<&PlanContext>,
         skip_annotation: bool,
     ) -> Result<SubProgram> {
-        let mut codegen = Codegen::new(skip_annotation, false);
         let first_doc = pipeline_vec.first().unwrap();
         let query_doc_value = first_doc.get("$match").unwrap();
         let query_doc = match query_doc_value {
@@ -333,19 +396,88 @@ impl SubProgram {
             },
         };
 
+        // The $sort following the $match is skipped
+        // if the documents are scanned in the order of an index.
+        let sort = pipeline_vec.get(1).and_then(SubProgram::sort_stage);
+        let plan = QueryPlanner::new(col_spec, false)
+            .with_sort(sort)
+            .with_context(plan_context)
+            .plan(query_doc)?;
+        let pipeline = if SubProgram::is_sort_satisfied(&plan) {
+            &pipeline_vec[2..]
+        } else {
+            &pipeline_vec[1..]
+        };
+
+        SubProgram::compile_aggregate_with_plan(
+            col_spec,
+            plan,
+            query_doc,
+            pipeline,
+            config,
+            plan_context,
+            skip_annotation,
+        )
+    }
+
+    /// The `$vectorSearch` stage scans the vector index for the nearest documents,
+    /// which are filtered by the filter of the stage, and limited by the limit of it.
+    fn compile_aggregate_with_vector_search(
+        col_spec: &CollectionSpecification,
+        pipeline_vec: Vec<Document>,
+        config: &Config,
+        plan_context: Option<&PlanContext>,
+        skip_annotation: bool,
+    ) -> Result<SubProgram> {
+        let stage = crate::try_unwrap_document!("$vectorSearch", pipeline_vec[0].get("$vectorSearch").unwrap());
+        let search = VectorSearch::parse(stage)?;
+        let plan = QueryPlanner::new(col_spec, false)
+            .with_context(plan_context)
+            .plan_vector_search(&search)?;
+
+        let mut pipeline = Vec::with_capacity(pipeline_vec.len());
+        pipeline.push(doc! { "$limit": search.limit as i64 });
+        pipeline.extend_from_slice(&pipeline_vec[1..]);
+
+        SubProgram::compile_aggregate_with_plan(
+            col_spec,
+            QueryPlan::IndexScan(Box::new(plan)),
+            &search.filter,
+            &pipeline,
+            config,
+            plan_context,
+            skip_annotation,
+        )
+    }
+
+    fn compile_aggregate_with_plan(
+        col_spec: &CollectionSpecification,
+        plan: QueryPlan,
+        query_doc: &Document,
+        pipeline: &[Document],
+        config: &Config,
+        plan_context: Option<&PlanContext>,
+        skip_annotation: bool,
+    ) -> Result<SubProgram> {
+        let mut codegen = Codegen::new(skip_annotation, false)
+            .with_sort_memory_limit(config.sort_memory_limit)
+            .with_sort_spill_dir(&config.sort_spill_dir)
+            .with_plan_context(plan_context);
+
         let ctx_ref = Rc::new(RefCell::new(AggregationCodeGenContext::default()));
         let ctx_ref2 = ctx_ref.clone();
         {
             let mut ctx = ctx_ref.borrow_mut();
-            codegen.emit_aggregation_before_query(&mut ctx, &pipeline_vec[1..])?;
+            codegen.emit_aggregation_before_query(&mut ctx, pipeline)?;
         }
-        codegen.emit_query_layout(
+        codegen.emit_query_layout_with_plan(
             col_spec,
+            plan,
             query_doc,
             |codegen: &mut Codegen| -> Result<()> {
                 let ctx_ref = ctx_ref.clone();
                 let mut ctx = ctx_ref.borrow_mut();
-                codegen.emit_aggregation_pipeline(&mut ctx, &pipeline_vec[1..])?;
+                codegen.emit_aggregation_pipeline(&mut ctx, pipeline)?;
                 Ok(())
             },
             Some(Box::new(move |codegen: &mut Codegen| -> Result<()> {
@@ -359,6 +491,22 @@ impl SubProgram {
         Ok(codegen.take())
     }
 
+    fn has_hint(plan_context: Option<&PlanContext>) -> bool {
+        plan_context.map(|context| context.hint.is_some()).unwrap_or(false)
+    }
+
+    fn sort_stage(stage: &Document) -> Option<&Document> {
+        if stage.len() != 1 {
+            return None;
+        }
+        stage.get_document("$sort").ok()
+    }
+
+    #[inline]
+    fn is_sort_satisfied(plan: &QueryPlan) -> bool {
+        matches!(plan, QueryPlan::IndexScan(plan) if plan.sort_satisfied)
+    }
+
 }
 
 fn open_bson_to_str(val: &Bson) -> Result<String> {
@@ -452,9 +600,11 @@ impl fmt::Display for SubProgram {
                     }
 
                     DbOp::FindByIndex => {
-                        let location = begin.add(pc + 1).cast::<u32>().read();
-                        writeln!(f, "{}: FindByIndex({})", pc, location)?;
-                        pc += 5;
+                        let index_scan_id = begin.add(pc + 1).cast::<u32>().read();
+                        let index_scan = &self.index_scans[index_scan_id as usize];
+                        let location = begin.add(pc + 5).cast::<u32>().read();
+                        writeln!(f, "{}: FindByIndex({}, {})", pc, index_scan, location)?;
+                        pc += 9;
                     }
 
                     DbOp::Next => {
@@ -597,6 +747,42 @@ impl fmt::Display for SubProgram {
                         pc += 1;
                     }
 
+                    DbOp::IsType => {
+                        writeln!(f, "{}: IsType", pc)?;
+                        pc += 1;
+                    }
+
+                    DbOp::Mod => {
+                        writeln!(f, "{}: Mod", pc)?;
+                        pc += 1;
+                    }
+
+                    DbOp::BitsAllSet => {
+                        writeln!(f, "{}: BitsAllSet", pc)?;
+                        pc += 1;
+                    }
+
+                    DbOp::BitsAnySet => {
+                        writeln!(f, "{}: BitsAnySet", pc)?;
+                        pc += 1;
+                    }
+
+                    DbOp::BitsAllClear => {
+                        writeln!(f, "{}: BitsAllClear", pc)?;
+                        pc += 1;
+                    }
+
+                    DbOp::BitsAnyClear => {
+                        writeln!(f, "{}: BitsAnyClear", pc)?;
+                        pc += 1;
+                    }
+
+                    DbOp::NextElement => {
+                        let location = begin.add(pc + 1).cast::<u32>().read();
+                        writeln!(f, "{}: NextElement({})", pc, location)?;
+                        pc += 5;
+                    }
+
                     DbOp::OpenRead => {
                         let idx = begin.add(pc + 1).cast::<u32>().read();
                         let value = &self.static_values[idx as usize];
@@ -636,6 +822,19 @@ impl fmt::Display for SubProgram {
                         pc += 9;
                     }
 
+                    DbOp::GetPathValues => {
+                        let static_id = begin.add(pc + 1).cast::<u32>().read();
+                        let val = &self.static_values[static_id as usize];
+                        let location = begin.add(pc + 5).cast::<u32>().read();
+                        writeln!(f, "{}: GetPathValues({}, {})", pc, val, location)?;
+                        pc += 9;
+                    }
+
+                    DbOp::ExpandArrays => {
+                        writeln!(f, "{}: ExpandArrays", pc)?;
+                        pc += 1;
+                    }
+
                     DbOp::SetField => {
                         let static_id = begin.add(pc + 1).cast::<u32>().read();
                         let val = &self.static_values[static_id as usize];
@@ -746,7 +945,7 @@ mod tests {
     use bson::{doc, Regex};
     use indexmap::indexmap;
     use polodb_line_diff::assert_eq;
-    use crate::Error;
+    use crate::{Config, Error};
 
     #[inline]
     fn new_spec<T: Into<String>>(name: T) -> CollectionSpecification {
@@ -821,21 +1020,59 @@ mod tests {
 75: Goto(43)
 
 80: Label(0, "compare_function")
-85: GetField("name", 129)
-94: PushValue("Vincent Chan")
-99: Equal
-100: FalseJump(129)
-105: Pop
+85: Goto(113)
+
+90: Label(7)
+95: PushValue("Vincent Chan")
+100: Equal
+101: FalseJump(107)
 106: Pop
-107: GetField("age", 129)
-116: PushValue(32)
-121: Equal
-122: FalseJump(129)
-127: Pop
-128: Pop
-
-129: Label(1, "compare_function_clean")
-134: Ret0
+
+107: Label(9)
+112: Ret0
+
+113: Label(8)
+118: GetPathValues("name", 253)
+127: ExpandArrays
+128: PushValue(0)
+
+133: Label(10)
+138: NextElement(157)
+143: Call(90, 1)
+152: FalseJump(133)
+
+157: Label(11)
+162: Pop
+163: Pop
+164: FalseJump(253)
+169: Goto(197)
+
+174: Label(12)
+179: PushValue(32)
+184: Equal
+185: FalseJump(191)
+190: Pop
+
+191: Label(14)
+196: Ret0
+
+197: Label(13)
+202: GetPathValues("age", 253)
+211: ExpandArrays
+212: PushValue(0)
+
+217: Label(15)
+222: NextElement(241)
+227: Call(174, 1)
+236: FalseJump(217)
+
+241: Label(16)
+246: Pop
+247: Pop
+248: FalseJump(253)
+
+253: Label(1, "compare_function_clean")
+258: Ret0
 "#;
         assert_eq!(expect, actual)
     }
@@ -878,15 +1115,34 @@ mod tests {
 75: Goto(43)
 
 80: Label(0, "compare_function")
-85: GetField("info.color", 107)
-94: PushValue("yellow")
-99: Equal
-100: FalseJump(107)
-105: Pop
+85: Goto(113)
+
+90: Label(7)
+95: PushValue("yellow")
+100: Equal
+101: FalseJump(107)
 106: Pop
 
-107: Label(1, "compare_function_clean")
+107: Label(9)
 112: Ret0
+
+113: Label(8)
+118: GetPathValues("info.color", 169)
+127: ExpandArrays
+128: PushValue(0)
+
+133: Label(10)
+138: NextElement(157)
+143: Call(90, 1)
+152: FalseJump(133)
+
+157: Label(11)
+162: Pop
+163: Pop
+164: FalseJump(169)
+
+169: Label(1, "compare_function_clean")
+174: Ret0
 "#;
         assert_eq!(expect, actual)
     }
@@ -914,15 +1170,45 @@ mod tests {
 27: Halt
 
 28: Label(1)
-33: GetField("age", 20)
-42: PushValue(32)
-47: Equal
-48: FalseJump(20)
-53: Pop
-54: Pop
-55: ResultRow
-56: Pop
-57: Goto(20)
+33: Dup
+34: Call(53, 1)
+43: FalseJump(20)
+48: Goto(148)
+
+53: Label(2, "compare_function")
+58: Goto(86)
+
+63: Label(5)
+68: PushValue(32)
+73: Equal
+74: FalseJump(80)
+79: Pop
+
+80: Label(7)
+85: Ret0
+
+86: Label(6)
+91: GetPathValues("age", 142)
+100: ExpandArrays
+101: PushValue(0)
+
+106: Label(8)
+111: NextElement(130)
+116: Call(63, 1)
+125: FalseJump(106)
+
+130: Label(9)
+135: Pop
+136: Pop
+137: FalseJump(142)
+
+142: Label(3, "compare_function_clean")
+147: Ret0
+
+148: Label(4)
+153: ResultRow
+154: Pop
+155: Goto(20)
 "#;
         assert_eq!(expect, actual)
     }
@@ -933,12 +1219,12 @@ mod tests {
 
         col_spec.indexes.insert(
             "age_1".into(),
-            IndexInfo {
-                keys: indexmap! {
+            IndexInfo::new(
+                indexmap! {
                     "age".into() => 1,
                 },
-                options: None,
-            },
+                None,
+            ),
         );
 
         let test_doc = doc! {
@@ -952,30 +1238,60 @@ mod tests {
         let expect = r#"Program:
 
 0: OpenRead(b"\x02$I\x00\x02test\x00\x02age_1\x00")
-5: PushValue(32)
-10: PushValue("test")
-15: FindByIndex(35)
-20: Goto(44)
+5: FindByIndex("age_1", { "age": ["[32, 32]"] }, 29)
+14: Goto(59)
 
-25: Label(2)
-30: NextIndexValue(44)
+19: Label(3)
+24: NextIndexValue(59)
+
+29: Label(6, "close")
+34: Close
+35: Halt
 
-35: Label(0)
-40: Pop
+36: Label(5, "not_this_item")
 41: Pop
-42: Close
-43: Halt
-
-44: Label(1)
-49: GetField("name", 35)
-58: PushValue("Vincent Chan")
-63: Equal
-64: FalseJump(35)
-69: Pop
-70: Pop
-71: ResultRow
-72: Pop
-73: Goto(25)
+42: Goto(19)
+
+47: Label(4, "result")
+52: ResultRow
+53: Pop
+54: Goto(19)
+
+59: Label(2, "compare")
+64: Dup
+65: Call(84, 1)
+74: FalseJump(36)
+79: Goto(47)
+
+84: Label(0, "compare_function")
+89: Goto(117)
+
+94: Label(7)
+99: PushValue("Vincent Chan")
+104: Equal
+105: FalseJump(111)
+110: Pop
+
+111: Label(9)
+116: Ret0
+
+117: Label(8)
+122: GetPathValues("name", 173)
+131: ExpandArrays
+132: PushValue(0)
+
+137: Label(10)
+142: NextElement(161)
+147: Call(94, 1)
+156: FalseJump(137)
+
+161: Label(11)
+166: Pop
+167: Pop
+168: FalseJump(173)
+
+173: Label(1, "compare_function_clean")
+178: Ret0
 "#;
         assert_eq!(expect, actual);
     }
@@ -1025,21 +1341,59 @@ mod tests {
 75: Goto(43)
 
 80: Label(0, "compare_function")
-85: GetField("_id", 129)
-94: PushValue(6)
-99: Equal
-100: FalseJump(129)
-105: Pop
+85: Goto(113)
+
+90: Label(7)
+95: PushValue(6)
+100: Equal
+101: FalseJump(107)
 106: Pop
-107: GetField("age", 129)
-116: PushValue(32)
-121: Equal
-122: FalseJump(129)
-127: Pop
-128: Pop
-
-129: Label(1, "compare_function_clean")
-134: Ret0
+
+107: Label(9)
+112: Ret0
+
+113: Label(8)
+118: GetPathValues("_id", 253)
+127: ExpandArrays
+128: PushValue(0)
+
+133: Label(10)
+138: NextElement(157)
+143: Call(90, 1)
+152: FalseJump(133)
+
+157: Label(11)
+162: Pop
+163: Pop
+164: FalseJump(253)
+169: Goto(197)
+
+174: Label(12)
+179: PushValue(32)
+184: Equal
+185: FalseJump(191)
+190: Pop
+
+191: Label(14)
+196: Ret0
+
+197: Label(13)
+202: GetPathValues("age", 253)
+211: ExpandArrays
+212: PushValue(0)
+
+217: Label(15)
+222: NextElement(241)
+227: Call(174, 1)
+236: FalseJump(217)
+
+241: Label(16)
+246: Pop
+247: Pop
+248: FalseJump(253)
+
+253: Label(1, "compare_function_clean")
+258: Ret0
 "#;
         assert_eq!(expect, actual)
     }
@@ -1089,38 +1443,79 @@ mod tests {
 75: Goto(43)
 
 80: Label(0, "compare_function")
-85: Goto(156)
+85: Goto(280)
 
 90: Label(8)
-95: GetField("age", 117)
-104: PushValue(11)
-109: Equal
-110: FalseJump(117)
-115: Pop
+95: Goto(123)
+
+100: Label(10)
+105: PushValue(11)
+110: Equal
+111: FalseJump(117)
 116: Pop
 
-117: Label(9)
+117: Label(12)
 122: Ret0
 
-123: Label(10)
-128: GetField("age", 150)
-137: PushValue(12)
-142: Equal
-143: FalseJump(150)
-148: Pop
-149: Pop
-
-150: Label(11)
-155: Ret0
-
-156: Label(7)
-161: Call(90, 0)
-170: TrueJump(189)
-175: Call(123, 0)
-184: TrueJump(189)
-
-189: Label(1, "compare_function_clean")
-194: Ret0
+123: Label(11)
+128: GetPathValues("age", 179)
+137: ExpandArrays
+138: PushValue(0)
+
+143: Label(13)
+148: NextElement(167)
+153: Call(100, 1)
+162: FalseJump(143)
+
+167: Label(14)
+172: Pop
+173: Pop
+174: FalseJump(179)
+
+179: Label(9)
+184: Ret0
+
+185: Label(15)
+190: Goto(218)
+
+195: Label(17)
+200: PushValue(12)
+205: Equal
+206: FalseJump(212)
+211: Pop
+
+212: Label(19)
+217: Ret0
+
+218: Label(18)
+223: GetPathValues("age", 274)
+232: ExpandArrays
+233: PushValue(0)
+
+238: Label(20)
+243: NextElement(262)
+248: Call(195, 1)
+257: FalseJump(238)
+
+262: Label(21)
+267: Pop
+268: Pop
+269: FalseJump(274)
+
+274: Label(16)
+279: Ret0
+
+280: Label(7)
+285: Call(90, 0)
+294: TrueJump(318)
+299: Call(185, 0)
+308: TrueJump(318)
+313: Goto(323)
+
+318: Label(22)
+
+323: Label(1, "compare_function_clean")
+328: Ret0
 "#;
         assert_eq!(expect, actual);
     }
@@ -1166,15 +1561,41 @@ mod tests {
 75: Goto(43)
 
 80: Label(0, "compare_function")
-85: GetField("price", 111)
-94: PushValue(100)
-99: Greater
-100: Not
-101: FalseJump(111)
-106: Pop2(2)
-
-111: Label(1, "compare_function_clean")
-116: Ret0
+85: Goto(113)
+
+90: Label(7)
+95: PushValue(100)
+100: Greater
+101: FalseJump(107)
+106: Pop
+
+107: Label(9)
+112: Ret0
+
+113: Label(8)
+118: GetPathValues("price", 170)
+127: ExpandArrays
+128: PushValue(0)
+
+133: Label(11)
+138: NextElement(157)
+143: Call(90, 1)
+152: FalseJump(133)
+
+157: Label(12)
+162: Pop
+163: Pop
+164: Not
+165: Goto(177)
+
+170: Label(10)
+175: StoreR0_2(1)
+
+177: Label(13)
+182: FalseJump(187)
+
+187: Label(1, "compare_function_clean")
+192: Ret0
 "#;
         assert_eq!(expect, actual);
     }
@@ -1222,20 +1643,59 @@ mod tests {
 75: Goto(43)
 
 80: Label(0, "compare_function")
-85: GetField("age", 144)
-94: PushValue(3)
-99: Greater
-100: FalseJump(144)
-105: Pop2(2)
-110: GetField("child", 144)
-119: GetField("age", 144)
-128: PushValue([1, 2])
-133: In
-134: FalseJump(144)
-139: Pop2(3)
-
-144: Label(1, "compare_function_clean")
-149: Ret0
+85: Goto(113)
+
+90: Label(7)
+95: PushValue(3)
+100: Greater
+101: FalseJump(107)
+106: Pop
+
+107: Label(9)
+112: Ret0
+
+113: Label(8)
+118: GetPathValues("age", 253)
+127: ExpandArrays
+128: PushValue(0)
+
+133: Label(10)
+138: NextElement(157)
+143: Call(90, 1)
+152: FalseJump(133)
+
+157: Label(11)
+162: Pop
+163: Pop
+164: FalseJump(253)
+169: Goto(197)
+
+174: Label(12)
+179: PushValue([1, 2])
+184: In
+185: FalseJump(191)
+190: Pop
+
+191: Label(14)
+196: Ret0
+
+197: Label(13)
+202: GetPathValues("child.age", 253)
+211: ExpandArrays
+212: PushValue(0)
+
+217: Label(15)
+222: NextElement(241)
+227: Call(174, 1)
+236: FalseJump(217)
+
+241: Label(16)
+246: Pop
+247: Pop
+248: FalseJump(253)
+
+253: Label(1, "compare_function_clean")
+258: Ret0
 "#;
         assert_eq!(expect, actual);
     }
@@ -1283,14 +1743,34 @@ mod tests {
 75: Goto(43)
 
 80: Label(0, "compare_function")
-85: GetField("name", 110)
-94: PushValue(//^Vincent//)
-99: Regex
-100: FalseJump(110)
-105: Pop2(2)
-
-110: Label(1, "compare_function_clean")
-115: Ret0
+85: Goto(113)
+
+90: Label(7)
+95: PushValue(//^Vincent//)
+100: Regex
+101: FalseJump(107)
+106: Pop
+
+107: Label(9)
+112: Ret0
+
+113: Label(8)
+118: GetPathValues("name", 169)
+127: ExpandArrays
+128: PushValue(0)
+
+133: Label(10)
+138: NextElement(157)
+143: Call(90, 1)
+152: FalseJump(133)
+
+157: Label(11)
+162: Pop
+163: Pop
+164: FalseJump(169)
+
+169: Label(1, "compare_function_clean")
+174: Ret0
 "#;
         assert_eq!(expect, actual);
     }
@@ -1365,14 +1845,34 @@ mod tests {
 108: Goto(43)
 
 113: Label(0, "compare_function")
-118: GetField("_id", 143)
-127: PushValue(3)
-132: Greater
-133: FalseJump(143)
-138: Pop2(2)
-
-143: Label(1, "compare_function_clean")
-148: Ret0
+118: Goto(146)
+
+123: Label(7)
+128: PushValue(3)
+133: Greater
+134: FalseJump(140)
+139: Pop
+
+140: Label(9)
+145: Ret0
+
+146: Label(8)
+151: GetPathValues("_id", 202)
+160: ExpandArrays
+161: PushValue(0)
+
+166: Label(10)
+171: NextElement(190)
+176: Call(123, 1)
+185: FalseJump(166)
+
+190: Label(11)
+195: Pop
+196: Pop
+197: FalseJump(202)
+
+202: Label(1, "compare_function_clean")
+207: Ret0
 "#;
         assert_eq!(expect, actual);
     }
@@ -1383,12 +1883,12 @@ mod tests {
 
         col_spec.indexes.insert(
             "age_1".into(),
-            IndexInfo {
-                keys: indexmap! {
+            IndexInfo::new(
+                indexmap! {
                     "age".into() => 1,
                 },
-                options: None,
-            },
+                None,
+            ),
         );
 
         let query_doc = doc! {
@@ -1440,14 +1940,34 @@ mod tests {
 93: Goto(43)
 
 98: Label(0, "compare_function")
-103: GetField("_id", 128)
-112: PushValue(3)
-117: Greater
-118: FalseJump(128)
-123: Pop2(2)
-
-128: Label(1, "compare_function_clean")
-133: Ret0
+103: Goto(131)
+
+108: Label(7)
+113: PushValue(3)
+118: Greater
+119: FalseJump(125)
+124: Pop
+
+125: Label(9)
+130: Ret0
+
+131: Label(8)
+136: GetPathValues("_id", 187)
+145: ExpandArrays
+146: PushValue(0)
+
+151: Label(10)
+156: NextElement(175)
+161: Call(108, 1)
+170: FalseJump(151)
+
+175: Label(11)
+180: Pop
+181: Pop
+182: FalseJump(187)
+
+187: Label(1, "compare_function_clean")
+192: Ret0
 "#;
         assert_eq!(expect, actual);
     }
@@ -1463,7 +1983,7 @@ mod tests {
                     },
                 },
             },
-        ], false).unwrap();
+        ], &Config::default(), None, false).unwrap();
         let actual = format!("Program:\n\n{}", program);
         let expect = r#"Program:
 
@@ -1494,14 +2014,34 @@ mod tests {
 75: Goto(43)
 
 80: Label(0, "compare_function")
-85: GetField("age", 110)
-94: PushValue(18)
-99: Greater
-100: FalseJump(110)
-105: Pop2(2)
-
-110: Label(1, "compare_function_clean")
-115: Ret0
+85: Goto(113)
+
+90: Label(7)
+95: PushValue(18)
+100: Greater
+101: FalseJump(107)
+106: Pop
+
+107: Label(9)
+112: Ret0
+
+113: Label(8)
+118: GetPathValues("age", 169)
+127: ExpandArrays
+128: PushValue(0)
+
+133: Label(10)
+138: NextElement(157)
+143: Call(90, 1)
+152: FalseJump(133)
+
+157: Label(11)
+162: Pop
+163: Pop
+164: FalseJump(169)
+
+169: Label(1, "compare_function_clean")
+174: Ret0
 "#;
         assert_eq!(expect, actual);
     }
@@ -1520,17 +2060,17 @@ mod tests {
             doc! {
                 "$count": "total",
             },
-        ], false).unwrap();
+        ], &Config::default(), None, false).unwrap();
         let actual = format!("Program:\n\n{}", program);
 
         let expect = r#"Program:
 
 0: OpenRead("test")
 5: Rewind(25)
-10: Goto(158)
+10: Goto(170)
 
 15: Label(4)
-20: Next(158)
+20: Next(170)
 
 25: Label(7, "close")
 30: PushValue(null)
@@ -1544,7 +2084,7 @@ mod tests {
 
 57: Label(5, "result")
 62: Call(76, 1)
-71: Goto(148)
+71: Goto(160)
 
 76: Label(0)
 81: Dup
@@ -1556,39 +2096,64 @@ mod tests {
 102: Ret0
 
 103: Label(10)
-108: Call(130, 1)
-117: Pop
-118: ExternalIsCompleted($count)
-123: PushNull
-124: FalseJump(82)
-129: Ret0
-
-130: Label(9, "final_result_row_fun")
-135: EqualNull
-136: TrueJump(142)
-141: ResultRow
-
-142: Label(12)
-147: Ret0
-
-148: Label(8, "next_item_label")
-153: Goto(15)
-
-158: Label(3, "compare")
-163: Dup
-164: Call(183, 1)
-173: FalseJump(46)
-178: Goto(57)
-
-183: Label(1, "compare_function")
-188: GetField("age", 213)
-197: PushValue(18)
-202: Greater
-203: FalseJump(213)
-208: Pop2(2)
-
-213: Label(2, "compare_function_clean")
-218: Ret0
+108: EqualNull
+109: TrueJump(136)
+114: Call(142, 1)
+123: Pop
+124: ExternalIsCompleted($count)
+129: PushNull
+130: FalseJump(82)
+135: Ret0
+
+136: Label(12)
+141: Ret0
+
+142: Label(9, "final_result_row_fun")
+147: EqualNull
+148: TrueJump(154)
+153: ResultRow
+
+154: Label(13)
+159: Ret0
+
+160: Label(8, "next_item_label")
+165: Goto(15)
+
+170: Label(3, "compare")
+175: Dup
+176: Call(195, 1)
+185: FalseJump(46)
+190: Goto(57)
+
+195: Label(1, "compare_function")
+200: Goto(228)
+
+205: Label(14)
+210: PushValue(18)
+215: Greater
+216: FalseJump(222)
+221: Pop
+
+222: Label(16)
+227: Ret0
+
+228: Label(15)
+233: GetPathValues("age", 284)
+242: ExpandArrays
+243: PushValue(0)
+
+248: Label(17)
+253: NextElement(272)
+258: Call(205, 1)
+267: FalseJump(248)
+
+272: Label(18)
+277: Pop
+278: Pop
+279: FalseJump(284)
+
+284: Label(2, "compare_function_clean")
+289: Ret0
 "#;
         assert_eq!(expect, actual);
     }
@@ -1600,7 +2165,7 @@ mod tests {
             doc! {
                 "$count": "total",
             },
-        ], false).unwrap();
+        ], &Config::default(), None, false).unwrap();
         let actual = format!("Program:\n\n{}", program);
         let expect = r#"Program:
 
@@ -1619,7 +2184,7 @@ mod tests {
 
 46: Label(0)
 51: Call(65, 1)
-60: Goto(137)
+60: Goto(149)
 
 65: Label(3)
 70: Dup
@@ -1631,23 +2196,28 @@ mod tests {
 91: Ret0
 
 92: Label(6)
-97: Call(119, 1)
-106: Pop
-107: ExternalIsCompleted($count)
-112: PushNull
-113: FalseJump(71)
-118: Ret0
-
-119: Label(5, "final_result_row_fun")
-124: EqualNull
-125: TrueJump(131)
-130: ResultRow
-
-131: Label(8)
-136: Ret0
+97: EqualNull
+98: TrueJump(125)
+103: Call(131, 1)
+112: Pop
+113: ExternalIsCompleted($count)
+118: PushNull
+119: FalseJump(71)
+124: Ret0
+
+125: Label(8)
+130: Ret0
+
+131: Label(5, "final_result_row_fun")
+136: EqualNull
+137: TrueJump(143)
+142: ResultRow
+
+143: Label(9)
+148: Ret0
 
-137: Label(4, "next_item_label")
-142: Goto(15)
+149: Label(4, "next_item_label")
+154: Goto(15)
 "#;
         assert_eq!(expect, actual);
     }
@@ -1663,7 +2233,7 @@ mod tests {
                     },
                 },
             },
-        ], false);
+        ], &Config::default(), None, false);
         assert!(program.is_err());
         match program {
             Err(Error::InvalidField(i)) => {
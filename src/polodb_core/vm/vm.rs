@@ -13,18 +13,21 @@
 // limitations under the License.
 
 use crate::cursor::Cursor;
+use crate::db::db_inner::DatabaseInner;
 use crate::errors::{
     FieldTypeUnexpectedStruct, RegexError, UnexpectedTypeForOpStruct,
 };
-use crate::index::{IndexHelper, IndexHelperOperation};
+use crate::index::{vector_index, IndexHelper, IndexHelperOperation, IndexKeyRange, TEXT_SCORE_FIELD, VECTOR_SEARCH_SCORE_FIELD};
 use crate::transaction::TransactionInner;
-use crate::vm::op::{generic_cmp, DbOp};
+use crate::vm::op::{query_cmp, DbOp};
 use crate::vm::SubProgram;
 use crate::{Error, Metrics, Result};
 use bson::{Bson, Document};
 use regex::RegexBuilder;
 use std::cell::Cell;
 use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use crate::vm::vm_external_func::VmExternalFuncStatus;
 
 macro_rules! try_vm {
@@ -78,6 +81,26 @@ pub(crate) struct VM {
     pub(crate) program: SubProgram,
     global_vars: Vec<Bson>,
     metrics: Metrics,
+    // set when a stage of the pipeline doesn't accept more documents,
+    // the scan is finished without reading the remaining documents
+    scan_stopped: bool,
+    // the keys of the documents found by a multikey index,
+    // a document has an entry for every element of an array
+    seen_pkeys: Option<HashSet<Vec<u8>>>,
+    // the documents found by a `$text`, `$near` or `$vectorSearch` query
+    ranked_matches: Option<RankedMatches>,
+    // the index scan of `$geoWithin` or `$geoIntersects`,
+    // the points of the documents are tested again
+    geo_filter_scan: Option<u32>,
+    // the scores of the `$text` or `$vectorSearch` query are removed from the results
+    strip_scores: bool,
+}
+
+struct RankedMatches {
+    index_scan_id: u32,
+    /// The keys of the documents and their scores or distances,
+    /// the next document is popped from the last.
+    matches: Vec<(Vec<u8>, f64)>,
 }
 
 unsafe impl Send for VM {}
@@ -88,6 +111,9 @@ impl VM {
         let stack = Vec::with_capacity(STACK_SIZE);
         let pc = program.instructions.as_ptr();
         let mut global_vars = Vec::<Bson>::new();
+        let strip_scores = program.index_scans
+            .iter()
+            .any(|index_scan| index_scan.text.is_some() || index_scan.vector.is_some());
 
         for item in &program.global_variables {
             global_vars.push(item.init_value.clone());
@@ -107,6 +133,11 @@ impl VM {
             program,
             global_vars,
             metrics,
+            scan_stopped: false,
+            seen_pkeys: None,
+            ranked_matches: None,
+            geo_filter_scan: None,
+            strip_scores,
         }
     }
 
@@ -151,6 +182,7 @@ impl VM {
             let item = cursor.copy_data()?;
             let doc = bson::from_slice(item.as_ref())?;
             self.stack.push(Bson::Document(doc));
+            self.metrics.add_scanned_doc_count();
             is_empty.set(false);
         } else {
             is_empty.set(true);
@@ -175,65 +207,269 @@ impl VM {
         Ok(true)
     }
 
-    fn find_by_index(&mut self) -> Result<bool> {
-        let stack_len = self.stack.len();
-        // let col_name = self.stack[stack_len - 1].as_str().expect("col_name must be string").to_string();
-        let query_value = &self.stack[stack_len - 2];
-
+    fn find_by_index(&mut self, index_scan_id: u32) -> Result<bool> {
+        let index_scan = &self.program.index_scans[index_scan_id as usize];
+        if index_scan.text.is_some() {
+            return self.find_by_text(index_scan_id);
+        }
+        if index_scan.vector.is_some() {
+            return self.find_by_vector(index_scan_id);
+        }
+        if let Some(geo_scan) = &index_scan.geo {
+            if geo_scan.query.is_near() {
+                return self.find_by_near(index_scan_id);
+            }
+            self.geo_filter_scan = Some(index_scan_id);
+        }
         let cursor = self.r1.as_mut().unwrap();
-        let result = cursor.reset_by_index_value(query_value)?;
+        let found = cursor.reset_by_index_ranges(&index_scan.ranges, index_scan.reverse)?;
 
-        if !result {
+        self.seen_pkeys = if index_scan.multikey {
+            Some(HashSet::new())
+        } else {
+            None
+        };
+
+        if !found {
             return Ok(false);
         }
 
-        let key = cursor.peek_key().expect("key must exist");
+        self.metrics.add_find_by_index_count();
+
+        self.push_index_value()
+    }
 
-        let index_value = self.read_index_value_by_index_key(key.as_ref())?;
+    // The scores of the terms of every document are added up,
+    // then the documents are given out in the descending order of the scores.
+    fn find_by_text(&mut self, index_scan_id: u32) -> Result<bool> {
+        let index_scan = &self.program.index_scans[index_scan_id as usize];
+        let text_scan = index_scan.text.as_ref().unwrap();
+        let cursor = self.r1.as_mut().unwrap();
 
-        if index_value.is_none() {
-            return Ok(false);
+        let mut scores = HashMap::<Vec<u8>, f64>::new();
+        let mut found = cursor.reset_by_index_ranges(&index_scan.ranges, false)?;
+        while found {
+            let key = cursor.peek_key().expect("key must exist");
+            let value = cursor.copy_data()?;
+            let score = <[u8; 8]>::try_from(value.as_slice()).map_err(|_| Error::DecodeEOF)?;
+            *scores.entry(VM::pkey_in_kv_of_index_key(key.as_ref())?).or_insert(0.0) += f64::from_be_bytes(score);
+            found = cursor.next_index_key()?;
+        }
+
+        let mut found = cursor.reset_by_index_ranges(&text_scan.negated_ranges, false)?;
+        while found {
+            let key = cursor.peek_key().expect("key must exist");
+            scores.remove(&VM::pkey_in_kv_of_index_key(key.as_ref())?);
+            found = cursor.next_index_key()?;
         }
 
-        self.stack.push(index_value.unwrap());
+        let mut matches: Vec<(Vec<u8>, f64)> = scores.into_iter().collect();
+        matches.sort_by(|(a_key, a_score), (b_key, b_score)| {
+            a_score.partial_cmp(b_score).unwrap_or(Ordering::Equal).then_with(|| b_key.cmp(a_key))
+        });
+        self.ranked_matches = Some(RankedMatches {
+            index_scan_id,
+            matches,
+        });
 
         self.metrics.add_find_by_index_count();
 
-        Ok(true)
+        self.push_ranked_match()
+    }
+
+    // The points in the cells are tested,
+    // then the documents are given out in the ascending order of the distances.
+    fn find_by_near(&mut self, index_scan_id: u32) -> Result<bool> {
+        let index_scan = &self.program.index_scans[index_scan_id as usize];
+        let geo_scan = index_scan.geo.as_ref().unwrap();
+
+        let mut matches = Vec::<(Vec<u8>, f64)>::new();
+        let mut found = self.r1.as_mut().unwrap().reset_by_index_ranges(&index_scan.ranges, false)?;
+        while found {
+            let key = self.r1.as_ref().unwrap().peek_key().expect("key must exist");
+            let pkey_in_kv = VM::pkey_in_kv_of_index_key(key.as_ref())?;
+            let doc = self.read_document_by_pkey_in_kv(&pkey_in_kv)?;
+            if let Some(distance) = doc.and_then(|doc| geo_scan.test(&doc)) {
+                matches.push((pkey_in_kv, distance));
+            }
+            found = self.r1.as_mut().unwrap().next_index_key()?;
+        }
+
+        matches.sort_by(|(a_key, a_distance), (b_key, b_distance)| {
+            b_distance.partial_cmp(a_distance).unwrap_or(Ordering::Equal).then_with(|| b_key.cmp(a_key))
+        });
+        self.ranked_matches = Some(RankedMatches {
+            index_scan_id,
+            matches,
+        });
+
+        self.metrics.add_find_by_index_count();
+
+        self.push_ranked_match()
+    }
+
+    // The lists of the nearest centroids are scanned until there are enough candidates,
+    // then the nearest candidates are given out in the descending order of the scores.
+    fn find_by_vector(&mut self, index_scan_id: u32) -> Result<bool> {
+        let index_scan = &self.program.index_scans[index_scan_id as usize];
+        let vector_scan = index_scan.vector.as_ref().unwrap();
+
+        let centroids = vector_index::load_centroids(&self.txn, &vector_scan.index_prefix)?;
+        let lists = vector_index::rank_lists(&centroids, &vector_scan.query_vector, vector_scan.similarity);
+
+        let cursor = self.r1.as_mut().unwrap();
+        let mut matches = Vec::<(Vec<u8>, f64)>::new();
+        for list in lists {
+            if matches.len() >= vector_scan.num_candidates {
+                break;
+            }
+            let range = IndexKeyRange::with_prefix(vector_index::list_prefix(&vector_scan.index_prefix, list)?);
+            let mut found = cursor.reset_by_index_ranges(&[range], false)?;
+            while found {
+                let key = cursor.peek_key().expect("key must exist");
+                let vector = vector_index::decode_vector(cursor.copy_data()?.as_slice())?;
+                // the vectors of other dimensions are not comparable
+                if vector.len() == vector_scan.query_vector.len() {
+                    let score = vector_scan.similarity.score(&vector_scan.query_vector, &vector);
+                    matches.push((VM::pkey_in_kv_of_index_key(key.as_ref())?, score));
+                }
+                found = cursor.next_index_key()?;
+            }
+        }
+
+        matches.sort_by(|(a_key, a_score), (b_key, b_score)| {
+            b_score.partial_cmp(a_score).unwrap_or(Ordering::Equal).then_with(|| a_key.cmp(b_key))
+        });
+        matches.truncate(vector_scan.num_candidates);
+        matches.reverse();
+        self.ranked_matches = Some(RankedMatches {
+            index_scan_id,
+            matches,
+        });
+
+        self.metrics.add_find_by_index_count();
+
+        self.push_ranked_match()
+    }
+
+    // Push the next document found by the `$text`, `$near` or `$vectorSearch` query,
+    // the score of `$text` or `$vectorSearch` is added to the document,
+    // and the documents without all the phrases are skipped.
+    fn push_ranked_match(&mut self) -> Result<bool> {
+        loop {
+            let ranked_matches = self.ranked_matches.as_mut().unwrap();
+            let index_scan_id = ranked_matches.index_scan_id;
+            let (pkey_in_kv, rank) = match ranked_matches.matches.pop() {
+                Some(item) => item,
+                None => return Ok(false),
+            };
+
+            let mut doc = match self.read_document_by_pkey_in_kv(&pkey_in_kv)? {
+                Some(doc) => doc,
+                None => continue,
+            };
+            self.metrics.add_scanned_doc_count();
+
+            if let Some(text_scan) = &self.program.index_scans[index_scan_id as usize].text {
+                if !text_scan.search.matches_phrases(&doc) {
+                    continue;
+                }
+                doc.insert(TEXT_SCORE_FIELD, Bson::Double(rank));
+            }
+            if self.program.index_scans[index_scan_id as usize].vector.is_some() {
+                doc.insert(VECTOR_SEARCH_SCORE_FIELD, Bson::Double(rank));
+            }
+
+            self.stack.push(Bson::Document(doc));
+            return Ok(true);
+        }
+    }
+
+    #[inline]
+    fn matches_geo_filter(&self, value: &Bson) -> bool {
+        let index_scan_id = match self.geo_filter_scan {
+            Some(index_scan_id) => index_scan_id,
+            None => return true,
+        };
+        let geo_scan = self.program.index_scans[index_scan_id as usize].geo.as_ref().unwrap();
+        match value {
+            Bson::Document(doc) => geo_scan.test(doc).is_some(),
+            _ => false,
+        }
+    }
+
+    // Push the document of the current index key to the stack,
+    // the keys without the document are skipped.
+    fn push_index_value(&mut self) -> Result<bool> {
+        loop {
+            let key = self.r1.as_ref().unwrap().peek_key().expect("key must exist");
+
+            let value = self.read_index_value_by_index_key(key.as_ref())?;
+            if let Some(value) = value.filter(|value| self.matches_geo_filter(value)) {
+                self.stack.push(value);
+                self.metrics.add_scanned_doc_count();
+                return Ok(true);
+            }
+
+            if !self.r1.as_mut().unwrap().next_index_key()? {
+                return Ok(false);
+            }
+        }
     }
 
     fn read_index_value_by_index_key(
         &mut self,
         index_key: &[u8],
     ) -> Result<Option<Bson>> {
-        let slices = crate::utils::bson::split_stacked_keys(index_key.as_ref())?;
+        let pkey_in_kv = VM::pkey_in_kv_of_index_key(index_key)?;
+
+        if let Some(seen_pkeys) = &mut self.seen_pkeys {
+            if !seen_pkeys.insert(pkey_in_kv.clone()) {
+                return Ok(None);
+            }
+        }
+
+        let doc = self.read_document_by_pkey_in_kv(&pkey_in_kv)?;
+        Ok(doc.map(Bson::Document))
+    }
+
+    // The key of the document referenced by the index key.
+    fn pkey_in_kv_of_index_key(index_key: &[u8]) -> Result<Vec<u8>> {
+        let slices = crate::utils::bson::split_stacked_keys(index_key)?;
         let pkey = slices.last().expect("pkey must exist");
 
         let col_name = &slices[1];
 
-        let pkey_in_kv = crate::utils::bson::stacked_key(vec![col_name, pkey])?;
+        crate::utils::bson::stacked_key(vec![col_name, pkey])
+    }
 
+    fn read_document_by_pkey_in_kv(&self, pkey_in_kv: &[u8]) -> Result<Option<Document>> {
         let db_iter = self.txn.rocksdb_txn.new_iterator();
         db_iter.seek_to_first();
 
-        db_iter.seek(pkey_in_kv.as_slice());
+        db_iter.seek(pkey_in_kv);
 
         if !db_iter.valid() {
             return Ok(None);
         }
         let current_key = db_iter.copy_key()?;
 
-        if current_key.as_slice().cmp(pkey_in_kv.as_slice()) != Ordering::Equal {
+        if current_key.as_slice().cmp(pkey_in_kv) != Ordering::Equal {
             return Ok(None);
         }
 
         let buf = db_iter.copy_data()?;
         let doc = bson::from_slice(buf.as_ref())?;
 
-        Ok(Some(Bson::Document(doc)))
+        Ok(Some(doc))
     }
 
     fn next(&mut self) -> Result<()> {
+        if self.scan_stopped {
+            self.r0 = 0;
+            return Ok(());
+        }
+
         let cursor = self.r1.as_mut().unwrap();
         cursor.next()?;
 
@@ -241,6 +477,7 @@ impl VM {
             let bytes = cursor.copy_data()?;
             let doc = bson::from_slice(bytes.as_ref())?;
             self.stack.push(Bson::Document(doc));
+            self.metrics.add_scanned_doc_count();
 
             debug_assert!(
                 self.stack.len() <= 64,
@@ -257,28 +494,23 @@ impl VM {
     }
 
     fn next_index_value(&mut self) -> Result<()> {
-        let cursor = self.r1.as_mut().unwrap();
-        cursor.next()?;
-        let current_key = cursor.peek_key();
-        if current_key.is_none() {
+        if self.scan_stopped {
             self.r0 = 0;
             return Ok(());
         }
-        let current_key = current_key.unwrap();
-        if !current_key.starts_with(cursor.prefix_bytes.as_slice()) {
-            self.r0 = 0;
+
+        if self.ranked_matches.is_some() {
+            self.r0 = if self.push_ranked_match()? { 1 } else { 0 };
             return Ok(());
         }
 
-        let value_opt = self.read_index_value_by_index_key(current_key.as_ref())?;
-        if value_opt.is_none() {
+        let cursor = self.r1.as_mut().unwrap();
+        if !cursor.next_index_key()? {
             self.r0 = 0;
             return Ok(());
         }
 
-        self.stack.push(value_opt.unwrap());
-
-        self.r0 = 1;
+        self.r0 = if self.push_index_value()? { 1 } else { 0 };
 
         Ok(())
     }
@@ -407,8 +639,9 @@ impl VM {
         let pkey = data_doc.get("_id").unwrap();
         let txn = &self.txn;
 
+        let mut multikey_indexes = Vec::new();
         for (index_name, index_info) in index_meta {
-            IndexHelper::try_execute_with_index_info(
+            let is_multikey = IndexHelper::try_execute_with_index_info(
                 IndexHelperOperation::Insert,
                 data_doc,
                 info.col_name.as_str(),
@@ -417,6 +650,13 @@ impl VM {
                 index_info,
                 txn,
             )?;
+            if is_multikey && !index_info.multikey {
+                multikey_indexes.push(index_name.clone());
+            }
+        }
+
+        if !multikey_indexes.is_empty() {
+            DatabaseInner::mark_multikey_indexes(txn, info.col_name.as_str(), &multikey_indexes)?;
         }
 
         Ok(())
@@ -548,14 +788,15 @@ impl VM {
                     }
 
                     DbOp::FindByIndex => {
-                        let location = self.pc.add(1).cast::<u32>().read();
+                        let index_scan_id = self.pc.add(1).cast::<u32>().read();
+                        let location = self.pc.add(5).cast::<u32>().read();
 
-                        let found = try_vm!(self, self.find_by_index());
+                        let found = try_vm!(self, self.find_by_index(index_scan_id));
 
                         if !found {
                             self.reset_location(location);
                         } else {
-                            self.pc = self.pc.add(5);
+                            self.pc = self.pc.add(9);
                         }
                     }
 
@@ -668,6 +909,43 @@ impl VM {
                         }
                     }
 
+                    DbOp::GetPathValues => {
+                        let path_stat_id = self.pc.add(1).cast::<u32>().read();
+                        let location = self.pc.add(5).cast::<u32>().read();
+
+                        let path = self.borrow_static(path_stat_id as usize);
+                        let values = match (&self.stack[self.stack.len() - 1], path) {
+                            (Bson::Document(doc), Bson::String(path)) => {
+                                crate::utils::bson::get_path_values(doc, path)
+                            }
+                            _ => Vec::new(),
+                        };
+
+                        if values.is_empty() {
+                            self.r0 = 0;
+                            self.reset_location(location);
+                        } else {
+                            self.r0 = 1;
+                            self.stack.push(Bson::Array(values));
+                            self.pc = self.pc.add(9);
+                        }
+                    }
+
+                    DbOp::ExpandArrays => {
+                        let top = self.stack.len() - 1;
+                        if let Bson::Array(values) = &self.stack[top] {
+                            let mut expanded = Vec::with_capacity(values.len());
+                            for value in values {
+                                expanded.push(value.clone());
+                                if let Bson::Array(elements) = value {
+                                    expanded.extend(elements.iter().cloned());
+                                }
+                            }
+                            self.stack[top] = Bson::Array(expanded);
+                        }
+                        self.pc = self.pc.add(1);
+                    }
+
                     DbOp::UnsetField => {
                         let field_id = self.pc.add(1).cast::<u32>().read();
 
@@ -789,7 +1067,7 @@ impl VM {
                         let val1 = &self.stack[self.stack.len() - 2];
                         let val2 = &self.stack[self.stack.len() - 1];
 
-                        let cmp = try_vm!(self, generic_cmp(op, val1, val2));
+                        let cmp = try_vm!(self, query_cmp(op, val1, val2));
 
                         self.r0 = if cmp { 1 } else { 0 };
 
@@ -807,9 +1085,13 @@ impl VM {
 
                         self.r0 = 0;
 
+                        // the values not comparable, such as the arrays, are compared as `$eq` does
                         for item in top1.as_array().unwrap().iter() {
-                            let cmp_result = crate::utils::bson::value_cmp(top2, item);
-                            if let Ok(Ordering::Equal) = cmp_result {
+                            let is_equal = match crate::utils::bson::value_cmp(top2, item) {
+                                Ok(ordering) => ordering == Ordering::Equal,
+                                Err(_) => top2 == item,
+                            };
+                            if is_equal {
                                 self.r0 = 1;
                                 break;
                             }
@@ -824,6 +1106,86 @@ impl VM {
                         self.pc = self.pc.add(1);
                     }
 
+                    // stack
+                    // -1: Array of type codes
+                    // -2: value
+                    DbOp::IsType => {
+                        let types = &self.stack[self.stack.len() - 1];
+                        let val = &self.stack[self.stack.len() - 2];
+
+                        let code = crate::utils::bson::type_code(val);
+                        let matched = types
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .any(|item| item.as_i32() == Some(code));
+                        self.r0 = if matched { 1 } else { 0 };
+
+                        self.pc = self.pc.add(1);
+                    }
+
+                    // stack
+                    // -1: [divisor, remainder]
+                    // -2: value
+                    DbOp::Mod => {
+                        let operand = self.stack[self.stack.len() - 1].as_array().unwrap();
+                        let val = &self.stack[self.stack.len() - 2];
+
+                        let divisor = operand[0].as_i64().unwrap();
+                        let remainder = operand[1].as_i64().unwrap();
+                        let matched = crate::utils::bson::truncated_i64(val)
+                            .is_some_and(|val| val.wrapping_rem(divisor) == remainder);
+                        self.r0 = if matched { 1 } else { 0 };
+
+                        self.pc = self.pc.add(1);
+                    }
+
+                    // stack
+                    // -1: bitmask
+                    // -2: value
+                    DbOp::BitsAllSet | DbOp::BitsAnySet | DbOp::BitsAllClear | DbOp::BitsAnyClear => {
+                        let mask = self.stack[self.stack.len() - 1].as_i64().unwrap();
+                        let val = &self.stack[self.stack.len() - 2];
+
+                        let matched = crate::utils::bson::exact_i64(val).is_some_and(|val| {
+                            let bits = val & mask;
+                            match op {
+                                DbOp::BitsAllSet => bits == mask,
+                                DbOp::BitsAnySet => bits != 0,
+                                DbOp::BitsAllClear => bits == 0,
+                                _ => bits != mask,
+                            }
+                        });
+                        self.r0 = if matched { 1 } else { 0 };
+
+                        self.pc = self.pc.add(1);
+                    }
+
+                    // stack
+                    // -1: index of the next element
+                    // -2: Array
+                    DbOp::NextElement => {
+                        let location = self.pc.add(1).cast::<u32>().read();
+                        let len = self.stack.len();
+                        let index = self.stack[len - 1].as_i64().unwrap() as usize;
+
+                        let next = match &self.stack[len - 2] {
+                            Bson::Array(arr) => arr.get(index).cloned(),
+                            _ => None,
+                        };
+                        match next {
+                            Some(elem) => {
+                                self.stack[len - 1] = Bson::Int64((index + 1) as i64);
+                                self.stack.push(elem);
+                                self.pc = self.pc.add(5);
+                            }
+                            None => {
+                                self.r0 = 0;
+                                self.reset_location(location);
+                            }
+                        }
+                    }
+
                     DbOp::Regex => {
                         let val1 = &self.stack[self.stack.len() - 2];
                         let val2 = &self.stack[self.stack.len() - 1];
@@ -907,6 +1269,12 @@ impl VM {
                     }
 
                     DbOp::ResultRow => {
+                        if self.strip_scores {
+                            if let Some(Bson::Document(doc)) = self.stack.last_mut() {
+                                doc.remove(TEXT_SCORE_FIELD);
+                                doc.remove(VECTOR_SEARCH_SCORE_FIELD);
+                            }
+                        }
                         self.pc = self.pc.add(1);
                         self.state = VmState::HasRow;
                         return Ok(());
@@ -914,6 +1282,8 @@ impl VM {
 
                     DbOp::Close => {
                         self.r1 = None;
+                        self.ranked_matches = None;
+                        self.geo_filter_scan = None;
                         self.txn.auto_commit()?;
 
                         self.pc = self.pc.add(1);
@@ -951,6 +1321,9 @@ impl VM {
                         let func = &self.program.external_funcs[id as usize];
                         let params = &self.stack[self.stack.len() - size_of_param..];
                         let result = try_vm!(self, func.call(params));
+                        if func.is_exhausted() {
+                            self.scan_stopped = true;
+                        }
                         // pop params
                         self.stack.resize(self.stack.len() - size_of_param, Bson::Null);
                         self.r0 = match result {
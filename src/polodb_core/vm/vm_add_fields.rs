@@ -83,7 +83,7 @@ impl VmExternalFunc for VmFuncAddFields {
                 OperatorExpr::Constant(v) => v.clone(),
                 OperatorExpr::Alias(alias) => {
                     let alias = alias.as_str();
-                    doc.get(alias).cloned().unwrap_or(Bson::Null)
+                    crate::utils::bson::try_get_document_value(&doc, alias).unwrap_or(Bson::Null)
                 }
             };
             doc.insert(k.clone(), value);
@@ -0,0 +1,56 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bson::Bson;
+use crate::vm::operators::{is_truthy, OpRegistry, OperatorExpr};
+use crate::vm::vm_external_func::{VmExternalFunc, VmExternalFuncStatus};
+use crate::Result;
+
+/// The `$expr` of a query, which evaluates an aggregation expression on the document.
+///
+/// The document is passed through if the expression is true,
+/// otherwise r0 is set to false.
+pub(crate) struct VmFuncExpr {
+    expr: OperatorExpr,
+}
+
+impl VmFuncExpr {
+
+    pub(crate) fn compile(paths: &mut Vec<String>, registry: OpRegistry, value: &Bson) -> Result<Box<dyn VmExternalFunc>> {
+        let expr = OperatorExpr::compile(paths, &registry, value)?;
+        Ok(Box::new(VmFuncExpr {
+            expr,
+        }))
+    }
+
+}
+
+impl VmExternalFunc for VmFuncExpr {
+    fn name(&self) -> &str {
+        "expr"
+    }
+
+    fn call(&self, args: &[Bson]) -> Result<VmExternalFuncStatus> {
+        let arg0 = &args[0];
+        if is_truthy(&self.expr.evaluate(arg0)) {
+            Ok(VmExternalFuncStatus::Next(arg0.clone()))
+        } else {
+            Ok(VmExternalFuncStatus::Continue)
+        }
+    }
+
+    fn is_completed(&self) -> bool {
+        true
+    }
+}
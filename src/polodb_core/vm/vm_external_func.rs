@@ -24,4 +24,10 @@ pub(crate) trait VmExternalFunc {
     fn name(&self) -> &str;
     fn call(&self, args: &[Bson]) -> Result<VmExternalFuncStatus>;
     fn is_completed(&self) -> bool;
+
+    // Return true if the function will not accept any more input,
+    // so the scan feeding the pipeline can be stopped early.
+    fn is_exhausted(&self) -> bool {
+        false
+    }
 }
@@ -64,4 +64,8 @@ impl VmExternalFunc for VmFuncLimit {
     fn is_completed(&self) -> bool {
         true
     }
+
+    fn is_exhausted(&self) -> bool {
+        self.remain.load(Ordering::Relaxed) == 0
+    }
 }
@@ -0,0 +1,321 @@
+// Copyright 2024 Vincent Chan
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bson::{Bson, Document};
+use indexmap::IndexMap;
+use crate::{Error, Result};
+use crate::errors::mk_invalid_aggregate_field;
+use crate::index::PartialFilter;
+use crate::vm::vm_external_func::{VmExternalFunc, VmExternalFuncStatus};
+
+// The key of an element of an array tested by `$elemMatch` with only operators,
+// such as `{ "$elemMatch": { "$gte": 5 } }`.
+const ELEMENT_KEY: &str = "";
+
+enum FieldProjection {
+    Include,
+    Exclude,
+    /// Skip the elements of an array, and keep the following ones up to the limit.
+    Slice {
+        skip: i64,
+        limit: Option<i64>,
+    },
+    /// Keep the first element of an array matching the filter.
+    ElemMatch {
+        filter: PartialFilter,
+        on_element: bool,
+    },
+    /// The projection of the fields of an embedded document,
+    /// or of the documents in an array.
+    Nested(IndexMap<String, FieldProjection>),
+}
+
+/// The `$project` stage, it also serves the projection of `find`.
+///
+/// In an inclusion projection, only the listed fields and `_id` are returned,
+/// in an exclusion projection, all the fields but the listed ones are returned.
+/// The `$slice` of an array doesn't change the mode.
+pub(crate) struct VmFuncProject {
+    fields: IndexMap<String, FieldProjection>,
+    is_inclusion: bool,
+}
+
+impl VmFuncProject {
+
+    pub(crate) fn compile(paths: &mut Vec<String>, value: &Bson) -> Result<Box<dyn VmExternalFunc>> {
+        let projection = match value {
+            Bson::Document(doc) => doc,
+            _ => {
+                let invalid_err = mk_invalid_aggregate_field(paths);
+                return Err(Error::InvalidField(invalid_err));
+            }
+        };
+
+        let mut fields = IndexMap::<String, FieldProjection>::new();
+        let mut inclusion: Option<&str> = None;
+        let mut exclusion: Option<&str> = None;
+        for (key, value) in projection {
+            let field = crate::path_hint_3!(paths, key.clone(), {
+                VmFuncProject::compile_field(paths, key, value)?
+            });
+            if key != "_id" {
+                match field {
+                    FieldProjection::Include | FieldProjection::ElemMatch { .. } => {
+                        inclusion.get_or_insert(key);
+                    }
+                    FieldProjection::Exclude => {
+                        exclusion.get_or_insert(key);
+                    }
+                    _ => (),
+                }
+            }
+            VmFuncProject::insert_path(&mut fields, key, field)?;
+        }
+
+        if let (Some(_), Some(excluded)) = (inclusion, exclusion) {
+            return Err(Error::ValidationError(format!(
+                "cannot do exclusion on the field '{}' in an inclusion projection",
+                excluded,
+            )));
+        }
+
+        let is_inclusion = match inclusion {
+            Some(_) => true,
+            None => exclusion.is_none() && matches!(fields.get("_id"), Some(FieldProjection::Include)),
+        };
+        // the `_id` is returned unless it's excluded explicitly
+        if is_inclusion && !fields.contains_key("_id") {
+            fields.insert("_id".to_string(), FieldProjection::Include);
+        }
+
+        Ok(Box::new(VmFuncProject {
+            fields,
+            is_inclusion,
+        }))
+    }
+
+    fn compile_field(paths: &mut Vec<String>, key: &str, value: &Bson) -> Result<FieldProjection> {
+        let field = match value {
+            Bson::Boolean(true) => FieldProjection::Include,
+            Bson::Boolean(false) => FieldProjection::Exclude,
+            Bson::Int32(0) | Bson::Int64(0) => FieldProjection::Exclude,
+            Bson::Double(val) if *val == 0.0 => FieldProjection::Exclude,
+            Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) => FieldProjection::Include,
+            Bson::Document(doc) if doc.len() == 1 && doc.contains_key("$slice") => {
+                VmFuncProject::compile_slice(key, doc.get("$slice").unwrap())?
+            }
+            Bson::Document(doc) if doc.len() == 1 && doc.contains_key("$elemMatch") => {
+                if key.contains('.') {
+                    return Err(Error::ValidationError(format!(
+                        "$elemMatch projection can't be applied to the nested field '{}'",
+                        key,
+                    )));
+                }
+                VmFuncProject::compile_elem_match(key, doc.get("$elemMatch").unwrap())?
+            }
+            _ => {
+                let invalid_err = mk_invalid_aggregate_field(paths);
+                return Err(Error::InvalidField(invalid_err));
+            }
+        };
+        Ok(field)
+    }
+
+    // `{ "$slice": n }` keeps the first n elements, or the last n elements if n is negative,
+    // `{ "$slice": [skip, limit] }` skips the elements from the end if skip is negative.
+    fn compile_slice(key: &str, value: &Bson) -> Result<FieldProjection> {
+        let invalid_slice = || Error::ValidationError(format!("invalid $slice of the field '{}'", key));
+        let as_i64 = |value: &Bson| match value {
+            Bson::Int32(val) => Some(*val as i64),
+            Bson::Int64(val) => Some(*val),
+            Bson::Double(val) if val.fract() == 0.0 => Some(*val as i64),
+            _ => None,
+        };
+
+        let field = match value {
+            Bson::Array(arr) if arr.len() == 2 => {
+                let skip = as_i64(&arr[0]).ok_or_else(invalid_slice)?;
+                let limit = as_i64(&arr[1]).ok_or_else(invalid_slice)?;
+                if limit <= 0 {
+                    return Err(invalid_slice());
+                }
+                FieldProjection::Slice {
+                    skip,
+                    limit: Some(limit),
+                }
+            }
+            _ => {
+                let count = as_i64(value).ok_or_else(invalid_slice)?;
+                if count >= 0 {
+                    FieldProjection::Slice {
+                        skip: 0,
+                        limit: Some(count),
+                    }
+                } else {
+                    FieldProjection::Slice {
+                        skip: count,
+                        limit: None,
+                    }
+                }
+            }
+        };
+        Ok(field)
+    }
+
+    fn compile_elem_match(key: &str, value: &Bson) -> Result<FieldProjection> {
+        let condition = match value {
+            Bson::Document(doc) => doc,
+            _ => return Err(Error::ValidationError(format!("$elemMatch of the field '{}' must be a document", key))),
+        };
+        // the operators are applied to the element itself
+        let on_element = !condition.is_empty() && condition.keys().all(|key| key.starts_with('$') && key != "$and");
+        let filter = if on_element {
+            let mut wrapped = Document::new();
+            wrapped.insert(ELEMENT_KEY, condition.clone());
+            PartialFilter::parse(&wrapped)
+        } else {
+            PartialFilter::parse(condition)
+        };
+        let filter = filter.map_err(|_| {
+            Error::ValidationError(format!("unsupported $elemMatch projection of the field '{}'", key))
+        })?;
+        Ok(FieldProjection::ElemMatch {
+            filter,
+            on_element,
+        })
+    }
+
+    // The dotted path is stored as the nested projections.
+    fn insert_path(fields: &mut IndexMap<String, FieldProjection>, path: &str, field: FieldProjection) -> Result<()> {
+        let (head, rest) = match path.split_once('.') {
+            Some((head, rest)) => (head, Some(rest)),
+            None => (path, None),
+        };
+        let path_collision = || Error::ValidationError(format!("path collision at '{}'", path));
+
+        match rest {
+            None => {
+                if fields.contains_key(head) {
+                    return Err(path_collision());
+                }
+                fields.insert(head.to_string(), field);
+            }
+            Some(rest) => {
+                let nested = fields
+                    .entry(head.to_string())
+                    .or_insert_with(|| FieldProjection::Nested(IndexMap::new()));
+                match nested {
+                    FieldProjection::Nested(nested) => VmFuncProject::insert_path(nested, rest, field)?,
+                    _ => return Err(path_collision()),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn project_document(&self, doc: &Document, fields: &IndexMap<String, FieldProjection>) -> Document {
+        let mut result = Document::new();
+        for (key, value) in doc {
+            match fields.get(key) {
+                Some(field) => {
+                    if let Some(value) = self.project_value(value, field) {
+                        result.insert(key.clone(), value);
+                    }
+                }
+                None => {
+                    if !self.is_inclusion {
+                        result.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    fn project_value(&self, value: &Bson, field: &FieldProjection) -> Option<Bson> {
+        match field {
+            FieldProjection::Include => Some(value.clone()),
+            FieldProjection::Exclude => None,
+            FieldProjection::Slice { skip, limit } => match value {
+                Bson::Array(arr) => Some(Bson::Array(slice_array(arr, *skip, *limit))),
+                _ => Some(value.clone()),
+            },
+            FieldProjection::ElemMatch { filter, on_element } => {
+                let arr = value.as_array()?;
+                let found = arr.iter().find(|item| match item {
+                    _ if *on_element => {
+                        let mut wrapped = Document::new();
+                        wrapped.insert(ELEMENT_KEY, (*item).clone());
+                        filter.matches(&wrapped)
+                    }
+                    Bson::Document(doc) => filter.matches(doc),
+                    _ => false,
+                })?;
+                Some(Bson::Array(vec![found.clone()]))
+            }
+            FieldProjection::Nested(fields) => match value {
+                Bson::Document(doc) => Some(Bson::Document(self.project_document(doc, fields))),
+                Bson::Array(arr) => {
+                    let items = arr
+                        .iter()
+                        .filter_map(|item| match item {
+                            Bson::Document(doc) => Some(Bson::Document(self.project_document(doc, fields))),
+                            // the values without the fields are only kept by an exclusion
+                            _ if self.is_inclusion => None,
+                            _ => Some(item.clone()),
+                        })
+                        .collect();
+                    Some(Bson::Array(items))
+                }
+                _ if self.is_inclusion => None,
+                _ => Some(value.clone()),
+            },
+        }
+    }
+
+}
+
+fn slice_array(arr: &[Bson], skip: i64, limit: Option<i64>) -> Vec<Bson> {
+    let len = arr.len() as i64;
+    let start = if skip < 0 { (len + skip).max(0) } else { skip.min(len) };
+    let end = match limit {
+        Some(limit) => start.saturating_add(limit).min(len),
+        None => len,
+    };
+    arr[start as usize..end as usize].to_vec()
+}
+
+impl VmExternalFunc for VmFuncProject {
+    fn name(&self) -> &str {
+        "project"
+    }
+
+    fn call(&self, args: &[Bson]) -> Result<VmExternalFuncStatus> {
+        let arg0 = &args[0];
+        if arg0.as_null().is_some() {
+            return Ok(VmExternalFuncStatus::Next(Bson::Null));
+        }
+        let doc = match arg0 {
+            Bson::Document(doc) => doc,
+            _ => return Err(Error::UnknownAggregationOperation("Invalid argument for $project".to_string())),
+        };
+        let result = self.project_document(doc, &self.fields);
+        Ok(VmExternalFuncStatus::Next(Bson::Document(result)))
+    }
+
+    fn is_completed(&self) -> bool {
+        true
+    }
+}
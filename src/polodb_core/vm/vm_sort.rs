@@ -13,31 +13,81 @@
 // limitations under the License.
 
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::sync::atomic::AtomicUsize;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use bson::{Bson, Document};
 use crate::vm::vm_external_func::{VmExternalFunc, VmExternalFuncStatus};
 use crate::{Result, Error};
 use crate::errors::mk_invalid_aggregate_field;
+use crate::vm::operators::MetaOperator;
 
+const HEAP_INIT_CAPACITY: usize = 1024;
+
+/// The maximum number of runs merged at the same time.
+const MERGE_FAN_IN: usize = 16;
+
+/// The `$sort` stage.
+///
+/// The documents are buffered in memory until the size of the buffer
+/// exceeds the memory limit, then the buffer is sorted and written to
+/// a temporary file in the spill directory as a sorted run.
+/// When all the documents are received, the runs are merged,
+/// at most `MERGE_FAN_IN` runs at a time.
+///
+/// If the `$sort` is followed by a `$limit`, only the first `top_k`
+/// documents are kept in a bounded heap, until the heap exceeds the memory limit.
 pub(crate) struct VmFuncSort {
-    order_map: HashMap<String, i8>,
-    buffer: RefCell<Vec<Document>>,
-    idx: AtomicUsize,
+    orders: Rc<SortOrders>,
+    memory_limit: usize,
+    spill_dir: PathBuf,
+    state: RefCell<SortState>,
+}
+
+enum SortState {
+    Input {
+        buffer: Vec<Document>,
+        buffer_size: usize,
+        runs: Vec<SortedRun>,
+    },
+    TopK {
+        heap: BinaryHeap<TopKEntry>,
+        heap_size: usize,
+        top_k: usize,
+        seq: usize,
+    },
+    Output(Vec<SortedRun>),
 }
 
 impl VmFuncSort {
-    pub(crate) fn compile(paths: &mut Vec<String>, val: &Bson) -> Result<Box<dyn VmExternalFunc>> {
-        let order_map = match val {
+    pub(crate) fn compile(
+        paths: &mut Vec<String>,
+        val: &Bson,
+        memory_limit: usize,
+        spill_dir: &Path,
+        top_k: Option<usize>,
+    ) -> Result<Box<dyn VmExternalFunc>> {
+        let orders = match val {
             Bson::Document(doc) => {
-                let mut result = HashMap::default();
+                let mut result = Vec::with_capacity(doc.len());
                 for (k, v) in doc.iter() {
                     let order = match v {
                         Bson::Int32(val) => *val as i8,
                         Bson::Int64(val) => *val as i8,
+                        // the documents found by `$text` or `$vectorSearch` are sorted by the descending scores
+                        Bson::Document(meta) => match meta.get_str("$meta").ok().and_then(MetaOperator::meta_field) {
+                            Some(field) => {
+                                result.push((field.to_string(), -1));
+                                continue;
+                            }
+                            None => return Err(Error::ValidationError("Invalid sort value".into())),
+                        },
                         _ => return Err(Error::ValidationError("Invalid sort value".into()))
                     };
-                    result.insert(k.clone(), order);
+                    result.push((k.clone(), order));
                 }
                 result
             }
@@ -46,44 +96,197 @@ impl VmFuncSort {
                 return Err(Error::InvalidField(invalid_err))
             }
         };
+        let state = match top_k {
+            Some(top_k) => SortState::TopK {
+                heap: BinaryHeap::with_capacity(top_k.min(HEAP_INIT_CAPACITY)),
+                heap_size: 0,
+                top_k,
+                seq: 0,
+            },
+            None => SortState::Input {
+                buffer: Vec::default(),
+                buffer_size: 0,
+                runs: Vec::default(),
+            },
+        };
         let result = VmFuncSort {
-            order_map,
-            buffer: RefCell::new(Vec::default()),
-            idx: AtomicUsize::new(0),
+            orders: Rc::new(SortOrders::new(orders)),
+            memory_limit,
+            spill_dir: spill_dir.to_path_buf(),
+            state: RefCell::new(state),
         };
         Ok(Box::new(result))
     }
 
-    fn i8_to_ordering(i: i8) -> std::cmp::Ordering {
-        match i {
-            1 => std::cmp::Ordering::Less,
-            -1 => std::cmp::Ordering::Greater,
-            _ => std::cmp::Ordering::Equal,
-        }
-    }
-
-    fn sort_array(&self) {
-        let mut array = self.buffer.borrow_mut();
-        array.sort_by(|a, b| {
-            for (k, v) in self.order_map.iter() {
-                let a_val = a.get(k);
-                let b_val = b.get(k);
-                match (a_val, b_val) {
-                    (Some(a_val), Some(b_val)) => {
-                        let result =  crate::utils::bson::value_cmp(a_val, b_val).expect("Invalid sort value");
-                        match result {
-                            std::cmp::Ordering::Equal => continue,
-                            std::cmp::Ordering::Less => return Self::i8_to_ordering(v.clone()),
-                            std::cmp::Ordering::Greater => return Self::i8_to_ordering(-v.clone()),
-                        }
-                    }
-                    (Some(_), None) => return Self::i8_to_ordering(v.clone()),
-                    (None, Some(_)) => return Self::i8_to_ordering(-v.clone()),
-                    (None, None) => continue,
+    fn sort_array(&self, array: &mut [Document]) -> Result<()> {
+        array.sort_by(|a, b| self.orders.compare_or_record(a, b));
+        self.orders.take_error()
+    }
+
+    fn push(&self, doc: &Document) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        let (buffer, buffer_size, runs) = match &mut *state {
+            SortState::Input { buffer, buffer_size, runs } => (buffer, buffer_size, runs),
+            SortState::TopK { heap, heap_size, top_k, seq } => {
+                self.push_top_k(heap, heap_size, *top_k, *seq, doc)?;
+                *seq += 1;
+                if *heap_size <= self.memory_limit {
+                    return Ok(());
+                }
+
+                // The heap is too large, it's written as the first run,
+                // and the following documents are sorted in runs.
+                let docs = std::mem::take(heap)
+                    .into_sorted_vec()
+                    .into_iter()
+                    .map(|entry| entry.doc)
+                    .collect::<Vec<Document>>();
+                self.orders.take_error()?;
+                *state = SortState::Input {
+                    buffer: Vec::default(),
+                    buffer_size: 0,
+                    runs: vec![SortedRun::spill_all(&self.spill_dir, docs)?],
+                };
+                return Ok(());
+            }
+            SortState::Output(_) => {
+                return Err(Error::ValidationError("the documents are already sorted".into()));
+            }
+        };
+
+        *buffer_size += doc_size(doc)?;
+        buffer.push(doc.clone());
+
+        if *buffer_size > self.memory_limit {
+            let mut docs = std::mem::take(buffer);
+            self.sort_array(&mut docs)?;
+            runs.push(SortedRun::spill_all(&self.spill_dir, docs)?);
+            *buffer_size = 0;
+        }
+
+        Ok(())
+    }
+
+    // The heap is a max-heap, the top is the last document of the top k,
+    // it's replaced if the new document is sorted before it.
+    fn push_top_k(
+        &self,
+        heap: &mut BinaryHeap<TopKEntry>,
+        heap_size: &mut usize,
+        top_k: usize,
+        seq: usize,
+        doc: &Document,
+    ) -> Result<()> {
+        if top_k == 0 {
+            return Ok(());
+        }
+        let entry = TopKEntry {
+            doc: doc.clone(),
+            size: doc_size(doc)?,
+            seq,
+            orders: self.orders.clone(),
+        };
+        if heap.len() < top_k {
+            *heap_size += entry.size;
+            heap.push(entry);
+            return self.orders.take_error();
+        }
+        let mut top = heap.peek_mut().unwrap();
+        if entry < *top {
+            *heap_size = *heap_size - top.size + entry.size;
+            *top = entry;
+        }
+        drop(top);
+        self.orders.take_error()
+    }
+
+    // Start to output the sorted documents,
+    // the documents remaining in the buffer is the last run.
+    fn finish_input(&self) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        match &mut *state {
+            SortState::Input { buffer, runs, .. } => {
+                let mut docs = std::mem::take(buffer);
+                self.sort_array(&mut docs)?;
+
+                let mut runs = std::mem::take(runs);
+                runs.push(SortedRun::in_memory(docs));
+
+                *state = SortState::Output(self.merge_runs(runs)?);
+            }
+            SortState::TopK { heap, .. } => {
+                let docs = std::mem::take(heap)
+                    .into_sorted_vec()
+                    .into_iter()
+                    .map(|entry| entry.doc)
+                    .collect::<Vec<Document>>();
+                self.orders.take_error()?;
+                *state = SortState::Output(vec![SortedRun::in_memory(docs)]);
+            }
+            SortState::Output(_) => (),
+        }
+        Ok(())
+    }
+
+    // Merge the consecutive runs into new runs until there are
+    // at most `MERGE_FAN_IN` runs left, so the number of the open files is bounded.
+    // The merged runs keep the order of the runs, so the sort is still stable.
+    fn merge_runs(&self, mut runs: Vec<SortedRun>) -> Result<Vec<SortedRun>> {
+        while runs.len() > MERGE_FAN_IN {
+            let mut merged = Vec::with_capacity(runs.len().div_ceil(MERGE_FAN_IN));
+            let mut remaining = runs.into_iter();
+            loop {
+                let mut group = remaining.by_ref().take(MERGE_FAN_IN).collect::<Vec<SortedRun>>();
+                if group.is_empty() {
+                    break;
+                }
+                for run in group.iter_mut() {
+                    run.open()?;
                 }
+                merged.push(SortedRun::spill(&self.spill_dir, || self.pop_min(&mut group))?);
             }
-            std::cmp::Ordering::Equal
-        });
+            runs = merged;
+        }
+
+        for run in runs.iter_mut() {
+            run.open()?;
+        }
+        Ok(runs)
+    }
+
+    fn next_sorted(&self) -> Result<Option<Document>> {
+        let mut state = self.state.borrow_mut();
+        match &mut *state {
+            SortState::Output(runs) => self.pop_min(runs),
+            SortState::Input { .. } | SortState::TopK { .. } => Ok(None),
+        }
+    }
+
+    // Pop the smallest head of the runs, the run with the smaller index
+    // is preferred if the documents are equal, so the sort is stable.
+    fn pop_min(&self, runs: &mut [SortedRun]) -> Result<Option<Document>> {
+        let mut min_idx: Option<usize> = None;
+        for (idx, run) in runs.iter().enumerate() {
+            let head = match &run.head {
+                Some(head) => head,
+                None => continue,
+            };
+            let is_less = match min_idx {
+                Some(min_idx) => {
+                    let min_head = runs[min_idx].head.as_ref().unwrap();
+                    self.orders.compare(head, min_head)? == Ordering::Less
+                }
+                None => true,
+            };
+            if is_less {
+                min_idx = Some(idx);
+            }
+        }
+
+        match min_idx {
+            Some(idx) => runs[idx].pop(),
+            None => Ok(None),
+        }
     }
 }
 
@@ -96,20 +299,14 @@ impl VmExternalFunc for VmFuncSort {
         let arg0 = &args[0];
         match arg0 {
             Bson::Document(doc) => {
-                let mut buffer = self.buffer.borrow_mut();
-                buffer.push(doc.clone());
+                self.push(doc)?;
                 Ok(VmExternalFuncStatus::Continue)
             }
             Bson::Null => {
-                self.sort_array();
-                let next = {
-                    let idx = self.idx.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                    let buffer = self.buffer.borrow();
-                    if idx >= buffer.len() {
-                        Bson::Null
-                    } else {
-                        buffer[idx].clone().into()
-                    }
+                self.finish_input()?;
+                let next = match self.next_sorted()? {
+                    Some(doc) => doc.into(),
+                    None => Bson::Null,
                 };
                 Ok(VmExternalFuncStatus::Next(next))
             }
@@ -120,8 +317,417 @@ impl VmExternalFunc for VmFuncSort {
     }
 
     fn is_completed(&self) -> bool {
-        let idx = self.idx.load(std::sync::atomic::Ordering::Relaxed);
-        let buffer = self.buffer.borrow();
-        idx >= buffer.len()
+        match &*self.state.borrow() {
+            SortState::Input { buffer, runs, .. } => buffer.is_empty() && runs.is_empty(),
+            SortState::TopK { heap, .. } => heap.is_empty(),
+            SortState::Output(runs) => runs.iter().all(|run| run.head.is_none()),
+        }
+    }
+}
+
+/// The keys to sort the documents.
+///
+/// The documents are compared by `Ord` in the heap and in `sort_by`,
+/// where the comparison can't fail, so the first error is kept
+/// until it's taken by `take_error`.
+struct SortOrders {
+    orders: Vec<(String, i8)>,
+    error: RefCell<Option<Error>>,
+}
+
+impl SortOrders {
+
+    fn new(orders: Vec<(String, i8)>) -> SortOrders {
+        SortOrders {
+            orders,
+            error: RefCell::new(None),
+        }
+    }
+
+    fn i8_to_ordering(i: i8) -> Ordering {
+        match i {
+            1 => Ordering::Less,
+            -1 => Ordering::Greater,
+            _ => Ordering::Equal,
+        }
+    }
+
+    /// The value of the key to sort the document.
+    ///
+    /// An array is sorted by its smallest element in the ascending order,
+    /// and by its largest element in the descending order.
+    fn sort_value(doc: &Document, key: &str, order: i8) -> Option<Bson> {
+        match crate::utils::bson::try_get_document_value(doc, key)? {
+            Bson::Array(arr) => {
+                let elements = arr.into_iter();
+                if order < 0 {
+                    elements.max_by(|a, b| crate::utils::bson::value_cmp(a, b).unwrap_or(Ordering::Equal))
+                } else {
+                    elements.min_by(|a, b| crate::utils::bson::value_cmp(a, b).unwrap_or(Ordering::Equal))
+                }
+            }
+            value => Some(value),
+        }
+    }
+
+    // A missing value is sorted as null, like the documents found by an index.
+    fn compare(&self, a: &Document, b: &Document) -> Result<Ordering> {
+        for (k, v) in self.orders.iter() {
+            let a_val = Self::sort_value(a, k, *v).unwrap_or(Bson::Null);
+            let b_val = Self::sort_value(b, k, *v).unwrap_or(Bson::Null);
+            match crate::utils::bson::value_cmp(&a_val, &b_val)? {
+                Ordering::Equal => continue,
+                Ordering::Less => return Ok(Self::i8_to_ordering(*v)),
+                Ordering::Greater => return Ok(Self::i8_to_ordering(-v)),
+            }
+        }
+        Ok(Ordering::Equal)
+    }
+
+    fn compare_or_record(&self, a: &Document, b: &Document) -> Ordering {
+        match self.compare(a, b) {
+            Ok(ordering) => ordering,
+            Err(err) => {
+                self.error.borrow_mut().get_or_insert(err);
+                Ordering::Equal
+            }
+        }
+    }
+
+    fn take_error(&self) -> Result<()> {
+        match self.error.borrow_mut().take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+}
+
+/// A document in the top-k heap, the documents are compared
+/// by the sort orders, then by the order they arrive.
+struct TopKEntry {
+    doc: Document,
+    size: usize,
+    seq: usize,
+    orders: Rc<SortOrders>,
+}
+
+impl Ord for TopKEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.orders.compare_or_record(&self.doc, &other.doc)
+            .then(self.seq.cmp(&other.seq))
     }
 }
+
+impl PartialOrd for TopKEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for TopKEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for TopKEntry {}
+
+fn doc_size(doc: &Document) -> Result<usize> {
+    let mut counter = ByteCounter(0);
+    doc.to_writer(&mut counter)?;
+    Ok(counter.0)
+}
+
+struct ByteCounter(usize);
+
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The sorted documents to be merged.
+///
+/// The file of a spilled run is closed until the run is opened to be merged.
+struct SortedRun {
+    source: RunSource,
+    head: Option<Document>,
+}
+
+enum RunSource {
+    Memory(std::vec::IntoIter<Document>),
+    File(SpillFile),
+}
+
+impl SortedRun {
+
+    fn in_memory(docs: Vec<Document>) -> SortedRun {
+        let mut iter = docs.into_iter();
+        let head = iter.next();
+        SortedRun {
+            source: RunSource::Memory(iter),
+            head,
+        }
+    }
+
+    fn spill_all(dir: &Path, docs: Vec<Document>) -> Result<SortedRun> {
+        let mut iter = docs.into_iter();
+        SortedRun::spill(dir, || Ok(iter.next()))
+    }
+
+    fn spill<F>(dir: &Path, next: F) -> Result<SortedRun>
+    where
+        F: FnMut() -> Result<Option<Document>>,
+    {
+        let file = SpillFile::write(dir, next)?;
+        Ok(SortedRun {
+            source: RunSource::File(file),
+            head: None,
+        })
+    }
+
+    fn open(&mut self) -> Result<()> {
+        if let RunSource::File(file) = &mut self.source {
+            if !file.is_open() {
+                file.open()?;
+                self.head = file.read_next()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_next(&mut self) -> Result<Option<Document>> {
+        match &mut self.source {
+            RunSource::Memory(iter) => Ok(iter.next()),
+            RunSource::File(file) => file.read_next(),
+        }
+    }
+
+    fn pop(&mut self) -> Result<Option<Document>> {
+        let next = self.read_next()?;
+        Ok(std::mem::replace(&mut self.head, next))
+    }
+
+}
+
+/// A temporary file removed when it's dropped.
+struct SpillFile {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+}
+
+impl SpillFile {
+
+    fn write<F>(dir: &Path, mut next: F) -> Result<SpillFile>
+    where
+        F: FnMut() -> Result<Option<Document>>,
+    {
+        let path = dir.join(format!("polodb-sort-{}.tmp", uuid::Uuid::new_v4()));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let result = SpillFile {
+            path,
+            reader: None,
+        };
+
+        let mut writer = BufWriter::new(file);
+        while let Some(doc) = next()? {
+            doc.to_writer(&mut writer)?;
+        }
+        writer.into_inner().map_err(|err| err.into_error())?;
+
+        Ok(result)
+    }
+
+    fn is_open(&self) -> bool {
+        self.reader.is_some()
+    }
+
+    fn open(&mut self) -> Result<()> {
+        let file = File::open(&self.path)?;
+        self.reader = Some(BufReader::new(file));
+        Ok(())
+    }
+
+    fn read_next(&mut self) -> Result<Option<Document>> {
+        let reader = self.reader.as_mut().expect("the file is not readable");
+        if reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let doc = Document::from_reader(reader)?;
+        Ok(Some(doc))
+    }
+
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        // close the file before it's removed
+        self.reader = None;
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Bson, doc};
+    use crate::vm::vm_external_func::{VmExternalFunc, VmExternalFuncStatus};
+    use super::{SortOrders, SortState, VmFuncSort, MERGE_FAN_IN};
+
+    fn sort_all(sort: &VmFuncSort, values: &[i32]) -> Vec<i32> {
+        for (idx, value) in values.iter().enumerate() {
+            let doc = doc! { "value": value, "idx": idx as i32 };
+            assert!(matches!(sort.call(&[doc.into()]).unwrap(), VmExternalFuncStatus::Continue));
+        }
+
+        let mut result = Vec::new();
+        while let VmExternalFuncStatus::Next(Bson::Document(doc)) = sort.call(&[Bson::Null]).unwrap() {
+            result.push(doc.get_i32("value").unwrap());
+        }
+        assert!(sort.is_completed());
+        result
+    }
+
+    fn new_sort(memory_limit: usize) -> VmFuncSort {
+        VmFuncSort {
+            orders: std::rc::Rc::new(SortOrders::new(vec![("value".to_string(), -1)])),
+            memory_limit,
+            spill_dir: std::env::temp_dir(),
+            state: std::cell::RefCell::new(SortState::Input {
+                buffer: Vec::new(),
+                buffer_size: 0,
+                runs: Vec::new(),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_sort_in_memory() {
+        let sort = new_sort(usize::MAX);
+        assert_eq!(sort_all(&sort, &[3, 1, 4, 1, 5, 9, 2, 6]), vec![9, 6, 5, 4, 3, 2, 1, 1]);
+    }
+
+    #[test]
+    fn test_sort_with_spilled_runs() {
+        let values = (0..100).map(|i| (i * 37) % 100).collect::<Vec<i32>>();
+        // every run holds a few documents
+        let sort = new_sort(100);
+        for (idx, value) in values.iter().enumerate() {
+            sort.call(&[doc! { "value": value, "idx": idx as i32 }.into()]).unwrap();
+        }
+        match &*sort.state.borrow() {
+            SortState::Input { runs, .. } => assert!(runs.len() > 10),
+            _ => unreachable!(),
+        }
+
+        let mut result = Vec::new();
+        while let VmExternalFuncStatus::Next(Bson::Document(doc)) = sort.call(&[Bson::Null]).unwrap() {
+            result.push(doc.get_i32("value").unwrap());
+        }
+        let expected = (0..100).rev().collect::<Vec<i32>>();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_spilled_sort_is_stable() {
+        let sort = new_sort(60);
+        assert_eq!(sort_all(&sort, &[2, 1, 2, 1, 2, 1]), vec![2, 2, 2, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_merge_runs_in_passes() {
+        let values = (0..2000).map(|i| (i * 37) % 2000).collect::<Vec<i32>>();
+        let sort = new_sort(100);
+        for (idx, value) in values.iter().enumerate() {
+            sort.call(&[doc! { "value": value, "idx": idx as i32 }.into()]).unwrap();
+        }
+        match &*sort.state.borrow() {
+            SortState::Input { runs, .. } => assert!(runs.len() > MERGE_FAN_IN * MERGE_FAN_IN),
+            _ => unreachable!(),
+        }
+
+        let first = sort.call(&[Bson::Null]).unwrap();
+        match &*sort.state.borrow() {
+            SortState::Output(runs) => assert!(runs.len() <= MERGE_FAN_IN),
+            _ => unreachable!(),
+        }
+
+        let mut result = Vec::new();
+        let mut next = first;
+        while let VmExternalFuncStatus::Next(Bson::Document(doc)) = next {
+            result.push(doc.get_i32("value").unwrap());
+            next = sort.call(&[Bson::Null]).unwrap();
+        }
+        let expected = (0..2000).rev().collect::<Vec<i32>>();
+        assert_eq!(result, expected);
+        assert!(sort.is_completed());
+    }
+
+    #[test]
+    fn test_sort_invalid_value() {
+        let sort = new_sort(60);
+        sort.call(&[doc! { "value": { "a": 1 } }.into()]).unwrap();
+        sort.call(&[doc! { "value": { "a": 2 } }.into()]).unwrap();
+        let result = sort.call(&[doc! { "value": { "a": 3 } }.into()])
+            .and_then(|_| sort.call(&[Bson::Null]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_sort_top_k() {
+        let sort = VmFuncSort::compile(&mut Vec::new(), &doc! { "value": -1 }.into(), usize::MAX, &std::env::temp_dir(), Some(3)).unwrap();
+        let values = [3, 1, 4, 1, 5, 9, 2, 6, 5];
+        for (idx, value) in values.iter().enumerate() {
+            sort.call(&[doc! { "value": value, "idx": idx as i32 }.into()]).unwrap();
+        }
+
+        let mut result = Vec::new();
+        while let VmExternalFuncStatus::Next(Bson::Document(doc)) = sort.call(&[Bson::Null]).unwrap() {
+            result.push((doc.get_i32("value").unwrap(), doc.get_i32("idx").unwrap()));
+        }
+        // the equal documents are kept in the order they arrive
+        assert_eq!(result, vec![(9, 5), (6, 7), (5, 4)]);
+        assert!(sort.is_completed());
+    }
+
+    #[test]
+    fn test_sort_top_k_over_memory_limit() {
+        let sort = VmFuncSort {
+            orders: std::rc::Rc::new(SortOrders::new(vec![("value".to_string(), -1)])),
+            memory_limit: 100,
+            spill_dir: std::env::temp_dir(),
+            state: std::cell::RefCell::new(SortState::TopK {
+                heap: std::collections::BinaryHeap::new(),
+                heap_size: 0,
+                top_k: 50,
+                seq: 0,
+            }),
+        };
+        let values = (0..100).map(|i| (i * 37) % 100).collect::<Vec<i32>>();
+        for (idx, value) in values.iter().enumerate() {
+            sort.call(&[doc! { "value": value, "idx": idx as i32 }.into()]).unwrap();
+        }
+        match &*sort.state.borrow() {
+            SortState::Input { runs, .. } => assert!(!runs.is_empty()),
+            _ => unreachable!(),
+        }
+
+        let mut result = Vec::new();
+        while let VmExternalFuncStatus::Next(Bson::Document(doc)) = sort.call(&[Bson::Null]).unwrap() {
+            result.push(doc.get_i32("value").unwrap());
+        }
+        // the documents after the top k are dropped by the `$limit`
+        let expected = (0..100).rev().collect::<Vec<i32>>();
+        assert_eq!(result, expected);
+        assert!(sort.is_completed());
+    }
+
+}